@@ -0,0 +1,95 @@
+//! Traversal benchmarks. Run with `cargo bench`.
+//!
+//! Set `FS_HELPER_BENCH_DIR` to benchmark against an existing large tree;
+//! otherwise a synthetic tree is generated in the temp directory. The "largest first" run
+//! reads metadata through io_uring with `--features io-uring` on Linux.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use fs_helper::{Priority, ReadDir};
+
+const ITERATIONS: u32 = 5;
+
+fn create_tree(root: &Path, width: usize, depth: usize, files: usize) {
+    fs::create_dir_all(root).unwrap();
+    for i in 0..files {
+        fs::File::create(root.join(format!("file{}.txt", i))).unwrap();
+    }
+    if depth > 0 {
+        for i in 0..width {
+            create_tree(&root.join(format!("dir{}", i)), width, depth - 1, files);
+        }
+    }
+}
+
+/// Naive recursive walk with `std::fs`, used as the reference implementation.
+fn std_walk(dir: &Path, count: &mut usize) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            std_walk(&entry.path(), count);
+        } else {
+            *count += 1;
+        }
+    }
+}
+
+fn bench<F: FnMut() -> usize>(name: &str, mut f: F) {
+    let mut total = Duration::ZERO;
+    let mut count = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        count = f();
+        total += start.elapsed();
+    }
+    println!("{:<24} {:>8} files {:>12.3?} / iter", name, count, total / ITERATIONS);
+}
+
+fn main() {
+    let (root, generated) = match env::var_os("FS_HELPER_BENCH_DIR") {
+        Some(dir) => (PathBuf::from(dir), false),
+        None => {
+            let root = env::temp_dir().join("fs-helper-bench");
+            if !root.exists() {
+                create_tree(&root, 4, 5, 20);
+            }
+            (root, true)
+        }
+    };
+
+    bench("std::fs recursive", || {
+        let mut count = 0;
+        std_walk(&root, &mut count);
+        count
+    });
+    bench("ReadDir", || ReadDir::try_new(&root).unwrap().count());
+    bench("ReadDir lazy", || {
+        let mut rd = ReadDir::try_new(&root).unwrap();
+        rd.is_lazy = true;
+        rd.count()
+    });
+    bench("ReadDir shared dirs", || {
+        let mut rd = ReadDir::try_new(&root).unwrap();
+        rd.is_lazy = true;
+        rd.shared_dirs = true;
+        rd.count()
+    });
+    bench("ReadDir multithreaded", || {
+        let mut rd = ReadDir::try_new(&root).unwrap();
+        rd.is_multithreaded = true;
+        rd.count()
+    });
+    bench("ReadDir largest first", || {
+        let mut rd = ReadDir::try_new(&root).unwrap();
+        rd.is_lazy = true;
+        rd.priority = Some(Priority::Largest);
+        rd.count()
+    });
+
+    if generated {
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! Rule matching benchmarks. Run with `cargo bench --bench rules`.
+//!
+//! Compares a compiled rule set against checking each rule on its own, the way naive
+//! per-pattern matching does, for a small and a large ignore file, and extension rules
+//! taking the string fast path against the same rules written as general globs.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use fs_helper::RuleSet;
+
+const ITERATIONS: u32 = 5;
+const PATHS: usize = 20_000;
+
+/// Rules like those of a large generated ignore file: names, extensions and anchored
+/// patterns; all exclude, so one rule set per rule gives the same answers.
+fn rules(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| match i % 3 {
+            0 => format!("name{}", i),
+            1 => format!("*.ext{}", i),
+            _ => format!("dir{}/**/*.tmp", i),
+        })
+        .collect()
+}
+
+/// Extension rules, as `*.ext` or, forcing the general matcher, as `*[.]ext`.
+fn extension_rules(count: usize, glob: bool) -> String {
+    let dot = if glob { "[.]" } else { "." };
+    let rules: Vec<_> = (0..count).map(|i| format!("*{}ext{}", dot, i * 7)).collect();
+    rules.join("\n")
+}
+
+fn paths() -> Vec<(PathBuf, bool)> {
+    (0..PATHS)
+        .map(|i| {
+            let path = format!("dir{}/sub{}/file{}.ext{}", i % 97, i % 13, i, i % 1000);
+            (PathBuf::from(path), i % 10 == 0)
+        })
+        .collect()
+}
+
+fn bench<F: FnMut() -> usize>(name: &str, mut f: F) {
+    let mut total = Duration::ZERO;
+    let mut count = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        count = f();
+        total += start.elapsed();
+    }
+    println!("{:<32} {:>8} included {:>12.3?} / iter", name, count, total / ITERATIONS);
+}
+
+fn main() {
+    let paths = paths();
+    for count in [10, 1000] {
+        let rules = rules(count);
+        let compiled = RuleSet::parse(&rules.join("\n"));
+        let separate: Vec<RuleSet> = rules.iter().map(|rule| RuleSet::parse(rule)).collect();
+        bench(&format!("compiled, {} rules", count), || {
+            let included = paths.iter().filter(|(path, dir)| compiled.is_included(path, *dir));
+            included.count()
+        });
+        bench(&format!("per pattern, {} rules", count), || {
+            let included = paths.iter().filter(|(path, is_dir)| {
+                separate.iter().all(|rule| rule.is_included(path, *is_dir))
+            });
+            included.count()
+        });
+    }
+    for count in [1, 100] {
+        for glob in [false, true] {
+            let rules = RuleSet::parse(&extension_rules(count, glob));
+            let kind = if glob { "glob" } else { "fast path" };
+            bench(&format!("{} extensions, {}", count, kind), || {
+                let included = paths.iter().filter(|(path, dir)| rules.is_included(path, *dir));
+                included.count()
+            });
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::path::Path;
+
+use crate::result::Result;
+
+/// Access control list of a file or directory, in the native format of the platform:
+/// POSIX ACLs on Linux (access and default lists), the DACL on Windows.
+/// Entries whose permissions are fully expressed by the mode bits have no ACL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    /// Named parts of the list with their raw contents.
+    parts: Vec<(&'static str, Vec<u8>)>,
+}
+
+/// Checks whether the filesystem holding `path` can store ACLs, so callers know up front
+/// whether copying with `CopyOptions::acls` preserves them. False on platforms without
+/// ACL support.
+pub fn acls_supported<P: AsRef<Path>>(path: P) -> Result<bool> {
+    Ok(sys::supported(path.as_ref())?)
+}
+
+/// Returns the ACL of a file or directory, or `None` if it has none (or the filesystem
+/// does not support ACLs). Symbolic links are not followed.
+pub fn read_acl<P: AsRef<Path>>(path: P) -> Result<Option<Acl>> {
+    let parts = sys::read(path.as_ref())?;
+    Ok((!parts.is_empty()).then_some(Acl { parts }))
+}
+
+/// Sets the ACL of a file or directory. Fails with an error of kind
+/// `io::ErrorKind::Unsupported` if the filesystem can not store it.
+pub fn write_acl<P: AsRef<Path>>(path: P, acl: &Acl) -> Result<()> {
+    Ok(sys::write(path.as_ref(), &acl.parts)?)
+}
+
+/// Copies the ACL of `from` to `to`; returns whether there was one to copy.
+pub(crate) fn copy_acl(from: &Path, to: &Path) -> Result<bool> {
+    match read_acl(from)? {
+        Some(acl) => write_acl(to, &acl).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+
+    // the kernel stores POSIX ACLs as extended attributes
+    const ACCESS: &str = "system.posix_acl_access";
+    const DEFAULT: &str = "system.posix_acl_default";
+    const ENODATA: i32 = 61;
+    const ENOTSUP: i32 = 95;
+
+    extern "C" {
+        fn lgetxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut c_void,
+            size: usize,
+        ) -> isize;
+        fn lsetxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const c_void,
+            size: usize,
+            flags: c_int,
+        ) -> c_int;
+    }
+
+    fn c_str(s: &[u8]) -> io::Result<CString> {
+        CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Reads an attribute; `None` if it is not set.
+    fn get(path: &CString, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let name = c_str(name.as_bytes())?;
+        loop {
+            // SAFETY: valid C strings; a null buffer of size 0 asks for the size.
+            let size = unsafe { lgetxattr(path.as_ptr(), name.as_ptr(), ptr::null_mut(), 0) };
+            if size < 0 {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    Some(ENODATA) => Ok(None),
+                    _ => Err(e),
+                };
+            }
+            let mut value = vec![0u8; size as usize];
+            // SAFETY: `value` has room for `size` bytes.
+            let n = unsafe {
+                lgetxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_mut_ptr() as *mut c_void,
+                    value.len(),
+                )
+            };
+            if n >= 0 {
+                value.truncate(n as usize);
+                return Ok(Some(value));
+            }
+            let e = io::Error::last_os_error();
+            // ERANGE: the attribute grew in between
+            if e.raw_os_error() != Some(34) {
+                return Err(e);
+            }
+        }
+    }
+
+    pub(super) fn supported(path: &Path) -> io::Result<bool> {
+        let path = c_str(path.as_os_str().as_bytes())?;
+        match get(&path, ACCESS) {
+            Ok(_) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(ENOTSUP) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(super) fn read(path: &Path) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+        let path = c_str(path.as_os_str().as_bytes())?;
+        let mut parts = Vec::new();
+        for name in [ACCESS, DEFAULT] {
+            match get(&path, name) {
+                Ok(Some(value)) => parts.push((name, value)),
+                Ok(None) => {}
+                Err(e) if e.raw_os_error() == Some(ENOTSUP) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(parts)
+    }
+
+    pub(super) fn write(path: &Path, parts: &[(&'static str, Vec<u8>)]) -> io::Result<()> {
+        let path = c_str(path.as_os_str().as_bytes())?;
+        for (name, value) in parts {
+            let name = c_str(name.as_bytes())?;
+            // SAFETY: valid C strings and a buffer of `value.len()` bytes.
+            let ret = unsafe {
+                lsetxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_ptr() as *const c_void,
+                    value.len(),
+                    0,
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    Some(ENOTSUP) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+                    _ => Err(e),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::slice;
+
+    const DACL: &str = "dacl";
+    const SE_FILE_OBJECT: i32 = 1;
+    const DACL_SECURITY_INFORMATION: u32 = 4;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn GetNamedSecurityInfoW(
+            name: *const u16,
+            object_type: i32,
+            info: u32,
+            owner: *mut *mut c_void,
+            group: *mut *mut c_void,
+            dacl: *mut *mut c_void,
+            sacl: *mut *mut c_void,
+            descriptor: *mut *mut c_void,
+        ) -> u32;
+        fn SetNamedSecurityInfoW(
+            name: *const u16,
+            object_type: i32,
+            info: u32,
+            owner: *mut c_void,
+            group: *mut c_void,
+            dacl: *mut c_void,
+            sacl: *mut c_void,
+        ) -> u32;
+    }
+
+    extern "system" {
+        fn LocalFree(mem: *mut c_void) -> *mut c_void;
+    }
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    pub(super) fn supported(path: &Path) -> io::Result<bool> {
+        // NTFS and ReFS always have security descriptors; FAT volumes fail to report one
+        Ok(read(path).is_ok())
+    }
+
+    pub(super) fn read(path: &Path) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+        let name = wide(path);
+        let mut dacl = ptr::null_mut();
+        let mut descriptor = ptr::null_mut();
+        // SAFETY: `name` is NUL-terminated; the out pointers are valid.
+        let ret = unsafe {
+            GetNamedSecurityInfoW(
+                name.as_ptr(),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut dacl,
+                ptr::null_mut(),
+                &mut descriptor,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret as i32));
+        }
+        let mut parts = Vec::new();
+        if !dacl.is_null() {
+            let header = dacl as *const u8;
+            // SAFETY: an ACL starts with its header; AclSize (bytes 2..4) is its full length.
+            let bytes = unsafe {
+                let size = u16::from_le_bytes([*header.add(2), *header.add(3)]);
+                slice::from_raw_parts(header, size as usize).to_vec()
+            };
+            parts.push((DACL, bytes));
+        }
+        // SAFETY: the descriptor was allocated by GetNamedSecurityInfoW.
+        unsafe { LocalFree(descriptor) };
+        Ok(parts)
+    }
+
+    pub(super) fn write(path: &Path, parts: &[(&'static str, Vec<u8>)]) -> io::Result<()> {
+        let name = wide(path);
+        for (_, acl) in parts.iter().filter(|(part, _)| *part == DACL) {
+            // the ACL must be aligned like the DWORDs it contains
+            let mut aligned = vec![0u32; acl.len().div_ceil(4)];
+            // SAFETY: `aligned` holds at least `acl.len()` bytes.
+            unsafe {
+                ptr::copy_nonoverlapping(acl.as_ptr(), aligned.as_mut_ptr().cast(), acl.len())
+            };
+            // SAFETY: `name` is NUL-terminated and `aligned` holds a valid ACL.
+            let ret = unsafe {
+                SetNamedSecurityInfoW(
+                    name.as_ptr(),
+                    SE_FILE_OBJECT,
+                    DACL_SECURITY_INFORMATION,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    aligned.as_mut_ptr().cast(),
+                    ptr::null_mut(),
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::from_raw_os_error(ret as i32));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    pub(super) fn supported(_path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    pub(super) fn read(_path: &Path) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    pub(super) fn write(_path: &Path, _parts: &[(&'static str, Vec<u8>)]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ACLs are only supported on Linux and Windows",
+        ))
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use crate::acl::{acls_supported, read_acl, Acl};
+    use crate::copy::{copy_dir, CopyOptions};
+    use crate::fixture::TreeBuilder;
+
+    /// A POSIX ACL granting user 1000 read access, in the kernel's xattr format.
+    fn sample_acl() -> Acl {
+        let mut value = 2u32.to_le_bytes().to_vec();
+        // (tag, permissions, id): user, named user, group, mask, other
+        for (tag, perm, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 4, 1000),
+            (0x04, 4, u32::MAX),
+            (0x10, 4, u32::MAX),
+            (0x20, 4, u32::MAX),
+        ] {
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&perm.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        Acl {
+            parts: vec![("system.posix_acl_access", value)],
+        }
+    }
+
+    #[test]
+    fn copy_preserves_acls() {
+        let src = TreeBuilder::new().file("a.txt", b"a").build().unwrap();
+        let file = src.join("a.txt");
+        if !acls_supported(&file).unwrap() {
+            return;
+        }
+        assert_eq!(read_acl(&file).unwrap(), None);
+        let acl = sample_acl();
+        crate::acl::write_acl(&file, &acl).unwrap();
+        assert_eq!(read_acl(&file).unwrap(), Some(acl.clone()));
+
+        let dst = TreeBuilder::new().build().unwrap();
+        let options = CopyOptions {
+            acls: true,
+            ..CopyOptions::default()
+        };
+        copy_dir(src.path(), dst.join("copy"), &options).unwrap();
+        assert_eq!(read_acl(dst.join("copy/a.txt")).unwrap(), Some(acl));
+    }
+}
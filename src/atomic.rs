@@ -0,0 +1,105 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::durability::Durability;
+use crate::result::Result;
+use crate::vfs::set_mode_of;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a unique temporary path next to `path`, on the same filesystem.
+pub(crate) fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.tmp", name, process::id(), n))
+}
+
+/// Writes `bytes` to `path` atomically: readers see either the old or the new contents.
+/// The data is written to a temporary file in the same directory, flushed to disk and renamed.
+/// A replaced file keeps its permissions.
+///
+/// # Arguments:
+///
+/// * `path` - destination file.
+/// * `bytes` - new contents.
+pub fn write_atomic<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Result<()> {
+    write_atomic_with(path, bytes, Durability::DataOnly)
+}
+
+/// Like [`write_atomic`], with a chosen durability: `Durability::DataAndDirs` also flushes
+/// the directory, so the new contents survive a crash right after the call returns.
+/// With `Durability::None` the rename is still atomic for readers, but a crash may leave
+/// an empty file.
+///
+/// # Arguments:
+///
+/// * `path` - destination file.
+/// * `bytes` - new contents.
+/// * `durability` - what to flush to disk.
+pub fn write_atomic_with<P: AsRef<Path>>(
+    path: P,
+    bytes: &[u8],
+    durability: Durability,
+) -> Result<()> {
+    Ok(write_atomic_from(path.as_ref(), &mut &bytes[..], durability, None)?)
+}
+
+/// Like [`write_atomic_with`], writing everything read from `data`; the file gets the
+/// permission bits `mode`, or if `None` those of the file it replaces.
+pub(crate) fn write_atomic_from(
+    path: &Path,
+    data: &mut dyn Read,
+    durability: Durability,
+    mode: Option<u32>,
+) -> io::Result<()> {
+    let tmp = temp_path_for(path);
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        io::copy(data, &mut file)?;
+        let permissions = match (mode, fs::metadata(path)) {
+            (Some(mode), _) => {
+                let mut permissions = file.metadata()?.permissions();
+                set_mode_of(&mut permissions, mode);
+                Some(permissions)
+            }
+            (None, Ok(meta)) if meta.is_file() => Some(meta.permissions()),
+            _ => None,
+        };
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        durability.sync_file(&file)?;
+        fs::rename(&tmp, path)?;
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => durability.sync_dir(dir),
+            _ => durability.sync_dir(Path::new(".")),
+        }
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::atomic::{write_atomic, write_atomic_with};
+    use crate::durability::Durability;
+    use crate::fixture::TreeBuilder;
+    use std::fs;
+
+    #[test]
+    fn write_atomic_replaces() {
+        let tree = TreeBuilder::new().build().unwrap();
+        let file = tree.join("file.txt");
+        write_atomic(&file, b"old").unwrap();
+        write_atomic(&file, b"new").unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+        write_atomic_with(&file, b"durable", Durability::DataAndDirs).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"durable");
+        assert_eq!(fs::read_dir(tree.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::Path;
+
+use crate::result::Result;
+
+/// Inode flags of a file, as shown by `lsattr` and changed by `chattr` on Linux.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttrs {
+    /// The file can not be modified, renamed, removed or linked to (`i`).
+    pub immutable: bool,
+    /// The file can only be opened for appending (`a`).
+    pub append_only: bool,
+    /// The file is skipped by `dump` and backup tools honouring the flag (`d`).
+    pub no_dump: bool,
+}
+
+/// Returns the attributes of a file or directory.
+/// Fails with an error of kind `io::ErrorKind::Unsupported` on other platforms and on
+/// filesystems without inode flags.
+pub fn get_attrs<P: AsRef<Path>>(path: P) -> Result<FileAttrs> {
+    Ok(sys::get(path.as_ref())?)
+}
+
+/// Sets or clears the immutable attribute (requires `CAP_LINUX_IMMUTABLE`).
+///
+/// # Arguments:
+///
+/// * `path` - file or directory.
+/// * `on` - whether the attribute is set.
+pub fn set_immutable<P: AsRef<Path>>(path: P, on: bool) -> Result<()> {
+    Ok(sys::update(path.as_ref(), |attrs| attrs.immutable = on)?)
+}
+
+/// Sets or clears the append-only attribute (requires `CAP_LINUX_IMMUTABLE`).
+///
+/// # Arguments:
+///
+/// * `path` - file or directory.
+/// * `on` - whether the attribute is set.
+pub fn set_append_only<P: AsRef<Path>>(path: P, on: bool) -> Result<()> {
+    Ok(sys::update(path.as_ref(), |attrs| attrs.append_only = on)?)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod sys {
+    use std::ffi::{c_int, c_ulong};
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    use crate::attrs::FileAttrs;
+
+    // _IOR('f', 1, long) and _IOW('f', 2, long); the kernel transfers an int
+    const FS_IOC_GETFLAGS: c_ulong = 0x8008_6601;
+    const FS_IOC_SETFLAGS: c_ulong = 0x4008_6602;
+    const FS_IMMUTABLE_FL: c_int = 0x10;
+    const FS_APPEND_FL: c_int = 0x20;
+    const FS_NODUMP_FL: c_int = 0x40;
+    const O_NONBLOCK: i32 = 0o4000;
+    // ENOTTY, EOPNOTSUPP: the filesystem has no inode flags
+    const UNSUPPORTED: [i32; 2] = [25, 95];
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    fn open(path: &Path) -> io::Result<fs::File> {
+        // non-blocking, so opening a FIFO does not wait for a writer
+        fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(O_NONBLOCK)
+            .open(path)
+    }
+
+    fn call(file: &fs::File, request: c_ulong, flags: &mut c_int) -> io::Result<()> {
+        // SAFETY: the descriptor is open and `flags` is a valid int for the kernel to use.
+        if unsafe { ioctl(file.as_raw_fd(), request, flags as *mut c_int) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(code) if UNSUPPORTED.contains(&code) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, e))
+            }
+            _ => Err(e),
+        }
+    }
+
+    fn attrs_of(flags: c_int) -> FileAttrs {
+        FileAttrs {
+            immutable: flags & FS_IMMUTABLE_FL != 0,
+            append_only: flags & FS_APPEND_FL != 0,
+            no_dump: flags & FS_NODUMP_FL != 0,
+        }
+    }
+
+    pub(crate) fn get(path: &Path) -> io::Result<FileAttrs> {
+        let mut flags = 0;
+        call(&open(path)?, FS_IOC_GETFLAGS, &mut flags)?;
+        Ok(attrs_of(flags))
+    }
+
+    pub(crate) fn update<F: FnOnce(&mut FileAttrs)>(path: &Path, change: F) -> io::Result<()> {
+        let file = open(path)?;
+        let mut flags = 0;
+        call(&file, FS_IOC_GETFLAGS, &mut flags)?;
+        let mut attrs = attrs_of(flags);
+        change(&mut attrs);
+        // other flags (e.g. extents or compression) are kept as they are
+        for (on, flag) in [
+            (attrs.immutable, FS_IMMUTABLE_FL),
+            (attrs.append_only, FS_APPEND_FL),
+            (attrs.no_dump, FS_NODUMP_FL),
+        ] {
+            if on {
+                flags |= flag;
+            } else {
+                flags &= !flag;
+            }
+        }
+        call(&file, FS_IOC_SETFLAGS, &mut flags)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) mod sys {
+    use std::io;
+    use std::path::Path;
+
+    use crate::attrs::FileAttrs;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file attributes are only supported on Linux",
+        )
+    }
+
+    pub(crate) fn get(_path: &Path) -> io::Result<FileAttrs> {
+        Err(unsupported())
+    }
+
+    pub(crate) fn update<F: FnOnce(&mut FileAttrs)>(_path: &Path, _change: F) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attrs::{get_attrs, set_append_only, FileAttrs};
+    use crate::fixture::TreeBuilder;
+    use crate::ReadDir;
+    use std::io;
+
+    #[test]
+    fn file_attrs() {
+        let tree = TreeBuilder::new().file("log.txt", b"").build().unwrap();
+        let path = tree.join("log.txt");
+        let attrs = match get_attrs(&path) {
+            Ok(attrs) => attrs,
+            Err(e) => {
+                let source = std::error::Error::source(&e).unwrap();
+                let kind = source.downcast_ref::<io::Error>().unwrap().kind();
+                assert_eq!(kind, io::ErrorKind::Unsupported);
+                return;
+            }
+        };
+        assert_eq!(attrs, FileAttrs::default());
+        let entry = ReadDir::try_new(tree.path()).unwrap().next().unwrap();
+        assert_eq!(entry.attrs().unwrap(), attrs);
+        // setting attributes needs CAP_LINUX_IMMUTABLE
+        if set_append_only(&path, true).is_ok() {
+            assert!(get_attrs(&path).unwrap().append_only);
+            set_append_only(&path, false).unwrap();
+            assert!(!get_attrs(&path).unwrap().append_only);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::entry::Entry;
+use crate::result::{Error, Result};
+use crate::visit::{walk, Control, Visitor};
+
+const SETUID: u32 = 0o4000;
+const SETGID: u32 = 0o2000;
+const WORLD_WRITABLE: u32 = 0o002;
+
+/// What [`audit_permissions`] reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditPolicy {
+    /// Report files that anyone may write. Directories are checked against `max_dir_mode`
+    /// instead; symbolic links are not checked.
+    pub world_writable: bool,
+    /// Report files with the setuid or setgid bit.
+    pub setuid: bool,
+    /// If set, report entries owned by users (uids) not in the list.
+    pub owners: Option<Vec<u32>>,
+    /// Report directories with permission bits beyond these (e.g. group or world writable
+    /// directories for `0o755`).
+    pub max_dir_mode: u32,
+}
+
+impl Default for AuditPolicy {
+    fn default() -> AuditPolicy {
+        AuditPolicy {
+            world_writable: true,
+            setuid: true,
+            owners: None,
+            max_dir_mode: 0o755,
+        }
+    }
+}
+
+/// A permission problem found by [`audit_permissions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionIssue {
+    /// A file anyone may write.
+    WorldWritable,
+    /// A file running with the privileges of its owner.
+    Setuid,
+    /// A file running with the privileges of its group.
+    Setgid,
+    /// An entry owned by a user not allowed by the policy.
+    UnexpectedOwner(u32),
+    /// A directory with more permissions than allowed; holds its mode.
+    PermissiveDir(u32),
+}
+
+/// An entry with a permission problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditFinding {
+    pub path: PathBuf,
+    pub issue: PermissionIssue,
+}
+
+struct Audit<'a> {
+    policy: &'a AuditPolicy,
+    found: Vec<AuditFinding>,
+}
+
+impl Audit<'_> {
+    fn report(&mut self, path: &Path, issue: PermissionIssue) {
+        self.found.push(AuditFinding {
+            path: path.to_path_buf(),
+            issue,
+        });
+    }
+
+    fn check_owner(&mut self, path: &Path, meta: &fs::Metadata) {
+        if let Some(owners) = &self.policy.owners {
+            if !owners.contains(&meta.uid()) {
+                self.report(path, PermissionIssue::UnexpectedOwner(meta.uid()));
+            }
+        }
+    }
+}
+
+impl Visitor for Audit<'_> {
+    fn enter_dir(&mut self, dir: &Path, _depth: usize) -> Control {
+        if let Ok(meta) = fs::symlink_metadata(dir) {
+            self.check_owner(dir, &meta);
+            let mode = meta.mode() & 0o777;
+            if mode & !self.policy.max_dir_mode != 0 {
+                self.report(dir, PermissionIssue::PermissiveDir(mode));
+            }
+        }
+        Control::Continue
+    }
+
+    fn file(&mut self, entry: &Entry) -> Control {
+        let path = entry.path();
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => return Control::Continue,
+        };
+        self.check_owner(path, &meta);
+        // the mode of a link itself is meaningless
+        if meta.file_type().is_symlink() {
+            return Control::Continue;
+        }
+        let mode = meta.mode();
+        if self.policy.world_writable && mode & WORLD_WRITABLE != 0 {
+            self.report(path, PermissionIssue::WorldWritable);
+        }
+        if self.policy.setuid && mode & SETUID != 0 {
+            self.report(path, PermissionIssue::Setuid);
+        }
+        if self.policy.setuid && mode & SETGID != 0 {
+            self.report(path, PermissionIssue::Setgid);
+        }
+        Control::Continue
+    }
+
+    fn error(&mut self, _path: &Path, _error: Error) -> Control {
+        Control::Continue
+    }
+}
+
+/// Walks the tree under `root` and reports permission problems: world-writable files,
+/// setuid and setgid files, entries owned by unexpected users and overly permissive
+/// directories (including the root). Findings are sorted by path;
+/// entries that can not be read are skipped.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `policy` - what to report.
+pub fn audit_permissions<P: AsRef<Path>>(
+    root: P,
+    policy: &AuditPolicy,
+) -> Result<Vec<AuditFinding>> {
+    let mut audit = Audit {
+        policy,
+        found: Vec::new(),
+    };
+    walk(root, &mut audit)?;
+    // stable, so the issues of an entry keep their order
+    audit.found.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(audit.found)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::{audit_permissions, AuditPolicy, PermissionIssue};
+    use crate::fixture::TreeBuilder;
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn audit_finds_permission_issues() {
+        let tree = TreeBuilder::new()
+            .file("ok.txt", b"")
+            .file("open.txt", b"")
+            .file("bin/tool", b"")
+            .dir("shared")
+            .build()
+            .unwrap();
+        fs::set_permissions(tree.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(tree.join("ok.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(tree.join("open.txt"), fs::Permissions::from_mode(0o666)).unwrap();
+        fs::set_permissions(tree.join("bin"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(tree.join("bin/tool"), fs::Permissions::from_mode(0o6755)).unwrap();
+        fs::set_permissions(tree.join("shared"), fs::Permissions::from_mode(0o777)).unwrap();
+
+        let found = audit_permissions(tree.path(), &AuditPolicy::default()).unwrap();
+        let issues: Vec<_> = found
+            .iter()
+            .map(|f| (f.path.strip_prefix(tree.path()).unwrap(), f.issue))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                ("bin/tool".as_ref(), PermissionIssue::Setuid),
+                ("bin/tool".as_ref(), PermissionIssue::Setgid),
+                ("open.txt".as_ref(), PermissionIssue::WorldWritable),
+                ("shared".as_ref(), PermissionIssue::PermissiveDir(0o777)),
+            ]
+        );
+
+        let uid = fs::metadata(tree.path()).unwrap().uid();
+        let policy = AuditPolicy {
+            world_writable: false,
+            setuid: false,
+            owners: Some(vec![uid.wrapping_add(1)]),
+            max_dir_mode: 0o777,
+        };
+        let found = audit_permissions(tree.path(), &policy).unwrap();
+        assert_eq!(found.len(), 6);
+        assert!(found
+            .iter()
+            .all(|f| f.issue == PermissionIssue::UnexpectedOwner(uid)));
+    }
+}
@@ -0,0 +1,941 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::atomic::write_atomic;
+use crate::cancel::{CancelToken, Cancelled};
+#[cfg(feature = "delta")]
+use crate::delta::{Delta, DEFAULT_BLOCK_SIZE};
+use crate::encoding::{decode_path, encode_path};
+use crate::format::format_count;
+use crate::hash::Hash;
+use crate::paths::simplify_verbatim;
+use crate::progress::{CopyReader, Operation, ProgressEvent, ProgressSink};
+use crate::remote::{not_found, LocalDir, Remote};
+use crate::result::{Error, ErrorKind, Result};
+use crate::stats::{IoStats, Phase, Recorder};
+
+const MAGIC: &str = "fs-helper-baseline 2";
+
+/// Most literal data of a delta held in memory; files changed more are sent whole.
+#[cfg(feature = "delta")]
+const MAX_DELTA_LITERAL: u64 = 8 * 1024 * 1024;
+
+/// State of two trees after they were last synchronized by [`sync_bidirectional`]: the hash
+/// of every file and symbolic link, as computed by [`Remote::hash`], by relative path.
+/// Changes on each side are found by comparing with it, so a file changed on one side only
+/// is not mistaken for a conflict.
+///
+/// Stored as text with [`Baseline::save`]: a header followed by one line per entry with
+/// the hash and the [encoded](crate::encode_path) path, tab-separated.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Baseline {
+    pub entries: BTreeMap<PathBuf, Hash>,
+}
+
+impl Baseline {
+    /// Records the state of the tree at `root`, e.g. to start syncing two identical trees.
+    pub fn capture<P: AsRef<Path>>(root: P) -> Result<Baseline> {
+        Baseline::capture_remote(&LocalDir::new(root))
+    }
+
+    /// Records the state of a tree of a [`Remote`], e.g. a [`LocalDir`] of another
+    /// filesystem.
+    pub fn capture_remote<R: Remote + ?Sized>(tree: &R) -> Result<Baseline> {
+        let mut entries = BTreeMap::new();
+        for path in tree.list()? {
+            let hash = tree.hash(&path)?;
+            entries.insert(path, hash);
+        }
+        Ok(Baseline { entries })
+    }
+
+    /// Reads a baseline stored by [`Baseline::save`]; a missing file is an empty baseline,
+    /// for a first sync. Fails with an error of kind `ErrorKind::Encoding` if the file is
+    /// invalid.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Baseline> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Baseline::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = || Error::new(ErrorKind::Encoding, "invalid sync baseline");
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid());
+        }
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let (hash, path) = line.split_once('\t').ok_or_else(invalid)?;
+            entries.insert(decode_path(path)?, hash.parse().map_err(|_| invalid())?);
+        }
+        Ok(Baseline { entries })
+    }
+
+    /// Stores the baseline atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut out = format!("{}\n", MAGIC);
+        for (path, hash) in &self.entries {
+            out.push_str(&format!("{}\t{}\n", hash, encode_path(path)));
+        }
+        write_atomic(path, out.as_bytes())
+    }
+}
+
+/// One side's version of an entry in a [`Conflict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    /// Hash of the contents, or of the target of a symbolic link.
+    pub hash: Hash,
+    /// Modification time, if the platform records it.
+    pub modified: Option<SystemTime>,
+}
+
+/// An entry changed differently on both sides since the baseline. A version is `None` on
+/// the side that deleted the entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub local: Option<Version>,
+    pub remote: Option<Version>,
+}
+
+/// How a [`Conflict`] is resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Make both sides like the local side.
+    KeepLocal,
+    /// Make both sides like the remote side.
+    KeepRemote,
+    /// Keep the local version at the path and the remote one next to it, with
+    /// `.conflict` appended to its name, on both sides; `.conflict.1`, `.conflict.2` and so
+    /// on if that name is taken. A deleted version is not kept.
+    KeepBoth,
+}
+
+/// Resolves a conflict in favor of the most recently modified version; an edit wins over
+/// a deletion, and the local version wins ties.
+pub fn newest_wins(conflict: &Conflict) -> Resolution {
+    let modified = |version: Option<Version>| version.map(|v| v.modified);
+    match (modified(conflict.local), modified(conflict.remote)) {
+        (Some(_), None) => Resolution::KeepLocal,
+        (None, Some(_)) => Resolution::KeepRemote,
+        (Some(local), Some(remote)) if remote > local => Resolution::KeepRemote,
+        _ => Resolution::KeepLocal,
+    }
+}
+
+/// Resolves every conflict by keeping both versions.
+pub fn keep_both(_conflict: &Conflict) -> Resolution {
+    Resolution::KeepBoth
+}
+
+/// Changes made by [`sync_bidirectional`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BisyncReport {
+    /// Entries changed only on the remote side, updated or deleted locally.
+    pub to_local: Vec<PathBuf>,
+    /// Entries changed only on the local side, updated or deleted remotely.
+    pub to_remote: Vec<PathBuf>,
+    /// Entries changed on both sides, with how they were resolved.
+    pub conflicts: Vec<(PathBuf, Resolution)>,
+    /// State of both trees after the sync, to pass to the next one.
+    pub baseline: Baseline,
+    /// Bytes read and written to send files, and the files sent.
+    pub stats: IoStats,
+}
+
+impl fmt::Display for BisyncReport {
+    /// Formats the report for people, e.g. `12 to local, 3 to remote, 1 conflicts`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} to local, {} to remote, {} conflicts",
+            format_count(self.to_local.len() as u64),
+            format_count(self.to_remote.len() as u64),
+            format_count(self.conflicts.len() as u64)
+        )
+    }
+}
+
+/// Which tree a [`Propagation`] changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The local entry is made like the remote one.
+    ToLocal,
+    /// The remote entry is made like the local one.
+    ToRemote,
+}
+
+/// A planned change of one entry: the entry of one tree is copied to the other tree, or
+/// deleted there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Propagation {
+    pub path: PathBuf,
+    pub direction: Direction,
+    /// Whether the entry is deleted rather than copied, having been deleted in the tree it
+    /// comes from.
+    pub delete: bool,
+    /// Whether the propagation resolves a conflict, rather than a change in one tree.
+    pub conflict: bool,
+    /// Hash of the entry in the previous baseline, which the entry keeps in the baseline
+    /// of a sync that failed before the propagation.
+    pub base: Option<Hash>,
+}
+
+/// A copy of the remote version of a conflicting entry, made next to it in both trees when
+/// both versions are kept, see [`Resolution::KeepBoth`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictCopy {
+    pub path: PathBuf,
+    /// Path of the copy.
+    pub copy: PathBuf,
+}
+
+/// What a sync changes, planned by [`plan_sync`] from both trees and the baseline without
+/// changing anything, to be reviewed and applied by [`apply_sync`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// Copies of conflicting entries, made before the entries change.
+    pub copies: Vec<ConflictCopy>,
+    /// Entries to copy or delete, by path.
+    pub propagations: Vec<Propagation>,
+    /// Entries changed in both trees, with how they are resolved.
+    pub conflicts: Vec<(PathBuf, Resolution)>,
+    /// State of both trees once the plan is applied.
+    pub baseline: Baseline,
+}
+
+/// Settings of [`sync_bidirectional`], [`sync_remote`] and [`apply_sync`].
+#[derive(Clone, Default)]
+pub struct SyncOptions {
+    /// If set, receives `BytesCopied` events for the files sent and a final
+    /// `OperationFinished`.
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    /// If set, the sync stops between entries once the token is cancelled, e.g. by ctrl-c
+    /// with `CancelToken::on_signals`, and fails with an error of kind
+    /// `ErrorKind::Cancelled` whose source is a [`SyncFailed`]. The entry being sent is
+    /// finished first.
+    pub cancel: Option<CancelToken>,
+}
+
+/// Cause of errors of [`apply_sync`], and so of [`sync_remote`] and
+/// [`sync_bidirectional`], that stop a sync part way, with what was changed before; get it
+/// with `error.source()` and `downcast_ref`. The error has the kind of the failure, or
+/// `ErrorKind::Cancelled` with a [`Cancelled`] error when the sync was cancelled.
+#[derive(Debug)]
+pub struct SyncFailed {
+    /// Entry that could not be changed.
+    pub path: PathBuf,
+    /// Changes made before the failure. Its baseline has the previous state of the entries
+    /// not changed yet, so that the next sync picks up where this one stopped.
+    pub report: BisyncReport,
+    pub error: Error,
+}
+
+impl fmt::Display for SyncFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} (after {})", self.path.display(), self.error, self.report)
+    }
+}
+
+impl std::error::Error for SyncFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Synchronizes two trees in both directions: entries changed on one side since the
+/// baseline (created, modified or deleted) are changed on the other side too, and entries
+/// changed on both sides are passed to `resolve`, e.g. [`newest_wins`], [`keep_both`] or a
+/// function asking the user. Entries are files and symbolic links; directories are created
+/// as needed and left in place when emptied.
+///
+/// # Arguments:
+///
+/// * `local` - root of one tree.
+/// * `remote` - root of the other tree.
+/// * `baseline` - state after the previous sync, e.g. from [`Baseline::load`].
+/// * `resolve` - decides how each conflict is resolved.
+/// * `options` - progress and cancellation.
+pub fn sync_bidirectional<L, R, F>(
+    local: L,
+    remote: R,
+    baseline: &Baseline,
+    resolve: F,
+    options: &SyncOptions,
+) -> Result<BisyncReport>
+where
+    L: AsRef<Path>,
+    R: AsRef<Path>,
+    F: FnMut(&Conflict) -> Resolution,
+{
+    let local = LocalDir::new(simplify_verbatim(fs::canonicalize(local)?));
+    let remote = LocalDir::new(simplify_verbatim(fs::canonicalize(remote)?));
+    sync_remote(&local, &remote, baseline, resolve, options)
+}
+
+/// Synchronizes a local tree with a [`Remote`] backend in both directions, as
+/// [`sync_bidirectional`] does with two local trees. The local tree is a [`LocalDir`],
+/// of the real filesystem or another one, or any other backend. The sync is planned with
+/// [`plan_sync`] before anything changes, then applied with [`apply_sync`].
+///
+/// # Arguments:
+///
+/// * `local` - the local tree.
+/// * `remote` - the other tree.
+/// * `baseline` - state after the previous sync, e.g. from [`Baseline::load`].
+/// * `resolve` - decides how each conflict is resolved.
+/// * `options` - progress and cancellation.
+pub fn sync_remote<L, R, F>(
+    local: &L,
+    remote: &R,
+    baseline: &Baseline,
+    resolve: F,
+    options: &SyncOptions,
+) -> Result<BisyncReport>
+where
+    L: Remote + ?Sized,
+    R: Remote + ?Sized,
+    F: FnMut(&Conflict) -> Resolution,
+{
+    let plan = plan_sync(local, remote, baseline, resolve)?;
+    apply_sync(local, remote, &plan, options)
+}
+
+/// Plans the sync of two trees, as [`sync_remote`] does, without changing them: entries
+/// changed in one tree since the baseline are to be propagated to the other, and entries
+/// changed in both are passed to `resolve` now.
+///
+/// # Arguments:
+///
+/// * `local` - the local tree.
+/// * `remote` - the other tree.
+/// * `baseline` - state after the previous sync, e.g. from [`Baseline::load`].
+/// * `resolve` - decides how each conflict is resolved.
+pub fn plan_sync<L, R, F>(
+    local: &L,
+    remote: &R,
+    baseline: &Baseline,
+    mut resolve: F,
+) -> Result<SyncPlan>
+where
+    L: Remote + ?Sized,
+    R: Remote + ?Sized,
+    F: FnMut(&Conflict) -> Resolution,
+{
+    let local_files: BTreeSet<PathBuf> = local.list()?.into_iter().collect();
+    let remote_files: BTreeSet<PathBuf> = remote.list()?.into_iter().collect();
+    let mut paths: BTreeSet<&Path> = baseline.entries.keys().map(PathBuf::as_path).collect();
+    paths.extend(local_files.iter().map(PathBuf::as_path));
+    paths.extend(remote_files.iter().map(PathBuf::as_path));
+
+    let mut plan = SyncPlan::default();
+    let synced = &mut plan.baseline.entries;
+    for path in paths {
+        let l = version(local, path, local_files.contains(path))?;
+        let r = version(remote, path, remote_files.contains(path))?;
+        let base = baseline.entries.get(path).copied();
+        let (lh, rh) = (l.map(|v| v.hash), r.map(|v| v.hash));
+        let propagation = |direction, exists: bool, conflict| Propagation {
+            path: path.to_path_buf(),
+            direction,
+            delete: !exists,
+            conflict,
+            base,
+        };
+        let result = if lh == rh {
+            lh
+        } else if lh == base {
+            plan.propagations.push(propagation(Direction::ToLocal, rh.is_some(), false));
+            rh
+        } else if rh == base {
+            plan.propagations.push(propagation(Direction::ToRemote, lh.is_some(), false));
+            lh
+        } else {
+            let conflict = Conflict {
+                path: path.to_path_buf(),
+                local: l,
+                remote: r,
+            };
+            let resolution = resolve(&conflict);
+            plan.conflicts.push((path.to_path_buf(), resolution));
+            match (resolution, lh, rh) {
+                (Resolution::KeepLocal, _, _) | (Resolution::KeepBoth, Some(_), None) => {
+                    let to_remote = propagation(Direction::ToRemote, lh.is_some(), true);
+                    plan.propagations.push(to_remote);
+                    lh
+                }
+                (Resolution::KeepRemote, _, _) | (Resolution::KeepBoth, None, _) => {
+                    plan.propagations.push(propagation(Direction::ToLocal, rh.is_some(), true));
+                    rh
+                }
+                (Resolution::KeepBoth, Some(_), Some(theirs)) => {
+                    let copy = conflict_path(local, remote, path, |path| {
+                        synced.contains_key(path) || baseline.entries.contains_key(path)
+                    })?;
+                    synced.insert(copy.clone(), theirs);
+                    plan.copies.push(ConflictCopy {
+                        path: path.to_path_buf(),
+                        copy,
+                    });
+                    plan.propagations.push(propagation(Direction::ToRemote, true, true));
+                    lh
+                }
+            }
+        };
+        if let Some(hash) = result {
+            synced.insert(path.to_path_buf(), hash);
+        }
+    }
+    Ok(plan)
+}
+
+/// Applies a sync planned by [`plan_sync`]: makes the copies of conflicting entries, then
+/// the propagations, in order. Files replacing files are sent as [deltas](crate::delta)
+/// of the old versions when that is smaller. Stops at the first failure, or once
+/// cancelled, with an error whose source is a [`SyncFailed`].
+///
+/// # Arguments:
+///
+/// * `local` - the local tree the plan was made for.
+/// * `remote` - the other tree.
+/// * `plan` - what to change.
+/// * `options` - progress and cancellation.
+pub fn apply_sync<L, R>(
+    local: &L,
+    remote: &R,
+    plan: &SyncPlan,
+    options: &SyncOptions,
+) -> Result<BisyncReport>
+where
+    L: Remote + ?Sized,
+    R: Remote + ?Sized,
+{
+    let transfer = Transfer {
+        progress: options.progress.as_deref(),
+        stats: Recorder::start(),
+    };
+    let cancelled = || {
+        let cancelled = options.cancel.as_ref().is_some_and(|token| token.is_cancelled());
+        cancelled.then(|| {
+            let stats = transfer.stats.stats();
+            Error::new(ErrorKind::Cancelled, Cancelled { stats })
+        })
+    };
+    for (i, copy) in plan.copies.iter().enumerate() {
+        let result = match cancelled() {
+            Some(e) => Err(e),
+            None => copy_conflict(local, remote, copy, &transfer),
+        };
+        if let Err(e) = result {
+            return Err(failed(plan, i, 0, &copy.copy, e, &transfer.stats));
+        }
+    }
+    for (i, propagation) in plan.propagations.iter().enumerate() {
+        let result = match (cancelled(), propagation.direction) {
+            (Some(e), _) => Err(e),
+            (None, Direction::ToLocal) => propagate(remote, local, propagation, &transfer),
+            (None, Direction::ToRemote) => propagate(local, remote, propagation, &transfer),
+        };
+        if let Err(e) = result {
+            let copies = plan.copies.len();
+            return Err(failed(plan, copies, i, &propagation.path, e, &transfer.stats));
+        }
+    }
+    if let Some(progress) = transfer.progress {
+        progress.event(&ProgressEvent::OperationFinished {
+            operation: Operation::Sync,
+        });
+    }
+    let stats = transfer.stats.stats();
+    Ok(applied(plan, plan.copies.len(), plan.propagations.len(), stats))
+}
+
+/// Sends files for [`apply_sync`], reporting and counting what is sent.
+struct Transfer<'a> {
+    progress: Option<&'a dyn ProgressSink>,
+    stats: Recorder,
+}
+
+impl Transfer<'_> {
+    /// Writes `data` to the file `path` of `to`.
+    fn send<B>(&self, to: &B, path: &Path, data: &mut dyn Read, mode: Option<u32>) -> Result<()>
+    where
+        B: Remote + ?Sized,
+    {
+        let mut data = CopyReader::new(data, path, self.progress);
+        self.stats.time(Phase::Data, || to.write(path, &mut data, mode))?;
+        self.stats.read(data.bytes);
+        self.stats.written(data.bytes);
+        Ok(())
+    }
+}
+
+/// Returns what the first `copies` copies and `propagations` propagations of a plan
+/// changed.
+fn applied(plan: &SyncPlan, copies: usize, propagations: usize, stats: IoStats) -> BisyncReport {
+    let mut report = BisyncReport {
+        baseline: plan.baseline.clone(),
+        stats,
+        ..BisyncReport::default()
+    };
+    let entries = &mut report.baseline.entries;
+    for copy in &plan.copies[copies..] {
+        entries.remove(&copy.copy);
+    }
+    let mut conflicts = 0;
+    for (i, propagation) in plan.propagations.iter().enumerate() {
+        let path = propagation.path.clone();
+        if i >= propagations {
+            match propagation.base {
+                Some(hash) => entries.insert(path, hash),
+                None => entries.remove(&path),
+            };
+        } else if propagation.conflict {
+            // conflicts are planned along with their propagations
+            conflicts += 1;
+        } else if propagation.direction == Direction::ToLocal {
+            report.to_local.push(path);
+        } else {
+            report.to_remote.push(path);
+        }
+    }
+    report.conflicts = plan.conflicts[..conflicts].to_vec();
+    report
+}
+
+fn failed(
+    plan: &SyncPlan,
+    copies: usize,
+    propagations: usize,
+    path: &Path,
+    error: Error,
+    stats: &Recorder,
+) -> Error {
+    let failed = SyncFailed {
+        path: path.to_path_buf(),
+        report: applied(plan, copies, propagations, stats.stats()),
+        error,
+    };
+    Error::new(failed.error.kind(), failed)
+}
+
+/// Returns the first of `<name>.conflict`, `<name>.conflict.1`, ... next to `path` that
+/// exists on neither side, and is not `taken`.
+fn conflict_path<L, R, T>(local: &L, remote: &R, path: &Path, taken: T) -> Result<PathBuf>
+where
+    L: Remote + ?Sized,
+    R: Remote + ?Sized,
+    T: Fn(&Path) -> bool,
+{
+    let name = path.file_name().unwrap_or_default();
+    let mut n = 0;
+    loop {
+        let mut candidate = name.to_os_string();
+        candidate.push(".conflict");
+        if n > 0 {
+            candidate.push(format!(".{}", n));
+        }
+        let candidate = path.with_file_name(candidate);
+        let free = !taken(&candidate)
+            && local.stat(&candidate)?.is_none()
+            && remote.stat(&candidate)?.is_none();
+        if free {
+            return Ok(candidate);
+        }
+        n += 1;
+    }
+}
+
+fn version<R: Remote + ?Sized>(side: &R, path: &Path, exists: bool) -> Result<Option<Version>> {
+    if !exists {
+        return Ok(None);
+    }
+    Ok(Some(Version {
+        hash: side.hash(path)?,
+        modified: side.stat(path)?.and_then(|stat| stat.modified),
+    }))
+}
+
+/// Copies the remote version of a conflicting entry next to it, in both trees.
+fn copy_conflict<L, R>(
+    local: &L,
+    remote: &R,
+    copy: &ConflictCopy,
+    transfer: &Transfer,
+) -> Result<()>
+where
+    L: Remote + ?Sized,
+    R: Remote + ?Sized,
+{
+    let stat = remote.stat(&copy.path)?.ok_or_else(|| not_found(&copy.path))?;
+    transfer.stats.file();
+    if stat.is_symlink {
+        let target = remote.read_link(&copy.path)?;
+        local.symlink(&target, &copy.copy)?;
+        return remote.symlink(&target, &copy.copy);
+    }
+    transfer.send(local, &copy.copy, &mut remote.open(&copy.path)?, stat.mode)?;
+    transfer.send(remote, &copy.copy, &mut local.open(&copy.copy)?, stat.mode)
+}
+
+/// Makes the entry of `to` like the one of `from`: a copy, or no entry.
+fn propagate<A, B>(from: &A, to: &B, propagation: &Propagation, transfer: &Transfer) -> Result<()>
+where
+    A: Remote + ?Sized,
+    B: Remote + ?Sized,
+{
+    let path = &propagation.path;
+    if propagation.delete {
+        return to.delete(path);
+    }
+    let stat = from.stat(path)?.ok_or_else(|| not_found(path))?;
+    transfer.stats.file();
+    if stat.is_symlink {
+        return to.symlink(&from.read_link(path)?, path);
+    }
+    #[cfg(feature = "delta")]
+    if to.stat(path)?.is_some_and(|old| !old.is_symlink) {
+        if let Some(delta) = file_delta(from, to, path, stat.len, transfer)? {
+            transfer.stats.time(Phase::Data, || to.patch(path, &delta, stat.mode))?;
+            transfer.stats.written(stat.len);
+            if let Some(progress) = transfer.progress {
+                progress.event(&ProgressEvent::BytesCopied {
+                    path,
+                    bytes: stat.len,
+                });
+            }
+            return Ok(());
+        }
+    }
+    transfer.send(to, path, &mut from.open(path)?, stat.mode)
+}
+
+/// Computes the delta turning the file `path` of `to` into the one of `from`, of length
+/// `len`; `None` if sending the whole file is about as cheap.
+#[cfg(feature = "delta")]
+fn file_delta<A, B>(
+    from: &A,
+    to: &B,
+    path: &Path,
+    len: u64,
+    transfer: &Transfer,
+) -> Result<Option<Delta>>
+where
+    A: Remote + ?Sized,
+    B: Remote + ?Sized,
+{
+    let signature = to.signature(path, DEFAULT_BLOCK_SIZE)?;
+    let max_literal = (len / 2).min(MAX_DELTA_LITERAL);
+    let mut new = CopyReader::new(from.open(path)?, path, None);
+    let delta = transfer.stats.time(Phase::Data, || {
+        Delta::compute_bounded(&signature, &mut new, max_literal)
+    })?;
+    transfer.stats.read(new.bytes);
+    Ok(delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bisync::{
+        keep_both, newest_wins, plan_sync, sync_bidirectional, sync_remote, Baseline, Conflict,
+        Direction, Propagation, Resolution, SyncFailed, SyncOptions,
+    };
+    use crate::diff::dirs_equal;
+    use crate::fixture::TreeBuilder;
+    use crate::remote::{LocalDir, Remote, RemoteStat};
+    use crate::result::Result;
+    use crate::times::set_mtime;
+    use crate::vfs::MemFs;
+    use crate::ErrorKind;
+    use std::cell::Cell;
+    use std::error::Error;
+    use std::fs;
+    use std::io::{self, Read};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    /// A local directory that fails to write one path and counts the files it patches.
+    struct Flaky {
+        dir: LocalDir,
+        fail: Option<PathBuf>,
+        patched: Cell<usize>,
+    }
+
+    impl Remote for Flaky {
+        fn list(&self) -> Result<Vec<PathBuf>> {
+            self.dir.list()
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+            self.dir.open(path)
+        }
+
+        fn read_link(&self, path: &Path) -> Result<PathBuf> {
+            self.dir.read_link(path)
+        }
+
+        fn write(&self, path: &Path, data: &mut dyn Read, mode: Option<u32>) -> Result<()> {
+            if self.fail.as_deref() == Some(path) {
+                return Err(io::Error::other("disconnected").into());
+            }
+            self.dir.write(path, data, mode)
+        }
+
+        fn symlink(&self, target: &Path, path: &Path) -> Result<()> {
+            self.dir.symlink(target, path)
+        }
+
+        fn delete(&self, path: &Path) -> Result<()> {
+            self.dir.delete(path)
+        }
+
+        fn stat(&self, path: &Path) -> Result<Option<RemoteStat>> {
+            self.dir.stat(path)
+        }
+
+        #[cfg(feature = "delta")]
+        fn patch(&self, path: &Path, delta: &crate::delta::Delta, mode: Option<u32>) -> Result<()> {
+            self.patched.set(self.patched.get() + 1);
+            self.dir.patch(path, delta, mode)
+        }
+    }
+
+    #[test]
+    fn bidirectional_sync() {
+        let files = |builder: TreeBuilder| {
+            builder
+                .file("same.txt", b"same")
+                .file("local-edit.txt", b"v1")
+                .file("remote-delete.txt", b"bye")
+                .file("both.txt", b"v1")
+                .file("edit-delete.txt", b"v1")
+        };
+        let local = files(TreeBuilder::new()).build().unwrap();
+        let remote = files(TreeBuilder::new()).build().unwrap();
+        let store = TreeBuilder::new().build().unwrap();
+        Baseline::capture(local.path())
+            .unwrap()
+            .save(store.join("baseline"))
+            .unwrap();
+        let baseline = Baseline::load(store.join("baseline")).unwrap();
+        assert_eq!(baseline.entries.len(), 5);
+
+        fs::write(local.join("local-edit.txt"), b"v2").unwrap();
+        fs::create_dir(local.join("new")).unwrap();
+        fs::write(local.join("new/local.txt"), b"new").unwrap();
+        #[cfg(unix)]
+        for name in ["local-edit.txt", "new/local.txt"] {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(local.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::remove_file(remote.join("remote-delete.txt")).unwrap();
+        fs::write(local.join("both.txt"), b"local").unwrap();
+        fs::write(remote.join("both.txt"), b"remote").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        set_mtime(remote.join("both.txt"), later).unwrap();
+        fs::write(local.join("edit-delete.txt"), b"v2").unwrap();
+        fs::remove_file(remote.join("edit-delete.txt")).unwrap();
+
+        let sync = |baseline: &Baseline, resolve: fn(&Conflict) -> Resolution| {
+            let options = SyncOptions::default();
+            sync_bidirectional(local.path(), remote.path(), baseline, resolve, &options)
+        };
+        let report = sync(&baseline, newest_wins).unwrap();
+        assert_eq!(report.to_local, [Path::new("remote-delete.txt")]);
+        assert_eq!(
+            report.to_remote,
+            [Path::new("local-edit.txt"), Path::new("new/local.txt")]
+        );
+        assert_eq!(
+            report.conflicts,
+            [
+                (Path::new("both.txt").to_path_buf(), Resolution::KeepRemote),
+                (Path::new("edit-delete.txt").to_path_buf(), Resolution::KeepLocal)
+            ]
+        );
+        assert_eq!(report.to_string(), "1 to local, 2 to remote, 2 conflicts");
+        assert_eq!(fs::read(local.join("both.txt")).unwrap(), b"remote");
+        assert!(dirs_equal(local.path(), remote.path()).unwrap());
+        #[cfg(unix)]
+        for name in ["local-edit.txt", "new/local.txt"] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(remote.join(name)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        // nothing changed since: nothing to do
+        let baseline = report.baseline;
+        let report = sync(&baseline, keep_both);
+        assert!(report.unwrap().to_local.is_empty());
+
+        fs::write(local.join("same.txt"), b"mine").unwrap();
+        fs::write(remote.join("same.txt"), b"theirs").unwrap();
+        let report = sync(&baseline, keep_both);
+        assert_eq!(report.unwrap().conflicts.len(), 1);
+        assert_eq!(fs::read(remote.join("same.txt")).unwrap(), b"mine");
+        assert_eq!(fs::read(local.join("same.txt.conflict")).unwrap(), b"theirs");
+        assert!(dirs_equal(local.path(), remote.path()).unwrap());
+
+        // the earlier conflict copy is kept, on both sides
+        let baseline = Baseline::capture(local.path()).unwrap();
+        fs::write(local.join("same.txt"), b"mine 2").unwrap();
+        fs::write(remote.join("same.txt"), b"theirs 2").unwrap();
+        fs::write(remote.join("same.txt.conflict.1"), b"user file").unwrap();
+        let report = sync(&baseline, keep_both);
+        let report = report.unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.to_local, [Path::new("same.txt.conflict.1")]);
+        assert_eq!(fs::read(local.join("same.txt.conflict")).unwrap(), b"theirs");
+        assert_eq!(fs::read(local.join("same.txt.conflict.1")).unwrap(), b"user file");
+        assert_eq!(fs::read(local.join("same.txt.conflict.2")).unwrap(), b"theirs 2");
+        assert!(report.baseline.entries.contains_key(Path::new("same.txt.conflict.2")));
+        assert!(dirs_equal(local.path(), remote.path()).unwrap());
+    }
+
+    #[test]
+    fn sync_mem_fs() {
+        let fs = MemFs::new();
+        for side in ["/local", "/remote"] {
+            fs.write(format!("{}/same.txt", side), "same").unwrap();
+            fs.write(format!("{}/edit.txt", side), "v1").unwrap();
+        }
+        let local = LocalDir::new_in(Arc::new(fs.clone()), "/local");
+        let remote = LocalDir::new_in(Arc::new(fs.clone()), "/remote");
+        let baseline = Baseline::capture_remote(&local).unwrap();
+        fs.write("/local/edit.txt", "v2").unwrap();
+        fs.write("/remote/sub/new.txt", "new").unwrap();
+        fs.symlink("same.txt", "/remote/link").unwrap();
+
+        let options = SyncOptions::default();
+        let report = sync_remote(&local, &remote, &baseline, newest_wins, &options).unwrap();
+        assert_eq!(report.to_string(), "2 to local, 1 to remote, 0 conflicts");
+        assert_eq!(fs.read("/remote/edit.txt").unwrap(), b"v2");
+        assert_eq!(fs.read("/local/sub/new.txt").unwrap(), b"new");
+        assert_eq!(fs.read("/local/link").unwrap(), b"same");
+        assert_eq!(local.list().unwrap(), remote.list().unwrap());
+        assert_eq!(report.baseline, Baseline::capture_remote(&remote).unwrap());
+    }
+
+    #[test]
+    fn planned_sync_stops_part_way() {
+        let big: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let files = |builder: TreeBuilder| builder.file("a.txt", b"a").file("big.bin", &big);
+        let local = files(TreeBuilder::new()).build().unwrap();
+        let remote = files(TreeBuilder::new()).build().unwrap();
+        let baseline = Baseline::capture(local.path()).unwrap();
+        let mut edited = big.clone();
+        edited[50_000..50_005].copy_from_slice(b"edit!");
+        fs::write(local.join("big.bin"), &edited).unwrap();
+        fs::write(local.join("c.txt"), b"c").unwrap();
+        fs::write(remote.join("a.txt"), b"a2").unwrap();
+
+        let (local_dir, remote_dir) = (LocalDir::new(local.path()), LocalDir::new(remote.path()));
+        let plan = plan_sync(&local_dir, &remote_dir, &baseline, newest_wins).unwrap();
+        let directions: Vec<_> = plan
+            .propagations
+            .iter()
+            .map(|p| (p.path.to_str().unwrap(), p.direction))
+            .collect();
+        assert_eq!(
+            directions,
+            [
+                ("a.txt", Direction::ToLocal),
+                ("big.bin", Direction::ToRemote),
+                ("c.txt", Direction::ToRemote)
+            ]
+        );
+        assert_eq!(
+            plan.propagations[2],
+            Propagation {
+                path: PathBuf::from("c.txt"),
+                direction: Direction::ToRemote,
+                delete: false,
+                conflict: false,
+                base: None,
+            }
+        );
+        // planning changes nothing
+        assert_eq!(fs::read(local.join("a.txt")).unwrap(), b"a");
+
+        let flaky = Flaky {
+            dir: remote_dir,
+            fail: Some(PathBuf::from("c.txt")),
+            patched: Cell::new(0),
+        };
+        let options = SyncOptions::default();
+        let err = sync_remote(&local_dir, &flaky, &baseline, newest_wins, &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::File);
+        let failed = err.source().unwrap().downcast_ref::<SyncFailed>().unwrap();
+        assert_eq!(failed.path, Path::new("c.txt"));
+        assert_eq!(failed.report.to_local, [Path::new("a.txt")]);
+        assert_eq!(failed.report.to_remote, [Path::new("big.bin")]);
+        assert!(!failed.report.baseline.entries.contains_key(Path::new("c.txt")));
+        assert_eq!(fs::read(remote.join("big.bin")).unwrap(), edited);
+        assert_eq!(flaky.patched.get(), usize::from(cfg!(feature = "delta")));
+
+        // the next sync picks up where this one stopped
+        let flaky = Flaky { fail: None, ..flaky };
+        let baseline = &failed.report.baseline;
+        let report = sync_remote(&local_dir, &flaky, baseline, newest_wins, &options).unwrap();
+        assert_eq!(report.to_string(), "0 to local, 1 to remote, 0 conflicts");
+        assert!(dirs_equal(local.path(), remote.path()).unwrap());
+    }
+
+    #[test]
+    fn sync_progress_and_cancel() {
+        use crate::cancel::{CancelToken, Cancelled};
+        use crate::progress::{Operation, ProgressEvent};
+        use std::sync::Mutex;
+
+        let files = |builder: TreeBuilder| builder.file("a.txt", b"a").file("b.txt", b"b");
+        let local = files(TreeBuilder::new()).build().unwrap();
+        let remote = files(TreeBuilder::new()).build().unwrap();
+        let baseline = Baseline::capture(local.path()).unwrap();
+        fs::write(local.join("a.txt"), b"local").unwrap();
+        fs::write(remote.join("b.txt"), b"remote!").unwrap();
+
+        let token = CancelToken::new();
+        token.cancel();
+        let options = SyncOptions {
+            cancel: Some(token),
+            ..SyncOptions::default()
+        };
+        let sync = |options: &SyncOptions| {
+            sync_bidirectional(local.path(), remote.path(), &baseline, newest_wins, options)
+        };
+        let err = sync(&options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        let failed = err.source().unwrap().downcast_ref::<SyncFailed>().unwrap();
+        assert!(failed.error.source().unwrap().is::<Cancelled>());
+        assert_eq!(failed.report.baseline, baseline);
+        assert_eq!(fs::read(remote.join("a.txt")).unwrap(), b"a");
+
+        let events = Arc::new(Mutex::new((0, 0)));
+        let sink = Arc::clone(&events);
+        let options = SyncOptions {
+            progress: Some(Arc::new(move |event: &ProgressEvent| {
+                let mut events = sink.lock().unwrap();
+                match event {
+                    ProgressEvent::BytesCopied { bytes, .. } => events.0 += bytes,
+                    ProgressEvent::OperationFinished {
+                        operation: Operation::Sync,
+                    } => events.1 += 1,
+                    _ => {}
+                }
+            })),
+            ..SyncOptions::default()
+        };
+        let report = sync(&options).unwrap();
+        assert_eq!((report.stats.files, report.stats.bytes_written), (2, 12));
+        assert!(report.stats.bytes_read >= 12);
+        assert_eq!(*events.lock().unwrap(), (12, 1));
+        assert!(dirs_equal(local.path(), remote.path()).unwrap());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::fold::walk_fold;
+use crate::result::Result;
+
+/// Number and total size of a group of files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeStats {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl TypeStats {
+    fn add(&mut self, other: TypeStats) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Regular files of a tree grouped by type, as computed by [`classify`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BreakdownReport {
+    /// Files by lowercase extension; files without one are counted under `""`.
+    pub by_extension: BTreeMap<String, TypeStats>,
+    /// All regular files.
+    pub total: TypeStats,
+}
+
+impl BreakdownReport {
+    /// Groups the files by MIME type, guessed from the extension;
+    /// unknown extensions count as `application/octet-stream`.
+    pub fn by_mime(&self) -> BTreeMap<&'static str, TypeStats> {
+        self.group_by(|ext| Some(mime_type(ext).unwrap_or("application/octet-stream")))
+    }
+
+    /// Groups the files written in programming, markup and configuration languages
+    /// by language; other files are left out.
+    pub fn by_language(&self) -> BTreeMap<&'static str, TypeStats> {
+        self.group_by(language)
+    }
+
+    /// Returns the extensions by descending total size.
+    pub fn largest_extensions(&self) -> Vec<(&str, TypeStats)> {
+        let mut extensions: Vec<_> = self
+            .by_extension
+            .iter()
+            .map(|(ext, stats)| (ext.as_str(), *stats))
+            .collect();
+        extensions.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+        extensions
+    }
+
+    fn group_by<F>(&self, key: F) -> BTreeMap<&'static str, TypeStats>
+    where
+        F: Fn(&str) -> Option<&'static str>,
+    {
+        let mut groups: BTreeMap<&'static str, TypeStats> = BTreeMap::new();
+        for (ext, stats) in &self.by_extension {
+            if let Some(key) = key(ext) {
+                groups.entry(key).or_default().add(*stats);
+            }
+        }
+        groups
+    }
+}
+
+/// Summarizes the regular files under `root` by extension, with counts and byte totals,
+/// for a quick picture of what a tree holds. Symbolic links are not followed or counted.
+/// Directories are read in parallel.
+pub fn classify<P: AsRef<Path>>(root: P) -> Result<BreakdownReport> {
+    let by_extension = walk_fold(
+        root,
+        |_| BTreeMap::new(),
+        |mut groups: BTreeMap<String, TypeStats>, entry| {
+            if let Ok(meta) = fs::symlink_metadata(entry.path()) {
+                if meta.is_file() {
+                    let ext = entry
+                        .path()
+                        .extension()
+                        .map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase());
+                    groups.entry(ext).or_default().add(TypeStats {
+                        files: 1,
+                        bytes: meta.len(),
+                    });
+                }
+            }
+            groups
+        },
+        |mut a, b| {
+            for (ext, stats) in b {
+                a.entry(ext).or_default().add(stats);
+            }
+            a
+        },
+    )?;
+    let mut total = TypeStats::default();
+    for stats in by_extension.values() {
+        total.add(*stats);
+    }
+    Ok(BreakdownReport {
+        by_extension,
+        total,
+    })
+}
+
+fn mime_type(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "txt" | "log" | "md" | "rst" => "text/plain",
+        "htm" | "html" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "js" | "mjs" => "text/javascript",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "yaml" | "yml" => "application/yaml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "wasm" => "application/wasm",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/vnd.microsoft.icon",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "mp4" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "ttf" => "font/ttf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => return None,
+    })
+}
+
+fn language(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "rs" => "Rust",
+        "c" | "h" => "C",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => "C++",
+        "cs" => "C#",
+        "go" => "Go",
+        "java" => "Java",
+        "kt" | "kts" => "Kotlin",
+        "scala" => "Scala",
+        "swift" => "Swift",
+        "m" | "mm" => "Objective-C",
+        "py" | "pyi" => "Python",
+        "rb" => "Ruby",
+        "php" => "PHP",
+        "pl" | "pm" => "Perl",
+        "lua" => "Lua",
+        "hs" => "Haskell",
+        "ml" | "mli" => "OCaml",
+        "ex" | "exs" => "Elixir",
+        "erl" | "hrl" => "Erlang",
+        "clj" | "cljs" => "Clojure",
+        "zig" => "Zig",
+        "dart" => "Dart",
+        "r" => "R",
+        "jl" => "Julia",
+        "js" | "mjs" | "cjs" | "jsx" => "JavaScript",
+        "ts" | "tsx" => "TypeScript",
+        "sh" | "bash" | "zsh" => "Shell",
+        "ps1" => "PowerShell",
+        "sql" => "SQL",
+        "htm" | "html" => "HTML",
+        "css" | "scss" | "sass" => "CSS",
+        "md" => "Markdown",
+        "json" => "JSON",
+        "toml" => "TOML",
+        "yaml" | "yml" => "YAML",
+        "xml" => "XML",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::breakdown::{classify, TypeStats};
+    use crate::fixture::TreeBuilder;
+
+    #[test]
+    fn classify_tree() {
+        let tree = TreeBuilder::new()
+            .file("main.rs", b"fn main() {}")
+            .file("src/lib.RS", b"")
+            .file("src/util.h", b"123")
+            .file("README", b"1234")
+            .file("docs/logo.png", b"12345678")
+            .symlink("link.rs", "main.rs")
+            .build()
+            .unwrap();
+        let report = classify(tree.path()).unwrap();
+        let stats = |files, bytes| TypeStats { files, bytes };
+        assert_eq!(report.total, stats(5, 27));
+        assert_eq!(report.by_extension["rs"], stats(2, 12));
+        assert_eq!(report.by_extension[""], stats(1, 4));
+        assert_eq!(report.largest_extensions()[0], ("rs", stats(2, 12)));
+
+        let languages = report.by_language();
+        assert_eq!(languages.len(), 2);
+        assert_eq!(languages["Rust"], stats(2, 12));
+        assert_eq!(languages["C"], stats(1, 3));
+        let mime = report.by_mime();
+        assert_eq!(mime["image/png"], stats(1, 8));
+        assert_eq!(mime["application/octet-stream"], stats(4, 19));
+    }
+}
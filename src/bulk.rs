@@ -0,0 +1,425 @@
+//! Directory listings that return the metadata of the entries with their names, in one
+//! system call per batch of entries instead of one metadata read per entry.
+//!
+//! On macOS this uses `getattrlistbulk`, which reads names, types, sizes, modification
+//! times and devices of many entries at once. On Windows it uses `NtQueryDirectoryFile`
+//! with a large buffer, whose `FileIdBothDirectoryInformation` records hold the attributes,
+//! size and times of each entry; devices are not reported there. Sizes of directories are
+//! reported as 0.
+
+use std::ffi::OsString;
+use std::io;
+use std::mem;
+use std::ptr;
+
+use crate::vfs::FsMetadata;
+
+/// An entry of a listing: its name and metadata, or the error reading it.
+pub(crate) type BulkEntry = io::Result<(OsString, FsMetadata)>;
+
+#[cfg(target_os = "macos")]
+pub(crate) use self::macos::list;
+#[cfg(windows)]
+pub(crate) use self::windows::list;
+
+/// Reads a value at `pos` of a record, whose fields need not be aligned. Reads past the
+/// end of the record give zeroes.
+fn read<T: Copy>(record: &[u8], pos: usize) -> T {
+    let mut value = mem::MaybeUninit::<T>::zeroed();
+    let available = record.len().saturating_sub(pos).min(mem::size_of::<T>());
+    // SAFETY: at most `available` bytes are copied, all inside `record`; `T` is plain data
+    // for which zeroes and any bytes are valid.
+    unsafe {
+        let src = record.as_ptr().add(pos.min(record.len()));
+        ptr::copy_nonoverlapping(src, value.as_mut_ptr().cast(), available);
+        value.assume_init()
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use std::ffi::{c_int, c_void, CStr, OsStr, OsString};
+    use std::fs::File;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::slice;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{read, BulkEntry};
+    use crate::vfs::{FileKind, FsMetadata};
+
+    const ATTR_BIT_MAP_COUNT: u16 = 5;
+    const ATTR_CMN_NAME: u32 = 0x0000_0001;
+    const ATTR_CMN_DEVID: u32 = 0x0000_0002;
+    const ATTR_CMN_OBJTYPE: u32 = 0x0000_0008;
+    const ATTR_CMN_MODTIME: u32 = 0x0000_0400;
+    const ATTR_CMN_ERROR: u32 = 0x2000_0000;
+    const ATTR_CMN_RETURNED_ATTRS: u32 = 0x8000_0000;
+    const ATTR_FILE_DATALENGTH: u32 = 0x0000_0200;
+    const VREG: u32 = 1;
+    const VDIR: u32 = 2;
+    const VLNK: u32 = 5;
+    const EINTR: i32 = 4;
+    /// Room for about a thousand entries per call.
+    const BUFFER_LEN: usize = 256 * 1024;
+
+    #[repr(C)]
+    struct AttrList {
+        bitmapcount: u16,
+        reserved: u16,
+        commonattr: u32,
+        volattr: u32,
+        dirattr: u32,
+        fileattr: u32,
+        forkattr: u32,
+    }
+
+    /// The attribute groups returned for an entry (`attribute_set_t`).
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct AttributeSet {
+        commonattr: u32,
+        volattr: u32,
+        dirattr: u32,
+        fileattr: u32,
+        forkattr: u32,
+    }
+
+    /// Where a variable-length attribute is, relative to this reference.
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct AttrReference {
+        offset: i32,
+        length: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Timespec {
+        sec: i64,
+        nsec: i64,
+    }
+
+    extern "C" {
+        fn getattrlistbulk(
+            dirfd: c_int,
+            attrs: *mut AttrList,
+            buf: *mut c_void,
+            len: usize,
+            options: u64,
+        ) -> c_int;
+    }
+
+    /// Lists a directory with the metadata of its entries, without following links.
+    pub(crate) fn list(dir: &Path) -> io::Result<Vec<BulkEntry>> {
+        let dir = File::open(dir)?;
+        let mut attrs = AttrList {
+            bitmapcount: ATTR_BIT_MAP_COUNT,
+            reserved: 0,
+            commonattr: ATTR_CMN_RETURNED_ATTRS
+                | ATTR_CMN_NAME
+                | ATTR_CMN_ERROR
+                | ATTR_CMN_DEVID
+                | ATTR_CMN_OBJTYPE
+                | ATTR_CMN_MODTIME,
+            volattr: 0,
+            dirattr: 0,
+            fileattr: ATTR_FILE_DATALENGTH,
+            forkattr: 0,
+        };
+        // u64 elements keep the buffer aligned for the length of the first entry
+        let mut buf = vec![0u64; BUFFER_LEN / 8];
+        let mut entries = Vec::new();
+        loop {
+            let fd = dir.as_raw_fd();
+            let count =
+                unsafe { getattrlistbulk(fd, &mut attrs, buf.as_mut_ptr().cast(), BUFFER_LEN, 0) };
+            if count < 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() == Some(EINTR) {
+                    continue;
+                }
+                return Err(e);
+            }
+            if count == 0 {
+                return Ok(entries);
+            }
+            let bytes = unsafe { slice::from_raw_parts(buf.as_ptr().cast::<u8>(), BUFFER_LEN) };
+            let mut start = 0;
+            for _ in 0..count {
+                let len = read::<u32>(bytes, start) as usize;
+                if len == 0 || start + len > bytes.len() {
+                    break;
+                }
+                entries.push(parse(&bytes[start..start + len]));
+                start += len;
+            }
+        }
+    }
+
+    /// Parses one entry; its attributes follow in the order of their bits, those not
+    /// returned taking no room.
+    fn parse(entry: &[u8]) -> BulkEntry {
+        let mut pos = mem::size_of::<u32>();
+        let returned = read::<AttributeSet>(entry, pos);
+        pos += mem::size_of::<AttributeSet>();
+        let common = returned.commonattr;
+        if common & ATTR_CMN_ERROR != 0 {
+            let error = read::<u32>(entry, pos);
+            pos += mem::size_of::<u32>();
+            if error != 0 {
+                return Err(io::Error::from_raw_os_error(error as i32));
+            }
+        }
+        let mut name = OsString::new();
+        if common & ATTR_CMN_NAME != 0 {
+            let reference = read::<AttrReference>(entry, pos);
+            let start = (pos as isize + reference.offset as isize) as usize;
+            let end = (start + reference.length as usize).min(entry.len());
+            let bytes = entry.get(start..end).unwrap_or_default();
+            let text = CStr::from_bytes_until_nul(bytes).map_or(bytes, CStr::to_bytes);
+            name = OsStr::from_bytes(text).to_os_string();
+            pos += mem::size_of::<AttrReference>();
+        }
+        let mut device = None;
+        if common & ATTR_CMN_DEVID != 0 {
+            // `dev_t` is signed; std widens `st_dev` the same way
+            device = Some(read::<i32>(entry, pos) as u64);
+            pos += mem::size_of::<i32>();
+        }
+        let mut kind = FileKind::Other;
+        if common & ATTR_CMN_OBJTYPE != 0 {
+            kind = match read::<u32>(entry, pos) {
+                VREG => FileKind::File,
+                VDIR => FileKind::Dir,
+                VLNK => FileKind::Symlink,
+                _ => FileKind::Other,
+            };
+            pos += mem::size_of::<u32>();
+        }
+        let mut modified = None;
+        if common & ATTR_CMN_MODTIME != 0 {
+            let time = read::<Timespec>(entry, pos);
+            let nanos = Duration::from_nanos(time.nsec.clamp(0, 999_999_999) as u64);
+            modified = Some(match u64::try_from(time.sec) {
+                Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nanos,
+                Err(_) => UNIX_EPOCH - Duration::from_secs(time.sec.unsigned_abs()) + nanos,
+            });
+            pos += mem::size_of::<Timespec>();
+        }
+        let mut len = 0;
+        if returned.fileattr & ATTR_FILE_DATALENGTH != 0 {
+            len = read::<i64>(entry, pos).max(0) as u64;
+        }
+        if name.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "entry without a name"));
+        }
+        let meta = FsMetadata {
+            kind,
+            len,
+            modified,
+            device,
+        };
+        Ok((name, meta))
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::{c_void, OsString};
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::windows::ffi::OsStringExt;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+    use std::ptr;
+    use std::slice;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{read, BulkEntry};
+    use crate::vfs::{FileKind, FsMetadata};
+
+    const FILE_LIST_DIRECTORY: u32 = 1;
+    const FILE_SHARE_ALL: u32 = 7;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    const FILE_ID_BOTH_DIRECTORY_INFORMATION: u32 = 37;
+    const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    /// Reparse tags of links (symbolic links, junctions), which std also reports as links.
+    const NAME_SURROGATE: u32 = 0x2000_0000;
+    const STATUS_NO_MORE_FILES: i32 = 0x8000_0006_u32 as i32;
+    /// Seconds from 1601, where `FILETIME` starts, to the Unix epoch.
+    const EPOCH_DIFFERENCE: u64 = 11_644_473_600;
+    /// Room for several hundred entries per call; larger buffers are refused by SMB shares.
+    const BUFFER_LEN: usize = 64 * 1024;
+
+    // offsets in a FILE_ID_BOTH_DIR_INFORMATION record
+    const NEXT_ENTRY_OFFSET: usize = 0;
+    const LAST_WRITE_TIME: usize = 24;
+    const END_OF_FILE: usize = 40;
+    const FILE_ATTRIBUTES: usize = 56;
+    const FILE_NAME_LENGTH: usize = 60;
+    /// Holds the reparse tag for reparse points.
+    const EA_SIZE: usize = 64;
+    const FILE_NAME: usize = 104;
+
+    #[repr(C)]
+    struct IoStatusBlock {
+        status: usize,
+        information: usize,
+    }
+
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn NtQueryDirectoryFile(
+            handle: *mut c_void,
+            event: *mut c_void,
+            apc_routine: *mut c_void,
+            apc_context: *mut c_void,
+            io_status: *mut IoStatusBlock,
+            info: *mut c_void,
+            len: u32,
+            class: u32,
+            single_entry: u8,
+            file_name: *mut c_void,
+            restart_scan: u8,
+        ) -> i32;
+        fn RtlNtStatusToDosError(status: i32) -> u32;
+    }
+
+    /// Lists a directory with the metadata of its entries, without following links.
+    pub(crate) fn list(dir: &Path) -> io::Result<Vec<BulkEntry>> {
+        let dir = OpenOptions::new()
+            .access_mode(FILE_LIST_DIRECTORY)
+            .share_mode(FILE_SHARE_ALL)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(dir)?;
+        // u64 elements keep the records, aligned to 8 bytes, aligned in memory too
+        let mut buf = vec![0u64; BUFFER_LEN / 8];
+        let mut entries = Vec::new();
+        loop {
+            let mut status = IoStatusBlock {
+                status: 0,
+                information: 0,
+            };
+            // SAFETY: the handle is open for listing and synchronous; the buffer and the
+            // status block outlive the call.
+            let ret = unsafe {
+                NtQueryDirectoryFile(
+                    dir.as_raw_handle(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    &mut status,
+                    buf.as_mut_ptr().cast(),
+                    BUFFER_LEN as u32,
+                    FILE_ID_BOTH_DIRECTORY_INFORMATION,
+                    0,
+                    ptr::null_mut(),
+                    0,
+                )
+            };
+            if ret == STATUS_NO_MORE_FILES {
+                return Ok(entries);
+            }
+            if ret < 0 {
+                // SAFETY: plain conversion of a status code
+                let code = unsafe { RtlNtStatusToDosError(ret) };
+                return Err(io::Error::from_raw_os_error(code as i32));
+            }
+            // SAFETY: the buffer is `BUFFER_LEN` initialized bytes
+            let bytes = unsafe { slice::from_raw_parts(buf.as_ptr().cast::<u8>(), BUFFER_LEN) };
+            let filled = status.information.min(BUFFER_LEN);
+            let mut start = 0;
+            while start < filled {
+                let record = &bytes[start..filled];
+                if let Some(entry) = parse(record) {
+                    entries.push(Ok(entry));
+                }
+                match read::<u32>(record, NEXT_ENTRY_OFFSET) as usize {
+                    0 => break,
+                    next => start += next,
+                }
+            }
+        }
+    }
+
+    /// Parses one record; returns `None` for `.` and `..`.
+    fn parse(record: &[u8]) -> Option<(OsString, FsMetadata)> {
+        let name_len = read::<u32>(record, FILE_NAME_LENGTH) as usize;
+        let name_bytes = record.get(FILE_NAME..FILE_NAME + name_len).unwrap_or_default();
+        let wide: Vec<u16> = name_bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let dot = u16::from(b'.');
+        if wide.is_empty() || wide == [dot] || wide == [dot, dot] {
+            return None;
+        }
+        let attributes = read::<u32>(record, FILE_ATTRIBUTES);
+        let is_link = attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0
+            && read::<u32>(record, EA_SIZE) & NAME_SURROGATE != 0;
+        let kind = if is_link {
+            FileKind::Symlink
+        } else if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        };
+        let len = match kind {
+            FileKind::Dir => 0,
+            _ => read::<i64>(record, END_OF_FILE).max(0) as u64,
+        };
+        let meta = FsMetadata {
+            kind,
+            len,
+            modified: Some(file_time(read::<i64>(record, LAST_WRITE_TIME))),
+            device: None,
+        };
+        Some((OsString::from_wide(&wide), meta))
+    }
+
+    /// Converts a `FILETIME`, in 100 ns intervals since 1601.
+    fn file_time(intervals: i64) -> std::time::SystemTime {
+        let since_1601 = Duration::from_nanos(intervals.max(0) as u64 * 100);
+        let epoch = Duration::from_secs(EPOCH_DIFFERENCE);
+        match since_1601.checked_sub(epoch) {
+            Some(since_epoch) => UNIX_EPOCH + since_epoch,
+            None => UNIX_EPOCH - (epoch - since_1601),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bulk::list;
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::{FileKind, FsMetadata};
+    use std::fs;
+
+    #[test]
+    fn bulk_listing_matches_std() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"12345")
+            .file("sub/b.txt", b"")
+            .symlink("link", "a.txt")
+            .build()
+            .unwrap();
+        let entries = list(tree.path()).unwrap().into_iter();
+        let mut entries: Vec<_> = entries.map(Result::unwrap).collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.to_str().unwrap()).collect();
+        assert_eq!(names, ["a.txt", "link", "sub"]);
+        for (name, meta) in &entries {
+            let expected = FsMetadata::from(fs::symlink_metadata(tree.join(name)).unwrap());
+            assert_eq!((meta.kind, meta.modified), (expected.kind, expected.modified));
+            if meta.kind == FileKind::File {
+                assert_eq!((meta.len, meta.device), (expected.len, expected.device));
+            }
+        }
+    }
+}
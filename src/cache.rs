@@ -0,0 +1,252 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use crate::atomic::write_atomic;
+use crate::dirs;
+use crate::hash::Hash;
+use crate::quota::QuotaTracker;
+use crate::result::{Error, ErrorKind, Result};
+use crate::retention::{cleanup, CleanupReport, RetentionPolicy};
+
+/// A directory of cached values stored by key, one file per value.
+///
+/// Values are written atomically, so readers never see a partial value, even across
+/// processes. Optionally, the directory is kept under a maximum size by evicting the
+/// oldest values first, and values older than a time to live are treated as missing.
+/// Age is measured from the last `put`.
+///
+/// Keys are relative paths (`"thumbnails/42.png"`), unless keys are hashed: then any
+/// string can be a key and values are stored by hash, sharded like blobs of the
+/// content-addressed store. Namespaces are subdirectories with their own settings;
+/// their names should not look like keys of the parent.
+#[derive(Debug)]
+pub struct CacheDir {
+    root: PathBuf,
+    hash_keys: bool,
+    max_size: Option<u64>,
+    ttl: Option<Duration>,
+    /// Created by the first `put` with a maximum size.
+    quota: Mutex<Option<QuotaTracker>>,
+}
+
+impl CacheDir {
+    /// Opens the cache stored in `root`, creating the directory if needed.
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<CacheDir> {
+        fs::create_dir_all(&root)?;
+        Ok(CacheDir {
+            root: root.as_ref().to_path_buf(),
+            hash_keys: false,
+            max_size: None,
+            ttl: None,
+            quota: Mutex::new(None),
+        })
+    }
+
+    /// Opens the cache of an application in the per-user cache directory
+    /// (see [`dirs::cache_dir`]), e.g. `~/.cache/app` on Linux.
+    /// Fails with an error of kind `ErrorKind::File` if the directory is unknown.
+    ///
+    /// # Arguments:
+    ///
+    /// * `app` - name of the application's subdirectory.
+    pub fn for_app(app: &str) -> Result<CacheDir> {
+        let dir = dirs::cache_dir().ok_or_else(|| {
+            Error::new(ErrorKind::File, "the user cache directory is unknown")
+        })?;
+        CacheDir::open(dir.join(app))
+    }
+
+    /// Stores values by hash of their keys, so keys can be any string.
+    pub fn with_hashed_keys(mut self, on: bool) -> CacheDir {
+        self.hash_keys = on;
+        self
+    }
+
+    /// Keeps the total size of the cache under `max_size` bytes: a `put` that would exceed
+    /// it first evicts the oldest values.
+    pub fn with_max_size(mut self, max_size: u64) -> CacheDir {
+        self.max_size = Some(max_size);
+        self.quota = Mutex::new(None);
+        self
+    }
+
+    /// Treats values stored longer ago than `ttl` as missing; they are removed when read
+    /// or by [`CacheDir::evict`].
+    pub fn with_ttl(mut self, ttl: Duration) -> CacheDir {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the cache in the subdirectory `name`, with the same settings. The size of
+    /// its values also counts towards the maximum size of this cache.
+    pub fn namespace(&self, name: &str) -> Result<CacheDir> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => {}
+            _ => {
+                let message = format!("{:?}: invalid cache namespace", name);
+                return Err(Error::new(ErrorKind::Encoding, message));
+            }
+        }
+        Ok(CacheDir {
+            hash_keys: self.hash_keys,
+            max_size: self.max_size,
+            ttl: self.ttl,
+            ..CacheDir::open(self.root.join(name))?
+        })
+    }
+
+    /// Returns the root directory of the cache.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path of the file holding the value of `key`.
+    /// Fails with an error of kind `ErrorKind::Encoding` if keys are not hashed and `key`
+    /// is not a relative path without `..`.
+    pub fn path_of(&self, key: &str) -> Result<PathBuf> {
+        if self.hash_keys {
+            let hex = Hash::of(key.as_bytes()).to_hex();
+            return Ok(self.root.join(&hex[..2]).join(hex));
+        }
+        let relative = Path::new(key);
+        let valid = !key.is_empty()
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !valid {
+            let message = format!("{:?}: invalid cache key", key);
+            return Err(Error::new(ErrorKind::Encoding, message));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Stores the value of `key` atomically, replacing any previous value, and returns
+    /// the values evicted to make room.
+    pub fn put(&self, key: &str, value: &[u8]) -> Result<CleanupReport> {
+        let path = self.path_of(key)?;
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => {
+                fs::create_dir_all(path.parent().unwrap())?;
+                write_atomic(&path, value)?;
+                return Ok(CleanupReport::default());
+            }
+        };
+        let mut quota = self.lock();
+        if quota.is_none() {
+            let policy = RetentionPolicy {
+                max_age: self.ttl,
+                ..RetentionPolicy::default()
+            };
+            *quota = Some(QuotaTracker::new(&self.root, max_size)?.with_policy(policy));
+        }
+        let relative = path.strip_prefix(&self.root).unwrap();
+        quota.as_mut().unwrap().write(relative, value)
+    }
+
+    /// Returns the value of `key`, or `None` if there is none or it has expired.
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path_of(key)?;
+        if self.is_expired(&path)? {
+            self.remove(key)?;
+            return Ok(None);
+        }
+        match fs::read(&path) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Removes the value of `key`; returns whether there was one.
+    pub fn remove(&self, key: &str) -> Result<bool> {
+        let path = self.path_of(key)?;
+        let len = match fs::metadata(&path) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        fs::remove_file(&path)?;
+        if let Some(quota) = self.lock().as_mut() {
+            quota.released(len);
+        }
+        Ok(true)
+    }
+
+    /// Removes the expired values and, if a maximum size is set, the oldest values over it.
+    pub fn evict(&self) -> Result<CleanupReport> {
+        let policy = RetentionPolicy {
+            max_age: self.ttl,
+            max_total_size: self.max_size,
+            ..RetentionPolicy::default()
+        };
+        let report = cleanup(&self.root, &policy, false)?;
+        if let Some(quota) = self.lock().as_mut() {
+            quota.refresh()?;
+        }
+        Ok(report)
+    }
+
+    fn is_expired(&self, path: &Path) -> Result<bool> {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return Ok(false),
+        };
+        let modified = match fs::metadata(path) {
+            Ok(meta) => meta.modified()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        Ok(age > ttl)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<QuotaTracker>> {
+        self.quota.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::CacheDir;
+    use crate::fixture::TreeBuilder;
+    use crate::times::set_mtime;
+    use crate::ErrorKind;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn cache_put_get_evict() {
+        let tree = TreeBuilder::new().dir("cache").build().unwrap();
+        let cache = CacheDir::open(tree.join("cache"))
+            .unwrap()
+            .with_max_size(8)
+            .with_ttl(Duration::from_secs(3600));
+        cache.put("a/1", b"1111").unwrap();
+        assert_eq!(cache.get("a/1").unwrap().unwrap(), b"1111");
+        assert_eq!(cache.get("missing").unwrap(), None);
+        let err = cache.get("../outside").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Encoding);
+
+        let old = SystemTime::now() - Duration::from_secs(60);
+        set_mtime(cache.path_of("a/1").unwrap(), old).unwrap();
+        let report = cache.put("b", b"22222").unwrap();
+        assert_eq!(report.removed, [cache.path_of("a/1").unwrap()]);
+        assert_eq!(cache.get("a/1").unwrap(), None);
+
+        let expired = SystemTime::now() - Duration::from_secs(7200);
+        set_mtime(cache.path_of("b").unwrap(), expired).unwrap();
+        assert_eq!(cache.get("b").unwrap(), None);
+        assert!(!cache.path_of("b").unwrap().exists());
+
+        let hashed = cache.namespace("hashed").unwrap().with_hashed_keys(true);
+        hashed.put("https://example.com/?q=1", b"page").unwrap();
+        assert_eq!(hashed.get("https://example.com/?q=1").unwrap().unwrap(), b"page");
+        assert!(hashed.path_of("any key").unwrap().starts_with(tree.join("cache/hashed")));
+        assert!(hashed.remove("https://example.com/?q=1").unwrap());
+        assert!(cache.namespace("../x").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::stats::IoStats;
+
+/// Set by the signal handlers installed by [`CancelToken::on_signals`].
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+/// Requests an operation in progress to stop, from another thread or a signal handler.
+///
+/// Operations check the token between files, so nothing is left half written: a file being
+/// copied when the token is cancelled is finished first. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    signals: bool,
+}
+
+impl CancelToken {
+    /// Creates a token that is cancelled only by [`CancelToken::cancel`].
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Creates a token that is also cancelled when the process receives ctrl-c or
+    /// `SIGTERM` (ctrl-c, ctrl-break or closing the console on Windows), instead of the
+    /// process being killed. A second signal kills the process as usual, so a stuck
+    /// operation can still be interrupted.
+    ///
+    /// The handlers are installed by the first call and stay installed; once a signal has
+    /// arrived, all the tokens created by this function are cancelled.
+    #[cfg(feature = "signals")]
+    pub fn on_signals() -> crate::result::Result<CancelToken> {
+        sys::install()?;
+        Ok(CancelToken {
+            flag: Arc::default(),
+            signals: true,
+        })
+    }
+
+    /// Cancels the token and its clones.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// Checks whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) || self.signals && SIGNALLED.load(Ordering::Relaxed)
+    }
+}
+
+/// Cause of errors of kind `ErrorKind::Cancelled`, with what was done before the operation
+/// stopped; get it with `error.source()` and `downcast_ref`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cancelled {
+    /// Work done before the operation stopped.
+    pub stats: IoStats,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled after {}", self.stats)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[cfg(all(feature = "signals", unix))]
+mod sys {
+    use std::ffi::c_int;
+    use std::io;
+    use std::sync::atomic::Ordering;
+    use std::sync::OnceLock;
+
+    use crate::cancel::SIGNALLED;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_DFL: usize = 0;
+    const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn handle(signum: c_int) {
+        SIGNALLED.store(true, Ordering::Relaxed);
+        // the next signal gets the default action; signal() is async-signal-safe
+        // SAFETY: restores the default disposition.
+        unsafe {
+            signal(signum, SIG_DFL);
+        }
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        static INSTALLED: OnceLock<io::Result<()>> = OnceLock::new();
+        let result = INSTALLED.get_or_init(|| {
+            for signum in [SIGINT, SIGTERM] {
+                // SAFETY: `handle` only stores to an atomic and calls signal().
+                if unsafe { signal(signum, handle as extern "C" fn(c_int) as usize) } == SIG_ERR
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+#[cfg(all(feature = "signals", windows))]
+mod sys {
+    use std::io;
+    use std::sync::atomic::Ordering;
+    use std::sync::OnceLock;
+
+    use crate::cancel::SIGNALLED;
+
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+
+    extern "system" fn handle(_event: u32) -> i32 {
+        // after the first event, the default handler terminates the process
+        let first = !SIGNALLED.swap(true, Ordering::Relaxed);
+        first as i32
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        static INSTALLED: OnceLock<io::Result<()>> = OnceLock::new();
+        // SAFETY: `handle` only touches an atomic.
+        let result = INSTALLED.get_or_init(|| match unsafe { SetConsoleCtrlHandler(handle, 1) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+#[cfg(all(feature = "signals", not(any(unix, windows))))]
+mod sys {
+    use std::io;
+
+    pub(super) fn install() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "signal handling is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cancel::CancelToken;
+
+    #[test]
+    fn cancel_token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert!(!CancelToken::new().is_cancelled());
+    }
+
+    #[test]
+    #[cfg(all(feature = "signals", unix))]
+    fn cancelled_by_sigterm() {
+        use std::ffi::c_int;
+        use std::thread;
+        use std::time::Duration;
+
+        extern "C" {
+            fn raise(signum: c_int) -> c_int;
+        }
+
+        let token = CancelToken::on_signals().unwrap();
+        assert!(!token.is_cancelled());
+        // SAFETY: the handler is installed, so the process is not terminated.
+        assert_eq!(unsafe { raise(15) }, 0);
+        for _ in 0..100 {
+            if token.is_cancelled() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(token.is_cancelled());
+        assert!(!CancelToken::new().is_cancelled());
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::Path;
+
+use crate::atomic::temp_path_for;
+use crate::case::is_case_insensitive_fs;
+use crate::links::symlink;
+use crate::result::Result;
+
+/// Features of a filesystem, as detected by [`probe_capabilities`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FsCapabilities {
+    /// Symbolic links can be created (on Windows this may need a privilege).
+    pub symlinks: bool,
+    /// Hard links can be created.
+    pub hard_links: bool,
+    /// Files can share extents (reflinks: Btrfs, XFS); detected on Linux.
+    pub reflinks: bool,
+    /// User extended attributes can be set; detected on Linux.
+    pub xattrs: bool,
+    /// Names that differ only by case are different entries.
+    pub case_sensitive: bool,
+    /// Files with holes use less space than their size; detected on Unix.
+    pub sparse_files: bool,
+    /// Maximum length of a name component in bytes, if known.
+    pub max_component: Option<u64>,
+    /// Maximum length of a path in bytes, if known.
+    pub max_path: Option<u64>,
+}
+
+/// Size of the hole of the sparse probe file; larger than any common block size.
+const SPARSE_PROBE_LEN: u64 = 1024 * 1024;
+
+/// Detects what the filesystem holding `dir` supports, so copy and sync code can choose
+/// strategies (and warn users) before starting. Probe entries are created in a temporary
+/// subdirectory of `dir` and removed afterwards; a failed probe means "not supported".
+///
+/// # Arguments:
+///
+/// * `dir` - writable directory on the filesystem to check.
+pub fn probe_capabilities<P: AsRef<Path>>(dir: P) -> Result<FsCapabilities> {
+    let dir = dir.as_ref();
+    let probe = temp_path_for(&dir.join("fs-helper-probe"));
+    fs::create_dir(&probe)?;
+    let result = probe_in(&probe);
+    fs::remove_dir_all(&probe)?;
+    result
+}
+
+fn probe_in(probe: &Path) -> Result<FsCapabilities> {
+    let file = probe.join("file");
+    fs::write(&file, b"probe")?;
+    let (max_component, max_path) = sys::limits(probe);
+    Ok(FsCapabilities {
+        symlinks: symlink(Path::new("file"), &probe.join("symlink")).is_ok(),
+        hard_links: fs::hard_link(&file, probe.join("hard_link")).is_ok(),
+        reflinks: sys::reflink(&file, &probe.join("reflink")),
+        xattrs: sys::set_xattr(&file),
+        case_sensitive: !is_case_insensitive_fs(probe)?,
+        sparse_files: sys::sparse(&probe.join("sparse")),
+        max_component,
+        max_path,
+    })
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{c_char, c_int, c_long, CString};
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    use crate::capabilities::SPARSE_PROBE_LEN;
+
+    #[cfg(target_os = "linux")]
+    const PC_NAME_MAX: c_int = 3;
+    #[cfg(target_os = "linux")]
+    const PC_PATH_MAX: c_int = 4;
+    #[cfg(not(target_os = "linux"))]
+    const PC_NAME_MAX: c_int = 4;
+    #[cfg(not(target_os = "linux"))]
+    const PC_PATH_MAX: c_int = 5;
+
+    extern "C" {
+        fn pathconf(path: *const c_char, name: c_int) -> c_long;
+    }
+
+    pub(super) fn limits(dir: &Path) -> (Option<u64>, Option<u64>) {
+        let path = match CString::new(dir.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return (None, None),
+        };
+        // SAFETY: `path` is a valid C string; -1 means no limit or an error.
+        let limit = |name| u64::try_from(unsafe { pathconf(path.as_ptr(), name) }).ok();
+        (limit(PC_NAME_MAX), limit(PC_PATH_MAX))
+    }
+
+    pub(super) fn sparse(path: &Path) -> bool {
+        let written = (|| {
+            let file = fs::File::create(path)?;
+            file.set_len(SPARSE_PROBE_LEN)?;
+            file.sync_all()?;
+            fs::metadata(path)
+        })();
+        // blocks are counted in units of 512 bytes
+        written.is_ok_and(|meta| meta.blocks() * 512 < SPARSE_PROBE_LEN)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(super) fn reflink(from: &Path, to: &Path) -> bool {
+        use std::ffi::c_ulong;
+        use std::os::unix::io::AsRawFd;
+
+        // _IOW(0x94, 9, int)
+        const FICLONE: c_ulong = 0x4004_9409;
+
+        extern "C" {
+            fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+        }
+
+        let (src, dst) = match (fs::File::open(from), fs::File::create(to)) {
+            (Ok(src), Ok(dst)) => (src, dst),
+            _ => return false,
+        };
+        // SAFETY: both descriptors are open.
+        unsafe { ioctl(dst.as_raw_fd(), FICLONE, src.as_raw_fd()) == 0 }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn reflink(_from: &Path, _to: &Path) -> bool {
+        false
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(super) fn set_xattr(path: &Path) -> bool {
+        use std::ffi::c_void;
+
+        extern "C" {
+            fn setxattr(
+                path: *const c_char,
+                name: *const c_char,
+                value: *const c_void,
+                size: usize,
+                flags: c_int,
+            ) -> c_int;
+        }
+
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+        let value = b"1";
+        // SAFETY: valid C strings and a one byte value.
+        unsafe {
+            setxattr(
+                path.as_ptr(),
+                c"user.fs-helper-probe".as_ptr(),
+                value.as_ptr() as *const c_void,
+                value.len(),
+                0,
+            ) == 0
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn set_xattr(_path: &Path) -> bool {
+        false
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::path::Path;
+
+    pub(super) fn limits(_dir: &Path) -> (Option<u64>, Option<u64>) {
+        // NTFS; paths may be up to 32767 UTF-16 units with the `\\?\` prefix
+        (Some(255), Some(32767))
+    }
+
+    pub(super) fn sparse(_path: &Path) -> bool {
+        false
+    }
+
+    pub(super) fn reflink(_from: &Path, _to: &Path) -> bool {
+        false
+    }
+
+    pub(super) fn set_xattr(_path: &Path) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capabilities::probe_capabilities;
+    use crate::case::is_case_insensitive_fs;
+    use crate::fixture::TreeBuilder;
+    use std::fs;
+
+    #[test]
+    fn probe_tmp_filesystem() {
+        let tree = TreeBuilder::new().build().unwrap();
+        let caps = probe_capabilities(tree.path()).unwrap();
+        assert_eq!(
+            caps.case_sensitive,
+            !is_case_insensitive_fs(tree.path()).unwrap()
+        );
+        #[cfg(unix)]
+        {
+            assert!(caps.symlinks && caps.hard_links);
+            assert!(caps.max_component.is_some_and(|max| max >= 255));
+        }
+        // the probe directory is removed
+        assert_eq!(fs::read_dir(tree.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::hash::Hash;
+use crate::result::Result;
+
+/// Returns the path of a blob in the store under `root`.
+/// Blobs are sharded by the first two hex digits of the hash: `root/ab/abcdef...`.
+pub fn blob_path<P: AsRef<Path>>(root: P, hash: &Hash) -> PathBuf {
+    let hex = hash.to_hex();
+    root.as_ref().join(&hex[..2]).join(hex)
+}
+
+/// Stores `bytes` in the content-addressed store under `root` and returns its hash.
+/// Storing the same contents twice is a no-op.
+pub fn store_blob<P: AsRef<Path>>(root: P, bytes: &[u8]) -> Result<Hash> {
+    let hash = Hash::of(bytes);
+    let path = blob_path(&root, &hash);
+    if !path.exists() {
+        fs::create_dir_all(path.parent().unwrap())?;
+        write_atomic(&path, bytes)?;
+    }
+    Ok(hash)
+}
+
+/// Reads a blob from the store under `root`.
+pub fn load_blob<P: AsRef<Path>>(root: P, hash: &Hash) -> Result<Vec<u8>> {
+    Ok(fs::read(blob_path(root, hash))?)
+}
+
+/// Removes every blob that is not in `live` (and empty shard directories).
+/// Returns the number of removed blobs.
+pub fn gc<P: AsRef<Path>>(root: P, live: &HashSet<Hash>) -> Result<usize> {
+    let mut removed = 0;
+    for shard in fs::read_dir(root)? {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for blob in fs::read_dir(shard.path())? {
+            let blob = blob?;
+            let keep = blob
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<Hash>().ok())
+                .is_some_and(|hash| live.contains(&hash));
+            if !keep {
+                fs::remove_file(blob.path())?;
+                removed += 1;
+            }
+        }
+        if fs::read_dir(shard.path())?.next().is_none() {
+            fs::remove_dir(shard.path())?;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cas::{blob_path, gc, load_blob, store_blob};
+    use crate::fixture::TreeBuilder;
+    use std::collections::HashSet;
+
+    #[test]
+    fn store_and_gc() {
+        let tree = TreeBuilder::new().build().unwrap();
+        let root = &tree.join("store");
+        let a = store_blob(root, b"first").unwrap();
+        let b = store_blob(root, b"second").unwrap();
+        assert_eq!(store_blob(root, b"first").unwrap(), a);
+        assert_eq!(load_blob(root, &a).unwrap(), b"first");
+
+        let live = HashSet::from([a]);
+        assert_eq!(gc(root, &live).unwrap(), 1);
+        assert!(blob_path(root, &a).exists());
+        assert!(!blob_path(root, &b).exists());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+use crate::vfs::{FileKind, ReadFs, RealFs};
+
+/// Detects whether the filesystem containing `dir` compares names case-insensitively
+/// (e.g. default volumes on macOS and Windows).
+/// A temporary probe file is created in `dir` and removed afterwards.
+///
+/// # Arguments:
+///
+/// * `dir` - writable directory on the filesystem to check.
+pub fn is_case_insensitive_fs<P: AsRef<Path>>(dir: P) -> Result<bool> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let name = format!(".fs-helper-case-probe-{}-{}", process::id(), n);
+    let probe = dir.as_ref().join(&name);
+    fs::File::create(&probe)?;
+    let insensitive = dir.as_ref().join(name.to_uppercase()).exists();
+    fs::remove_file(&probe)?;
+    Ok(insensitive)
+}
+
+/// Returns the case-folded form of a name, used for case-insensitive comparisons.
+/// Non-UTF-8 names are folded lossily.
+pub fn fold_case<S: AsRef<OsStr>>(name: S) -> String {
+    name.as_ref().to_string_lossy().to_lowercase()
+}
+
+/// Compares two names ignoring case.
+pub fn eq_ignore_case<A: AsRef<OsStr>, B: AsRef<OsStr>>(a: A, b: B) -> bool {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    a == b || fold_case(a) == fold_case(b)
+}
+
+/// Compares two paths component by component, ignoring case.
+pub fn path_eq_ignore_case<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> bool {
+    let mut a = a.as_ref().components();
+    let mut b = b.as_ref().components();
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(x), Some(y)) if eq_ignore_case(x.as_os_str(), y.as_os_str()) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Compares two names, ignoring case if `case_sensitive` is false.
+pub fn names_eq<A: AsRef<OsStr>, B: AsRef<OsStr>>(a: A, b: B, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        a.as_ref() == b.as_ref()
+    } else {
+        eq_ignore_case(a, b)
+    }
+}
+
+/// Returns the sets of entries under `root` whose relative paths differ only by case
+/// (e.g. `README.md` and `readme.md`, or `Docs/a.txt` and `docs/A.txt`), which would be
+/// the same entry on case-insensitive filesystems. Each set is sorted, and so is the list
+/// of sets.
+/// Directories that can not be read are skipped.
+pub fn find_case_collisions<P: AsRef<Path>>(root: P) -> Result<Vec<Vec<PathBuf>>> {
+    find_case_collisions_in(&RealFs, root.as_ref())
+}
+
+/// Like [`find_case_collisions`], for the tree of another filesystem.
+pub(crate) fn find_case_collisions_in(fs: &dyn ReadFs, root: &Path) -> Result<Vec<Vec<PathBuf>>> {
+    let root = simplify_verbatim(fs.canonicalize(root)?);
+    let mut by_folded: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut stack = vec![root.clone()];
+    while let Some(dir) = stack.pop() {
+        let entries = match fs.read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if entry.kind == FileKind::Dir {
+                stack.push(entry.path.clone());
+            }
+            let relative = entry.path.strip_prefix(&root).unwrap_or(&entry.path);
+            by_folded.entry(fold_case(relative)).or_default().push(entry.path);
+        }
+    }
+    let mut collisions: Vec<_> = by_folded.into_values().filter(|paths| paths.len() > 1).collect();
+    collisions.iter_mut().for_each(|set| set.sort());
+    collisions.sort();
+    Ok(collisions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::case::{
+        eq_ignore_case, find_case_collisions, is_case_insensitive_fs, names_eq,
+        path_eq_ignore_case,
+    };
+    use crate::copy::{copy_dir, CopyOptions};
+    use crate::fixture::TreeBuilder;
+    use crate::ErrorKind;
+
+    #[test]
+    fn case_folding_compare() {
+        assert!(eq_ignore_case("README.md", "readme.MD"));
+        assert!(eq_ignore_case("Ärger", "äRGER"));
+        assert!(!eq_ignore_case("readme", "readme2"));
+        assert!(path_eq_ignore_case("/Tmp/Foo/BAR", "/tmp/foo/bar"));
+        assert!(!path_eq_ignore_case("/tmp/foo", "/tmp/foo/bar"));
+        assert!(!names_eq("A", "a", true));
+        assert!(names_eq("A", "a", false));
+    }
+
+    #[test]
+    fn case_insensitive_fs_probe() {
+        let tree = TreeBuilder::new().file("a.txt", b"").build().unwrap();
+        is_case_insensitive_fs(tree.path()).unwrap();
+        is_case_insensitive_fs(tree.path()).unwrap();
+        let names: Vec<_> = std::fs::read_dir(tree.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a.txt"]);
+    }
+
+    #[test]
+    fn case_collisions_found() {
+        let probe = TreeBuilder::new().build().unwrap();
+        if is_case_insensitive_fs(probe.path()).unwrap() {
+            return;
+        }
+        let tree = TreeBuilder::new()
+            .file("README.md", b"")
+            .file("readme.md", b"")
+            .file("Src/a.txt", b"")
+            .file("src/A.TXT", b"")
+            .file("src/b.txt", b"")
+            .build()
+            .unwrap();
+        let collisions = find_case_collisions(tree.path()).unwrap();
+        assert_eq!(
+            collisions,
+            [
+                vec![tree.join("README.md"), tree.join("readme.md")],
+                vec![tree.join("Src"), tree.join("src")],
+                vec![tree.join("Src/a.txt"), tree.join("src/A.TXT")],
+            ]
+        );
+
+        let options = CopyOptions {
+            check_case_collisions: true,
+            ..CopyOptions::default()
+        };
+        let dst = TreeBuilder::new().build().unwrap();
+        let err = copy_dir(tree.path(), dst.join("copy"), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert!(!dst.join("copy").exists());
+    }
+
+    #[test]
+    fn case_collisions_across_dirs() {
+        let probe = TreeBuilder::new().build().unwrap();
+        if is_case_insensitive_fs(probe.path()).unwrap() {
+            return;
+        }
+        let tree = TreeBuilder::new()
+            .file("Docs/a.txt", b"")
+            .file("docs/A.txt", b"")
+            .file("docs/b.txt", b"")
+            .file("Other/b.txt", b"")
+            .build()
+            .unwrap();
+        let collisions = find_case_collisions(tree.path()).unwrap();
+        assert_eq!(
+            collisions,
+            [
+                vec![tree.join("Docs"), tree.join("docs")],
+                vec![tree.join("Docs/a.txt"), tree.join("docs/A.txt")],
+            ]
+        );
+    }
+}
@@ -0,0 +1,777 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::temp_path_for;
+use crate::diff::diff;
+use crate::encoding::{decode_path, encode_path};
+use crate::result::{Error, ErrorKind, Result};
+use crate::vfs::{FileKind, Fs, ReadFs, RealFs};
+
+const MAGIC: &str = "fs-helper-changes 1";
+
+/// Contents of an entry touched by a [`Change`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Content {
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+impl Content {
+    /// Reads the contents of a file or the target of a symbolic link.
+    pub(crate) fn read(fs: &dyn ReadFs, path: &Path) -> Result<Content> {
+        if fs.symlink_metadata(path)?.kind == FileKind::Symlink {
+            Ok(Content::Symlink(fs.read_link(path)?))
+        } else {
+            let mut bytes = Vec::new();
+            fs.open(path)?.read_to_end(&mut bytes)?;
+            Ok(Content::File(bytes))
+        }
+    }
+
+    /// Writes the contents to `path`; a file gets the permission bits `mode`, or if `None`
+    /// those of the file it replaces.
+    pub(crate) fn write(&self, fs: &dyn Fs, path: &Path, mode: Option<u32>) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs.create_dir_all(parent)?;
+        }
+        match self {
+            Content::File(bytes) => Ok(fs.write_atomic(path, &mut &bytes[..], mode)?),
+            Content::Symlink(target) => {
+                if fs.symlink_metadata(path).is_ok() {
+                    fs.remove_file(path)?;
+                }
+                Ok(fs.symlink(target, path)?)
+            }
+        }
+    }
+}
+
+/// A change of one entry; paths are relative to the root the change applies to.
+/// Removed and replaced contents are kept, so a change can be verified and inverted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Create {
+        path: PathBuf,
+        content: Content,
+    },
+    Modify {
+        path: PathBuf,
+        old: Content,
+        new: Content,
+    },
+    Delete {
+        path: PathBuf,
+        content: Content,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+impl Change {
+    fn inverted(&self) -> Change {
+        match self.clone() {
+            Change::Create { path, content } => Change::Delete { path, content },
+            Change::Delete { path, content } => Change::Create { path, content },
+            Change::Modify { path, old, new } => Change::Modify {
+                path,
+                old: new,
+                new: old,
+            },
+            Change::Rename { from, to } => Change::Rename { from: to, to: from },
+        }
+    }
+
+    /// Checks that the change can be applied to the tree as planned so far: entries to
+    /// create do not exist, and entries to modify, delete or rename are in the expected state.
+    fn check(&self, plan: &Plan<'_>) -> Result<()> {
+        let conflict = |path: &Path, reason: &str| {
+            Error::new(
+                ErrorKind::Conflict,
+                format!("{}: {}", plan.root.join(path).display(), reason),
+            )
+        };
+        let exists = |path: &Path| plan.exists(path);
+        match self {
+            Change::Create { path, .. } if exists(path) => Err(conflict(path, "already exists")),
+            Change::Modify { path, old, .. } | Change::Delete { path, content: old }
+                if !exists(path) || plan.content(path)? != *old =>
+            {
+                Err(conflict(path, "does not have the expected contents"))
+            }
+            Change::Rename { from, .. } if !exists(from) => Err(conflict(from, "does not exist")),
+            Change::Rename { to, .. } if exists(to) => Err(conflict(to, "already exists")),
+            _ => Ok(()),
+        }
+    }
+
+    fn apply(&self, fs: &dyn Fs, root: &Path) -> Result<()> {
+        match self {
+            Change::Create { path, content } => content.write(fs, &root.join(path), None),
+            Change::Modify { path, new, .. } => new.write(fs, &root.join(path), None),
+            Change::Delete { path, .. } => Ok(fs.remove_file(&root.join(path))?),
+            Change::Rename { from, to } => {
+                let to = root.join(to);
+                if let Some(parent) = to.parent() {
+                    fs.create_dir_all(parent)?;
+                }
+                Ok(fs.rename(&root.join(from), &to)?)
+            }
+        }
+    }
+
+    /// Applies the change with the new contents, if any, already written to `staged`.
+    fn apply_staged(&self, fs: &dyn Fs, root: &Path, staged: &Path) -> Result<()> {
+        match self {
+            Change::Create { path, .. } | Change::Modify { path, .. } => {
+                let path = root.join(path);
+                if let Some(parent) = path.parent() {
+                    fs.create_dir_all(parent)?;
+                }
+                Ok(fs.rename(staged, &path)?)
+            }
+            _ => self.apply(fs, root),
+        }
+    }
+
+    /// Returns the contents the change writes, if any.
+    fn new_content(&self) -> Option<&Content> {
+        match self {
+            Change::Create { content, .. } => Some(content),
+            Change::Modify { new, .. } => Some(new),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a tree as the changes checked so far leave it, so that each change is
+/// checked against the entries the earlier ones create, modify, delete or rename.
+struct Plan<'a> {
+    fs: &'a dyn Fs,
+    root: &'a Path,
+    /// Entries changed so far, by path relative to the root. Entries below a changed
+    /// path are in the state of that path.
+    changed: HashMap<PathBuf, Slot>,
+}
+
+/// The planned state of an entry.
+#[derive(Clone)]
+enum Slot {
+    Removed,
+    Written(Content),
+    /// The entry now at this path relative to the root.
+    From(PathBuf),
+}
+
+impl<'a> Plan<'a> {
+    fn new(fs: &'a dyn Fs, root: &'a Path) -> Plan<'a> {
+        Plan {
+            fs,
+            root,
+            changed: HashMap::new(),
+        }
+    }
+
+    /// Returns the planned state of the entry at `path`.
+    fn slot(&self, path: &Path) -> Slot {
+        for ancestor in path.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+            match self.changed.get(ancestor) {
+                Some(slot) if ancestor == path => return slot.clone(),
+                Some(Slot::From(from)) => {
+                    return Slot::From(from.join(path.strip_prefix(ancestor).unwrap()))
+                }
+                // nothing is below a removed entry or a written file
+                Some(_) => return Slot::Removed,
+                None => {}
+            }
+        }
+        Slot::From(path.to_path_buf())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        match self.slot(path) {
+            Slot::Removed => false,
+            Slot::Written(_) => true,
+            Slot::From(from) => self.fs.symlink_metadata(&self.root.join(from)).is_ok(),
+        }
+    }
+
+    fn content(&self, path: &Path) -> Result<Content> {
+        match self.slot(path) {
+            Slot::Written(content) => Ok(content),
+            Slot::From(from) => Content::read(self.fs, &self.root.join(from)),
+            Slot::Removed => Err(Error::new(ErrorKind::Conflict, "entry is removed")),
+        }
+    }
+
+    /// Checks a change against the planned state and then plans it. Returns the path of
+    /// the entry in the tree the change replaces, if any.
+    fn add(&mut self, change: &Change) -> Result<Option<PathBuf>> {
+        change.check(self)?;
+        let replaced = |plan: &Plan<'_>, path: &Path| match plan.slot(path) {
+            Slot::From(from) => Some(from),
+            _ => None,
+        };
+        match change {
+            Change::Create { path, content } => {
+                self.changed.insert(path.clone(), Slot::Written(content.clone()));
+                Ok(None)
+            }
+            Change::Modify { path, new, .. } => {
+                let replaced = replaced(self, path);
+                self.changed.insert(path.clone(), Slot::Written(new.clone()));
+                Ok(replaced)
+            }
+            Change::Delete { path, .. } => {
+                let replaced = replaced(self, path);
+                self.changed.insert(path.clone(), Slot::Removed);
+                Ok(replaced)
+            }
+            Change::Rename { from, to } => {
+                let slot = self.slot(from);
+                self.changed.retain(|path, _| !path.starts_with(to));
+                let moved: Vec<_> =
+                    self.changed.keys().filter(|p| p.starts_with(from)).cloned().collect();
+                for path in moved {
+                    let slot = self.changed.remove(&path).unwrap();
+                    self.changed.insert(to.join(path.strip_prefix(from).unwrap()), slot);
+                }
+                self.changed.insert(to.clone(), slot);
+                self.changed.insert(from.clone(), Slot::Removed);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Cause of an error applying a [`ChangeSet`] when the changes already applied could not
+/// all be undone, which leaves the tree partly changed.
+#[derive(Debug)]
+pub struct RollbackFailed {
+    /// Why the change set failed.
+    pub error: Error,
+    /// Why undoing the changes applied before failed, one error per change not undone.
+    pub rollback: Vec<Error>,
+}
+
+impl fmt::Display for RollbackFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (and {} changes not undone", self.error, self.rollback.len())?;
+        for error in &self.rollback {
+            write!(f, "; {}", error)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl std::error::Error for RollbackFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A list of changes to a tree, to be computed (e.g. with [`ChangeSet::from_diff`]),
+/// reviewed, stored and applied later. Changes are applied in order.
+///
+/// Change sets are stored as plain text with [`ChangeSet::to_text`] and read back with
+/// [`ChangeSet::parse`]: a header followed by one line per change with tab-separated
+/// fields; file contents are hex-encoded and paths [encoded](crate::encode_path).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChangeSet {
+    pub changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    /// Computes the changes turning tree `old` into tree `new`. An entry removed from one
+    /// path and added with the same contents at another is reported as a rename.
+    /// Empty directories are not compared.
+    ///
+    /// # Arguments:
+    ///
+    /// * `old` - tree before the changes.
+    /// * `new` - tree after the changes.
+    pub fn from_diff<A: AsRef<Path>, B: AsRef<Path>>(old: A, new: B) -> Result<ChangeSet> {
+        let (old, new) = (old.as_ref(), new.as_ref());
+        let diff = diff(old, new)?;
+        let mut removed: HashMap<Content, Vec<PathBuf>> = HashMap::new();
+        for path in diff.removed {
+            removed
+                .entry(Content::read(&RealFs, &old.join(&path))?)
+                .or_default()
+                .push(path);
+        }
+        let mut changes = Vec::new();
+        let mut created = Vec::new();
+        for path in diff.added {
+            let content = Content::read(&RealFs, &new.join(&path))?;
+            match removed.get_mut(&content).and_then(|paths| paths.pop()) {
+                Some(from) => changes.push(Change::Rename { from, to: path }),
+                None => created.push(Change::Create { path, content }),
+            }
+        }
+        for path in diff.modified {
+            changes.push(Change::Modify {
+                old: Content::read(&RealFs, &old.join(&path))?,
+                new: Content::read(&RealFs, &new.join(&path))?,
+                path,
+            });
+        }
+        let mut deleted: Vec<_> = removed
+            .into_iter()
+            .flat_map(|(content, paths)| paths.into_iter().map(move |path| (path, content.clone())))
+            .collect();
+        deleted.sort_by(|a, b| a.0.cmp(&b.0));
+        let deleted = deleted
+            .into_iter()
+            .map(|(path, content)| Change::Delete { path, content });
+        // deletes first, then renames and modifications, then creates
+        changes.splice(0..0, deleted);
+        changes.extend(created);
+        Ok(ChangeSet { changes })
+    }
+
+    /// Returns the change set undoing this one.
+    pub fn invert(&self) -> ChangeSet {
+        ChangeSet {
+            changes: self.changes.iter().rev().map(Change::inverted).collect(),
+        }
+    }
+
+    /// Checks whether there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Applies the changes to the tree at `root`, all or nothing: every change is checked
+    /// first, against the tree as the changes before it leave it, and if a change fails,
+    /// those already applied are undone. Fails with an error of kind `ErrorKind::Conflict`
+    /// if the tree is not in the state the changes expect. If undoing fails too, the source
+    /// of the error is a [`RollbackFailed`].
+    ///
+    /// Changes may depend on each other, e.g. a file renamed and then modified.
+    ///
+    /// # Arguments:
+    ///
+    /// * `root` - root directory the changes are relative to.
+    pub fn apply<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        self.apply_in(&RealFs, root)
+    }
+
+    /// Like [`ChangeSet::apply`], for a tree of another filesystem, e.g. a
+    /// [`MemFs`](crate::MemFs).
+    ///
+    /// # Arguments:
+    ///
+    /// * `fs` - filesystem holding the tree.
+    /// * `root` - root directory the changes are relative to.
+    pub fn apply_in<P: AsRef<Path>>(&self, fs: &dyn Fs, root: P) -> Result<()> {
+        let root = root.as_ref();
+        self.plan(fs, root)?;
+        for (i, change) in self.changes.iter().enumerate() {
+            if let Err(e) = change.apply(fs, root) {
+                return Err(self.roll_back(fs, root, i, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every change against the tree as the changes before it leave it. Returns the
+    /// path of the entry in the tree each change replaces, if any.
+    fn plan(&self, fs: &dyn Fs, root: &Path) -> Result<Vec<Option<PathBuf>>> {
+        let mut plan = Plan::new(fs, root);
+        self.changes.iter().map(|change| plan.add(change)).collect()
+    }
+
+    /// Undoes the first `applied` changes after `error`, and returns the error to report.
+    fn roll_back(&self, fs: &dyn Fs, root: &Path, applied: usize, error: Error) -> Error {
+        let rollback: Vec<_> = self.changes[..applied]
+            .iter()
+            .rev()
+            .filter_map(|change| change.inverted().apply(fs, root).err())
+            .collect();
+        if rollback.is_empty() {
+            return error;
+        }
+        Error::new(error.kind(), RollbackFailed { error, rollback })
+    }
+
+    /// Applies the changes to the tree at `root` in two phases, like [`ChangeSet::apply`]
+    /// but keeping the tree consistent for as long as possible: all new and modified
+    /// contents are first written to a staging directory in `root`, and only once they all
+    /// are does the tree change, by renames. If writing fails (e.g. the disk is full), the
+    /// tree is left untouched; the staging directory is removed either way.
+    ///
+    /// # Arguments:
+    ///
+    /// * `root` - root directory the changes are relative to.
+    pub fn apply_staged<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        self.apply_staged_in(&RealFs, root)
+    }
+
+    /// Like [`ChangeSet::apply_staged`], for a tree of another filesystem.
+    ///
+    /// # Arguments:
+    ///
+    /// * `fs` - filesystem holding the tree.
+    /// * `root` - root directory the changes are relative to.
+    pub fn apply_staged_in<P: AsRef<Path>>(&self, fs: &dyn Fs, root: P) -> Result<()> {
+        let root = root.as_ref();
+        let replaced = self.plan(fs, root)?;
+        // in the root, so the final renames stay on one filesystem
+        let staging = temp_path_for(&root.join("staging"));
+        fs.create_dir_all(&staging)?;
+        let result = self.commit_staged(fs, root, &staging, &replaced);
+        let _ = remove_staging(fs, &staging);
+        result
+    }
+
+    fn commit_staged(
+        &self,
+        fs: &dyn Fs,
+        root: &Path,
+        staging: &Path,
+        replaced: &[Option<PathBuf>],
+    ) -> Result<()> {
+        let staged = |i: usize| staging.join(i.to_string());
+        for (i, change) in self.changes.iter().enumerate() {
+            if let Some(content) = change.new_content() {
+                // a modified file keeps its permissions, also when renamed before
+                let mode = match (change, &replaced[i]) {
+                    (
+                        Change::Modify {
+                            old: Content::File(_),
+                            ..
+                        },
+                        Some(path),
+                    ) => fs.mode(&root.join(path)).ok(),
+                    _ => None,
+                };
+                content.write(fs, &staged(i), mode)?;
+            }
+        }
+        for (i, change) in self.changes.iter().enumerate() {
+            if let Err(e) = change.apply_staged(fs, root, &staged(i)) {
+                return Err(self.roll_back(fs, root, i, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the change set as text.
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", MAGIC);
+        for change in &self.changes {
+            let fields = match change {
+                Change::Create { path, content } => {
+                    vec![
+                        "create".to_string(),
+                        encode_content(content),
+                        encode_path(path),
+                    ]
+                }
+                Change::Modify { path, old, new } => vec![
+                    "modify".to_string(),
+                    encode_content(old),
+                    encode_content(new),
+                    encode_path(path),
+                ],
+                Change::Delete { path, content } => {
+                    vec![
+                        "delete".to_string(),
+                        encode_content(content),
+                        encode_path(path),
+                    ]
+                }
+                Change::Rename { from, to } => {
+                    vec!["rename".to_string(), encode_path(from), encode_path(to)]
+                }
+            };
+            out.push_str(&fields.join("\t"));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Decodes a change set encoded by [`ChangeSet::to_text`].
+    /// Fails with an error of kind `ErrorKind::Encoding` if the text is invalid.
+    pub fn parse(text: &str) -> Result<ChangeSet> {
+        let invalid = || Error::new(ErrorKind::Encoding, "invalid change set");
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid());
+        }
+        let mut changes = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let change = match fields[..] {
+                ["create", content, path] => Change::Create {
+                    path: decode_path(path)?,
+                    content: decode_content(content).ok_or_else(invalid)?,
+                },
+                ["modify", old, new, path] => Change::Modify {
+                    path: decode_path(path)?,
+                    old: decode_content(old).ok_or_else(invalid)?,
+                    new: decode_content(new).ok_or_else(invalid)?,
+                },
+                ["delete", content, path] => Change::Delete {
+                    path: decode_path(path)?,
+                    content: decode_content(content).ok_or_else(invalid)?,
+                },
+                ["rename", from, to] => Change::Rename {
+                    from: decode_path(from)?,
+                    to: decode_path(to)?,
+                },
+                _ => return Err(invalid()),
+            };
+            changes.push(change);
+        }
+        Ok(ChangeSet { changes })
+    }
+}
+
+/// Removes the staging directory of [`ChangeSet::apply_staged_in`] and the staged contents
+/// left in it.
+fn remove_staging(fs: &dyn Fs, staging: &Path) -> Result<()> {
+    for entry in fs.read_dir(staging)? {
+        fs.remove_file(&entry?.path)?;
+    }
+    Ok(fs.remove_dir(staging)?)
+}
+
+/// Encodes contents as `file:<hex>` or `link:<encoded target>`.
+fn encode_content(content: &Content) -> String {
+    match content {
+        Content::File(bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("file:{}", hex)
+        }
+        Content::Symlink(target) => format!("link:{}", encode_path(target)),
+    }
+}
+
+fn decode_content(s: &str) -> Option<Content> {
+    if let Some(hex) = s.strip_prefix("file:") {
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        return Some(Content::File(bytes));
+    }
+    let target = s.strip_prefix("link:")?;
+    decode_path(target).ok().map(Content::Symlink)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::changeset::{Change, ChangeSet, Content};
+    use crate::diff::dirs_equal;
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::{MemFs, ReadFs};
+    use crate::ErrorKind;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn change_set_round_trip() {
+        let old = TreeBuilder::new()
+            .file("keep.txt", b"same")
+            .file("edit.txt", b"v1")
+            .file("old/name.txt", b"moved")
+            .file("gone.txt", b"bye")
+            .build()
+            .unwrap();
+        let new = TreeBuilder::new()
+            .file("keep.txt", b"same")
+            .file("edit.txt", b"v2\n\t")
+            .file("new/name.txt", b"moved")
+            .file("added.txt", b"hi")
+            .build()
+            .unwrap();
+        let changes = ChangeSet::from_diff(old.path(), new.path()).unwrap();
+        assert_eq!(
+            changes.changes,
+            [
+                Change::Delete {
+                    path: PathBuf::from("gone.txt"),
+                    content: Content::File(b"bye".to_vec())
+                },
+                Change::Rename {
+                    from: PathBuf::from("old/name.txt"),
+                    to: PathBuf::from("new/name.txt")
+                },
+                Change::Modify {
+                    path: PathBuf::from("edit.txt"),
+                    old: Content::File(b"v1".to_vec()),
+                    new: Content::File(b"v2\n\t".to_vec())
+                },
+                Change::Create {
+                    path: PathBuf::from("added.txt"),
+                    content: Content::File(b"hi".to_vec())
+                }
+            ]
+        );
+        let changes = ChangeSet::parse(&changes.to_text()).unwrap();
+
+        changes.apply(old.path()).unwrap();
+        fs::remove_dir(old.join("old")).unwrap();
+        assert!(dirs_equal(old.path(), new.path()).unwrap());
+        // applying again conflicts and changes nothing
+        let err = changes.apply(old.path()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        changes.invert().apply(old.path()).unwrap();
+        assert_eq!(fs::read(old.join("edit.txt")).unwrap(), b"v1");
+        assert_eq!(fs::read(old.join("old/name.txt")).unwrap(), b"moved");
+        assert!(!old.join("added.txt").exists());
+
+        // staged: the same changes, and the staging directory is gone afterwards
+        changes.apply_staged(old.path()).unwrap();
+        fs::remove_dir(old.join("old")).unwrap();
+        assert!(dirs_equal(old.path(), new.path()).unwrap());
+        let err = changes.apply_staged(old.path()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+
+        assert_eq!(
+            ChangeSet::parse("fs-helper-changes 1\ncreate\tfile:0\ta")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::Encoding
+        );
+    }
+
+    #[test]
+    fn change_set_mem_fs() {
+        let fs = MemFs::new();
+        fs.write("/t/edit.txt", "v1").unwrap();
+        fs.write("/t/gone.txt", "bye").unwrap();
+        fs.symlink("edit.txt", "/t/link").unwrap();
+        let changes = ChangeSet {
+            changes: vec![
+                Change::Delete {
+                    path: PathBuf::from("gone.txt"),
+                    content: Content::File(b"bye".to_vec()),
+                },
+                Change::Rename {
+                    from: PathBuf::from("link"),
+                    to: PathBuf::from("sub/link"),
+                },
+                Change::Modify {
+                    path: PathBuf::from("edit.txt"),
+                    old: Content::File(b"v1".to_vec()),
+                    new: Content::File(b"v2".to_vec()),
+                },
+                Change::Create {
+                    path: PathBuf::from("new/added.txt"),
+                    content: Content::Symlink(PathBuf::from("../edit.txt")),
+                },
+            ],
+        };
+        changes.apply_in(&fs, "/t").unwrap();
+        assert_eq!(fs.read("/t/edit.txt").unwrap(), b"v2");
+        assert_eq!(fs.read("/t/new/added.txt").unwrap(), b"v2");
+        assert_eq!(fs.read_link(Path::new("/t/sub/link")).unwrap(), Path::new("edit.txt"));
+        assert!(fs.symlink_metadata(Path::new("/t/gone.txt")).is_err());
+        let err = changes.apply_in(&fs, "/t").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+
+        changes.invert().apply_staged_in(&fs, "/t").unwrap();
+        assert_eq!(fs.read("/t/edit.txt").unwrap(), b"v1");
+        assert_eq!(fs.read("/t/gone.txt").unwrap(), b"bye");
+        assert!(fs.symlink_metadata(Path::new("/t/new/added.txt")).is_err());
+        // the staging directory is gone
+        assert_eq!(fs.read_dir(Path::new("/t")).unwrap().count(), 5);
+    }
+
+    #[test]
+    fn dependent_changes() {
+        let file = |bytes: &[u8]| Content::File(bytes.to_vec());
+        let changes = ChangeSet {
+            changes: vec![
+                Change::Rename {
+                    from: PathBuf::from("a"),
+                    to: PathBuf::from("b"),
+                },
+                Change::Modify {
+                    path: PathBuf::from("b"),
+                    old: file(b"v1"),
+                    new: file(b"v2"),
+                },
+                Change::Create {
+                    path: PathBuf::from("a"),
+                    content: file(b"new"),
+                },
+                Change::Rename {
+                    from: PathBuf::from("d"),
+                    to: PathBuf::from("e"),
+                },
+                Change::Delete {
+                    path: PathBuf::from("e/x"),
+                    content: file(b"x"),
+                },
+            ],
+        };
+        for staged in [false, true] {
+            let fs = MemFs::new();
+            fs.write("/t/a", "v1").unwrap();
+            fs.write("/t/d/x", "x").unwrap();
+            match staged {
+                false => changes.apply_in(&fs, "/t").unwrap(),
+                true => changes.apply_staged_in(&fs, "/t").unwrap(),
+            }
+            assert_eq!(fs.read("/t/a").unwrap(), b"new");
+            assert_eq!(fs.read("/t/b").unwrap(), b"v2");
+            assert_eq!(fs.read_dir(Path::new("/t/e")).unwrap().count(), 0);
+            // the renamed entry is gone from its old path
+            let err = changes.apply_in(&fs, "/t").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conflict);
+        }
+
+        let fs = MemFs::new();
+        fs.write("/t/a", "v1").unwrap();
+        let mut conflicting = changes.clone();
+        conflicting.changes[1] = Change::Modify {
+            path: PathBuf::from("a"),
+            old: file(b"v1"),
+            new: file(b"v2"),
+        };
+        let err = conflicting.apply_in(&fs, "/t").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert_eq!(fs.read("/t/a").unwrap(), b"v1");
+    }
+
+    #[test]
+    fn rollback_failures_reported() {
+        use crate::changeset::RollbackFailed;
+        use std::error::Error;
+
+        let tree = TreeBuilder::new().file("a", b"v1").file("f", b"").build().unwrap();
+        let changes = ChangeSet {
+            changes: vec![
+                Change::Rename {
+                    from: PathBuf::from("a"),
+                    to: PathBuf::from("b"),
+                },
+                Change::Create {
+                    path: PathBuf::from("a/x"),
+                    content: Content::File(Vec::new()),
+                },
+                // fails, as `f` is a file
+                Change::Create {
+                    path: PathBuf::from("f/g"),
+                    content: Content::File(Vec::new()),
+                },
+            ],
+        };
+        let err = changes.apply(tree.path()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::File);
+        // `a` is left as a directory, so `b` can not be renamed back
+        let failed = err.source().unwrap().downcast_ref::<RollbackFailed>().unwrap();
+        assert_eq!(failed.rollback.len(), 1);
+        assert_eq!(fs::read(tree.join("b")).unwrap(), b"v1");
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{self, Read};
+
+use crate::compare::read_full;
+use crate::hash::Hash;
+
+/// Gear table of the FastCDC rolling hash, generated with splitmix64 so that chunk
+/// boundaries are stable across versions of the crate.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Chunk size limits of the content-defined chunker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkerOptions {
+    pub min_size: usize,
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+impl Default for ChunkerOptions {
+    fn default() -> Self {
+        ChunkerOptions {
+            min_size: 2 * 1024,
+            avg_size: 8 * 1024,
+            max_size: 64 * 1024,
+        }
+    }
+}
+
+/// A content-defined chunk of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Offset of the chunk in the input.
+    pub offset: u64,
+    /// Hash of the chunk data.
+    pub hash: Hash,
+    pub data: Vec<u8>,
+}
+
+/// Splits a stream into variable-size chunks with boundaries defined by the content (FastCDC
+/// with normalized chunking), so that an edit only changes the chunks around it.
+pub struct Chunker<R> {
+    reader: R,
+    options: ChunkerOptions,
+    mask_s: u64,
+    mask_l: u64,
+    buf: Vec<u8>,
+    offset: u64,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    /// Creates a chunker reading from `reader`.
+    pub fn new(reader: R, options: ChunkerOptions) -> Chunker<R> {
+        let options = ChunkerOptions {
+            min_size: options.min_size.max(64),
+            avg_size: options.avg_size.max(options.min_size.max(64)),
+            max_size: options.max_size.max(options.avg_size),
+        };
+        let bits = options.avg_size.ilog2();
+        Chunker {
+            reader,
+            options,
+            // stricter mask before the average size, looser after it
+            mask_s: high_bits_mask(bits + 1),
+            mask_l: high_bits_mask(bits - 1),
+            buf: Vec::with_capacity(options.max_size),
+            offset: 0,
+            eof: false,
+        }
+    }
+
+    fn cut_point(&self) -> usize {
+        let data = &self.buf;
+        let ChunkerOptions {
+            min_size, avg_size, ..
+        } = self.options;
+        if data.len() <= min_size {
+            return data.len();
+        }
+        let normal = avg_size.min(data.len());
+        let mut fp = 0u64;
+        for (i, &b) in data.iter().enumerate().skip(min_size) {
+            fp = (fp << 1).wrapping_add(GEAR[b as usize]);
+            let mask = if i < normal { self.mask_s } else { self.mask_l };
+            if fp & mask == 0 {
+                return i + 1;
+            }
+        }
+        data.len()
+    }
+}
+
+fn high_bits_mask(bits: u32) -> u64 {
+    !0u64 << (64 - bits.clamp(1, 63))
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Chunk>;
+
+    /// Returns the next chunk.
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.eof && self.buf.len() < self.options.max_size {
+            let filled = self.buf.len();
+            self.buf.resize(self.options.max_size, 0);
+            match read_full(&mut self.reader, &mut self.buf[filled..]) {
+                Ok(n) => {
+                    self.buf.truncate(filled + n);
+                    self.eof = filled + n < self.options.max_size;
+                }
+                Err(e) => {
+                    self.buf.truncate(filled);
+                    return Some(Err(e));
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        let cut = self.cut_point();
+        let data: Vec<u8> = self.buf.drain(..cut).collect();
+        let chunk = Chunk {
+            offset: self.offset,
+            hash: Hash::of(&data),
+            data,
+        };
+        self.offset += cut as u64;
+        Some(Ok(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunk::{Chunker, ChunkerOptions};
+    use std::collections::HashSet;
+
+    fn data(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_are_stable_across_edits() {
+        let options = ChunkerOptions::default();
+        let old = data(500_000, 42);
+        let mut new = old.clone();
+        new.splice(250_000..250_000, data(100, 7));
+
+        let chunks: Vec<_> = Chunker::new(&old[..], options).map(|c| c.unwrap()).collect();
+        let joined: Vec<u8> = chunks.iter().flat_map(|c| c.data.clone()).collect();
+        assert_eq!(joined, old);
+        assert!(chunks.iter().all(|c| c.data.len() <= options.max_size));
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|c| c.data.len() >= options.min_size));
+
+        let old_hashes: HashSet<_> = chunks.iter().map(|c| c.hash).collect();
+        let new_chunks: Vec<_> = Chunker::new(&new[..], options).map(|c| c.unwrap()).collect();
+        let changed = new_chunks.iter().filter(|c| !old_hashes.contains(&c.hash)).count();
+        assert!(changed <= 3, "{} chunks changed", changed);
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::open::open_read_shared;
+use crate::result::Result;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Compares the contents of two files.
+/// Returns early if the sizes differ, otherwise compares the files chunk by chunk
+/// and stops at the first difference.
+pub fn files_equal<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> Result<bool> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut a = open_read_shared(a)?;
+    let mut b = open_read_shared(b)?;
+    let mut buf_a = vec![0u8; CHUNK_SIZE];
+    let mut buf_b = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = read_full(&mut a, &mut buf_a)?;
+        let m = read_full(&mut b, &mut buf_b)?;
+        if buf_a[..n] != buf_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads until `buf` is full or the end of file is reached.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::files_equal;
+    use crate::fixture::TreeBuilder;
+
+    #[test]
+    fn compare_files() {
+        let big = vec![7u8; 200_000];
+        let mut changed = big.clone();
+        changed[150_000] = 8;
+        let tree = TreeBuilder::new()
+            .file("a", &big)
+            .file("b", &big)
+            .file("c", &changed)
+            .file("d", b"short")
+            .build()
+            .unwrap();
+        let dir = tree.path();
+
+        assert!(files_equal(dir.join("a"), dir.join("b")).unwrap());
+        assert!(!files_equal(dir.join("a"), dir.join("c")).unwrap());
+        assert!(!files_equal(dir.join("a"), dir.join("d")).unwrap());
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::{Path, MAIN_SEPARATOR};
+
+use crate::result::Result;
+
+/// Returns the names of the children of `base` starting with `prefix`, for path completion
+/// in command lines and REPLs. Directories, and links to directories, end with the path
+/// separator. Like shells do, hidden entries (named with a leading dot, or with the hidden
+/// attribute on Windows) are only listed when `prefix` starts with a dot.
+/// Matching ignores case on Windows and macOS. Names are sorted ignoring case; names that
+/// are not valid Unicode are left out.
+///
+/// # Arguments:
+///
+/// * `base` - directory to list.
+/// * `prefix` - beginning of the name typed so far.
+pub fn list_dir_completions<P: AsRef<Path>>(base: P, prefix: &str) -> Result<Vec<String>> {
+    let show_hidden = prefix.starts_with('.');
+    let prefix = fold(prefix);
+    let mut names = Vec::new();
+    for entry in fs::read_dir(base)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !fold(&name).starts_with(&prefix) || (!show_hidden && is_hidden(&entry, &name)) {
+            continue;
+        }
+        // links are followed, so a link to a directory completes like one
+        let is_dir = fs::metadata(entry.path()).is_ok_and(|meta| meta.is_dir());
+        names.push((name, is_dir));
+    }
+    names.sort_by(|(a, _), (b, _)| a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(b)));
+    let names = names
+        .into_iter()
+        .map(|(name, is_dir)| match is_dir {
+            true => format!("{}{}", name, MAIN_SEPARATOR),
+            false => name,
+        })
+        .collect();
+    Ok(names)
+}
+
+/// Folds the case of a name where the usual filesystems ignore it.
+fn fold(name: &str) -> String {
+    if cfg!(any(windows, target_os = "macos")) {
+        name.to_lowercase()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(windows)]
+fn is_hidden(entry: &fs::DirEntry, name: &str) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    name.starts_with('.')
+        || entry
+            .metadata()
+            .is_ok_and(|meta| meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn is_hidden(_entry: &fs::DirEntry, name: &str) -> bool {
+    name.starts_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::complete::list_dir_completions;
+    use crate::fixture::TreeBuilder;
+    use std::path::MAIN_SEPARATOR;
+
+    #[test]
+    fn dir_completions() {
+        let tree = TreeBuilder::new()
+            .file("src.txt", b"")
+            .file("Setup.py", b"")
+            .file("src/main.rs", b"")
+            .file(".secret", b"")
+            .file("other", b"")
+            .build()
+            .unwrap();
+        let dir = format!("src{}", MAIN_SEPARATOR);
+        let expected: Vec<&str> = if cfg!(any(windows, target_os = "macos")) {
+            vec!["Setup.py", &dir, "src.txt"]
+        } else {
+            vec![&dir, "src.txt"]
+        };
+        assert_eq!(list_dir_completions(tree.path(), "s").unwrap(), expected);
+        assert_eq!(list_dir_completions(tree.path(), "").unwrap().len(), 4);
+        assert_eq!(list_dir_completions(tree.path(), ".").unwrap(), [".secret"]);
+    }
+}
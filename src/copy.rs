@@ -0,0 +1,1258 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::acl::copy_acl;
+use crate::atomic::temp_path_for;
+use crate::cancel::{CancelToken, Cancelled};
+use crate::case::find_case_collisions_in;
+use crate::direct;
+use crate::durability::Durability;
+use crate::entry::Entry;
+use crate::hash::{Hash, Hasher};
+use crate::open::open_read_shared;
+use crate::paths::{long_path, simplify_verbatim};
+use crate::progress::{Operation, ProgressEvent, ProgressSink};
+use crate::result::{Error, ErrorKind, Result};
+use crate::rules::RuleSet;
+use crate::stats::{IoStats, Phase, Recorder};
+use crate::streams;
+use crate::times::set_mtime;
+use crate::vfs::{FileKind, Fs, ReadFs, RealFs};
+use crate::visit::{walk, walk_in, Control, Visitor};
+
+/// Minimum buffer size of direct I/O copies, which bypass the read-ahead of the kernel.
+const DIRECT_BUF_SIZE: usize = 1024 * 1024;
+
+/// Number of bytes before the end of a partial copy compared with the source before the
+/// copy is resumed, unless verifying by hash, which compares all of them.
+const RESUME_CHECK_LEN: u64 = 1024 * 1024;
+
+/// How [`copy_dir`] recreates symbolic links. Links are never followed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Copy links with their targets unchanged.
+    #[default]
+    Preserve,
+    /// Like `Preserve`, but absolute links pointing inside the source tree are rewritten
+    /// as relative links, so the copy stays self-contained.
+    MakeRelative,
+}
+
+/// How [`copy_dir`] checks each copied file once it is written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Verify {
+    /// Trust the copy.
+    #[default]
+    Off,
+    /// Compare the sizes of the source and the copy.
+    Size,
+    /// Compare the SHA-256 hash of the data read from the source, computed while copying,
+    /// with the hash of the copy read back from the destination. When the kernel or direct
+    /// I/O copies the data, the source is hashed separately.
+    Hash,
+}
+
+/// A copied file that differs from its source, the cause of errors of kind
+/// `ErrorKind::Verification`; get it with `error.source()` and `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyFailure {
+    /// The copy does not have the size of the source.
+    Size { path: PathBuf, expected: u64, actual: u64 },
+    /// The contents of the copy do not have the hash of the source.
+    Hash { path: PathBuf, expected: Hash, actual: Hash },
+}
+
+impl VerifyFailure {
+    /// Returns the path of the copy.
+    pub fn path(&self) -> &Path {
+        match self {
+            VerifyFailure::Size { path, .. } | VerifyFailure::Hash { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for VerifyFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyFailure::Size {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: copied {} bytes instead of {}",
+                path.display(),
+                actual,
+                expected
+            ),
+            VerifyFailure::Hash {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: hash {} of the copy differs from {}",
+                path.display(),
+                actual,
+                expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyFailure {}
+
+/// Tuning of how file contents are copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoHints {
+    /// Size of the buffer used for reading and writing.
+    pub buffer_size: usize,
+    /// Lets the kernel copy the data (`copy_file_range` on Linux, which can also share
+    /// extents on Btrfs and XFS or copy server-side on NFS); falls back to the buffer.
+    pub fast_copy: bool,
+    /// Tells the kernel the copied data will not be needed again
+    /// (`posix_fadvise(POSIX_FADV_DONTNEED)` on Linux), so a bulk copy does not evict the
+    /// page cache of other processes. Pages are dropped as far as the kernel allows.
+    pub drop_cache: bool,
+    /// Reads and writes with direct (unbuffered) I/O, bypassing the page cache entirely
+    /// (`O_DIRECT` on Linux); for very large files. Buffers are aligned internally.
+    /// Falls back to the other settings where direct I/O is not supported.
+    pub direct_io: bool,
+    /// Reserves the space of each file before writing it (`fallocate` on Linux,
+    /// `SetFileInformationByHandle` on Windows), which reduces fragmentation and fails early
+    /// when the destination is full. Skipped when the kernel copies the data.
+    pub preallocate: bool,
+}
+
+impl Default for IoHints {
+    fn default() -> IoHints {
+        IoHints {
+            buffer_size: 64 * 1024,
+            fast_copy: true,
+            drop_cache: false,
+            direct_io: false,
+            preallocate: true,
+        }
+    }
+}
+
+/// Settings of [`copy_dir`].
+#[derive(Clone, Default)]
+pub struct CopyOptions {
+    /// How symbolic links are copied.
+    pub symlinks: Symlinks,
+    /// If set, receives `BytesCopied` events and a final `OperationFinished`.
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    /// If set, the copy fails with an error of kind `ErrorKind::Conflict`, before anything
+    /// is written, when the source has names differing only by case.
+    pub check_case_collisions: bool,
+    /// How file contents are read and written.
+    pub io_hints: IoHints,
+    /// What is flushed to disk: copied files, and with `Durability::DataAndDirs` every
+    /// directory of the copy and the parent of `dst`.
+    pub durability: Durability,
+    /// On macOS, also copies the extended attributes of files and directories with
+    /// `copyfile(3)`: resource forks, Finder flags and quarantine information, like a
+    /// Finder copy. Ignored on other platforms.
+    pub mac_metadata: bool,
+    /// On Windows, also copies the alternate data streams of files and directories, which
+    /// are otherwise dropped. Ignored on other platforms.
+    pub data_streams: bool,
+    /// Also copies ACLs (POSIX ACLs on Linux, DACLs on Windows). The copy fails with an error
+    /// if an ACL can not be stored at the destination; check [`acls_supported`] first.
+    ///
+    /// [`acls_supported`]: crate::acls_supported
+    pub acls: bool,
+    /// If set, only entries selected by these include/exclude rules are copied;
+    /// the rules apply to paths relative to `src`.
+    pub rules: Option<Arc<RuleSet>>,
+    /// How copied files are checked. A file that differs from its source fails the copy
+    /// with an error of kind `ErrorKind::Verification` caused by a [`VerifyFailure`].
+    pub verify: Verify,
+    /// If set, the copy stops once the token is cancelled, e.g. by ctrl-c with
+    /// `CancelToken::on_signals`, and fails with an error of kind `ErrorKind::Cancelled`
+    /// caused by a [`Cancelled`] report of what was copied. The file being copied is
+    /// finished first, so no file is left half written.
+    pub cancel: Option<CancelToken>,
+    /// Resumes an interrupted copy into the same `dst`: files already copied are skipped
+    /// and partial copies are continued. A complete copy gets the modification time of its
+    /// source, so files are skipped when their size and modification time match (and their
+    /// hash, with `Verify::Hash`). A shorter file is continued from its end if its last
+    /// bytes match the source (all of them, with `Verify::Hash`); other files are copied
+    /// again.
+    pub resume: bool,
+}
+
+struct CopyTree<'a> {
+    src_fs: &'a dyn ReadFs,
+    dst_fs: &'a dyn Fs,
+    /// Set when both trees are on the real filesystem: file contents are then copied
+    /// between open files, with the I/O hints, and the platform metadata is copied too.
+    native: bool,
+    src: &'a Path,
+    dst: &'a Path,
+    options: &'a CopyOptions,
+    stats: Recorder,
+    cancelled: bool,
+    error: Option<Error>,
+}
+
+impl CopyTree<'_> {
+    fn target(&self, path: &Path) -> PathBuf {
+        self.io_path(&self.dst.join(path.strip_prefix(self.src).unwrap()))
+    }
+
+    /// Returns the path to open a file of the real filesystem with, which may be too long
+    /// without the verbatim prefix on Windows.
+    fn io_path(&self, path: &Path) -> PathBuf {
+        match self.native {
+            true => long_path(path),
+            false => path.to_path_buf(),
+        }
+    }
+
+    /// Checks whether the rules, if any, select a path of the source tree.
+    fn selected(&self, path: &Path, is_dir: bool) -> bool {
+        match &self.options.rules {
+            Some(rules) => rules.is_included(path.strip_prefix(self.src).unwrap(), is_dir),
+            None => true,
+        }
+    }
+
+    /// Checks whether the copy was cancelled; checked before each file and directory.
+    fn cancel_requested(&mut self) -> bool {
+        let token = self.options.cancel.as_ref();
+        self.cancelled = token.is_some_and(|token| token.is_cancelled());
+        self.cancelled
+    }
+
+    fn run<F: FnOnce(&Self) -> Result<()>>(&mut self, step: F) -> Control {
+        match step(self) {
+            Ok(()) => Control::Continue,
+            Err(e) => {
+                self.error = Some(e);
+                Control::Stop
+            }
+        }
+    }
+
+    /// Reports copied bytes of the file `to`.
+    fn copied(&self, to: &Path, bytes: u64) {
+        self.stats.read(bytes);
+        self.stats.written(bytes);
+        if let Some(progress) = &self.options.progress {
+            progress.event(&ProgressEvent::BytesCopied { path: to, bytes });
+        }
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<()> {
+        if !self.native {
+            return self.copy_file_in(from, to);
+        }
+        let from = &long_path(from);
+        let start = match self.options.resume {
+            true => match self.stats.time(Phase::Verify, || self.resume_offset(from, to))? {
+                Some(start) => start,
+                None => return Ok(()),
+            },
+            false => 0,
+        };
+        let (writer, expected) =
+            self.stats.time(Phase::Data, || self.copy_data(from, to, start))?;
+        self.finish_file(from, to, &writer)?;
+        self.stats.file();
+        self.stats.time(Phase::Verify, || self.verify_file(from, to, expected))?;
+        if self.options.resume {
+            // marks the copy as complete, after it is verified
+            let modified = fs::metadata(from)?.modified()?;
+            self.stats.time(Phase::Metadata, || set_mtime(to, modified))?;
+        }
+        Ok(())
+    }
+
+    /// Copies a file between the filesystems of the trees, through the buffer, with its
+    /// permission bits where both filesystems support them.
+    fn copy_file_in(&self, from: &Path, to: &Path) -> Result<()> {
+        let expected = self.stats.time(Phase::Data, || -> Result<Option<Hash>> {
+            let mut reader = self.src_fs.open(from)?;
+            let mut writer = self.dst_fs.create(to)?;
+            let mut hasher = (self.options.verify == Verify::Hash).then(Hasher::new);
+            let mut buf = vec![0u8; self.options.io_hints.buffer_size.max(1)];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                writer.write_all(&buf[..n])?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&buf[..n]);
+                }
+                self.copied(to, n as u64);
+            }
+            writer.flush()?;
+            Ok(hasher.map(Hasher::finish))
+        })?;
+        self.stats.time(Phase::Metadata, || -> Result<()> {
+            let copied = self.src_fs.mode(from).and_then(|mode| self.dst_fs.set_mode(to, mode));
+            match copied {
+                Err(e) if e.kind() != io::ErrorKind::Unsupported => Err(e.into()),
+                _ => Ok(()),
+            }
+        })?;
+        self.stats.file();
+        self.stats.time(Phase::Verify, || self.verify_file(from, to, expected))
+    }
+    /// Returns where to resume copying `from` to `to`: `None` if the copy is complete,
+    /// the length of a partial copy that matches the source, or 0.
+    fn resume_offset(&self, from: &Path, to: &Path) -> Result<Option<u64>> {
+        let copy = match self.dst_fs.symlink_metadata(to) {
+            Ok(meta) if meta.kind == FileKind::File => meta,
+            _ => return Ok(Some(0)),
+        };
+        let source = self.src_fs.metadata(from)?;
+        let by_hash = self.options.verify == Verify::Hash;
+        let same_time = copy.modified.is_some() && copy.modified == source.modified;
+        if copy.len == source.len && same_time {
+            let complete = !by_hash || Hash::of_file(from)? == Hash::of_file(to)?;
+            return Ok((!complete).then_some(0));
+        }
+        if copy.len == 0 || copy.len > source.len {
+            return Ok(Some(0));
+        }
+        let check = if by_hash { copy.len } else { copy.len.min(RESUME_CHECK_LEN) };
+        let range = |path: &Path| -> Result<Hash> {
+            let mut file = open_read_shared(path)?;
+            file.seek(SeekFrom::Start(copy.len - check))?;
+            Ok(Hash::of_reader(file.take(check))?)
+        };
+        let matches = range(from)? == range(to)?;
+        Ok(Some(if matches { copy.len } else { 0 }))
+    }
+
+    /// Copies the contents of a file from offset `start`, keeping the first `start` bytes
+    /// of the copy; returns the copy and, when verifying by hash, the hash of the data read
+    /// if it all went through the buffer.
+    fn copy_data(&self, from: &Path, to: &Path, start: u64) -> Result<(fs::File, Option<Hash>)> {
+        let hints = &self.options.io_hints;
+        let copied = |bytes: u64| self.copied(to, bytes);
+        if hints.direct_io && start == 0 {
+            if let Some(writer) = self.copy_direct(from, to, copied)? {
+                return Ok((writer, None));
+            }
+        }
+        let mut reader = open_read_shared(from)?;
+        let mut writer = match start {
+            0 => fs::File::create(to)?,
+            _ => {
+                let mut writer = fs::OpenOptions::new().write(true).open(to)?;
+                reader.seek(SeekFrom::Start(start))?;
+                writer.seek(SeekFrom::Start(start))?;
+                writer
+            }
+        };
+        // copy_file_range starts at the file positions
+        let fast = hints.fast_copy && sys::copy_range(&reader, &writer, hints, copied)?;
+        let hashed = !fast && start == 0 && self.options.verify == Verify::Hash;
+        let mut hasher = hashed.then(Hasher::new);
+        if !fast {
+            self.preallocate(&reader, &writer)?;
+            let mut buf = vec![0u8; hints.buffer_size.max(1)];
+            let mut offset = start;
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                writer.write_all(&buf[..n])?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&buf[..n]);
+                }
+                if hints.drop_cache {
+                    sys::drop_cache(&reader, offset, n as u64);
+                }
+                offset += n as u64;
+                copied(n as u64);
+            }
+        }
+        Ok((writer, hasher.map(Hasher::finish)))
+    }
+
+    /// Checks a copied file as set by the options.
+    ///
+    /// # Arguments:
+    ///
+    /// * `from` - source file.
+    /// * `to` - copy.
+    /// * `expected` - hash of the data read from the source while copying, if computed.
+    fn verify_file(&self, from: &Path, to: &Path, expected: Option<Hash>) -> Result<()> {
+        let failure = match self.options.verify {
+            Verify::Off => None,
+            Verify::Size => {
+                let expected = self.src_fs.metadata(from)?.len;
+                let actual = self.dst_fs.metadata(to)?.len;
+                (expected != actual).then(|| VerifyFailure::Size {
+                    path: to.to_path_buf(),
+                    expected,
+                    actual,
+                })
+            }
+            Verify::Hash => {
+                let expected = match expected {
+                    Some(expected) => expected,
+                    None => {
+                        self.stats.read(self.src_fs.metadata(from)?.len);
+                        self.hash_of(self.src_fs, from)?
+                    }
+                };
+                self.stats.read(self.dst_fs.metadata(to)?.len);
+                let actual = self.hash_of(self.dst_fs, to)?;
+                (expected != actual).then(|| VerifyFailure::Hash {
+                    path: to.to_path_buf(),
+                    expected,
+                    actual,
+                })
+            }
+        };
+        match failure {
+            Some(failure) => Err(Error::new(ErrorKind::Verification, failure)),
+            None => Ok(()),
+        }
+    }
+
+    /// Hashes a file of one of the trees; a file of the real filesystem is opened so that
+    /// it can still be renamed or deleted on Windows.
+    fn hash_of(&self, fs: &dyn ReadFs, path: &Path) -> Result<Hash> {
+        match self.native {
+            true => Hash::of_file(path),
+            false => Ok(Hash::of_reader(fs.open(path)?)?),
+        }
+    }
+
+    /// Reserves space for a copy of `reader` in `writer`, if enabled. Filesystems without
+    /// support are ignored; running out of space is an error.
+    fn preallocate(&self, reader: &fs::File, writer: &fs::File) -> Result<()> {
+        if self.options.io_hints.preallocate {
+            let len = reader.metadata()?.len();
+            if len > 0 {
+                sys::preallocate(writer, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the permissions and flushes a copied file.
+    fn finish_file(&self, from: &Path, to: &Path, writer: &fs::File) -> Result<()> {
+        self.stats.time(Phase::Metadata, || -> Result<()> {
+            writer.set_permissions(fs::metadata(from)?.permissions())?;
+            if self.options.mac_metadata {
+                mac::copy_metadata(from, to)?;
+            }
+            if self.options.data_streams {
+                streams::copy_streams(from, to)?;
+            }
+            if self.options.acls {
+                // after the permissions, which would otherwise overwrite the ACL mask
+                copy_acl(from, to)?;
+            }
+            Ok(())
+        })?;
+        self.stats.time(Phase::Sync, || -> Result<()> {
+            self.options.durability.sync_file(writer)?;
+            if self.options.io_hints.drop_cache {
+                // after flushing, so the written pages are clean and can be dropped
+                sys::drop_cache(writer, 0, 0);
+            }
+            Ok(())
+        })
+    }
+
+    /// Copies the contents of a file with direct I/O; returns the copy, or `None` if direct
+    /// I/O is not supported for these files.
+    fn copy_direct<F: FnMut(u64)>(
+        &self,
+        from: &Path,
+        to: &Path,
+        copied: F,
+    ) -> Result<Option<fs::File>> {
+        let mut reader = match direct::open_direct(from)? {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        let mut writer = match direct::create_direct(to)? {
+            Some(writer) => writer,
+            None => return Ok(None),
+        };
+        self.preallocate(&reader, &writer)?;
+        let buffer_size = self.options.io_hints.buffer_size.max(DIRECT_BUF_SIZE);
+        direct::copy(&mut reader, &mut writer, buffer_size, copied)?;
+        Ok(Some(writer))
+    }
+
+    fn copy_symlink(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut target = self.src_fs.read_link(&self.io_path(from))?;
+        if self.options.symlinks == Symlinks::MakeRelative {
+            if let Some(relative) = relative_target(self.src, from, &target) {
+                target = relative;
+            }
+        }
+        let existing = match self.dst_fs.symlink_metadata(to) {
+            Ok(meta) => Some(meta.kind),
+            Err(_) => None,
+        };
+        match existing {
+            None => self.dst_fs.symlink(&target, to)?,
+            Some(FileKind::Symlink)
+                if self.options.resume
+                    && self.dst_fs.read_link(to).is_ok_and(|link| link == target) =>
+            {
+                return Ok(())
+            }
+            // replaces the entry in one step, through a link with a temporary name
+            Some(_) => {
+                let temp = temp_path_for(to);
+                self.dst_fs.symlink(&target, &temp)?;
+                if let Err(e) = self.dst_fs.rename(&temp, to) {
+                    let _ = self.dst_fs.remove_file(&temp);
+                    return Err(e.into());
+                }
+            }
+        }
+        self.stats.file();
+        Ok(())
+    }
+}
+
+impl Visitor for CopyTree<'_> {
+    fn enter_dir(&mut self, dir: &Path, depth: usize) -> Control {
+        if self.cancel_requested() {
+            return Control::Stop;
+        }
+        if depth > 0 && !self.selected(dir, true) {
+            return Control::Prune;
+        }
+        let target = self.target(dir);
+        self.run(|copy| {
+            copy.stats.time(Phase::Metadata, || {
+                copy.dst_fs.create_dir_all(&target)?;
+                if !copy.native {
+                    return Ok(());
+                }
+                let dir = &long_path(dir);
+                if copy.options.mac_metadata {
+                    mac::copy_metadata(dir, &target)?;
+                }
+                if copy.options.data_streams {
+                    streams::copy_streams(dir, &target)?;
+                }
+                if copy.options.acls {
+                    copy_acl(dir, &target)?;
+                }
+                Ok(())
+            })
+        })
+    }
+
+    fn file(&mut self, entry: &Entry) -> Control {
+        if self.cancel_requested() {
+            return Control::Stop;
+        }
+        if !self.selected(entry.path(), false) {
+            return Control::Continue;
+        }
+        let to = self.target(entry.path());
+        self.run(|copy| {
+            if entry.metadata()?.kind == FileKind::Symlink {
+                copy.stats.time(Phase::Metadata, || copy.copy_symlink(entry.path(), &to))
+            } else {
+                copy.copy_file(entry.path(), &to)
+            }
+        })
+    }
+
+    fn leave_dir(&mut self, dir: &Path, _depth: usize) -> Control {
+        if !self.native {
+            return Control::Continue;
+        }
+        let target = self.target(dir);
+        self.run(|copy| {
+            copy.stats.time(Phase::Sync, || Ok(copy.options.durability.sync_dir(&target)?))
+        })
+    }
+
+    fn error(&mut self, _path: &Path, error: Error) -> Control {
+        self.error = Some(error);
+        Control::Stop
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_int, c_uint};
+    use std::fs;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    use crate::copy::IoHints;
+
+    const POSIX_FADV_DONTNEED: c_int = 4;
+    const FALLOC_FL_KEEP_SIZE: c_int = 1;
+    // EPERM, EXDEV, EINVAL, ENOSYS, EOPNOTSUPP: the kernel can not copy between the files
+    const UNSUPPORTED: [i32; 5] = [1, 18, 22, 38, 95];
+    // EINVAL, ENOSYS, EOPNOTSUPP: the filesystem can not preallocate
+    const NO_PREALLOCATION: [i32; 3] = [22, 38, 95];
+
+    extern "C" {
+        fn copy_file_range(
+            fd_in: c_int,
+            off_in: *mut i64,
+            fd_out: c_int,
+            off_out: *mut i64,
+            len: usize,
+            flags: c_uint,
+        ) -> isize;
+        fn posix_fadvise(fd: c_int, offset: i64, len: i64, advice: c_int) -> c_int;
+        fn fallocate(fd: c_int, mode: c_int, offset: i64, len: i64) -> c_int;
+    }
+
+    /// Copies the rest of `reader` to `writer` in the kernel. Returns false, having copied
+    /// nothing, if the kernel can not copy between these files.
+    pub(super) fn copy_range<F: Fn(u64)>(
+        reader: &fs::File,
+        writer: &fs::File,
+        hints: &IoHints,
+        copied: F,
+    ) -> io::Result<bool> {
+        // larger requests mean fewer system calls; progress is still reported regularly
+        let chunk = hints.buffer_size.max(8 * 1024 * 1024);
+        let mut offset = 0;
+        loop {
+            // SAFETY: both descriptors are open; null offsets use and update the file positions.
+            let n = unsafe {
+                copy_file_range(
+                    reader.as_raw_fd(),
+                    ptr::null_mut(),
+                    writer.as_raw_fd(),
+                    ptr::null_mut(),
+                    chunk,
+                    0,
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                let unsupported = e
+                    .raw_os_error()
+                    .is_some_and(|code| UNSUPPORTED.contains(&code));
+                if unsupported && offset == 0 {
+                    return Ok(false);
+                }
+                return Err(e);
+            }
+            if n == 0 {
+                // procfs and sysfs files, and some filesystems across mounts, report no data
+                // for files that have some: copied through the buffer instead
+                return Ok(offset > 0);
+            }
+            if hints.drop_cache {
+                drop_cache(reader, offset, n as u64);
+            }
+            offset += n as u64;
+            copied(n as u64);
+        }
+    }
+
+    /// Advises the kernel to drop cached pages of a range (`len` 0: to the end of the file).
+    pub(super) fn drop_cache(file: &fs::File, offset: u64, len: u64) {
+        // SAFETY: the descriptor is open; the advice is only a hint.
+        unsafe {
+            posix_fadvise(
+                file.as_raw_fd(),
+                offset as i64,
+                len as i64,
+                POSIX_FADV_DONTNEED,
+            );
+        }
+    }
+
+    /// Allocates `len` bytes for `file` without changing its size, so a copy that ends
+    /// early does not leave a padded file.
+    pub(super) fn preallocate(file: &fs::File, len: u64) -> io::Result<()> {
+        loop {
+            // SAFETY: the descriptor is open.
+            let ret = unsafe { fallocate(file.as_raw_fd(), FALLOC_FL_KEEP_SIZE, 0, len as i64) };
+            if ret == 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(4) => {} // EINTR
+                Some(code) if NO_PREALLOCATION.contains(&code) => return Ok(()),
+                _ => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::fs;
+    use std::io;
+
+    use crate::copy::IoHints;
+
+    pub(super) fn copy_range<F: Fn(u64)>(
+        _reader: &fs::File,
+        _writer: &fs::File,
+        _hints: &IoHints,
+        _copied: F,
+    ) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    pub(super) fn drop_cache(_file: &fs::File, _offset: u64, _len: u64) {}
+
+    #[cfg(windows)]
+    pub(super) fn preallocate(file: &fs::File, len: u64) -> io::Result<()> {
+        use std::ffi::c_void;
+        use std::os::windows::io::AsRawHandle;
+
+        const FILE_ALLOCATION_INFO: i32 = 5;
+
+        #[repr(C)]
+        struct FileAllocationInfo {
+            allocation_size: i64,
+        }
+
+        extern "system" {
+            fn SetFileInformationByHandle(
+                file: *mut c_void,
+                class: i32,
+                info: *const c_void,
+                size: u32,
+            ) -> i32;
+        }
+
+        let info = FileAllocationInfo {
+            allocation_size: len as i64,
+        };
+        // SAFETY: the handle is open and `info` is a valid FILE_ALLOCATION_INFO.
+        let ok = unsafe {
+            SetFileInformationByHandle(
+                file.as_raw_handle(),
+                FILE_ALLOCATION_INFO,
+                &info as *const FileAllocationInfo as *const c_void,
+                std::mem::size_of::<FileAllocationInfo>() as u32,
+            )
+        };
+        if ok == 0 {
+            let e = io::Error::last_os_error();
+            // ERROR_DISK_FULL
+            if e.raw_os_error() == Some(112) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    pub(super) fn preallocate(_file: &fs::File, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod mac {
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+
+    // extended attributes, which hold resource forks, Finder info and quarantine flags
+    const COPYFILE_XATTR: u32 = 1 << 2;
+    const COPYFILE_NOFOLLOW: u32 = (1 << 18) | (1 << 19);
+
+    extern "C" {
+        fn copyfile(from: *const c_char, to: *const c_char, state: *mut c_void, flags: u32)
+            -> c_int;
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Copies the extended attributes of `from` to `to`.
+    pub(super) fn copy_metadata(from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (c_path(from)?, c_path(to)?);
+        // SAFETY: both paths are valid C strings; no state object is used.
+        let ret = unsafe {
+            copyfile(
+                from.as_ptr(),
+                to.as_ptr(),
+                ptr::null_mut(),
+                COPYFILE_XATTR | COPYFILE_NOFOLLOW,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod mac {
+    use std::io;
+    use std::path::Path;
+
+    pub(super) fn copy_metadata(_from: &Path, _to: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the target of the link `link` relative to its directory, if it is an absolute
+/// path within `root`.
+fn relative_target(root: &Path, link: &Path, target: &Path) -> Option<PathBuf> {
+    let inside = target.strip_prefix(root).ok()?;
+    if !target.is_absolute() || inside.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    let link_dir: Vec<_> = link
+        .parent()?
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .collect();
+    let inside: Vec<_> = inside.components().collect();
+    let common = link_dir
+        .iter()
+        .zip(&inside)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..link_dir.len() {
+        relative.push("..");
+    }
+    relative.extend(&inside[common..]);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
+/// Returns the canonical form of a path that may not exist yet: its nearest existing
+/// ancestor is canonicalized and the missing components appended.
+fn canonicalize_missing(fs: &dyn ReadFs, path: &Path) -> io::Result<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        match fs.canonicalize(existing) {
+            Ok(canonical) => return Ok(canonical.join(missing.iter().rev().collect::<PathBuf>())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match (existing.file_name(), existing.parent()) {
+                    (Some(name), Some(parent)) => {
+                        missing.push(name);
+                        existing = match parent.as_os_str().is_empty() {
+                            true => Path::new("."),
+                            false => parent,
+                        };
+                    }
+                    _ => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Copies the tree under `src` to `dst`, creating `dst` and its subdirectories as needed.
+/// Regular files are copied with their permissions; symbolic links are recreated as links.
+/// Stops at the first error. Returns the bytes copied and the time spent in each phase.
+/// Fails with an I/O error of kind `InvalidInput` if `dst` is within `src`.
+///
+/// # Arguments:
+///
+/// * `src` - source directory.
+/// * `dst` - destination directory.
+/// * `options` - copy settings.
+pub fn copy_dir<S: AsRef<Path>, D: AsRef<Path>>(
+    src: S,
+    dst: D,
+    options: &CopyOptions,
+) -> Result<IoStats> {
+    copy_tree(Arc::new(RealFs), src.as_ref(), &RealFs, dst.as_ref(), options, true)
+}
+
+/// Copies the tree under `src` of one filesystem to `dst` of another (or the same),
+/// e.g. between [`MemFs`](crate::MemFs) trees in tests, like [`copy_dir`]. File contents go
+/// through the buffer of the I/O hints and permission bits are copied where both
+/// filesystems support them. The other I/O hints, `durability`, `mac_metadata`,
+/// `data_streams` and `acls` need open files of the real filesystem and are ignored;
+/// `resume` is not supported. Fails like [`copy_dir`] if `dst` is within `src` (compared
+/// as paths, even on different filesystems).
+///
+/// # Arguments:
+///
+/// * `src_fs` - filesystem holding the source tree.
+/// * `src` - source directory.
+/// * `dst_fs` - filesystem to copy to.
+/// * `dst` - destination directory.
+/// * `options` - copy settings.
+pub fn copy_dir_in<S: AsRef<Path>, D: AsRef<Path>>(
+    src_fs: Arc<dyn ReadFs>,
+    src: S,
+    dst_fs: Arc<dyn Fs>,
+    dst: D,
+    options: &CopyOptions,
+) -> Result<IoStats> {
+    if options.resume {
+        let message = "resuming a copy needs the real filesystem";
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message).into());
+    }
+    copy_tree(src_fs, src.as_ref(), &*dst_fs, dst.as_ref(), options, false)
+}
+
+fn copy_tree(
+    src_fs: Arc<dyn ReadFs>,
+    src: &Path,
+    dst_fs: &dyn Fs,
+    dst: &Path,
+    options: &CopyOptions,
+    native: bool,
+) -> Result<IoStats> {
+    let src = simplify_verbatim(src_fs.canonicalize(src)?);
+    let dst = &simplify_verbatim(canonicalize_missing(dst_fs, dst)?);
+    // the copy would otherwise be copied again, endlessly
+    if dst.starts_with(&src) {
+        let message = format!("{}: destination is within the source", dst.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+    }
+    if options.check_case_collisions {
+        if let Some(set) = find_case_collisions_in(&*src_fs, &src)?.first() {
+            let names: Vec<_> = set.iter().map(|path| path.display().to_string()).collect();
+            let message = format!("names differ only by case: {}", names.join(", "));
+            return Err(Error::new(ErrorKind::Conflict, message));
+        }
+    }
+    let mut visitor = CopyTree {
+        src_fs: &*src_fs,
+        dst_fs,
+        native,
+        src: &src,
+        dst,
+        options,
+        stats: Recorder::start(),
+        cancelled: false,
+        error: None,
+    };
+    match native {
+        true => walk(&src, &mut visitor)?,
+        false => walk_in(Arc::clone(&src_fs), &src, &mut visitor)?,
+    }
+    if let Some(e) = visitor.error {
+        return Err(e);
+    }
+    if visitor.cancelled {
+        let cancelled = Cancelled {
+            stats: visitor.stats.stats(),
+        };
+        return Err(Error::new(ErrorKind::Cancelled, cancelled));
+    }
+    if let Some(parent) = dst.parent().filter(|_| native) {
+        if !parent.as_os_str().is_empty() {
+            visitor
+                .stats
+                .time(Phase::Sync, || options.durability.sync_dir(parent))?;
+        }
+    }
+    if let Some(progress) = &options.progress {
+        progress.event(&ProgressEvent::OperationFinished {
+            operation: Operation::Copy,
+        });
+    }
+    Ok(visitor.stats.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cancel::{CancelToken, Cancelled};
+    use crate::copy::{
+        copy_dir, copy_dir_in, CopyOptions, CopyTree, IoHints, Symlinks, Verify, VerifyFailure,
+    };
+    use crate::durability::Durability;
+    use crate::fixture::TreeBuilder;
+    use crate::links::symlink;
+    use crate::rules::RuleSet;
+    use crate::stats::{Phase, Recorder};
+    use crate::vfs::{Fs, MemFs, ReadFs, RealFs};
+    use crate::ErrorKind;
+    use std::error::Error;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn copy_dir_recreates_symlinks() {
+        let src = TreeBuilder::new()
+            .file("a/1.txt", b"one")
+            .dir("empty")
+            .build()
+            .unwrap();
+        let inside = src.join("a/1.txt");
+        symlink(&inside, &src.join("a/abs")).unwrap();
+        symlink(Path::new("1.txt"), &src.join("a/rel")).unwrap();
+        let dst = TreeBuilder::new().build().unwrap();
+
+        copy_dir(src.path(), dst.join("preserved"), &CopyOptions::default()).unwrap();
+        assert_eq!(fs::read(dst.join("preserved/a/1.txt")).unwrap(), b"one");
+        assert!(dst.join("preserved/empty").is_dir());
+        assert_eq!(fs::read_link(dst.join("preserved/a/abs")).unwrap(), inside);
+        assert_eq!(
+            fs::read_link(dst.join("preserved/a/rel")).unwrap(),
+            Path::new("1.txt")
+        );
+
+        let options = CopyOptions {
+            symlinks: Symlinks::MakeRelative,
+            ..CopyOptions::default()
+        };
+        copy_dir(src.path(), dst.join("relative"), &options).unwrap();
+        let link = fs::read_link(dst.join("relative/a/abs")).unwrap();
+        assert_eq!(link, Path::new("1.txt"));
+        assert_eq!(fs::read(dst.join("relative/a/abs")).unwrap(), b"one");
+
+        let options = CopyOptions {
+            rules: Some(Arc::new(RuleSet::parse("empty/\nrel"))),
+            ..CopyOptions::default()
+        };
+        copy_dir(src.path(), dst.join("selected"), &options).unwrap();
+        assert!(dst.join("selected/a/1.txt").exists());
+        assert!(!dst.join("selected/empty").exists());
+        assert!(fs::symlink_metadata(dst.join("selected/a/rel")).is_err());
+    }
+
+    #[test]
+    fn copy_dir_io_hints() {
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let src = TreeBuilder::new()
+            .file("big.bin", &contents)
+            .build()
+            .unwrap();
+        let dst = TreeBuilder::new().build().unwrap();
+        for (i, (fast_copy, direct_io)) in [(true, false), (false, false), (false, true)]
+            .into_iter()
+            .enumerate()
+        {
+            let options = CopyOptions {
+                io_hints: IoHints {
+                    buffer_size: 4096,
+                    fast_copy,
+                    drop_cache: true,
+                    direct_io,
+                    preallocate: true,
+                },
+                durability: Durability::DataAndDirs,
+                mac_metadata: true,
+                verify: Verify::Hash,
+                ..CopyOptions::default()
+            };
+            let target = dst.join(i.to_string());
+            let stats = copy_dir(src.path(), &target, &options).unwrap();
+            assert_eq!(fs::read(target.join("big.bin")).unwrap(), contents);
+            assert_eq!(stats.files, 1);
+            assert_eq!(stats.bytes_written, 200_000);
+            assert!(stats.bytes_read >= 400_000);
+            assert!(stats.phases.contains_key(&Phase::Verify));
+        }
+    }
+
+    #[test]
+    fn copy_dir_cancelled() {
+        let src = TreeBuilder::new()
+            .file("a/1.txt", b"one")
+            .file("b/2.txt", b"two")
+            .build()
+            .unwrap();
+        let dst = TreeBuilder::new().build().unwrap();
+        let token = CancelToken::new();
+        let options = CopyOptions {
+            cancel: Some(token.clone()),
+            ..CopyOptions::default()
+        };
+        assert_eq!(copy_dir(src.path(), dst.join("full"), &options).unwrap().files, 2);
+
+        token.cancel();
+        let err = copy_dir(src.path(), dst.join("cancelled"), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        let cancelled = err.source().unwrap().downcast_ref::<Cancelled>().unwrap();
+        assert_eq!(cancelled.stats.files, 0);
+        assert!(!dst.join("cancelled").exists());
+    }
+
+    #[test]
+    fn copy_dir_resumes() {
+        let big: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+        let src = TreeBuilder::new()
+            .file("done.txt", b"complete")
+            .file("big.bin", &big)
+            .file("changed.txt", b"new contents")
+            .symlink("link", "done.txt")
+            .build()
+            .unwrap();
+        let dst = TreeBuilder::new().build().unwrap();
+        let options = CopyOptions {
+            resume: true,
+            ..CopyOptions::default()
+        };
+        let stats = copy_dir(src.path(), dst.path(), &options).unwrap();
+        assert_eq!(stats.files, 4);
+
+        // an interrupted copy: a partial file and a stale one
+        let file = fs::OpenOptions::new().write(true).open(dst.join("big.bin")).unwrap();
+        file.set_len(60_000).unwrap();
+        fs::write(dst.join("changed.txt"), b"old").unwrap();
+        let stats = copy_dir(src.path(), dst.path(), &options).unwrap();
+        assert_eq!(stats.files, 2);
+        assert_eq!(stats.bytes_written, 40_000 + 12);
+        assert_eq!(fs::read(dst.join("big.bin")).unwrap(), big);
+        assert_eq!(fs::read(dst.join("changed.txt")).unwrap(), b"new contents");
+        assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("done.txt"));
+
+        let options = CopyOptions {
+            verify: Verify::Hash,
+            ..options
+        };
+        assert_eq!(copy_dir(src.path(), dst.path(), &options).unwrap().files, 0);
+
+        // a stale link is replaced, and copying again without resuming replaces entries
+        fs::remove_file(dst.join("link")).unwrap();
+        symlink(Path::new("big.bin"), &dst.join("link")).unwrap();
+        assert_eq!(copy_dir(src.path(), dst.path(), &options).unwrap().files, 1);
+        assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("done.txt"));
+        let stats = copy_dir(src.path(), dst.path(), &CopyOptions::default()).unwrap();
+        assert_eq!(stats.files, 4);
+        assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("done.txt"));
+        assert_eq!(fs::read_dir(dst.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn copy_dir_into_itself() {
+        let src = TreeBuilder::new().file("a/1.txt", b"one").build().unwrap();
+        for dst in [src.join("a/copy"), src.join("a/../b"), src.path().to_path_buf()] {
+            let err = copy_dir(src.path(), &dst, &CopyOptions::default()).unwrap_err();
+            let cause = err.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+            assert_eq!(cause.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert!(!src.join("a/copy").exists());
+        let sibling = src.path().with_file_name(format!(
+            "{}-copy",
+            src.path().file_name().unwrap().to_string_lossy()
+        ));
+        copy_dir(src.path(), &sibling, &CopyOptions::default()).unwrap();
+        assert_eq!(fs::read(sibling.join("a/1.txt")).unwrap(), b"one");
+        fs::remove_dir_all(sibling).unwrap();
+    }
+
+    #[test]
+    fn copy_dir_in_mem_fs() {
+        let src = MemFs::new();
+        src.write("/src/a/1.txt", "one").unwrap();
+        src.write("/src/2.sh", "#!/bin/sh").unwrap();
+        src.set_mode(Path::new("/src/2.sh"), 0o755).unwrap();
+        src.symlink("a/1.txt", "/src/link").unwrap();
+        let dst = MemFs::new();
+        let options = CopyOptions {
+            symlinks: Symlinks::MakeRelative,
+            verify: Verify::Hash,
+            ..CopyOptions::default()
+        };
+        let (from, to): (Arc<dyn ReadFs>, Arc<dyn Fs>) = (Arc::new(src), Arc::new(dst.clone()));
+        let stats = copy_dir_in(Arc::clone(&from), "/src", to, "/copy", &options).unwrap();
+        assert_eq!(stats.files, 3);
+        assert_eq!(stats.bytes_written, 12);
+        assert_eq!(dst.read("/copy/a/1.txt").unwrap(), b"one");
+        assert_eq!(dst.mode(Path::new("/copy/2.sh")).unwrap(), 0o755);
+        assert_eq!(dst.read_link(Path::new("/copy/link")).unwrap(), Path::new("a/1.txt"));
+
+        // to the real filesystem
+        let tree = TreeBuilder::new().build().unwrap();
+        copy_dir_in(from, "/src", Arc::new(RealFs), tree.join("copy"), &options).unwrap();
+        assert_eq!(fs::read(tree.join("copy/link")).unwrap(), b"one");
+        assert_eq!(RealFs.mode(&tree.join("copy/a/1.txt")).unwrap() & 0o777, 0o644);
+
+        let options = CopyOptions {
+            resume: true,
+            ..CopyOptions::default()
+        };
+        let (from, to) = (Arc::new(dst.clone()), Arc::new(dst));
+        assert!(copy_dir_in(from, "/copy", to, "/again", &options).is_err());
+    }
+
+    #[test]
+    fn verify_detects_differences() {
+        let tree = TreeBuilder::new()
+            .file("a.bin", b"same size")
+            .file("b.bin", b"SAME SIZE")
+            .build()
+            .unwrap();
+        let (a, b) = (tree.join("a.bin"), tree.join("b.bin"));
+        let verify_with = |verify| {
+            let options = CopyOptions {
+                verify,
+                ..CopyOptions::default()
+            };
+            let copy = CopyTree {
+                src_fs: &RealFs,
+                dst_fs: &RealFs,
+                native: true,
+                src: tree.path(),
+                dst: tree.path(),
+                options: &options,
+                stats: Recorder::start(),
+                cancelled: false,
+                error: None,
+            };
+            copy.verify_file(&a, &b, None)
+        };
+        assert!(verify_with(Verify::Size).is_ok());
+        let err = verify_with(Verify::Hash).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Verification);
+        let failure = err.source().unwrap().downcast_ref::<VerifyFailure>().unwrap();
+        assert!(matches!(failure, VerifyFailure::Hash { .. }));
+        assert_eq!(failure.path(), b);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn copy_procfs_file() {
+        let tree = TreeBuilder::new().build().unwrap();
+        let options = CopyOptions::default();
+        assert!(options.io_hints.fast_copy);
+        let copy = CopyTree {
+            src_fs: &RealFs,
+            dst_fs: &RealFs,
+            native: true,
+            src: Path::new("/proc/self"),
+            dst: tree.path(),
+            options: &options,
+            stats: Recorder::start(),
+            cancelled: false,
+            error: None,
+        };
+        // reported as empty to copy_file_range by some kernels
+        let to = tree.join("status");
+        copy.copy_data(Path::new("/proc/self/status"), &to, 0).unwrap();
+        assert!(fs::read_to_string(to).unwrap().starts_with("Name:"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use crate::format::{format_count, format_size};
+use crate::paths::simplify_verbatim;
+use crate::queue::{DoneGuard, WorkQueue};
+use crate::result::Result;
+use crate::vfs::FileKind;
+
+/// Numbers of entries in a tree, as computed by [`count_entries`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// Regular files.
+    pub files: u64,
+    /// Directories below the root.
+    pub dirs: u64,
+    /// Symbolic links (never followed).
+    pub symlinks: u64,
+    /// Total size of the regular files.
+    pub bytes: u64,
+}
+
+impl fmt::Display for Counts {
+    /// Formats the counts for people, e.g. `1,234 files, 56 directories, 7 symlinks, 1.2 MiB`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} directories, {} symlinks, {}",
+            format_count(self.files),
+            format_count(self.dirs),
+            format_count(self.symlinks),
+            format_size(self.bytes)
+        )
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    symlinks: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// Counts the files, directories and bytes of a tree.
+/// Much faster than counting the entries of a ReadDir: directories are read by a pool of
+/// worker threads and no paths are sent to the caller.
+/// Entries that can not be read are skipped, as in ReadDir.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `filter` - decides which entries are counted; directories it rejects are not descended into.
+pub fn count_entries<P, F>(root: P, filter: F) -> Result<Counts>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path, FileKind) -> bool + Sync,
+{
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let counters = Counters::default();
+    let queue = WorkQueue::new(root);
+    let workers = thread::available_parallelism().map_or(4, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(dir) = queue.pop() {
+                    let _done = DoneGuard(&queue);
+                    count_dir(&dir, &queue, &counters, &filter);
+                }
+            });
+        }
+    });
+    Ok(Counts {
+        files: counters.files.into_inner(),
+        dirs: counters.dirs.into_inner(),
+        symlinks: counters.symlinks.into_inner(),
+        bytes: counters.bytes.into_inner(),
+    })
+}
+
+fn count_dir<F>(dir: &Path, queue: &WorkQueue<PathBuf>, counters: &Counters, filter: &F)
+where
+    F: Fn(&Path, FileKind) -> bool,
+{
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let (mut files, mut dirs, mut symlinks, mut bytes) = (0, 0, 0, 0);
+    for entry in entries.flatten() {
+        let kind = match entry.file_type() {
+            Ok(file_type) => FileKind::from(file_type),
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        if !filter(&path, kind) {
+            continue;
+        }
+        match kind {
+            FileKind::Dir => {
+                dirs += 1;
+                queue.push(path);
+            }
+            FileKind::Symlink => symlinks += 1,
+            FileKind::File => {
+                files += 1;
+                bytes += entry.metadata().map_or(0, |meta| meta.len());
+            }
+            FileKind::Other => {}
+        }
+    }
+    counters.files.fetch_add(files, Ordering::Relaxed);
+    counters.dirs.fetch_add(dirs, Ordering::Relaxed);
+    counters.symlinks.fetch_add(symlinks, Ordering::Relaxed);
+    counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::count::{count_entries, Counts};
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::FileKind;
+
+    #[test]
+    fn count_entries_with_filter() {
+        let tree = TreeBuilder::new()
+            .file("1.txt", b"12345")
+            .file("a/2.txt", b"123")
+            .file("a/b/3.log", b"1")
+            .file("skip/4.txt", b"1234")
+            .symlink("link", "1.txt")
+            .build()
+            .unwrap();
+
+        let counts = count_entries(tree.path(), |_, _| true).unwrap();
+        let expected = Counts {
+            files: 4,
+            dirs: 3,
+            symlinks: 1,
+            bytes: 13,
+        };
+        assert_eq!(counts, expected);
+        assert_eq!(
+            counts.to_string(),
+            "4 files, 3 directories, 1 symlinks, 13 B"
+        );
+
+        let counts = count_entries(tree.path(), |path, kind| {
+            kind == FileKind::Dir && !path.ends_with("skip")
+                || path.extension().is_some_and(|ext| ext == "txt")
+        })
+        .unwrap();
+        let expected = Counts {
+            files: 2,
+            dirs: 2,
+            symlinks: 0,
+            bytes: 8,
+        };
+        assert_eq!(counts, expected);
+    }
+}
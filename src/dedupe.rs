@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::temp_path_for;
+use crate::format::{format_count, format_size};
+use crate::hash::Hash;
+use crate::identity::{link_count, FileId};
+use crate::result::Result;
+use crate::vfs::FileKind;
+use crate::ReadDir;
+
+/// Finds regular files under `root` with identical contents.
+/// Files are grouped by size first, so only candidates are hashed. Empty files are ignored.
+/// Returns groups of two or more paths, each sorted, canonical instance first.
+pub fn find_duplicates<P: AsRef<Path>>(root: P) -> Result<Vec<Vec<PathBuf>>> {
+    let mut rd = ReadDir::try_new(root)?;
+    rd.is_lazy = true;
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for entry in rd {
+        let meta = *entry.metadata()?;
+        if meta.kind == FileKind::File && meta.len > 0 {
+            by_size
+                .entry(meta.len)
+                .or_default()
+                .push(entry.into_path());
+        }
+    }
+    let mut groups = Vec::new();
+    for (_, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        let mut by_hash: HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            by_hash.entry(Hash::of_file(&path)?).or_default().push(path);
+        }
+        groups.extend(by_hash.into_values().filter(|paths| paths.len() > 1));
+    }
+    groups.iter_mut().for_each(|group| group.sort());
+    groups.sort();
+    Ok(groups)
+}
+
+/// Result of a deduplication.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DedupeReport {
+    /// Files that were (or, in dry-run mode, would be) replaced.
+    pub replaced: Vec<PathBuf>,
+    /// Number of bytes reclaimed.
+    pub bytes_reclaimed: u64,
+}
+
+impl fmt::Display for DedupeReport {
+    /// Formats the report for people, e.g. `12 files replaced, 1.2 MiB reclaimed`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files replaced, {} reclaimed",
+            format_count(self.replaced.len() as u64),
+            format_size(self.bytes_reclaimed)
+        )
+    }
+}
+
+/// Replaces duplicate files under `root` with hard links to a canonical instance
+/// (the first path of each group returned by [`find_duplicates`]).
+/// Each replacement is atomic: the link is created under a temporary name and renamed over the copy.
+/// Files that are already linked to the canonical instance are skipped. Only files whose
+/// links are all replaced count as reclaimed.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `dry_run` - only report what would be replaced.
+pub fn dedupe_hardlink<P: AsRef<Path>>(root: P, dry_run: bool) -> Result<DedupeReport> {
+    let mut report = DedupeReport::default();
+    for group in find_duplicates(root)? {
+        let canonical = &group[0];
+        let canonical_meta = fs::metadata(canonical)?;
+        let canonical_id = FileId::of(canonical)?;
+        let ids = group.iter().map(FileId::of).collect::<Result<Vec<_>>>()?;
+        let mut members: HashMap<FileId, u64> = HashMap::new();
+        ids.iter().for_each(|id| *members.entry(*id).or_default() += 1);
+        // copies already replaced, by inode
+        let mut handled = HashSet::new();
+        for (copy, id) in group.iter().zip(&ids).skip(1) {
+            if *id == canonical_id {
+                continue;
+            }
+            // read before the first of its links is replaced; links outside of the group
+            // keep the file
+            if handled.insert(*id) && link_count(copy)? == members[id] {
+                report.bytes_reclaimed += canonical_meta.len();
+            }
+            if !dry_run {
+                let tmp = temp_path_for(copy);
+                fs::hard_link(canonical, &tmp)?;
+                if let Err(e) = fs::rename(&tmp, copy) {
+                    let _ = fs::remove_file(&tmp);
+                    return Err(e.into());
+                }
+            }
+            report.replaced.push(copy.clone());
+        }
+    }
+    Ok(report)
+}
+
+/// Shares identical extents of duplicate files under `root` with a canonical instance using
+/// the kernel dedupe ioctl (`FIDEDUPERANGE`, supported by Btrfs and XFS). Unlike
+/// [`dedupe_hardlink`], every file keeps its inode, permissions and timestamps, and later
+/// writes to one copy do not affect the others. The kernel compares the data itself,
+/// so ranges modified concurrently are left alone.
+///
+/// Returns an error of kind `io::ErrorKind::Unsupported` on other platforms and filesystems.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `dry_run` - only report what would be deduplicated.
+pub fn dedupe_reflink<P: AsRef<Path>>(root: P, dry_run: bool) -> Result<DedupeReport> {
+    let mut report = DedupeReport::default();
+    for group in find_duplicates(root)? {
+        let canonical = &group[0];
+        let canonical_meta = fs::metadata(canonical)?;
+        let canonical_id = FileId::of(canonical)?;
+        for copy in &group[1..] {
+            if FileId::of(copy)? == canonical_id {
+                continue;
+            }
+            let deduped = if dry_run {
+                canonical_meta.len()
+            } else {
+                reflink::dedupe_range(canonical, copy, canonical_meta.len())?
+            };
+            if deduped > 0 {
+                report.bytes_reclaimed += deduped;
+                report.replaced.push(copy.clone());
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(target_os = "linux")]
+mod reflink {
+    use std::ffi::{c_int, c_ulong};
+    use std::fs;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    // _IOWR(0x94, 54, struct file_dedupe_range)
+    const FIDEDUPERANGE: c_ulong = 0xC018_9436;
+    const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+    // Filesystems limit the length of a single request; Btrfs caps it at 16 MiB.
+    const MAX_CHUNK: u64 = 16 * 1024 * 1024;
+
+    #[repr(C)]
+    struct FileDedupeRange {
+        src_offset: u64,
+        src_length: u64,
+        dest_count: u16,
+        reserved1: u16,
+        reserved2: u32,
+        info: [FileDedupeRangeInfo; 1],
+    }
+
+    #[repr(C)]
+    struct FileDedupeRangeInfo {
+        dest_fd: i64,
+        dest_offset: u64,
+        bytes_deduped: u64,
+        status: i32,
+        reserved: u32,
+    }
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    /// Deduplicates `len` bytes of `dst` against `src`, returning the number of bytes shared.
+    pub(super) fn dedupe_range(src: &Path, dst: &Path, len: u64) -> io::Result<u64> {
+        let src = fs::File::open(src)?;
+        // the destination must be opened for writing unless the caller is privileged
+        let dst = fs::OpenOptions::new().read(true).write(true).open(dst)?;
+        let mut offset = 0;
+        while offset < len {
+            let mut range = FileDedupeRange {
+                src_offset: offset,
+                src_length: (len - offset).min(MAX_CHUNK),
+                dest_count: 1,
+                reserved1: 0,
+                reserved2: 0,
+                info: [FileDedupeRangeInfo {
+                    dest_fd: dst.as_raw_fd() as i64,
+                    dest_offset: offset,
+                    bytes_deduped: 0,
+                    status: 0,
+                    reserved: 0,
+                }],
+            };
+            // SAFETY: `range` is a valid file_dedupe_range with one info record.
+            let ret = unsafe {
+                ioctl(
+                    src.as_raw_fd(),
+                    FIDEDUPERANGE,
+                    &mut range as *mut FileDedupeRange,
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    Some(22) | Some(95) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+                    _ => Err(e),
+                };
+            }
+            let info = &range.info[0];
+            if info.status < 0 {
+                return Err(io::Error::from_raw_os_error(-info.status));
+            }
+            if info.status == FILE_DEDUPE_RANGE_DIFFERS || info.bytes_deduped == 0 {
+                break;
+            }
+            offset += info.bytes_deduped;
+        }
+        Ok(offset)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod reflink {
+    use std::io;
+    use std::path::Path;
+
+    pub(super) fn dedupe_range(_src: &Path, _dst: &Path, _len: u64) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extent deduplication is only supported on Linux",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dedupe::{dedupe_hardlink, dedupe_reflink, find_duplicates};
+    use crate::fixture::TreeBuilder;
+    use std::fs;
+
+    #[test]
+    fn dedupe_with_hardlinks() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"same")
+            .file("sub/b.txt", b"same")
+            .file("c.txt", b"diff")
+            .file("empty1", b"")
+            .file("empty2", b"")
+            .build()
+            .unwrap();
+        let dir = tree.path();
+
+        assert_eq!(find_duplicates(dir).unwrap().len(), 1);
+        let report = dedupe_hardlink(dir, true).unwrap();
+        assert_eq!(report.bytes_reclaimed, 4);
+        let report = dedupe_hardlink(dir, false).unwrap();
+        assert_eq!(report.replaced.len(), 1);
+        assert_eq!(fs::read(dir.join("sub/b.txt")).unwrap(), b"same");
+        assert!(dedupe_hardlink(dir, false).unwrap().replaced.is_empty());
+
+        // copies linked to each other free one file; one linked from outside frees none
+        let tree = TreeBuilder::new()
+            .file("a/1", b"12345678")
+            .file("a/2", b"12345678")
+            .file("b/1", b"abc")
+            .file("b/2", b"abc")
+            .build()
+            .unwrap();
+        let outside = TreeBuilder::new().build().unwrap();
+        fs::hard_link(tree.join("a/2"), tree.join("a/3")).unwrap();
+        fs::hard_link(tree.join("b/2"), outside.join("b")).unwrap();
+        let report = dedupe_hardlink(tree.path(), true).unwrap();
+        assert_eq!((report.replaced.len(), report.bytes_reclaimed), (3, 8));
+        let report = dedupe_hardlink(tree.path(), false).unwrap();
+        assert_eq!((report.replaced.len(), report.bytes_reclaimed), (3, 8));
+        assert_eq!(fs::read(outside.join("b")).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn dedupe_with_reflinks() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"same")
+            .file("b.txt", b"same")
+            .build()
+            .unwrap();
+        let dir = tree.path();
+
+        assert_eq!(dedupe_reflink(dir, true).unwrap().bytes_reclaimed, 4);
+        // tmp filesystems usually do not support extent sharing
+        match dedupe_reflink(dir, false) {
+            Ok(report) => assert!(report.bytes_reclaimed <= 4),
+            Err(e) => assert!(std::error::Error::source(&e).is_some()),
+        }
+        assert_eq!(fs::read(dir.join("b.txt")).unwrap(), b"same");
+    }
+}
@@ -0,0 +1,448 @@
+//! Binary deltas between file versions, using the rsync algorithm:
+//! the old version is described by a [`Signature`] of per-block checksums, and the new version
+//! is encoded as a [`Delta`] of block references and literal data, so only changed blocks
+//! need to be transferred.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::atomic::write_atomic;
+use crate::compare::read_full;
+use crate::hash::Hash;
+use crate::open::open_read_shared;
+use crate::result::{Error, ErrorKind, Result};
+
+/// Default block size of signatures.
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+/// How much of the new version is read at a time when computing a delta.
+const READ_LEN: usize = 64 * 1024;
+
+/// Checksums of one block of the old version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: Hash,
+    pub len: usize,
+}
+
+/// Per-block checksums of the old version of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub block_size: usize,
+    pub blocks: Vec<BlockSignature>,
+}
+
+/// An instruction for reconstructing the new version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Copy `len` bytes from the old version, starting at `offset`.
+    Copy { offset: u64, len: u64 },
+    /// Write literal data.
+    Data(Vec<u8>),
+}
+
+/// Instructions reconstructing the new version of a file from the old one.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub ops: Vec<Op>,
+}
+
+/// Rolling checksum of the rsync algorithm.
+#[derive(Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Rolling {
+        let mut rolling = Rolling {
+            a: 0,
+            b: 0,
+            len: block.len() as u32,
+        };
+        for (i, &x) in block.iter().enumerate() {
+            rolling.a = rolling.a.wrapping_add(x as u32);
+            rolling.b = rolling
+                .b
+                .wrapping_add((block.len() - i) as u32 * x as u32);
+        }
+        rolling
+    }
+
+    fn roll(&mut self, out: u8, inc: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inc as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+impl Signature {
+    /// Computes the signature of everything read from `reader`.
+    pub fn of_reader<R: Read>(mut reader: R, block_size: usize) -> io::Result<Signature> {
+        let block_size = block_size.max(1);
+        let mut buf = vec![0u8; block_size];
+        let mut blocks = Vec::new();
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            blocks.push(BlockSignature {
+                weak: Rolling::new(&buf[..n]).value(),
+                strong: Hash::of(&buf[..n]),
+                len: n,
+            });
+            if n < block_size {
+                break;
+            }
+        }
+        Ok(Signature { block_size, blocks })
+    }
+
+    /// Computes the signature of a file.
+    pub fn of_file<P: AsRef<Path>>(path: P, block_size: usize) -> Result<Signature> {
+        Ok(Signature::of_reader(open_read_shared(path)?, block_size)?)
+    }
+}
+
+impl Delta {
+    fn push_copy(&mut self, offset: u64, len: u64) {
+        if let Some(Op::Copy { offset: o, len: l }) = self.ops.last_mut() {
+            if *o + *l == offset {
+                *l += len;
+                return;
+            }
+        }
+        self.ops.push(Op::Copy { offset, len });
+    }
+
+    fn push_data(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        match self.ops.last_mut() {
+            Some(Op::Data(last)) => last.extend_from_slice(data),
+            _ => self.ops.push(Op::Data(data.to_vec())),
+        }
+    }
+
+    /// Computes the delta turning the version described by `signature` into `new`.
+    pub fn compute(signature: &Signature, new: &[u8]) -> Delta {
+        // reading a slice does not fail, and the literal data is not bounded
+        let delta = Delta::compute_bounded(signature, new, u64::MAX);
+        delta.ok().flatten().unwrap_or_default()
+    }
+
+    /// Computes the delta turning the version described by `signature` into everything
+    /// read from `new`. Only a few blocks of `new` are held in memory besides the delta.
+    pub fn compute_reader<R: Read>(signature: &Signature, new: R) -> io::Result<Delta> {
+        Ok(Delta::compute_bounded(signature, new, u64::MAX)?.unwrap_or_default())
+    }
+
+    /// Like [`Delta::compute_reader`], giving up with `None` once the delta holds more
+    /// than `max_literal` bytes of literal data.
+    pub(crate) fn compute_bounded<R: Read>(
+        signature: &Signature,
+        mut new: R,
+        max_literal: u64,
+    ) -> io::Result<Option<Delta>> {
+        let bs = signature.block_size;
+        let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, block) in signature.blocks.iter().enumerate() {
+            if block.len == bs {
+                index.entry(block.weak).or_default().push(i);
+            }
+        }
+        let find = |weak: u32, data: &[u8]| -> Option<usize> {
+            let candidates = index.get(&weak)?;
+            let strong = Hash::of(data);
+            candidates
+                .iter()
+                .copied()
+                .find(|&i| signature.blocks[i].strong == strong)
+        };
+
+        let mut delta = Delta::default();
+        let mut literal = 0u64;
+        let mut push_literal = |delta: &mut Delta, data: &[u8]| {
+            literal += data.len() as u64;
+            delta.push_data(data);
+            literal <= max_literal
+        };
+        // the data read from `new` from the first byte not in the delta yet; the block
+        // being checked starts at `i`
+        let mut buf: Vec<u8> = Vec::new();
+        let mut eof = false;
+        let mut literal_start = 0;
+        let mut i = 0;
+        let mut rolling: Option<Rolling> = None;
+        loop {
+            if i + bs >= buf.len() && !eof {
+                // literal data well behind the block is final; the last block of the old
+                // version, shorter than the others, is never matched before the end
+                let end = match i.checked_sub(bs) {
+                    Some(end) if end > literal_start + READ_LEN => end,
+                    _ => literal_start,
+                };
+                if !push_literal(&mut delta, &buf[literal_start..end]) {
+                    return Ok(None);
+                }
+                buf.drain(..end);
+                i -= end;
+                literal_start = 0;
+                let filled = buf.len();
+                buf.resize(filled + READ_LEN.max(bs), 0);
+                let n = read_full(&mut new, &mut buf[filled..])?;
+                buf.truncate(filled + n);
+                eof = n == 0;
+                continue;
+            }
+            if i + bs > buf.len() {
+                break;
+            }
+            let mut weak = rolling.unwrap_or_else(|| Rolling::new(&buf[i..i + bs]));
+            if let Some(block) = find(weak.value(), &buf[i..i + bs]) {
+                if !push_literal(&mut delta, &buf[literal_start..i]) {
+                    return Ok(None);
+                }
+                delta.push_copy((block * bs) as u64, bs as u64);
+                i += bs;
+                literal_start = i;
+                rolling = None;
+            } else if i + bs < buf.len() {
+                weak.roll(buf[i], buf[i + bs]);
+                rolling = Some(weak);
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        // the last block of the old version may be shorter than the block size
+        let tail = &buf[literal_start..];
+        if let Some((last, block)) = signature.blocks.iter().enumerate().next_back() {
+            if block.len < bs && tail.len() >= block.len {
+                let start = buf.len() - block.len;
+                if Hash::of(&buf[start..]) == block.strong {
+                    if !push_literal(&mut delta, &buf[literal_start..start]) {
+                        return Ok(None);
+                    }
+                    delta.push_copy((last * bs) as u64, block.len as u64);
+                    return Ok(Some(delta));
+                }
+            }
+        }
+        Ok(push_literal(&mut delta, tail).then_some(delta))
+    }
+
+    /// Writes the new version, reading copied blocks from `old`.
+    pub fn apply<R: Read + Seek, W: Write>(&self, old: &mut R, out: &mut W) -> Result<()> {
+        for op in &self.ops {
+            match op {
+                Op::Copy { offset, len } => {
+                    old.seek(SeekFrom::Start(*offset))?;
+                    let copied = io::copy(&mut (&mut *old).take(*len), out)?;
+                    if copied != *len {
+                        return Err(Error::new(
+                            ErrorKind::Encoding,
+                            "delta refers past the end of the old version",
+                        ));
+                    }
+                }
+                Op::Data(data) => out.write_all(data)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a reader of the new version, like [`Delta::apply`] but reading copied blocks
+    /// from an old version that is only read forward: opened with `open`, and opened again
+    /// when a block before the current position is copied.
+    pub fn reader<R: Read, F: FnMut() -> io::Result<R>>(&self, open: F) -> DeltaReader<'_, R, F> {
+        DeltaReader {
+            ops: self.ops.iter(),
+            open,
+            old: None,
+            position: 0,
+            data: &[],
+            copy: 0,
+        }
+    }
+
+    /// Returns the number of literal bytes, i.e. what has to be transferred besides the ops.
+    pub fn literal_len(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                Op::Data(data) => data.len() as u64,
+                Op::Copy { .. } => 0,
+            })
+            .sum()
+    }
+}
+
+/// The new version of a file, read from a [`Delta`] and the old version; see
+/// [`Delta::reader`].
+pub struct DeltaReader<'a, R, F> {
+    ops: std::slice::Iter<'a, Op>,
+    open: F,
+    old: Option<R>,
+    /// Position in the old version.
+    position: u64,
+    /// Literal data of the current op left to read.
+    data: &'a [u8],
+    /// Bytes of the current op left to copy from the old version.
+    copy: u64,
+}
+
+impl<R: Read, F: FnMut() -> io::Result<R>> DeltaReader<'_, R, F> {
+    fn seek_old(&mut self, offset: u64) -> io::Result<&mut R> {
+        let old = match self.old.take() {
+            Some(old) if offset >= self.position => old,
+            _ => {
+                self.position = 0;
+                (self.open)()?
+            }
+        };
+        let old = self.old.insert(old);
+        let skip = offset - self.position;
+        let skipped = io::copy(&mut old.take(skip), &mut io::sink())?;
+        self.position += skipped;
+        if skipped < skip {
+            return Err(past_end());
+        }
+        Ok(old)
+    }
+}
+
+impl<R: Read, F: FnMut() -> io::Result<R>> Read for DeltaReader<'_, R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.data.is_empty() {
+                let n = self.data.len().min(buf.len());
+                buf[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+                return Ok(n);
+            }
+            if self.copy > 0 {
+                let len = self.copy.min(buf.len() as u64) as usize;
+                let n = self.seek_old(self.position)?.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(past_end());
+                }
+                self.position += n as u64;
+                self.copy -= n as u64;
+                return Ok(n);
+            }
+            match self.ops.next() {
+                Some(Op::Data(data)) => self.data = data,
+                Some(Op::Copy { offset, len }) => {
+                    self.seek_old(*offset)?;
+                    self.copy = *len;
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+fn past_end() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "delta refers past the end of the old version",
+    )
+}
+
+/// Computes the delta between two versions of a file.
+pub fn file_delta<A: AsRef<Path>, B: AsRef<Path>>(old: A, new: B) -> Result<Delta> {
+    let signature = Signature::of_file(old, DEFAULT_BLOCK_SIZE)?;
+    Ok(Delta::compute(&signature, &fs::read(new)?))
+}
+
+/// Reconstructs the new version from `old` and `delta`, writing it atomically to `out`.
+/// `out` may be the same path as `old`.
+pub fn apply_delta<A: AsRef<Path>, B: AsRef<Path>>(old: A, delta: &Delta, out: B) -> Result<()> {
+    let mut old = open_read_shared(old)?;
+    let mut buf = Vec::new();
+    delta.apply(&mut old, &mut buf)?;
+    write_atomic(out, &buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delta::{Delta, Op, Signature};
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn delta_round_trip() {
+        let old: Vec<u8> = (0..50_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = old.clone();
+        new.splice(10_000..10_010, b"inserted bytes".iter().copied());
+        new.extend_from_slice(b"tail");
+
+        let signature = Signature::of_reader(&old[..], 1024).unwrap();
+        let delta = Delta::compute(&signature, &new);
+        assert!(delta.literal_len() < 3000);
+        let mut out = Vec::new();
+        delta.apply(&mut Cursor::new(&old), &mut out).unwrap();
+        assert_eq!(out, new);
+
+        let delta = Delta::compute(&signature, &old);
+        assert_eq!(delta.literal_len(), 0);
+        assert_eq!(delta.ops.len(), 1);
+    }
+
+    #[test]
+    fn streamed_delta() {
+        let mut seed = 1u32;
+        let mut random = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (seed >> 16) as u8
+                })
+                .collect()
+        };
+        let old = random(300_000);
+        // new data longer than what is read at a time, then blocks of the old version
+        // out of order
+        let mut new = random(150_000);
+        new.extend_from_slice(&old[200_000..250_000]);
+        new.extend_from_slice(&old[..100_000]);
+        new.extend_from_slice(&old[299_000..]);
+
+        let signature = Signature::of_reader(&old[..], 1000).unwrap();
+        let delta = Delta::compute_reader(&signature, &new[..]).unwrap();
+        assert_eq!(delta, Delta::compute(&signature, &new));
+        assert_eq!(delta.literal_len(), 150_000);
+        assert!(Delta::compute_bounded(&signature, &new[..], 100_000).unwrap().is_none());
+
+        let mut opens = 0;
+        let mut out = Vec::new();
+        let mut reader = delta.reader(|| {
+            opens += 1;
+            Ok(&old[..])
+        });
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, new);
+        assert_eq!(opens, 2);
+
+        let past_end = Delta {
+            ops: vec![Op::Copy { offset: 299_990, len: 20 }],
+        };
+        assert!(past_end.reader(|| Ok(&old[..])).read_to_end(&mut out).is_err());
+    }
+}
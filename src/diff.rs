@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::compare::files_equal;
+use crate::result::Result;
+use crate::vfs::{ReadFs, RealFs};
+use crate::ReadDir;
+
+/// Differences between two trees. All paths are relative to the compared roots.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// Entries present only in the second tree.
+    pub added: Vec<PathBuf>,
+    /// Entries present only in the first tree.
+    pub removed: Vec<PathBuf>,
+    /// Entries present in both trees with different contents (or link targets).
+    pub modified: Vec<PathBuf>,
+}
+
+impl Diff {
+    /// Checks whether the trees are identical.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Returns the paths of all entries under `root` (except directories), relative to `root`.
+pub(crate) fn relative_files<P: AsRef<Path>>(root: P) -> Result<BTreeSet<PathBuf>> {
+    relative_files_in(Arc::new(RealFs), root.as_ref())
+}
+
+/// Like [`relative_files`], for the tree of another filesystem.
+pub(crate) fn relative_files_in(fs: Arc<dyn ReadFs>, root: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut rd = ReadDir::try_new_in(fs, root)?;
+    rd.is_lazy = true;
+    let root = rd.root().to_path_buf();
+    Ok(rd
+        .filter_map(|entry| entry.path().strip_prefix(&root).ok().map(PathBuf::from))
+        .collect())
+}
+
+pub(crate) fn entries_equal(a: &Path, b: &Path) -> Result<bool> {
+    let (meta_a, meta_b) = (fs::symlink_metadata(a)?, fs::symlink_metadata(b)?);
+    match (meta_a.file_type().is_symlink(), meta_b.file_type().is_symlink()) {
+        (true, true) => Ok(fs::read_link(a)? == fs::read_link(b)?),
+        (false, false) => files_equal(a, b),
+        _ => Ok(false),
+    }
+}
+
+/// Compares two trees by relative paths and file contents.
+/// Symbolic links are compared by their targets. Empty directories are not compared.
+///
+/// # Arguments:
+///
+/// * `a` - first (old) tree.
+/// * `b` - second (new) tree.
+pub fn diff<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> Result<Diff> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let files_a = relative_files(a)?;
+    let files_b = relative_files(b)?;
+    let mut diff = Diff {
+        added: files_b.difference(&files_a).cloned().collect(),
+        removed: files_a.difference(&files_b).cloned().collect(),
+        modified: Vec::new(),
+    };
+    for rel in files_a.intersection(&files_b) {
+        if !entries_equal(&a.join(rel), &b.join(rel))? {
+            diff.modified.push(rel.clone());
+        }
+    }
+    Ok(diff)
+}
+
+/// Checks whether two trees contain the same files with the same contents.
+pub fn dirs_equal<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> Result<bool> {
+    Ok(diff(a, b)?.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{diff, dirs_equal};
+    use crate::fixture::TreeBuilder;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn diff_trees() {
+        let tree = TreeBuilder::new()
+            .file("a/sub/same.txt", b"same")
+            .file("b/sub/same.txt", b"same")
+            .build()
+            .unwrap();
+        let dir = tree.path();
+        assert!(dirs_equal(dir.join("a"), dir.join("b")).unwrap());
+
+        fs::write(dir.join("a/only_a.txt"), b"a").unwrap();
+        fs::write(dir.join("b/only_b.txt"), b"b").unwrap();
+        fs::write(dir.join("b/sub/same.txt"), b"diff").unwrap();
+        let d = diff(dir.join("a"), dir.join("b")).unwrap();
+        assert_eq!(d.added, vec![PathBuf::from("only_b.txt")]);
+        assert_eq!(d.removed, vec![PathBuf::from("only_a.txt")]);
+        assert_eq!(d.modified, vec![PathBuf::from("sub/same.txt")]);
+        assert!(!dirs_equal(dir.join("a"), dir.join("b")).unwrap());
+    }
+}
@@ -0,0 +1,162 @@
+//! Unbuffered (direct) I/O, bypassing the page cache for large sequential reads and writes.
+//! Buffers, offsets and lengths of direct I/O must be multiples of the device block size;
+//! [`ALIGN`] covers the block sizes in common use.
+
+use std::alloc::{self, Layout};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::slice;
+
+/// Alignment of direct I/O buffers and transfer sizes.
+pub(crate) const ALIGN: usize = 4096;
+
+/// A zeroed heap buffer aligned to [`ALIGN`].
+pub(crate) struct AlignedBuf {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the buffer owns its allocation, like a Vec<u8>.
+unsafe impl Send for AlignedBuf {}
+
+impl AlignedBuf {
+    /// Allocates a buffer of at least `len` bytes, rounded up to a multiple of [`ALIGN`].
+    pub(crate) fn new(len: usize) -> AlignedBuf {
+        let len = align_up(len.max(1));
+        let layout = Layout::from_size_align(len, ALIGN).unwrap();
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuf { ptr, len }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` initialized bytes owned by the buffer.
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as above, and `&mut self` guarantees exclusive access.
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with this layout.
+        unsafe { alloc::dealloc(self.ptr, Layout::from_size_align(self.len, ALIGN).unwrap()) }
+    }
+}
+
+/// Rounds `len` up to a multiple of [`ALIGN`].
+pub(crate) fn align_up(len: usize) -> usize {
+    len.div_ceil(ALIGN) * ALIGN
+}
+
+/// Opens a file for direct reading; returns `None` if direct I/O is not supported
+/// by the platform or the filesystem (e.g. tmpfs).
+pub(crate) fn open_direct(path: &Path) -> io::Result<Option<fs::File>> {
+    sys::open(path, fs::OpenOptions::new().read(true))
+}
+
+/// Creates (or truncates) a file for direct writing; returns `None` if direct I/O is not
+/// supported.
+pub(crate) fn create_direct(path: &Path) -> io::Result<Option<fs::File>> {
+    sys::open(
+        path,
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true),
+    )
+}
+
+/// Reads until `buf` is full or the end of the file is reached; returns the number of
+/// bytes read. Only the last read of a file may return less than a full buffer.
+pub(crate) fn read_block(file: &mut fs::File, buf: &mut AlignedBuf) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            // a short read ends at the end of the file; the next offset would be unaligned
+            Ok(n) if n % ALIGN != 0 => return Ok(filled + n),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Copies `reader` to `writer`, both opened for direct I/O, calling `copied` after each
+/// block. The last block is written padded and the file is truncated to the copied size.
+pub(crate) fn copy<F: FnMut(u64)>(
+    reader: &mut fs::File,
+    writer: &mut fs::File,
+    buffer_size: usize,
+    mut copied: F,
+) -> io::Result<u64> {
+    let mut buf = AlignedBuf::new(buffer_size);
+    let mut total = 0;
+    loop {
+        let n = read_block(reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        buf[n..].fill(0);
+        writer.write_all(&buf[..align_up(n)])?;
+        total += n as u64;
+        copied(n as u64);
+        if n < buf.len() {
+            break;
+        }
+    }
+    writer.set_len(total)?;
+    Ok(total)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+    const O_DIRECT: i32 = 0o200000;
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+    const O_DIRECT: i32 = 0o40000;
+    const EINVAL: i32 = 22;
+
+    pub(super) fn open(path: &Path, options: &mut fs::OpenOptions) -> io::Result<Option<fs::File>> {
+        match options.custom_flags(O_DIRECT).open(path) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.raw_os_error() == Some(EINVAL) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    pub(super) fn open(
+        _path: &Path,
+        _options: &mut fs::OpenOptions,
+    ) -> io::Result<Option<fs::File>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,333 @@
+//! Directories held open while they are worked on, so that entries are reached relative to
+//! the open directory instead of by path. Renaming a directory, or swapping it for a
+//! symbolic link, while a walk or a delete is running can then not redirect the operation
+//! outside the tree it was started on: subdirectories are opened without following links.
+//!
+//! On Linux this uses `openat`, `fdopendir` and `unlinkat`. Other platforms fall back to
+//! paths, checking that every subdirectory opened is a real directory.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::Path;
+
+use crate::vfs::FileKind;
+
+/// An entry of a directory listing: its name and kind.
+pub(crate) type DirEntry = io::Result<(OsString, FileKind)>;
+
+#[cfg(target_os = "linux")]
+pub(crate) use self::linux::Dir;
+#[cfg(not(target_os = "linux"))]
+pub(crate) use self::fallback::Dir;
+
+fn not_a_dir(name: &OsStr) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotADirectory,
+        format!("{}: not a directory", Path::new(name).display()),
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::{c_char, c_int, c_void, CStr, CString, OsStr, OsString};
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    use super::{not_a_dir, DirEntry};
+    use crate::vfs::FileKind;
+
+    const AT_FDCWD: c_int = -100;
+    const AT_REMOVEDIR: c_int = 0x200;
+    const O_CLOEXEC: c_int = 0o2_000_000;
+    const O_PATH: c_int = 0o10_000_000;
+    #[cfg(any(target_arch = "aarch64", target_arch = "arm", target_arch = "powerpc64"))]
+    const O_DIRECTORY: c_int = 0o40_000;
+    #[cfg(any(target_arch = "aarch64", target_arch = "arm", target_arch = "powerpc64"))]
+    const O_NOFOLLOW: c_int = 0o100_000;
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "arm", target_arch = "powerpc64")))]
+    const O_DIRECTORY: c_int = 0o200_000;
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "arm", target_arch = "powerpc64")))]
+    const O_NOFOLLOW: c_int = 0o400_000;
+    const DT_UNKNOWN: u8 = 0;
+    const DT_DIR: u8 = 4;
+    const DT_REG: u8 = 8;
+    const DT_LNK: u8 = 10;
+
+    #[repr(C)]
+    struct Dirent64 {
+        d_ino: u64,
+        d_off: i64,
+        d_reclen: u16,
+        d_type: u8,
+        d_name: [c_char; 256],
+    }
+
+    extern "C" {
+        fn openat(dirfd: c_int, path: *const c_char, flags: c_int, ...) -> c_int;
+        fn unlinkat(dirfd: c_int, path: *const c_char, flags: c_int) -> c_int;
+        fn fchmodat(dirfd: c_int, path: *const c_char, mode: u32, flags: c_int) -> c_int;
+        fn fdopendir(fd: c_int) -> *mut c_void;
+        fn rewinddir(dir: *mut c_void);
+        fn readdir64(dir: *mut c_void) -> *mut Dirent64;
+        fn closedir(dir: *mut c_void) -> c_int;
+    }
+
+    fn c_str(name: &OsStr) -> io::Result<CString> {
+        CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn open_in(dirfd: c_int, path: &OsStr, flags: c_int) -> io::Result<OwnedFd> {
+        let path = c_str(path)?;
+        // SAFETY: `path` is a valid NUL-terminated string; no mode is needed without O_CREAT.
+        let fd = unsafe { openat(dirfd, path.as_ptr(), flags | O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just opened and is owned by nobody else.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// An open directory.
+    #[derive(Debug)]
+    pub(crate) struct Dir {
+        fd: OwnedFd,
+    }
+
+    impl Dir {
+        /// Opens the directory at `path` (following symbolic links, like `fs::read_dir`).
+        pub(crate) fn open(path: &Path) -> io::Result<Dir> {
+            let fd = open_in(AT_FDCWD, path.as_os_str(), O_DIRECTORY)?;
+            Ok(Dir { fd })
+        }
+
+        /// Opens the subdirectory `name`; fails if it is a symbolic link.
+        pub(crate) fn open_dir(&self, name: &OsStr) -> io::Result<Dir> {
+            match open_in(self.fd.as_raw_fd(), name, O_DIRECTORY | O_NOFOLLOW) {
+                Ok(fd) => Ok(Dir { fd }),
+                // ELOOP: the entry is a symbolic link
+                Err(e) if e.raw_os_error() == Some(40) => Err(not_a_dir(name)),
+                Err(e) => Err(e),
+            }
+        }
+
+        /// Lists the directory, without `.` and `..`.
+        pub(crate) fn entries(&self) -> io::Result<Vec<DirEntry>> {
+            // the stream takes ownership of the descriptor it is given
+            let fd = self.fd.try_clone()?.into_raw_fd();
+            // SAFETY: `fd` is an open directory descriptor whose ownership passes to the stream.
+            let stream = unsafe { fdopendir(fd) };
+            if stream.is_null() {
+                let e = io::Error::last_os_error();
+                // SAFETY: the stream was not created, so `fd` is still ours to close.
+                drop(unsafe { OwnedFd::from_raw_fd(fd) });
+                return Err(e);
+            }
+            // the duplicate shares the offset of earlier listings
+            // SAFETY: `stream` is a valid directory stream.
+            unsafe { rewinddir(stream) };
+            let mut entries = Vec::new();
+            loop {
+                // SAFETY: `stream` is valid; the entry is read before the next call.
+                let entry = unsafe { readdir64(stream) };
+                if entry.is_null() {
+                    break;
+                }
+                // SAFETY: `entry` points to a valid record with a NUL-terminated name.
+                let (name, d_type) = unsafe {
+                    let entry = &*entry;
+                    (CStr::from_ptr(entry.d_name.as_ptr()), entry.d_type)
+                };
+                let name = OsString::from_vec(name.to_bytes().to_vec());
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let kind = match d_type {
+                    DT_DIR => Ok(FileKind::Dir),
+                    DT_REG => Ok(FileKind::File),
+                    DT_LNK => Ok(FileKind::Symlink),
+                    DT_UNKNOWN => self.kind_of(&name),
+                    _ => Ok(FileKind::Other),
+                };
+                entries.push(kind.map(|kind| (name, kind)));
+            }
+            // SAFETY: `stream` is valid and not used afterwards.
+            unsafe { closedir(stream) };
+            Ok(entries)
+        }
+
+        /// Returns the kind of an entry, for filesystems that do not report it when listing.
+        fn kind_of(&self, name: &OsStr) -> io::Result<FileKind> {
+            let fd = open_in(self.fd.as_raw_fd(), name, O_PATH | O_NOFOLLOW)?;
+            Ok(File::from(fd).metadata()?.file_type().into())
+        }
+
+        /// Removes the file or symbolic link `name`.
+        pub(crate) fn remove_file(&self, name: &OsStr) -> io::Result<()> {
+            self.unlink(name, 0)
+        }
+
+        /// Removes the empty subdirectory `name`.
+        pub(crate) fn remove_dir(&self, name: &OsStr) -> io::Result<()> {
+            self.unlink(name, AT_REMOVEDIR)
+        }
+
+        /// Gives the owner full access to the subdirectory `name`, so its entries can be
+        /// listed and removed; fails if it is not a directory.
+        pub(crate) fn grant_owner(&self, name: &OsStr) -> io::Result<()> {
+            let fd = open_in(self.fd.as_raw_fd(), name, O_PATH | O_NOFOLLOW)?;
+            let meta = File::from(fd.try_clone()?).metadata()?;
+            if !meta.is_dir() {
+                return Err(not_a_dir(name));
+            }
+            // fchmod does not take O_PATH descriptors; the path of the descriptor can not
+            // be redirected
+            let path = c_str(format!("/proc/self/fd/{}", fd.as_raw_fd()).as_ref())?;
+            // SAFETY: `path` is a valid NUL-terminated string.
+            if unsafe { fchmodat(AT_FDCWD, path.as_ptr(), meta.mode() | 0o700, 0) } == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+
+        fn unlink(&self, name: &OsStr, flags: c_int) -> io::Result<()> {
+            let c_name = c_str(name)?;
+            // SAFETY: the descriptor is open and `c_name` is a valid NUL-terminated string.
+            if unsafe { unlinkat(self.fd.as_raw_fd(), c_name.as_ptr(), flags) } == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::{not_a_dir, DirEntry};
+
+    /// A directory, known by its path.
+    #[derive(Debug)]
+    pub(crate) struct Dir {
+        path: PathBuf,
+    }
+
+    impl Dir {
+        /// Opens the directory at `path` (following symbolic links, like `fs::read_dir`).
+        pub(crate) fn open(path: &Path) -> io::Result<Dir> {
+            if !fs::metadata(path)?.is_dir() {
+                return Err(not_a_dir(path.as_os_str()));
+            }
+            Ok(Dir {
+                path: path.to_path_buf(),
+            })
+        }
+
+        /// Opens the subdirectory `name`; fails if it is a symbolic link.
+        pub(crate) fn open_dir(&self, name: &OsStr) -> io::Result<Dir> {
+            let path = self.path.join(name);
+            if !fs::symlink_metadata(&path)?.is_dir() {
+                return Err(not_a_dir(name));
+            }
+            Ok(Dir { path })
+        }
+
+        /// Lists the directory.
+        pub(crate) fn entries(&self) -> io::Result<Vec<DirEntry>> {
+            Ok(fs::read_dir(&self.path)?
+                .map(|entry| {
+                    let entry = entry?;
+                    Ok((entry.file_name(), entry.file_type()?.into()))
+                })
+                .collect())
+        }
+
+        /// Removes the file or symbolic link `name`.
+        pub(crate) fn remove_file(&self, name: &OsStr) -> io::Result<()> {
+            fs::remove_file(self.path.join(name))
+        }
+
+        /// Removes the empty subdirectory `name`.
+        pub(crate) fn remove_dir(&self, name: &OsStr) -> io::Result<()> {
+            fs::remove_dir(self.path.join(name))
+        }
+
+        /// Gives the owner full access to the subdirectory `name`, so its entries can be
+        /// listed and removed; fails if it is not a directory. On Windows, where read-only
+        /// files can not be removed, clears the read-only attribute of files too.
+        #[cfg(unix)]
+        pub(crate) fn grant_owner(&self, name: &OsStr) -> io::Result<()> {
+            use std::os::unix::fs::PermissionsExt;
+
+            let path = self.path.join(name);
+            let meta = fs::symlink_metadata(&path)?;
+            if !meta.is_dir() {
+                return Err(not_a_dir(name));
+            }
+            let mut permissions = meta.permissions();
+            permissions.set_mode(permissions.mode() | 0o700);
+            fs::set_permissions(path, permissions)
+        }
+
+        #[cfg(not(unix))]
+        pub(crate) fn grant_owner(&self, name: &OsStr) -> io::Result<()> {
+            let path = self.path.join(name);
+            let meta = fs::symlink_metadata(&path)?;
+            if meta.file_type().is_symlink() {
+                return Err(not_a_dir(name));
+            }
+            let mut permissions = meta.permissions();
+            permissions.set_readonly(false);
+            fs::set_permissions(path, permissions)
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use crate::dirfd::Dir;
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::FileKind;
+    use std::ffi::{OsStr, OsString};
+    use std::fs;
+
+    #[test]
+    fn dir_handle_survives_rename() {
+        let tree = TreeBuilder::new()
+            .file("a/b/1.txt", b"")
+            .symlink("a/link", "b")
+            .build()
+            .unwrap();
+        let a = Dir::open(&tree.join("a")).unwrap();
+        let b = a.open_dir(OsStr::new("b")).unwrap();
+        assert!(a.open_dir(OsStr::new("link")).is_err());
+
+        fs::rename(tree.join("a"), tree.join("moved")).unwrap();
+        fs::create_dir_all(tree.join("a/b")).unwrap();
+        let mut entries: Vec<(OsString, FileKind)> =
+            a.entries().unwrap().into_iter().map(Result::unwrap).collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            [
+                (OsString::from("b"), FileKind::Dir),
+                (OsString::from("link"), FileKind::Symlink)
+            ]
+        );
+        assert_eq!(b.entries().unwrap().len(), 1);
+        fs::remove_file(tree.join("moved/b/1.txt")).unwrap();
+        a.remove_dir(OsStr::new("b")).unwrap();
+        assert!(!tree.join("moved/b").exists());
+        assert!(tree.join("a/b").exists());
+    }
+}
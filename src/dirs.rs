@@ -0,0 +1,128 @@
+//! Standard per-user directories. Each location can be overridden with the environment
+//! variable of the platform convention: `HOME` and the XDG base directory variables on
+//! Linux and other Unix systems, `USERPROFILE`, `APPDATA` and `LOCALAPPDATA` on Windows.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Reads an environment variable.
+type Lookup<'a> = &'a dyn Fn(&str) -> Option<OsString>;
+
+fn env_lookup(name: &str) -> Option<OsString> {
+    env::var_os(name)
+}
+
+/// Returns the variable as a path if it is set to an absolute path; relative values are
+/// ignored, as the XDG specification requires.
+fn absolute_var(lookup: Lookup, name: &str) -> Option<PathBuf> {
+    lookup(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+fn home_in(lookup: Lookup) -> Option<PathBuf> {
+    let name = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    absolute_var(lookup, name)
+}
+
+/// Resolves a directory: the first variable set, else `fallback` under the home directory.
+fn resolve(lookup: Lookup, vars: &[&str], fallback: &str) -> Option<PathBuf> {
+    vars.iter()
+        .find_map(|name| absolute_var(lookup, name))
+        .or_else(|| home_in(lookup).map(|home| home.join(fallback)))
+}
+
+#[cfg(windows)]
+mod platform {
+    pub(super) const CACHE: (&[&str], &str) = (&["LOCALAPPDATA"], "AppData\\Local");
+    pub(super) const CONFIG: (&[&str], &str) = (&["APPDATA"], "AppData\\Roaming");
+    pub(super) const DATA: (&[&str], &str) = (&["APPDATA"], "AppData\\Roaming");
+    pub(super) const DOWNLOADS: (&[&str], &str) = (&[], "Downloads");
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    pub(super) const CACHE: (&[&str], &str) = (&["XDG_CACHE_HOME"], "Library/Caches");
+    pub(super) const CONFIG: (&[&str], &str) =
+        (&["XDG_CONFIG_HOME"], "Library/Application Support");
+    pub(super) const DATA: (&[&str], &str) = (&["XDG_DATA_HOME"], "Library/Application Support");
+    pub(super) const DOWNLOADS: (&[&str], &str) = (&["XDG_DOWNLOAD_DIR"], "Downloads");
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    pub(super) const CACHE: (&[&str], &str) = (&["XDG_CACHE_HOME"], ".cache");
+    pub(super) const CONFIG: (&[&str], &str) = (&["XDG_CONFIG_HOME"], ".config");
+    pub(super) const DATA: (&[&str], &str) = (&["XDG_DATA_HOME"], ".local/share");
+    pub(super) const DOWNLOADS: (&[&str], &str) = (&["XDG_DOWNLOAD_DIR"], "Downloads");
+}
+
+/// Returns the home directory of the current user, if known.
+pub fn home_dir() -> Option<PathBuf> {
+    home_in(&env_lookup)
+}
+
+/// Returns the directory for non-essential cached data (`~/.cache` on Linux,
+/// `~/Library/Caches` on macOS, `%LOCALAPPDATA%` on Windows).
+pub fn cache_dir() -> Option<PathBuf> {
+    let (vars, fallback) = platform::CACHE;
+    resolve(&env_lookup, vars, fallback)
+}
+
+/// Returns the directory for configuration files (`~/.config` on Linux,
+/// `~/Library/Application Support` on macOS, `%APPDATA%` on Windows).
+pub fn config_dir() -> Option<PathBuf> {
+    let (vars, fallback) = platform::CONFIG;
+    resolve(&env_lookup, vars, fallback)
+}
+
+/// Returns the directory for application data (`~/.local/share` on Linux,
+/// `~/Library/Application Support` on macOS, `%APPDATA%` on Windows).
+pub fn data_dir() -> Option<PathBuf> {
+    let (vars, fallback) = platform::DATA;
+    resolve(&env_lookup, vars, fallback)
+}
+
+/// Returns the directory for downloaded files (`~/Downloads` unless overridden).
+pub fn downloads_dir() -> Option<PathBuf> {
+    let (vars, fallback) = platform::DOWNLOADS;
+    resolve(&env_lookup, vars, fallback)
+}
+
+/// Returns the directory for temporary files (`TMPDIR` on Unix, `TMP` or `TEMP` on Windows).
+pub fn temp_dir() -> PathBuf {
+    env::temp_dir()
+}
+
+#[cfg(test)]
+#[cfg(not(windows))]
+mod tests {
+    use crate::dirs::{platform, resolve};
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    #[test]
+    fn standard_dirs_with_overrides() {
+        let lookup = |name: &str| -> Option<OsString> {
+            match name {
+                "HOME" => Some("/home/u".into()),
+                "XDG_CACHE_HOME" => Some("/var/cache/u".into()),
+                "XDG_CONFIG_HOME" => Some("relative".into()),
+                _ => None,
+            }
+        };
+        let (vars, fallback) = platform::CACHE;
+        assert_eq!(
+            resolve(&lookup, vars, fallback),
+            Some(PathBuf::from("/var/cache/u"))
+        );
+        // relative overrides are ignored
+        let (vars, fallback) = platform::CONFIG;
+        assert_eq!(
+            resolve(&lookup, vars, fallback),
+            Some(PathBuf::from("/home/u").join(fallback))
+        );
+        assert_eq!(resolve(&|_| None, vars, fallback), None);
+    }
+}
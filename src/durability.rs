@@ -0,0 +1,45 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How much mutating operations flush to disk before they return, trading speed for
+/// crash consistency.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave flushing to the operating system; a crash may lose recent writes.
+    #[default]
+    None,
+    /// Flush the contents of written files (`fdatasync`).
+    DataOnly,
+    /// Also flush the directories that gained entries, so new and renamed names survive
+    /// a crash. Directories can only be flushed on Unix.
+    DataAndDirs,
+}
+
+impl Durability {
+    /// Flushes a written file as required.
+    pub(crate) fn sync_file(self, file: &fs::File) -> io::Result<()> {
+        match self {
+            Durability::None => Ok(()),
+            Durability::DataOnly | Durability::DataAndDirs => file.sync_data(),
+        }
+    }
+
+    /// Flushes a directory whose entries changed, as required.
+    pub(crate) fn sync_dir(self, dir: &Path) -> io::Result<()> {
+        match self {
+            Durability::DataAndDirs => sync_dir(dir),
+            Durability::None | Durability::DataOnly => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dirfd::Dir;
+use crate::entry::Entry;
+use crate::paths::simplify_verbatim;
+use crate::result::{Error, Result};
+use crate::vfs::FileKind;
+use crate::visit::{walk, Control, Visitor};
+
+struct EmptyDirs<F> {
+    ignored: F,
+    /// Directories being visited, with whether they contain anything.
+    open: Vec<bool>,
+    empty: Vec<PathBuf>,
+}
+
+impl<F: Fn(&Path) -> bool> Visitor for EmptyDirs<F> {
+    fn enter_dir(&mut self, _dir: &Path, _depth: usize) -> Control {
+        self.open.push(false);
+        Control::Continue
+    }
+
+    fn file(&mut self, entry: &Entry) -> Control {
+        if !(self.ignored)(entry.path()) {
+            *self.open.last_mut().unwrap() = true;
+        }
+        Control::Continue
+    }
+
+    fn leave_dir(&mut self, dir: &Path, depth: usize) -> Control {
+        let has_content = self.open.pop().unwrap();
+        if has_content {
+            if let Some(parent) = self.open.last_mut() {
+                *parent = true;
+            }
+        } else if depth > 0 {
+            self.empty.push(dir.to_path_buf());
+        }
+        Control::Continue
+    }
+
+    fn error(&mut self, _path: &Path, _error: Error) -> Control {
+        // what can not be read is not known to be empty
+        if let Some(current) = self.open.last_mut() {
+            *current = true;
+        }
+        Control::Continue
+    }
+}
+
+/// Returns the directories under `root` that contain no files, only (possibly nested)
+/// empty directories. Subdirectories come before their parents; the root is not included.
+pub fn find_empty_dirs<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>> {
+    find_empty_dirs_ignoring(root, |_| false)
+}
+
+/// Like [`find_empty_dirs`], but files for which `ignored` returns true
+/// (e.g. `.DS_Store` or `Thumbs.db`) do not count as contents.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `ignored` - decides which files are disregarded.
+pub fn find_empty_dirs_ignoring<P, F>(root: P, ignored: F) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> bool,
+{
+    let mut visitor = EmptyDirs {
+        ignored,
+        open: Vec::new(),
+        empty: Vec::new(),
+    };
+    walk(root, &mut visitor)?;
+    Ok(visitor.empty)
+}
+
+/// Removes the directories under `root` that contain no files, e.g. after a selective
+/// delete. The root itself is kept. Returns the removed directories, subdirectories
+/// before their parents.
+///
+/// Directories are opened and removed relative to their open parent, so one renamed or
+/// replaced with a link meanwhile can not make it remove anything outside `root`.
+pub fn remove_empty_dirs<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>> {
+    remove_empty_dirs_ignoring(root, |_| false)
+}
+
+/// Like [`remove_empty_dirs`], but files for which `ignored` returns true do not count as
+/// contents, like in [`find_empty_dirs_ignoring`]: they are deleted along with the
+/// directories that hold nothing else, and kept in the others.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `ignored` - decides which files are disregarded.
+pub fn remove_empty_dirs_ignoring<P, F>(root: P, ignored: F) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> bool,
+{
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let mut removed = Vec::new();
+    remove_empty_in(&Dir::open(&root)?, &root, &ignored, &mut removed)?;
+    Ok(removed)
+}
+
+/// Removes the empty subdirectories of `dir` and returns whether it is empty now, apart
+/// from ignored files. What can not be read is not known to be empty and is kept.
+fn remove_empty_in(
+    dir: &Dir,
+    path: &Path,
+    ignored: &dyn Fn(&Path) -> bool,
+    removed: &mut Vec<PathBuf>,
+) -> Result<bool> {
+    let entries = match dir.entries() {
+        Ok(entries) => entries,
+        Err(_) => return Ok(false),
+    };
+    let mut empty = true;
+    let mut ignored_files = Vec::new();
+    for entry in entries {
+        let name = match entry {
+            Ok((name, FileKind::Dir)) => name,
+            Ok((name, _)) if ignored(&path.join(&name)) => {
+                ignored_files.push(name);
+                continue;
+            }
+            _ => {
+                empty = false;
+                continue;
+            }
+        };
+        let sub_path = path.join(&name);
+        let sub_empty = match dir.open_dir(&name) {
+            Ok(sub_dir) => remove_empty_in(&sub_dir, &sub_path, ignored, removed)?,
+            Err(_) => false,
+        };
+        if sub_empty {
+            dir.remove_dir(&name)?;
+            removed.push(sub_path);
+        } else {
+            empty = false;
+        }
+    }
+    if empty {
+        for name in ignored_files {
+            dir.remove_file(&name)?;
+        }
+    }
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::empty::{
+        find_empty_dirs, find_empty_dirs_ignoring, remove_empty_dirs, remove_empty_dirs_ignoring,
+    };
+    use crate::fixture::TreeBuilder;
+    use std::path::Path;
+
+    #[test]
+    fn empty_dirs_found_and_removed() {
+        let tree = TreeBuilder::new()
+            .dir("a/b/c")
+            .dir("a/d")
+            .file("e/1.txt", b"")
+            .dir("e/f")
+            .file("g/.DS_Store", b"")
+            .build()
+            .unwrap();
+
+        let empty = find_empty_dirs(tree.path()).unwrap();
+        assert_eq!(empty.len(), 5);
+        assert_eq!(empty.last().unwrap(), &tree.join("a"));
+
+        let ignoring = find_empty_dirs_ignoring(tree.path(), |p| p.ends_with(".DS_Store")).unwrap();
+        assert!(ignoring.contains(&tree.join("g")));
+
+        assert_eq!(remove_empty_dirs(tree.path()).unwrap().len(), 5);
+        assert!(!tree.join("a").exists());
+        assert!(tree.join("e/1.txt").exists() && !tree.join("e/f").exists());
+        assert!(tree.join("g/.DS_Store").exists());
+    }
+
+    #[test]
+    fn empty_dirs_removed_ignoring() {
+        let tree = TreeBuilder::new()
+            .file("a/.DS_Store", b"")
+            .file("a/b/Thumbs.db", b"")
+            .file("c/.DS_Store", b"")
+            .file("c/1.txt", b"")
+            .file(".DS_Store", b"")
+            .build()
+            .unwrap();
+        let ignored = |p: &Path| p.ends_with(".DS_Store") || p.ends_with("Thumbs.db");
+
+        let removed = remove_empty_dirs_ignoring(tree.path(), ignored).unwrap();
+        assert_eq!(removed, [tree.join("a/b"), tree.join("a")]);
+        assert!(tree.join("c/.DS_Store").exists() && tree.join("c/1.txt").exists());
+        assert!(tree.join(".DS_Store").exists());
+    }
+}
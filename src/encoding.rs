@@ -0,0 +1,145 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use crate::result::{Error, ErrorKind, Result};
+
+/// Encodes a path into a printable string that can be decoded back losslessly
+/// with [`decode_path`], e.g. for storing in snapshots or JSON.
+///
+/// Valid Unicode is kept as is, except `%` and control characters. Those, and every byte
+/// that is not valid UTF-8 (or unpaired surrogate on Windows), are escaped as `%XX` (`%uXXXX`).
+pub fn encode_path<P: AsRef<Path>>(path: P) -> String {
+    encode_os_str(path.as_ref().as_os_str())
+}
+
+/// Decodes a string produced by [`encode_path`].
+pub fn decode_path(s: &str) -> Result<PathBuf> {
+    decode_os_str(s).map(PathBuf::from)
+}
+
+fn push_char(out: &mut String, c: char) {
+    if c == '%' || c.is_control() {
+        let mut buf = [0u8; 4];
+        for b in c.encode_utf8(&mut buf).bytes() {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    } else {
+        out.push(c);
+    }
+}
+
+fn invalid(s: &str) -> Error {
+    Error::new(ErrorKind::Encoding, format!("invalid encoded path: {}", s))
+}
+
+fn hex(s: &str, from: usize, len: usize) -> Option<u32> {
+    let digits = s.get(from..from + len)?;
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(unix)]
+fn encode_os_str(s: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut out = String::with_capacity(s.len());
+    for chunk in s.as_bytes().utf8_chunks() {
+        chunk.valid().chars().for_each(|c| push_char(&mut out, c));
+        for b in chunk.invalid() {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(unix)]
+fn decode_os_str(s: &str) -> Result<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s.as_bytes()[i] == b'%' {
+            let b = hex(s, i + 1, 2).ok_or_else(|| invalid(s))?;
+            bytes.push(b as u8);
+            i += 3;
+        } else {
+            bytes.push(s.as_bytes()[i]);
+            i += 1;
+        }
+    }
+    Ok(OsString::from_vec(bytes))
+}
+
+#[cfg(windows)]
+fn encode_os_str(s: &OsStr) -> String {
+    use std::os::windows::ffi::OsStrExt;
+
+    let mut out = String::with_capacity(s.len());
+    for c in char::decode_utf16(s.encode_wide()) {
+        match c {
+            Ok(c) => push_char(&mut out, c),
+            Err(e) => out.push_str(&format!("%u{:04X}", e.unpaired_surrogate())),
+        }
+    }
+    out
+}
+
+#[cfg(windows)]
+fn decode_os_str(s: &str) -> Result<OsString> {
+    use std::os::windows::ffi::OsStringExt;
+
+    let mut wide: Vec<u16> = Vec::with_capacity(s.len());
+    let mut utf8: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with("%u") {
+            let w = hex(s, i + 2, 4).ok_or_else(|| invalid(s))?;
+            wide.push(w as u16);
+            i += 6;
+        } else if s.as_bytes()[i] == b'%' {
+            utf8.clear();
+            while s[i..].starts_with('%') && !s[i..].starts_with("%u") {
+                utf8.push(hex(s, i + 1, 2).ok_or_else(|| invalid(s))? as u8);
+                i += 3;
+            }
+            let decoded = std::str::from_utf8(&utf8).map_err(|_| invalid(s))?;
+            wide.extend(decoded.encode_utf16());
+        } else {
+            let c = s[i..].chars().next().unwrap();
+            let mut buf = [0u16; 2];
+            wide.extend_from_slice(c.encode_utf16(&mut buf));
+            i += c.len_utf8();
+        }
+    }
+    Ok(OsString::from_wide(&wide))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::{decode_path, encode_path};
+    use std::path::PathBuf;
+
+    #[test]
+    fn encode_unicode_path() {
+        let path = PathBuf::from("/tmp/100%/файл\n.txt");
+        let encoded = encode_path(&path);
+        assert_eq!(encoded, "/tmp/100%25/файл%0A.txt");
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+        assert!(decode_path("/tmp/%G1").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encode_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = PathBuf::from(OsStr::from_bytes(b"/tmp/bad\xff\xfename"));
+        let encoded = encode_path(&path);
+        assert_eq!(encoded, "/tmp/bad%FF%FEname");
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+    }
+}
@@ -0,0 +1,247 @@
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+
+use crate::attrs::FileAttrs;
+use crate::result::Result;
+use crate::vfs::{FsMetadata, ReadFs, RealFs};
+
+/// An entry yielded by the ReadDir iterator.
+/// Metadata is read on first use and cached, so entries that are only matched by path
+/// cost no extra system calls.
+#[derive(Clone)]
+pub struct Entry {
+    path: EntryPath,
+    depth: usize,
+    fs: Option<Arc<dyn ReadFs>>,
+    meta: OnceLock<FsMetadata>,
+}
+
+#[derive(Clone)]
+enum EntryPath {
+    Full(PathBuf),
+    /// The path of the directory, shared with the entry's siblings, and the name; joined
+    /// on first use.
+    Shared {
+        dir: Arc<Path>,
+        name: OsString,
+        full: OnceLock<PathBuf>,
+    },
+}
+
+impl Entry {
+    pub(crate) fn new(path: PathBuf, depth: usize) -> Entry {
+        Entry {
+            path: EntryPath::Full(path),
+            depth,
+            fs: None,
+            meta: OnceLock::new(),
+        }
+    }
+
+    /// Creates an entry by name in a directory shared with its siblings, whose metadata is
+    /// read from the given filesystem.
+    pub(crate) fn shared(
+        dir: Arc<Path>,
+        name: OsString,
+        depth: usize,
+        fs: Arc<dyn ReadFs>,
+    ) -> Entry {
+        Entry {
+            path: EntryPath::Shared {
+                dir,
+                name,
+                full: OnceLock::new(),
+            },
+            depth,
+            fs: Some(fs),
+            meta: OnceLock::new(),
+        }
+    }
+
+    /// Creates an entry whose metadata is read from the given filesystem.
+    pub(crate) fn new_in(path: PathBuf, depth: usize, fs: Arc<dyn ReadFs>) -> Entry {
+        Entry {
+            fs: Some(fs),
+            ..Entry::new(path, depth)
+        }
+    }
+
+    /// Caches metadata already known, e.g. returned with the directory listing.
+    pub(crate) fn with_metadata(self, meta: Option<FsMetadata>) -> Entry {
+        Entry {
+            meta: meta.map(OnceLock::from).unwrap_or_default(),
+            ..self
+        }
+    }
+
+    /// Returns the full path of the entry.
+    pub fn path(&self) -> &Path {
+        match &self.path {
+            EntryPath::Full(path) => path,
+            EntryPath::Shared { dir, name, full } => full.get_or_init(|| dir.join(name)),
+        }
+    }
+
+    /// Consumes the entry and returns its path.
+    pub fn into_path(self) -> PathBuf {
+        match self.path {
+            EntryPath::Full(path) => path,
+            EntryPath::Shared { dir, name, full } => {
+                full.into_inner().unwrap_or_else(|| dir.join(name))
+            }
+        }
+    }
+
+    /// Returns the last component of the path, without building the full path.
+    pub fn file_name(&self) -> &OsStr {
+        match &self.path {
+            EntryPath::Full(path) => path.file_name().unwrap_or(path.as_os_str()),
+            EntryPath::Shared { name, .. } => name,
+        }
+    }
+
+    /// Returns the path of the entry's directory, shared with its siblings, if the entry was
+    /// yielded by a traversal with `shared_dirs` set.
+    pub fn shared_dir(&self) -> Option<&Arc<Path>> {
+        match &self.path {
+            EntryPath::Full(_) => None,
+            EntryPath::Shared { dir, .. } => Some(dir),
+        }
+    }
+
+    /// Returns the path as a string, replacing invalid Unicode sequences
+    /// with `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn path_lossy(&self) -> Cow<'_, str> {
+        self.path().to_string_lossy()
+    }
+
+    /// Returns the raw bytes of the path.
+    #[cfg(unix)]
+    pub fn path_bytes(&self) -> &[u8] {
+        use std::os::unix::ffi::OsStrExt;
+        self.path().as_os_str().as_bytes()
+    }
+
+    /// Returns the depth of the entry relative to the root directory.
+    /// Files located directly in the root have depth 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the metadata of the entry, without following symbolic links.
+    /// Read on the first call and cached; a failed read is retried on the next call.
+    pub fn metadata(&self) -> Result<&FsMetadata> {
+        if let Some(meta) = self.meta.get() {
+            return Ok(meta);
+        }
+        let meta = match &self.fs {
+            Some(fs) => fs.symlink_metadata(self.path())?,
+            None => RealFs.symlink_metadata(self.path())?,
+        };
+        Ok(self.meta.get_or_init(|| meta))
+    }
+
+    /// Returns the size of the entry in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> Result<u64> {
+        Ok(self.metadata()?.len)
+    }
+
+    /// Returns the last modification time of the entry, if the filesystem records it.
+    pub fn modified(&self) -> Result<Option<SystemTime>> {
+        Ok(self.metadata()?.modified)
+    }
+
+    /// Returns the inode flags of the entry (immutable, append-only), read on every call.
+    /// Symbolic links are followed.
+    pub fn attrs(&self) -> Result<FileAttrs> {
+        let attrs = match &self.fs {
+            Some(fs) => fs.attrs(self.path())?,
+            None => RealFs.attrs(self.path())?,
+        };
+        Ok(attrs)
+    }
+
+    /// Checks whether the entry has alternate data streams (NTFS), read on every call.
+    /// Always false on other platforms.
+    pub fn has_streams(&self) -> Result<bool> {
+        let streams = match &self.fs {
+            Some(fs) => fs.streams(self.path())?,
+            None => RealFs.streams(self.path())?,
+        };
+        Ok(!streams.is_empty())
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("path", &self.path())
+            .field("depth", &self.depth)
+            .finish()
+    }
+}
+
+/// Entries are equal if their paths and depths are; cached metadata is not compared.
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.path() == other.path() && self.depth == other.depth
+    }
+}
+
+impl Eq for Entry {}
+
+impl AsRef<Path> for Entry {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::FileKind;
+    use crate::ReadDir;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn entry_metadata_cached() {
+        let tree = TreeBuilder::new().file("a.txt", b"12345").build().unwrap();
+        let entry = ReadDir::try_new(tree.path()).unwrap().next().unwrap();
+        assert_eq!(entry.metadata().unwrap().kind, FileKind::File);
+        fs::write(tree.join("a.txt"), b"1").unwrap();
+        assert_eq!(entry.len().unwrap(), 5);
+        assert!(entry.modified().unwrap().is_some());
+    }
+
+    #[test]
+    fn entries_share_dirs() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"12345")
+            .file("b.txt", b"")
+            .file("sub/c.txt", b"")
+            .build()
+            .unwrap();
+        for lazy in [false, true] {
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.shared_dirs = true;
+            rd.is_lazy = lazy;
+            let mut entries: Vec<_> = rd.collect();
+            entries.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+            assert_eq!(entries[0].file_name(), "a.txt");
+            let (a, b) = (entries[0].shared_dir().unwrap(), entries[1].shared_dir().unwrap());
+            assert!(Arc::ptr_eq(a, b));
+            assert_eq!(entries[0].path(), tree.join("a.txt"));
+            assert_eq!(entries[0].len().unwrap(), 5);
+            assert_eq!(entries[2].clone().into_path(), tree.join("sub/c.txt"));
+        }
+        let entry = ReadDir::try_new(tree.path()).unwrap().next().unwrap();
+        assert!(entry.shared_dir().is_none());
+        assert!(entry.file_name().to_string_lossy().ends_with(".txt"));
+    }
+}
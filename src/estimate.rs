@@ -0,0 +1,141 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::fold::walk_fold;
+use crate::format::{format_count, format_duration, format_rate, format_size};
+use crate::progress::Operation;
+use crate::result::Result;
+
+/// Processing speed of an operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub bytes_per_sec: f64,
+    pub files_per_sec: f64,
+}
+
+impl Throughput {
+    /// Computes the throughput of a finished run, to be used for later estimates.
+    pub fn measured(bytes: u64, files: u64, elapsed: Duration) -> Throughput {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        Throughput {
+            bytes_per_sec: bytes as f64 / secs,
+            files_per_sec: files as f64 / secs,
+        }
+    }
+
+    /// Rough default throughput of an operation on a local SSD.
+    pub fn default_for(operation: Operation) -> Throughput {
+        match operation {
+            Operation::Scan => Throughput {
+                bytes_per_sec: f64::INFINITY,
+                files_per_sec: 50_000.0,
+            },
+            Operation::Hash => Throughput {
+                bytes_per_sec: 400e6,
+                files_per_sec: 5_000.0,
+            },
+            Operation::Copy | Operation::Sync => Throughput {
+                bytes_per_sec: 200e6,
+                files_per_sec: 1_000.0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Throughput {
+    /// Formats the throughput for people, e.g. `190.7 MiB/s, 1,000 files/s`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {} files/s",
+            format_rate(self.bytes_per_sec),
+            format_count(self.files_per_sec.round() as u64)
+        )
+    }
+}
+
+/// An operation to estimate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpPlan {
+    pub operation: Operation,
+    /// Trees the operation will process.
+    pub roots: Vec<PathBuf>,
+    /// Throughput of previous runs; defaults to [`Throughput::default_for`].
+    pub throughput: Option<Throughput>,
+}
+
+/// Estimated size and duration of an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    pub files: u64,
+    pub bytes: u64,
+    pub est_duration: Duration,
+}
+
+impl fmt::Display for Estimate {
+    /// Formats the estimate for people, e.g. `1,234 files, 1.2 GiB, about 4m 05s`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {}, about {}",
+            format_count(self.files),
+            format_size(self.bytes),
+            format_duration(self.est_duration)
+        )
+    }
+}
+
+/// Estimates an operation with a quick metadata-only pass over its roots.
+pub fn estimate(plan: &OpPlan) -> Result<Estimate> {
+    let (mut files, mut bytes) = (0u64, 0u64);
+    for root in &plan.roots {
+        let (f, b) = walk_fold(
+            root,
+            |_| (0u64, 0u64),
+            |(files, bytes), entry| {
+                let len = fs::symlink_metadata(entry.path()).map_or(0, |m| m.len());
+                (files + 1, bytes + len)
+            },
+            |a, b| (a.0 + b.0, a.1 + b.1),
+        )?;
+        files += f;
+        bytes += b;
+    }
+    let throughput = plan
+        .throughput
+        .unwrap_or_else(|| Throughput::default_for(plan.operation));
+    let secs = bytes as f64 / throughput.bytes_per_sec + files as f64 / throughput.files_per_sec;
+    Ok(Estimate {
+        files,
+        bytes,
+        est_duration: Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::estimate::{estimate, OpPlan, Throughput};
+    use crate::fixture::TreeBuilder;
+    use crate::Operation;
+    use std::time::Duration;
+
+    #[test]
+    fn estimate_copy() {
+        let tree = TreeBuilder::new()
+            .file("a", vec![0u8; 1000])
+            .file("sub/b", vec![0u8; 1000])
+            .build()
+            .unwrap();
+
+        let plan = OpPlan {
+            operation: Operation::Copy,
+            roots: vec![tree.path().to_path_buf()],
+            throughput: Some(Throughput::measured(1000, 1, Duration::from_secs(1))),
+        };
+        let estimate = estimate(&plan).unwrap();
+        assert_eq!((estimate.files, estimate.bytes), (2, 2000));
+        assert_eq!(estimate.est_duration, Duration::from_secs(4));
+    }
+}
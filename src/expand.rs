@@ -0,0 +1,201 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Expands `~`, `~user` and environment variables (`$VAR`, `${VAR}` and `%VAR%`) in a
+/// user-supplied path, like a shell would. Unknown users and variables are left unchanged;
+/// paths that are not valid Unicode are returned as they are.
+pub fn expand<P: AsRef<Path>>(path: P) -> PathBuf {
+    expand_with(path, |name| env::var(name).ok())
+}
+
+/// Like [`expand`], with variables (and the home directory, from `HOME` or `USERPROFILE`)
+/// looked up by `lookup` instead of the environment.
+///
+/// # Arguments:
+///
+/// * `path` - path to expand.
+/// * `lookup` - returns the value of a variable.
+pub fn expand_with<P, F>(path: P, lookup: F) -> PathBuf
+where
+    P: AsRef<Path>,
+    F: Fn(&str) -> Option<String>,
+{
+    let path = path.as_ref();
+    let text = match path.to_str() {
+        Some(text) => text,
+        None => return path.to_path_buf(),
+    };
+    let text = expand_tilde(text, &lookup);
+    PathBuf::from(expand_vars(&text, &lookup))
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || (cfg!(windows) && c == '\\')
+}
+
+/// Replaces a leading `~` or `~user` with the home directory.
+fn expand_tilde(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let rest = match text.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return text.to_string(),
+    };
+    let end = rest.find(is_separator).unwrap_or(rest.len());
+    let (user, tail) = rest.split_at(end);
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    let home = match user {
+        "" => lookup(var),
+        user => sys::home_of(user, lookup(var)),
+    };
+    match home {
+        Some(home) => home + tail,
+        None => text.to_string(),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Replaces `$VAR`, `${VAR}` and `%VAR%` with the values of known variables.
+fn expand_vars(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '%']) {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        // (variable name, length of the reference including the sigil)
+        let reference = match rest.as_bytes()[start] {
+            b'$' if after.starts_with('{') => {
+                after.find('}').map(|close| (&after[1..close], close + 2))
+            }
+            b'$' => {
+                let len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+                let starts_ok = after.starts_with(|c: char| !c.is_ascii_digit());
+                (len > 0 && starts_ok).then(|| (&after[..len], len + 1))
+            }
+            _ => after
+                .find('%')
+                .map(|close| (&after[..close], close + 2))
+                .filter(|(name, _)| !name.is_empty() && name.chars().all(is_name_char)),
+        };
+        match reference.and_then(|(name, len)| Some((lookup(name)?, len))) {
+            Some((value, len)) => {
+                out.push_str(&value);
+                rest = &rest[start + len..];
+            }
+            None => {
+                out.push_str(&rest[start..start + 1]);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{c_char, c_int, CStr, CString};
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    #[repr(C)]
+    struct Passwd {
+        pw_name: *mut c_char,
+        pw_passwd: *mut c_char,
+        pw_uid: u32,
+        pw_gid: u32,
+        pw_gecos: *mut c_char,
+        pw_dir: *mut c_char,
+        pw_shell: *mut c_char,
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    #[repr(C)]
+    struct Passwd {
+        pw_name: *mut c_char,
+        pw_passwd: *mut c_char,
+        pw_uid: u32,
+        pw_gid: u32,
+        pw_change: i64,
+        pw_class: *mut c_char,
+        pw_gecos: *mut c_char,
+        pw_dir: *mut c_char,
+        pw_shell: *mut c_char,
+        pw_expire: i64,
+    }
+
+    extern "C" {
+        fn getpwnam_r(
+            name: *const c_char,
+            pwd: *mut Passwd,
+            buf: *mut c_char,
+            buflen: usize,
+            result: *mut *mut Passwd,
+        ) -> c_int;
+    }
+
+    /// Returns the home directory of a user from the user database.
+    pub(super) fn home_of(user: &str, _home: Option<String>) -> Option<String> {
+        let name = CString::new(user).ok()?;
+        let mut buf = vec![0 as c_char; 16 * 1024];
+        let mut pwd = MaybeUninit::<Passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: the buffers are valid for the sizes given; `result` is set on success.
+        let ret = unsafe {
+            getpwnam_r(
+                name.as_ptr(),
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if ret != 0 || result.is_null() {
+            return None;
+        }
+        // SAFETY: on success `pwd` is initialized and `pw_dir` points into `buf`.
+        let dir = unsafe { CStr::from_ptr((*result).pw_dir) };
+        dir.to_str().ok().map(str::to_string)
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::path::Path;
+
+    /// Guesses the home directory of a user as a sibling of the current user's one
+    /// (`C:\Users\name`), if it exists.
+    pub(super) fn home_of(user: &str, home: Option<String>) -> Option<String> {
+        let dir = Path::new(&home?).parent()?.join(user);
+        dir.is_dir().then(|| dir.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expand::expand_with;
+    use std::path::PathBuf;
+
+    #[test]
+    fn expand_home_and_vars() {
+        let lookup = |name: &str| match name {
+            "HOME" | "USERPROFILE" => Some("/home/u".to_string()),
+            "DIR" => Some("data".to_string()),
+            _ => None,
+        };
+        let expand = |path: &str| expand_with(path, lookup);
+        assert_eq!(expand("~"), PathBuf::from("/home/u"));
+        assert_eq!(expand("~/a/$DIR"), PathBuf::from("/home/u/a/data"));
+        assert_eq!(expand("/x/${DIR}1/%DIR%"), PathBuf::from("/x/data1/data"));
+        assert_eq!(expand("/x/$UNSET/%20/$"), PathBuf::from("/x/$UNSET/%20/$"));
+        assert_eq!(expand("a~/$1"), PathBuf::from("a~/$1"));
+        assert_eq!(
+            expand("~no-such-user-here/x"),
+            PathBuf::from("~no-such-user-here/x")
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(expand("~root/x"), PathBuf::from("/root/x"));
+    }
+}
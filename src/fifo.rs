@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use crate::result::Result;
+
+/// Creates a named pipe (FIFO) at `path` with the given permission bits (reduced by the
+/// umask), like `mkfifo`. Processes can then exchange data by opening it for reading and
+/// for writing; opening one end blocks until the other is opened.
+/// Fails with an error of kind `io::ErrorKind::Unsupported` on platforms without FIFOs;
+/// on Windows, named pipes live outside the filesystem, see `create_named_pipe`.
+///
+/// # Arguments:
+///
+/// * `path` - FIFO to create; fails if anything already exists there.
+/// * `mode` - permission bits, e.g. `0o600`.
+pub fn create_fifo<P: AsRef<Path>>(path: P, mode: u32) -> Result<()> {
+    Ok(sys::create(path.as_ref(), mode)?)
+}
+
+/// Returns the path of the Windows named pipe called `name`, i.e. `\\.\pipe\name`,
+/// which clients open with `std::fs::OpenOptions`.
+#[cfg(windows)]
+pub fn pipe_path(name: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!(r"\\.\pipe\{}", name))
+}
+
+/// Creates the Windows named pipe called `name`, for reading and writing bytes, and waits
+/// for a client to open it (at [`pipe_path`]). The returned file is the server end of
+/// the connection.
+///
+/// # Arguments:
+///
+/// * `name` - name of the pipe, without the `\\.\pipe\` prefix.
+#[cfg(windows)]
+pub fn create_named_pipe(name: &str) -> Result<std::fs::File> {
+    Ok(sys::create_named_pipe(&pipe_path(name))?)
+}
+
+#[cfg(unix)]
+pub(crate) mod sys {
+    use std::ffi::{c_char, c_int, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    type Mode = u16;
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    type Mode = u32;
+
+    extern "C" {
+        fn mkfifo(path: *const c_char, mode: Mode) -> c_int;
+    }
+
+    pub(crate) fn create(path: &Path, mode: u32) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        if unsafe { mkfifo(c_path.as_ptr(), (mode & 0o7777) as Mode) } == 0 {
+            Ok(())
+        } else {
+            let e = io::Error::last_os_error();
+            Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        }
+    }
+}
+
+#[cfg(windows)]
+pub(crate) mod sys {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::FromRawHandle;
+    use std::path::Path;
+    use std::ptr;
+
+    const PIPE_ACCESS_DUPLEX: u32 = 0x3;
+    const PIPE_TYPE_BYTE: u32 = 0x0;
+    const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+    const BUFFER_SIZE: u32 = 64 * 1024;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+    const INVALID_HANDLE_VALUE: isize = -1;
+
+    extern "system" {
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            max_instances: u32,
+            out_buffer_size: u32,
+            in_buffer_size: u32,
+            default_timeout: u32,
+            security: *mut c_void,
+        ) -> *mut c_void;
+        fn ConnectNamedPipe(pipe: *mut c_void, overlapped: *mut c_void) -> i32;
+    }
+
+    pub(crate) fn create(path: &Path, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: FIFOs are not supported", path.display()),
+        ))
+    }
+
+    pub(crate) fn create_named_pipe(path: &Path) -> io::Result<File> {
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        // SAFETY: `name` is a valid NUL-terminated wide string; no security attributes.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_DUPLEX,
+                PIPE_TYPE_BYTE,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if handle as isize == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the handle was just created and is owned by nobody else.
+        let file = unsafe { File::from_raw_handle(handle) };
+        // SAFETY: the handle is a pipe opened for synchronous I/O.
+        if unsafe { ConnectNamedPipe(handle, ptr::null_mut()) } == 0 {
+            let e = io::Error::last_os_error();
+            // a client that opened the pipe before the call is connected too
+            if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED) {
+                return Err(e);
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) mod sys {
+    use std::io;
+    use std::path::Path;
+
+    pub(crate) fn create(path: &Path, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: FIFOs are not supported", path.display()),
+        ))
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use crate::fifo::create_fifo;
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::{FileKind, Fs, MemFs, ReadFs, RealFs};
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::fs::FileTypeExt;
+    use std::path::Path;
+    use std::thread;
+
+    #[test]
+    fn fifo_round_trip() {
+        let tree = TreeBuilder::new().dir("ipc").build().unwrap();
+        let fifo = tree.join("ipc/pipe");
+        create_fifo(&fifo, 0o600).unwrap();
+        assert!(fs::symlink_metadata(&fifo).unwrap().file_type().is_fifo());
+        assert!(create_fifo(&fifo, 0o600).is_err());
+
+        let writer = {
+            let fifo = fifo.clone();
+            thread::spawn(move || fs::write(fifo, b"ping").unwrap())
+        };
+        let mut message = String::new();
+        fs::File::open(&fifo)
+            .unwrap()
+            .read_to_string(&mut message)
+            .unwrap();
+        writer.join().unwrap();
+        assert_eq!(message, "ping");
+
+        let other = tree.join("ipc/other");
+        RealFs.create_fifo(&other, 0o600).unwrap();
+        assert_eq!(RealFs.symlink_metadata(&other).unwrap().kind, FileKind::Other);
+        let err = MemFs::new()
+            .create_fifo(Path::new("/pipe"), 0o600)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,173 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::links::symlink;
+use crate::result::Result;
+use crate::vfs::MemFs;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+enum Item {
+    File(PathBuf, Vec<u8>),
+    Dir(PathBuf),
+    Symlink(PathBuf, PathBuf),
+}
+
+/// Describes a tree of files for tests and materializes it on disk or in a [`MemFs`]:
+///
+/// ```
+/// # use fs_helper::TreeBuilder;
+/// let tree = TreeBuilder::new()
+///     .file("a/b.txt", "hi")
+///     .dir("c")
+///     .symlink("d", "a")
+///     .build()
+///     .unwrap();
+/// assert!(tree.path().join("a/b.txt").exists());
+/// // the tree is removed when `tree` is dropped
+/// ```
+///
+/// Paths are relative to the root of the tree; missing parent directories are created.
+#[derive(Default)]
+pub struct TreeBuilder {
+    items: Vec<Item>,
+}
+
+impl TreeBuilder {
+    /// Creates an empty tree description.
+    pub fn new() -> TreeBuilder {
+        TreeBuilder::default()
+    }
+
+    /// Adds a file with the given contents.
+    pub fn file<P: AsRef<Path>, C: AsRef<[u8]>>(mut self, path: P, contents: C) -> TreeBuilder {
+        let item = Item::File(path.as_ref().to_path_buf(), contents.as_ref().to_vec());
+        self.items.push(item);
+        self
+    }
+
+    /// Adds a directory.
+    pub fn dir<P: AsRef<Path>>(mut self, path: P) -> TreeBuilder {
+        self.items.push(Item::Dir(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Adds a symbolic link at `link` pointing to `target` (stored as given, relative or absolute).
+    pub fn symlink<L: AsRef<Path>, T: AsRef<Path>>(mut self, link: L, target: T) -> TreeBuilder {
+        let item = Item::Symlink(link.as_ref().to_path_buf(), target.as_ref().to_path_buf());
+        self.items.push(item);
+        self
+    }
+
+    /// Creates the tree in a new unique directory under the system temp directory.
+    pub fn build(self) -> Result<TempTree> {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let root = env::temp_dir().join(format!("fs-helper-tree-{}-{}", process::id(), n));
+        self.build_at(root)
+    }
+
+    /// Creates the tree in `root`, which is removed when the returned [`TempTree`] is dropped.
+    pub fn build_at<P: AsRef<Path>>(self, root: P) -> Result<TempTree> {
+        let tree = TempTree {
+            root: root.as_ref().to_path_buf(),
+        };
+        fs::create_dir_all(&tree.root)?;
+        for item in self.items {
+            match item {
+                Item::File(path, contents) => {
+                    let path = tree.root.join(path);
+                    create_parent(&path)?;
+                    fs::write(path, contents)?;
+                }
+                Item::Dir(path) => fs::create_dir_all(tree.root.join(path))?,
+                Item::Symlink(link, target) => {
+                    let link = tree.root.join(link);
+                    create_parent(&link)?;
+                    symlink(&target, &link)?;
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Creates the tree under `root` of an in-memory filesystem.
+    pub fn build_in<P: AsRef<Path>>(self, fs: &MemFs, root: P) -> Result<()> {
+        use crate::vfs::Fs;
+
+        let root = root.as_ref();
+        fs.create_dir_all(root)?;
+        for item in self.items {
+            match item {
+                Item::File(path, contents) => fs.write(root.join(path), contents)?,
+                Item::Dir(path) => fs.create_dir_all(&root.join(path))?,
+                Item::Symlink(link, target) => fs.symlink(target, root.join(link))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// A tree created by [`TreeBuilder`], removed when dropped.
+#[derive(Debug)]
+pub struct TempTree {
+    root: PathBuf,
+}
+
+impl TempTree {
+    /// Returns the root directory of the tree.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path of an entry of the tree.
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::{FileKind, MemFs, ReadFs};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn build_trees() {
+        let builder = || {
+            TreeBuilder::new()
+                .file("a/b.txt", "hi")
+                .dir("c")
+                .symlink("d", "a")
+        };
+        let tree = builder().build().unwrap();
+        let root = tree.path().to_path_buf();
+        assert_eq!(fs::read(tree.join("d/b.txt")).unwrap(), b"hi");
+        assert!(tree.join("c").is_dir());
+        drop(tree);
+        assert!(!root.exists());
+
+        let mem = MemFs::new();
+        builder().build_in(&mem, "/t").unwrap();
+        assert_eq!(mem.read("/t/d/b.txt").unwrap(), b"hi");
+        let kind = mem.symlink_metadata(Path::new("/t/d")).unwrap().kind;
+        assert_eq!(kind, FileKind::Symlink);
+    }
+}
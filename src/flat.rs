@@ -0,0 +1,305 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::vec;
+
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+use crate::vfs::FileKind;
+
+/// An entry of a [`ReadDirFlat`] listing: only what the directory itself records,
+/// without a full path or metadata, so listing allocates one name per entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatEntry {
+    name: OsString,
+    kind: FileKind,
+}
+
+impl FlatEntry {
+    /// Returns the name of the entry.
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Returns the kind of the entry. Symbolic links are not followed.
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+
+    /// Returns the path of the entry in `dir`, the listed directory.
+    pub fn path_in<P: AsRef<Path>>(&self, dir: P) -> PathBuf {
+        dir.as_ref().join(&self.name)
+    }
+}
+
+/// Lists the entries of a single directory (`.` and `..` excluded), for directories far
+/// too large to list with `fs::read_dir` comfortably, e.g. mail spools or object stores
+/// with millions of files. Unlike ReadDir, it does not descend into subdirectories and
+/// yields them too.
+///
+/// On Linux, entries are read in large batches with `getdents64`; kinds come from the
+/// directory itself, so no entry is stat'ed unless the filesystem does not record kinds.
+/// By default entries are sorted by name, which requires reading all of them first;
+/// set `raw_order` to get them in the order of the directory as they are read.
+pub struct ReadDirFlat {
+    /// Yield entries in the order of the directory instead of sorted by name.
+    pub raw_order: bool,
+    /// Size of the buffer of each batch read, in bytes.
+    pub buffer_size: usize,
+    dir: PathBuf,
+    stream: Option<sys::Stream>,
+    sorted: Option<vec::IntoIter<Result<FlatEntry>>>,
+}
+
+impl ReadDirFlat {
+    /// Opens a directory for listing.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<ReadDirFlat> {
+        let dir = simplify_verbatim(fs::canonicalize(dir)?);
+        if !fs::metadata(&dir)?.is_dir() {
+            let message = format!("{}: not a directory", dir.display());
+            return Err(io::Error::new(io::ErrorKind::NotADirectory, message).into());
+        }
+        Ok(ReadDirFlat {
+            raw_order: false,
+            buffer_size: 256 * 1024,
+            dir,
+            stream: None,
+            sorted: None,
+        })
+    }
+
+    /// Returns the listed directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn next_raw(&mut self) -> Option<Result<FlatEntry>> {
+        if self.stream.is_none() {
+            match sys::Stream::open(&self.dir, self.buffer_size) {
+                Ok(stream) => self.stream = Some(stream),
+                Err(e) => {
+                    self.stream = Some(sys::Stream::finished());
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+        let (name, kind) = match self.stream.as_mut()?.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let kind = match kind {
+            Some(kind) => Ok(kind),
+            None => fs::symlink_metadata(self.dir.join(&name))
+                .map(|meta| meta.file_type().into())
+                .map_err(Into::into),
+        };
+        Some(kind.map(|kind| FlatEntry { name, kind }))
+    }
+}
+
+impl Iterator for ReadDirFlat {
+    type Item = Result<FlatEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw_order {
+            return self.next_raw();
+        }
+        if self.sorted.is_none() {
+            let mut entries: Vec<Result<FlatEntry>> = Vec::new();
+            while let Some(entry) = self.next_raw() {
+                entries.push(entry);
+            }
+            // errors first, then the entries by name
+            entries.sort_by(|a, b| match (a, b) {
+                (Ok(a), Ok(b)) => a.name.cmp(&b.name),
+                (a, b) => a.is_ok().cmp(&b.is_ok()),
+            });
+            self.sorted = Some(entries.into_iter());
+        }
+        self.sorted.as_mut()?.next()
+    }
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod sys {
+    use std::ffi::{c_long, OsString};
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::ffi::OsStringExt;
+    use std::path::Path;
+
+    use crate::vfs::FileKind;
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_GETDENTS64: c_long = 217;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_GETDENTS64: c_long = 61;
+    const DT_DIR: u8 = 4;
+    const DT_REG: u8 = 8;
+    const DT_LNK: u8 = 10;
+    const DT_UNKNOWN: u8 = 0;
+    /// Offset of the name in a `linux_dirent64` record.
+    const NAME_OFFSET: usize = 19;
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    /// Batched reader of the records of a directory.
+    pub(super) struct Stream {
+        file: Option<File>,
+        buf: Vec<u8>,
+        pos: usize,
+        len: usize,
+    }
+
+    impl Stream {
+        pub(super) fn open(dir: &Path, buffer_size: usize) -> io::Result<Stream> {
+            Ok(Stream {
+                file: Some(File::open(dir)?),
+                // a record is at most 280 bytes
+                buf: vec![0; buffer_size.max(4096)],
+                pos: 0,
+                len: 0,
+            })
+        }
+
+        pub(super) fn finished() -> Stream {
+            Stream {
+                file: None,
+                buf: Vec::new(),
+                pos: 0,
+                len: 0,
+            }
+        }
+
+        /// Reads the next batch; returns false at the end of the directory.
+        fn fill(&mut self) -> io::Result<bool> {
+            let file = match &self.file {
+                Some(file) => file,
+                None => return Ok(false),
+            };
+            // SAFETY: the descriptor is open and the buffer is valid for `buf.len()` bytes.
+            let n = unsafe {
+                syscall(
+                    SYS_GETDENTS64,
+                    file.as_raw_fd() as c_long,
+                    self.buf.as_mut_ptr(),
+                    self.buf.len() as c_long,
+                )
+            };
+            if n < 0 {
+                self.file = None;
+                return Err(io::Error::last_os_error());
+            }
+            if n == 0 {
+                self.file = None;
+                return Ok(false);
+            }
+            self.pos = 0;
+            self.len = n as usize;
+            Ok(true)
+        }
+
+        /// Returns the next name and kind, if the directory records it.
+        pub(super) fn next(&mut self) -> Option<io::Result<(OsString, Option<FileKind>)>> {
+            loop {
+                if self.pos >= self.len {
+                    match self.fill() {
+                        Ok(true) => {}
+                        Ok(false) => return None,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                let record = &self.buf[self.pos..self.len];
+                let reclen = u16::from_ne_bytes([record[16], record[17]]) as usize;
+                let d_type = record[18];
+                let name = &record[NAME_OFFSET..reclen];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                self.pos += reclen;
+                if name == b"." || name == b".." {
+                    continue;
+                }
+                let kind = match d_type {
+                    DT_DIR => Some(FileKind::Dir),
+                    DT_REG => Some(FileKind::File),
+                    DT_LNK => Some(FileKind::Symlink),
+                    DT_UNKNOWN => None,
+                    _ => Some(FileKind::Other),
+                };
+                return Some(Ok((OsString::from_vec(name.to_vec()), kind)));
+            }
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod sys {
+    use std::ffi::OsString;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    use crate::vfs::FileKind;
+
+    pub(super) struct Stream {
+        entries: Option<fs::ReadDir>,
+    }
+
+    impl Stream {
+        pub(super) fn open(dir: &Path, _buffer_size: usize) -> io::Result<Stream> {
+            Ok(Stream {
+                entries: Some(fs::read_dir(dir)?),
+            })
+        }
+
+        pub(super) fn finished() -> Stream {
+            Stream { entries: None }
+        }
+
+        pub(super) fn next(&mut self) -> Option<io::Result<(OsString, Option<FileKind>)>> {
+            let entry = match self.entries.as_mut()?.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            let kind = entry.file_type().ok().map(FileKind::from);
+            Some(Ok((entry.file_name(), kind)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flat::ReadDirFlat;
+    use crate::fixture::TreeBuilder;
+    use crate::vfs::FileKind;
+
+    #[test]
+    fn flat_listing() {
+        let mut builder = TreeBuilder::new()
+            .dir("big/sub/deeper")
+            .symlink("big/link", "sub");
+        for i in 0..500 {
+            builder = builder.file(format!("big/{:03}", i), b"");
+        }
+        let tree = builder.build().unwrap();
+
+        let mut raw = ReadDirFlat::new(tree.join("big")).unwrap();
+        raw.raw_order = true;
+        raw.buffer_size = 4096;
+        assert_eq!(raw.count(), 502);
+
+        let entries: Vec<_> = ReadDirFlat::new(tree.join("big"))
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        assert_eq!(entries[0].name(), "000");
+        assert_eq!(entries[0].kind(), FileKind::File);
+        assert_eq!(entries[500].kind(), FileKind::Symlink);
+        assert_eq!(entries[501].kind(), FileKind::Dir);
+        assert_eq!(entries[501].path_in(tree.join("big")), tree.join("big/sub"));
+        assert!(ReadDirFlat::new(tree.join("big/000")).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::entry::Entry;
+use crate::paths::simplify_verbatim;
+use crate::queue::{DoneGuard, WorkQueue};
+use crate::result::Result;
+
+struct Node<T> {
+    parent: Option<usize>,
+    acc: Option<T>,
+    pending: usize,
+}
+
+struct Shared<T> {
+    nodes: Vec<Node<T>>,
+    error: Option<io::Error>,
+}
+
+/// Computes an aggregate per directory and merges the aggregates of subdirectories
+/// into their parents, returning the aggregate of the root
+/// (e.g. total size, file count or newest modification time).
+/// Directories are processed in parallel by a pool of worker threads.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `init` - creates the initial aggregate of a directory.
+/// * `fold_file` - adds an entry that is not a directory to the aggregate of its directory.
+/// * `merge_dir` - merges the aggregate of a subdirectory into the aggregate of its parent.
+pub fn walk_fold<P, T, I, F, M>(root: P, init: I, fold_file: F, merge_dir: M) -> Result<T>
+where
+    P: AsRef<Path>,
+    T: Send,
+    I: Fn(&Path) -> T + Sync,
+    F: Fn(T, &Entry) -> T + Sync,
+    M: Fn(T, T) -> T + Sync,
+{
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let shared = Mutex::new(Shared {
+        nodes: vec![Node {
+            parent: None,
+            acc: None,
+            pending: 1,
+        }],
+        error: None,
+    });
+    let queue = WorkQueue::new((0usize, root, 0usize));
+    let workers = thread::available_parallelism().map_or(4, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some((id, dir, depth)) = queue.pop() {
+                    let _done = DoneGuard(&queue);
+                    let mut acc = Some(init(&dir));
+                    let mut sub_dirs = Vec::new();
+                    if let Err(e) = fold_dir(&dir, depth, &mut acc, &mut sub_dirs, &fold_file) {
+                        shared.lock().unwrap().error.get_or_insert(e);
+                    }
+                    let mut state = shared.lock().unwrap();
+                    state.nodes[id].acc = acc;
+                    state.nodes[id].pending += sub_dirs.len();
+                    for sub_dir in sub_dirs {
+                        let child = state.nodes.len();
+                        state.nodes.push(Node {
+                            parent: Some(id),
+                            acc: None,
+                            pending: 1,
+                        });
+                        queue.push((child, sub_dir, depth + 1));
+                    }
+                    finish(&mut state.nodes, id, &merge_dir);
+                }
+            });
+        }
+    });
+    let mut state = shared.into_inner().unwrap();
+    if let Some(e) = state.error {
+        return Err(e.into());
+    }
+    Ok(state.nodes[0].acc.take().unwrap())
+}
+
+fn fold_dir<T, F>(
+    dir: &Path,
+    depth: usize,
+    acc: &mut Option<T>,
+    sub_dirs: &mut Vec<PathBuf>,
+    fold_file: &F,
+) -> io::Result<()>
+where
+    F: Fn(T, &Entry) -> T,
+{
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            sub_dirs.push(entry.path());
+        } else {
+            let folded = fold_file(acc.take().unwrap(), &Entry::new(entry.path(), depth + 1));
+            *acc = Some(folded);
+        }
+    }
+    Ok(())
+}
+
+/// Marks one pending item of the node as done and merges every completed node into its parent.
+fn finish<T, M: Fn(T, T) -> T>(nodes: &mut [Node<T>], mut id: usize, merge_dir: &M) {
+    loop {
+        nodes[id].pending -= 1;
+        if nodes[id].pending > 0 {
+            return;
+        }
+        let parent = match nodes[id].parent {
+            Some(parent) => parent,
+            None => return,
+        };
+        let child = nodes[id].acc.take().unwrap();
+        let acc = nodes[parent].acc.take().unwrap();
+        nodes[parent].acc = Some(merge_dir(acc, child));
+        id = parent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::fold::walk_fold;
+
+    #[test]
+    fn fold_sizes_and_counts() {
+        let tree = TreeBuilder::new()
+            .file("1.txt", b"12345")
+            .file("a/2.txt", b"123")
+            .file("a/b/3.txt", b"1")
+            .file("a/b/4.txt", b"1")
+            .dir("c")
+            .build()
+            .unwrap();
+
+        let (files, bytes, dirs) = walk_fold(
+            tree.path(),
+            |_| (0, 0, 1),
+            |(files, bytes, dirs), entry| {
+                let len = entry.path().metadata().unwrap().len();
+                (files + 1, bytes + len, dirs)
+            },
+            |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2),
+        )
+        .unwrap();
+        assert_eq!((files, bytes, dirs), (4, 10, 4));
+    }
+}
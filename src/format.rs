@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Formats a number of bytes with binary units, e.g. `1.2 MiB` for 1234567.
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // rounding to one decimal must not print `1024.0 KiB`
+    while value >= 1023.95 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats a count with thousands separators, e.g. `1,234,567`.
+pub fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Formats a duration with its two largest units, e.g. `850 ms`, `12.3 s`, `4m 05s`,
+/// `2h 03m` or `3d 04h`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0 => format!("{} ms", duration.as_millis()),
+        1..=59 => format!("{:.1} s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {:02}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// Formats a transfer rate, e.g. `12.5 MiB/s`; rates that are not finite print as `-`.
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    if !bytes_per_sec.is_finite() || bytes_per_sec < 0.0 {
+        return "-".to_string();
+    }
+    format!("{}/s", format_size(bytes_per_sec.round() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format_count, format_duration, format_rate, format_size};
+    use std::time::Duration;
+
+    #[test]
+    fn human_readable_formats() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1_234_567), "1.2 MiB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(format_size(u64::MAX), "16.0 EiB");
+
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+
+        assert_eq!(format_duration(Duration::from_millis(850)), "850 ms");
+        assert_eq!(format_duration(Duration::from_millis(12_345)), "12.3 s");
+        assert_eq!(format_duration(Duration::from_secs(245)), "4m 05s");
+        assert_eq!(format_duration(Duration::from_secs(7380)), "2h 03m");
+        assert_eq!(format_duration(Duration::from_secs(273_600)), "3d 04h");
+
+        assert_eq!(format_rate(13_107_200.0), "12.5 MiB/s");
+        assert_eq!(format_rate(f64::INFINITY), "-");
+    }
+}
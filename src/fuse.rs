@@ -0,0 +1,735 @@
+//! Read-only FUSE mounts of index snapshots, to browse a scanned tree as it was without
+//! restoring or extracting anything.
+//!
+//! The mounted tree has the entries, sizes and modification times of the snapshot. File
+//! contents and link targets are read from the snapshot's root, so a file that was changed
+//! since the snapshot (its size or modification time differs) reads as an I/O error
+//! instead of showing contents the snapshot does not describe. Filter
+//! [`Snapshot::entries`] before mounting to expose only part of a tree.
+//!
+//! The FUSE protocol is spoken directly over `/dev/fuse`, so no libfuse is needed. Mounting
+//! takes `CAP_SYS_ADMIN`, or the setuid `fusermount3` (or `fusermount`) helper otherwise.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::index::Snapshot;
+use crate::result::{Error, ErrorKind, Result};
+
+const ROOT_ID: u64 = 1;
+const MAX_WRITE: u32 = 128 * 1024;
+const BUFFER_LEN: usize = MAX_WRITE as usize + 4096;
+/// Attributes never change, so the kernel may cache them for long.
+const TTL_SECS: u64 = 3600;
+
+const LOOKUP: u32 = 1;
+const FORGET: u32 = 2;
+const GETATTR: u32 = 3;
+const READLINK: u32 = 5;
+const OPEN: u32 = 14;
+const READ: u32 = 15;
+const STATFS: u32 = 17;
+const RELEASE: u32 = 18;
+const INIT: u32 = 26;
+const OPENDIR: u32 = 27;
+const READDIR: u32 = 28;
+const RELEASEDIR: u32 = 29;
+const INTERRUPT: u32 = 36;
+const DESTROY: u32 = 38;
+const BATCH_FORGET: u32 = 42;
+
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EACCES: i32 = 13;
+const ENODEV: i32 = 19;
+const ENOTDIR: i32 = 20;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+
+const O_ACCMODE: u32 = 3;
+
+/// A mounted snapshot, served by a background thread until it is unmounted, either by
+/// [`Mount::unmount`], by dropping it, or externally (e.g. `fusermount -u`).
+#[derive(Debug)]
+pub struct Mount {
+    mountpoint: PathBuf,
+    helper: bool,
+    server: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl Mount {
+    /// Returns the directory the snapshot is mounted on.
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Unmounts the snapshot and waits for the server thread to stop. Files still open in
+    /// the mount keep working until they are closed.
+    pub fn unmount(mut self) -> Result<()> {
+        self.detach()
+    }
+
+    fn detach(&mut self) -> Result<()> {
+        let server = match self.server.take() {
+            Some(server) => server,
+            None => return Ok(()),
+        };
+        if self.helper {
+            let status = helper_command()?
+                .args(["-u", "-z", "--"])
+                .arg(&self.mountpoint)
+                .status()?;
+            if !status.success() {
+                let cause = format!("fusermount -u failed with {}", status);
+                return Err(Error::new(ErrorKind::File, cause));
+            }
+        } else {
+            sys::unmount(&self.mountpoint)?;
+        }
+        server.join().unwrap_or(Ok(()))
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let _ = self.detach();
+    }
+}
+
+/// Mounts a snapshot read-only on `mountpoint`, an existing directory, and serves it from
+/// a background thread.
+///
+/// # Arguments:
+///
+/// * `snapshot` - the snapshot, e.g. from [`Index::snapshot`](crate::index::Index::snapshot).
+/// * `mountpoint` - directory to mount the snapshot on.
+pub fn mount_snapshot<P: AsRef<Path>>(snapshot: &Snapshot, mountpoint: P) -> Result<Mount> {
+    let mountpoint = fs::canonicalize(mountpoint)?;
+    let tree = Tree::of(snapshot);
+    let (device, helper) = match sys::mount_device(&mountpoint) {
+        Ok(device) => (device, false),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            (mount_with_helper(&mountpoint)?, true)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let server = thread::spawn(move || Session::new(device, tree).run());
+    Ok(Mount {
+        mountpoint,
+        helper,
+        server: Some(server),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Dir,
+    File,
+    Symlink,
+}
+
+#[derive(Debug)]
+struct Node {
+    name: OsString,
+    parent: u64,
+    kind: Kind,
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Path of the entry in the snapshot's root.
+    source: PathBuf,
+    /// Sorted by name.
+    children: Vec<u64>,
+}
+
+/// The entries of a snapshot as nodes, numbered from 1 (the root).
+#[derive(Debug)]
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn of(snapshot: &Snapshot) -> Tree {
+        let root = Node {
+            name: OsString::new(),
+            parent: ROOT_ID,
+            kind: Kind::Dir,
+            len: 0,
+            modified: Some(snapshot.taken),
+            source: snapshot.root.clone(),
+            children: Vec::new(),
+        };
+        let mut tree = Tree { nodes: vec![root] };
+        let mut ids = HashMap::new();
+        let mut entries: Vec<_> = snapshot.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in entries {
+            let relative = match entry.path.strip_prefix(&snapshot.root) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => continue,
+            };
+            let id = tree.node_of(&mut ids, &snapshot.root, relative);
+            let node = tree.node_mut(id);
+            node.len = entry.len;
+            node.modified = entry.modified;
+        }
+        // the snapshot does not record kinds: leaves are whatever they are in the source
+        for node in &mut tree.nodes[1..] {
+            if node.children.is_empty() {
+                node.kind = match fs::symlink_metadata(&node.source) {
+                    Ok(meta) if meta.is_dir() => Kind::Dir,
+                    Ok(meta) if meta.file_type().is_symlink() => Kind::Symlink,
+                    _ => Kind::File,
+                };
+            }
+        }
+        let mut children: Vec<Vec<u64>> = tree.nodes.iter().map(|n| n.children.clone()).collect();
+        for list in &mut children {
+            list.sort_by(|a, b| tree.node(*a).name.cmp(&tree.node(*b).name));
+        }
+        for (node, list) in tree.nodes.iter_mut().zip(children) {
+            node.children = list;
+        }
+        tree
+    }
+
+    /// Returns the node of `relative`, adding it and its parents if needed.
+    fn node_of(&mut self, ids: &mut HashMap<PathBuf, u64>, root: &Path, relative: &Path) -> u64 {
+        if let Some(id) = ids.get(relative) {
+            return *id;
+        }
+        let parent = match relative.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => self.node_of(ids, root, parent),
+            _ => ROOT_ID,
+        };
+        let id = self.nodes.len() as u64 + 1;
+        self.nodes.push(Node {
+            name: relative.file_name().unwrap_or_default().to_os_string(),
+            parent,
+            kind: Kind::File,
+            len: 0,
+            modified: None,
+            source: root.join(relative),
+            children: Vec::new(),
+        });
+        let parent = self.node_mut(parent);
+        parent.kind = Kind::Dir;
+        parent.children.push(id);
+        ids.insert(relative.to_path_buf(), id);
+        id
+    }
+
+    fn get(&self, id: u64) -> Option<&Node> {
+        self.nodes.get((id as usize).checked_sub(1)?)
+    }
+
+    fn node(&self, id: u64) -> &Node {
+        &self.nodes[id as usize - 1]
+    }
+
+    fn node_mut(&mut self, id: u64) -> &mut Node {
+        &mut self.nodes[id as usize - 1]
+    }
+}
+
+/// Serves the requests of one mount.
+struct Session {
+    device: File,
+    tree: Tree,
+    uid: u32,
+    gid: u32,
+    open: HashMap<u64, File>,
+    next_handle: u64,
+}
+
+impl Session {
+    fn new(device: File, tree: Tree) -> Session {
+        Session {
+            device,
+            tree,
+            uid: sys::uid(),
+            gid: sys::gid(),
+            open: HashMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    /// Answers requests until the filesystem is unmounted.
+    fn run(mut self) -> Result<()> {
+        let mut buf = vec![0; BUFFER_LEN];
+        loop {
+            let len = match self.device.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.raw_os_error() == Some(ENODEV) => return Ok(()),
+                // interrupted before it was read
+                Err(e) if e.raw_os_error() == Some(ENOENT) => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if len < 40 {
+                return Err(Error::new(ErrorKind::Encoding, "short FUSE request"));
+            }
+            let request = &buf[..len];
+            let opcode = u32_at(request, 4);
+            let unique = u64_at(request, 8);
+            let node = u64_at(request, 16);
+            let body = &request[40..];
+            let reply = match opcode {
+                FORGET | BATCH_FORGET | INTERRUPT => continue,
+                DESTROY => return Ok(()),
+                _ => self.answer(opcode, node, body),
+            };
+            let (error, payload) = match reply {
+                Ok(payload) => (0, payload),
+                Err(errno) => (-errno, Vec::new()),
+            };
+            let mut out = Vec::with_capacity(16 + payload.len());
+            out.extend_from_slice(&(16 + payload.len() as u32).to_ne_bytes());
+            out.extend_from_slice(&error.to_ne_bytes());
+            out.extend_from_slice(&unique.to_ne_bytes());
+            out.extend_from_slice(&payload);
+            match self.device.write(&out) {
+                // the request was interrupted meanwhile
+                Err(e) if e.raw_os_error() == Some(ENOENT) => {}
+                Err(e) if e.raw_os_error() == Some(ENODEV) => return Ok(()),
+                Err(e) => return Err(e.into()),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Returns the payload of the reply to a request, or an errno.
+    fn answer(&mut self, opcode: u32, id: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        if opcode == INIT {
+            return init(body);
+        }
+        let node = self.tree.get(id).ok_or(ENOENT)?;
+        match opcode {
+            LOOKUP => {
+                let name = OsStr::from_bytes(body.split(|b| *b == 0).next().unwrap_or(body));
+                let child = node
+                    .children
+                    .iter()
+                    .copied()
+                    .find(|child| self.tree.node(*child).name == name)
+                    .ok_or(ENOENT)?;
+                let mut out = Vec::with_capacity(128);
+                out.extend_from_slice(&child.to_ne_bytes());
+                out.extend_from_slice(&0u64.to_ne_bytes());
+                out.extend_from_slice(&TTL_SECS.to_ne_bytes());
+                out.extend_from_slice(&TTL_SECS.to_ne_bytes());
+                out.extend_from_slice(&[0; 8]);
+                self.attr(child, &mut out);
+                Ok(out)
+            }
+            GETATTR => {
+                let mut out = Vec::with_capacity(104);
+                out.extend_from_slice(&TTL_SECS.to_ne_bytes());
+                out.extend_from_slice(&[0; 8]);
+                self.attr(id, &mut out);
+                Ok(out)
+            }
+            READLINK => {
+                if node.kind != Kind::Symlink {
+                    return Err(EINVAL);
+                }
+                let target = fs::read_link(&node.source).map_err(|_| EIO)?;
+                Ok(target.as_os_str().as_bytes().to_vec())
+            }
+            OPEN => {
+                if node.kind != Kind::File {
+                    return Err(EINVAL);
+                }
+                if u32_at(body, 0) & O_ACCMODE != 0 {
+                    return Err(EACCES);
+                }
+                let file = File::open(&node.source).map_err(|_| EIO)?;
+                let meta = file.metadata().map_err(|_| EIO)?;
+                if meta.len() != node.len || meta.modified().ok() != node.modified {
+                    return Err(EIO);
+                }
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.open.insert(handle, file);
+                let mut out = handle.to_ne_bytes().to_vec();
+                out.extend_from_slice(&[0; 8]);
+                Ok(out)
+            }
+            READ => {
+                let (handle, offset, size) = (u64_at(body, 0), u64_at(body, 8), u32_at(body, 16));
+                let file = self.open.get(&handle).ok_or(EINVAL)?;
+                let mut out = vec![0; size.min(MAX_WRITE) as usize];
+                let mut filled = 0;
+                while filled < out.len() {
+                    match file.read_at(&mut out[filled..], offset + filled as u64) {
+                        Ok(0) => break,
+                        Ok(n) => filled += n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(_) => return Err(EIO),
+                    }
+                }
+                out.truncate(filled);
+                Ok(out)
+            }
+            RELEASE => {
+                self.open.remove(&u64_at(body, 0));
+                Ok(Vec::new())
+            }
+            OPENDIR => match node.kind {
+                Kind::Dir => Ok(vec![0; 16]),
+                _ => Err(ENOTDIR),
+            },
+            READDIR => Ok(self.read_dir(id, u64_at(body, 8), u32_at(body, 16) as usize)),
+            RELEASEDIR => Ok(Vec::new()),
+            STATFS => {
+                // blocks, free blocks, available blocks, files, free files
+                let mut out = Vec::with_capacity(80);
+                for value in [0, 0, 0, self.tree.nodes.len() as u64, 0] {
+                    out.extend_from_slice(&value.to_ne_bytes());
+                }
+                // block size, maximum name length, fragment size, padding, spare
+                for value in [4096u32, 255, 4096, 0, 0, 0, 0, 0, 0, 0] {
+                    out.extend_from_slice(&value.to_ne_bytes());
+                }
+                Ok(out)
+            }
+            _ => Err(ENOSYS),
+        }
+    }
+
+    /// Appends the attributes of a node (`struct fuse_attr`).
+    fn attr(&self, id: u64, out: &mut Vec<u8>) {
+        let node = self.tree.node(id);
+        let (mode, nlink) = match node.kind {
+            Kind::Dir => (0o040555, 2),
+            Kind::File => (0o100444, 1),
+            Kind::Symlink => (0o120777, 1),
+        };
+        let modified = node
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let (secs, nanos) = (modified.as_secs(), modified.subsec_nanos());
+        for value in [id, node.len, node.len.div_ceil(512), secs, secs, secs] {
+            out.extend_from_slice(&value.to_ne_bytes());
+        }
+        // times' nanoseconds, mode, links, owner, group, device, block size, flags
+        for value in [nanos, nanos, nanos, mode, nlink, self.uid, self.gid, 0, 4096, 0] {
+            out.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+
+    /// Lists the entries of a directory from `offset` (an entry number) in `struct
+    /// fuse_dirent`s, as many as fit in `size` bytes.
+    fn read_dir(&self, id: u64, offset: u64, size: usize) -> Vec<u8> {
+        let node = self.tree.node(id);
+        let mut entries = vec![(id, OsStr::new(".")), (node.parent, OsStr::new(".."))];
+        let children = node.children.iter();
+        entries.extend(children.map(|child| (*child, self.tree.node(*child).name.as_os_str())));
+        let mut out = Vec::new();
+        for (i, (child, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            let name = name.as_bytes();
+            let len = (24 + name.len()).div_ceil(8) * 8;
+            if out.len() + len > size {
+                break;
+            }
+            let kind = match self.tree.node(child).kind {
+                Kind::Dir => 4u32,
+                Kind::File => 8,
+                Kind::Symlink => 10,
+            };
+            out.extend_from_slice(&child.to_ne_bytes());
+            out.extend_from_slice(&(i as u64 + 1).to_ne_bytes());
+            out.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+            out.extend_from_slice(&kind.to_ne_bytes());
+            out.extend_from_slice(name);
+            out.resize(out.len() + len - 24 - name.len(), 0);
+        }
+        out
+    }
+}
+
+/// Answers the handshake (`struct fuse_init_out`), with protocol version 7.31.
+fn init(body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+    if body.len() < 8 || u32_at(body, 0) < 7 {
+        return Err(EIO);
+    }
+    let mut out = Vec::with_capacity(64);
+    // major, minor, maximum readahead, flags
+    for value in [7u32, 31, u32_at(body, 8), 0] {
+        out.extend_from_slice(&value.to_ne_bytes());
+    }
+    // maximum background requests, congestion threshold
+    out.extend_from_slice(&16u16.to_ne_bytes());
+    out.extend_from_slice(&12u16.to_ne_bytes());
+    // maximum write, time granularity
+    out.extend_from_slice(&MAX_WRITE.to_ne_bytes());
+    out.extend_from_slice(&1u32.to_ne_bytes());
+    out.resize(64, 0);
+    Ok(out)
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0; 4];
+    if let Some(field) = bytes.get(at..at + 4) {
+        buf.copy_from_slice(field);
+    }
+    u32::from_ne_bytes(buf)
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    if let Some(field) = bytes.get(at..at + 8) {
+        buf.copy_from_slice(field);
+    }
+    u64::from_ne_bytes(buf)
+}
+
+fn helper_command() -> io::Result<Command> {
+    for helper in ["fusermount3", "fusermount"] {
+        let found = Command::new(helper)
+            .arg("-V")
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if found {
+            return Ok(Command::new(helper));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "mounting needs CAP_SYS_ADMIN or fusermount",
+    ))
+}
+
+/// Mounts through the setuid helper, which sends the opened `/dev/fuse` back over a socket.
+fn mount_with_helper(mountpoint: &Path) -> Result<File> {
+    let (ours, theirs) = std::os::unix::net::UnixStream::pair()?;
+    let fd = theirs.as_raw_fd();
+    let mut command = helper_command()?;
+    command
+        .args(["-o", "ro,nosuid,nodev,fsname=fs-helper,subtype=fs-helper", "--"])
+        .arg(mountpoint)
+        .env("_FUSE_COMMFD", fd.to_string());
+    sys::inherit(&mut command, fd);
+    let status = command.status()?;
+    drop(theirs);
+    if !status.success() {
+        let cause = format!("fusermount failed with {}", status);
+        return Err(Error::new(ErrorKind::File, cause));
+    }
+    Ok(sys::receive_fd(&ours)?)
+}
+
+mod sys {
+    use std::ffi::{c_char, c_int, c_ulong, c_void, CString};
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::process::CommandExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    const MS_RDONLY: c_ulong = 1;
+    const MS_NOSUID: c_ulong = 2;
+    const MS_NODEV: c_ulong = 4;
+    const MNT_DETACH: c_int = 2;
+    const F_SETFD: c_int = 2;
+    const SOL_SOCKET: c_int = 1;
+    const SCM_RIGHTS: c_int = 1;
+
+    #[repr(C)]
+    struct IoVec {
+        base: *mut c_void,
+        len: usize,
+    }
+
+    #[repr(C)]
+    struct MsgHdr {
+        name: *mut c_void,
+        name_len: u32,
+        iov: *mut IoVec,
+        iov_len: usize,
+        control: *mut c_void,
+        control_len: usize,
+        flags: c_int,
+    }
+
+    #[repr(C)]
+    struct CmsgHdr {
+        len: usize,
+        level: c_int,
+        kind: c_int,
+    }
+
+    extern "C" {
+        fn mount(
+            source: *const c_char,
+            target: *const c_char,
+            fstype: *const c_char,
+            flags: c_ulong,
+            data: *const c_void,
+        ) -> c_int;
+        fn umount2(target: *const c_char, flags: c_int) -> c_int;
+        fn getuid() -> u32;
+        fn getgid() -> u32;
+        fn fcntl(fd: c_int, cmd: c_int, arg: c_int) -> c_int;
+        fn recvmsg(fd: c_int, msg: *mut MsgHdr, flags: c_int) -> isize;
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Opens `/dev/fuse` and mounts it on `mountpoint`.
+    pub(super) fn mount_device(mountpoint: &Path) -> io::Result<File> {
+        let device = OpenOptions::new().read(true).write(true).open("/dev/fuse")?;
+        let options = format!(
+            "fd={},rootmode=40000,user_id={},group_id={}",
+            device.as_raw_fd(),
+            uid(),
+            gid()
+        );
+        let options = CString::new(options)?;
+        let target = c_path(mountpoint)?;
+        let flags = MS_RDONLY | MS_NOSUID | MS_NODEV;
+        // SAFETY: all strings are NUL-terminated and outlive the call.
+        let result = unsafe {
+            mount(
+                c"fs-helper".as_ptr(),
+                target.as_ptr(),
+                c"fuse.fs-helper".as_ptr(),
+                flags,
+                options.as_ptr() as *const c_void,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(device)
+    }
+
+    pub(super) fn unmount(mountpoint: &Path) -> io::Result<()> {
+        let target = c_path(mountpoint)?;
+        // SAFETY: `target` is NUL-terminated.
+        if unsafe { umount2(target.as_ptr(), MNT_DETACH) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(super) fn uid() -> u32 {
+        // SAFETY: getuid can not fail.
+        unsafe { getuid() }
+    }
+
+    pub(super) fn gid() -> u32 {
+        // SAFETY: getgid can not fail.
+        unsafe { getgid() }
+    }
+
+    /// Lets the child of `command` inherit `fd`.
+    pub(super) fn inherit(command: &mut Command, fd: c_int) {
+        // SAFETY: fcntl is async-signal-safe.
+        unsafe {
+            command.pre_exec(move || match fcntl(fd, F_SETFD, 0) {
+                -1 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            });
+        }
+    }
+
+    /// Receives a file descriptor sent with `SCM_RIGHTS`.
+    pub(super) fn receive_fd(socket: &UnixStream) -> io::Result<File> {
+        let mut byte = 0u8;
+        let mut iov = IoVec {
+            base: &mut byte as *mut u8 as *mut c_void,
+            len: 1,
+        };
+        // header and one descriptor, 8-byte aligned
+        let mut control = [0u64; 3];
+        let mut msg = MsgHdr {
+            name: std::ptr::null_mut(),
+            name_len: 0,
+            iov: &mut iov,
+            iov_len: 1,
+            control: control.as_mut_ptr() as *mut c_void,
+            control_len: std::mem::size_of_val(&control),
+            flags: 0,
+        };
+        // SAFETY: every pointer in `msg` is valid for the lengths given.
+        if unsafe { recvmsg(socket.as_raw_fd(), &mut msg, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `control` is aligned and large enough for a header.
+        let header = unsafe { &*(control.as_ptr() as *const CmsgHdr) };
+        if msg.control_len < std::mem::size_of::<CmsgHdr>() + 4
+            || header.level != SOL_SOCKET
+            || header.kind != SCM_RIGHTS
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "fusermount sent no file descriptor",
+            ));
+        }
+        let fd = control[2] as u32 as c_int;
+        // SAFETY: the descriptor was just received and is owned by nobody else.
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::fuse::mount_snapshot;
+    use crate::index::Index;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn mounts_snapshot() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"alpha")
+            .file("b/c.txt", b"gamma")
+            .symlink("b/link", "../a.txt")
+            .file("changed.txt", b"old")
+            .build()
+            .unwrap();
+        let store = TreeBuilder::new().dir("index").dir("mnt").build().unwrap();
+        let snapshot = Index::open(store.join("index"))
+            .unwrap()
+            .update(tree.path())
+            .unwrap();
+        let mount = match mount_snapshot(&snapshot, store.join("mnt")) {
+            Ok(mount) => mount,
+            // no FUSE or no permission to mount in this environment
+            Err(_) => return,
+        };
+        fs::write(tree.join("changed.txt"), b"new contents").unwrap();
+        let mnt = mount.mountpoint().to_path_buf();
+        let mut names: Vec<_> = fs::read_dir(&mnt)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "b", "changed.txt"]);
+        assert_eq!(fs::read(mnt.join("b/c.txt")).unwrap(), b"gamma");
+        assert_eq!(fs::read_link(mnt.join("b/link")).unwrap(), Path::new("../a.txt"));
+        assert_eq!(fs::read(mnt.join("b/link")).unwrap(), b"alpha");
+        assert_eq!(fs::metadata(mnt.join("changed.txt")).unwrap().len(), 3);
+        assert!(fs::read(mnt.join("changed.txt")).is_err());
+        assert!(fs::write(mnt.join("a.txt"), b"x").is_err());
+        mount.unmount().unwrap();
+        assert!(fs::read_dir(&mnt).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::direct::{self, AlignedBuf};
+use crate::open::open_read_shared;
+use crate::progress::{Operation, ProgressEvent, ProgressSink};
+use crate::result::{Error, ErrorKind, Result};
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 digest of file contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+    /// Computes the hash of a byte slice.
+    pub fn of(bytes: &[u8]) -> Hash {
+        let mut hasher = Hasher::new();
+        hasher.update(bytes);
+        hasher.finish()
+    }
+
+    /// Computes the hash of the contents of a file.
+    pub fn of_file<P: AsRef<Path>>(path: P) -> Result<Hash> {
+        Ok(Hash::of_reader(open_read_shared(path)?)?)
+    }
+
+    /// Computes the hash of the contents of a file with direct I/O, bypassing the page
+    /// cache, so hashing very large files does not evict data other processes need.
+    /// Falls back to [`Hash::of_file`] where direct I/O is not supported.
+    pub fn of_file_direct<P: AsRef<Path>>(path: P) -> Result<Hash> {
+        let path = path.as_ref();
+        let mut file = match direct::open_direct(path)? {
+            Some(file) => file,
+            None => return Hash::of_file(path),
+        };
+        let mut hasher = Hasher::new();
+        let mut buf = AlignedBuf::new(1024 * 1024);
+        loop {
+            let n = direct::read_block(&mut file, &mut buf)?;
+            hasher.update(&buf[..n]);
+            if n < buf.len() {
+                return Ok(hasher.finish());
+            }
+        }
+    }
+
+    /// Computes the hash of the contents of a file, reporting progress to `progress`.
+    pub fn of_file_with_progress<P: AsRef<Path>>(
+        path: P,
+        progress: &dyn ProgressSink,
+    ) -> Result<Hash> {
+        let path = path.as_ref();
+        let hash = Hash::of_reader_with(open_read_shared(path)?, |bytes| {
+            progress.event(&ProgressEvent::BytesHashed { path, bytes })
+        })?;
+        progress.event(&ProgressEvent::OperationFinished {
+            operation: Operation::Hash,
+        });
+        Ok(hash)
+    }
+
+    /// Computes the hash of everything read from `reader`.
+    pub fn of_reader<R: Read>(reader: R) -> io::Result<Hash> {
+        Hash::of_reader_with(reader, |_| {})
+    }
+
+    fn of_reader_with<R: Read, F: FnMut(u64)>(mut reader: R, mut on_read: F) -> io::Result<Hash> {
+        let mut hasher = Hasher::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(hasher.finish()),
+                Ok(n) => {
+                    hasher.update(&buf[..n]);
+                    on_read(n as u64);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the lowercase hexadecimal representation of the hash.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl FromStr for Hash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Hash> {
+        let invalid = || Error::new(ErrorKind::Encoding, format!("invalid hash: {}", s));
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Hash(bytes))
+    }
+}
+
+/// Incremental SHA-256 hasher.
+#[derive(Clone)]
+pub struct Hasher {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Hasher::new()
+    }
+}
+
+impl Hasher {
+    /// Creates a new hasher.
+    pub fn new() -> Hasher {
+        Hasher {
+            state: H0,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Feeds data into the hasher.
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+        if self.block_len > 0 {
+            let n = bytes.len().min(64 - self.block_len);
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&bytes[..n]);
+            self.block_len += n;
+            bytes = &bytes[n..];
+            if self.block_len < 64 {
+                return;
+            }
+            let block = self.block;
+            self.compress(&block);
+            self.block_len = 0;
+        }
+        let mut chunks = bytes.chunks_exact(64);
+        for chunk in &mut chunks {
+            self.compress(chunk.try_into().unwrap());
+        }
+        let rest = chunks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+        self.block_len = rest.len();
+    }
+
+    /// Returns the hash of all data fed so far.
+    pub fn finish(mut self) -> Hash {
+        let bit_len = self.total_len.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        let pad_len = (119 - self.block_len) % 64;
+        padding.resize(1 + pad_len, 0);
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+        let total_len = self.total_len;
+        self.update(&padding);
+        self.total_len = total_len;
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        Hash(out)
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::hash::{Hash, Hasher};
+
+    #[test]
+    fn sha256_known_values() {
+        assert_eq!(
+            Hash::of(b"").to_hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            Hash::of(b"abc").to_hex(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let data = vec![b'a'; 1000];
+        let mut hasher = Hasher::new();
+        data.chunks(7).for_each(|chunk| hasher.update(chunk));
+        assert_eq!(hasher.finish(), Hash::of(&data));
+        let hash = Hash::of(b"abc");
+        assert_eq!(hash.to_hex().parse::<Hash>().unwrap(), hash);
+    }
+
+    #[test]
+    fn hash_file_direct() {
+        let contents: Vec<u8> = (0..3_000_000u32).map(|i| (i % 253) as u8).collect();
+        let tree = TreeBuilder::new()
+            .file("big.bin", &contents)
+            .file("empty", b"")
+            .build()
+            .unwrap();
+        for name in ["big.bin", "empty"] {
+            let path = tree.join(name);
+            assert_eq!(
+                Hash::of_file_direct(&path).unwrap(),
+                Hash::of_file(&path).unwrap()
+            );
+        }
+    }
+}
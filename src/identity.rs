@@ -0,0 +1,169 @@
+use std::path::Path;
+
+use crate::result::Result;
+
+/// Identity of a file on the system: its device and inode numbers on Unix, its volume
+/// serial number and file index on Windows. Two paths have the same `FileId` exactly when
+/// they lead to the same file, e.g. hard links to one inode, or a directory reached
+/// through a symbolic link or a bind mount, so it can be used as a key to find hard links
+/// or loops. Identities are only stable while the file exists: a new file may reuse the
+/// inode of a removed one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId {
+    device: u64,
+    inode: u64,
+}
+
+impl FileId {
+    /// Returns the identity of the file at `path`, following symbolic links.
+    /// Fails with an error of kind `io::ErrorKind::Unsupported` on platforms without
+    /// file identities.
+    pub fn of<P: AsRef<Path>>(path: P) -> Result<FileId> {
+        let (device, inode) = sys::id_of(path.as_ref())?;
+        Ok(FileId { device, inode })
+    }
+
+    /// Returns the identifier of the device (or volume) holding the file.
+    pub fn device(&self) -> u64 {
+        self.device
+    }
+
+    /// Returns the number of the file on its device (inode number or file index).
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+}
+
+/// Returns the number of hard links to the file at `path`, following symbolic links.
+pub(crate) fn link_count(path: &Path) -> Result<u64> {
+    Ok(sys::links_of(path)?)
+}
+
+/// Checks whether two paths lead to the same file, following symbolic links.
+///
+/// # Arguments:
+///
+/// * `a` - first path.
+/// * `b` - second path.
+pub fn same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<bool> {
+    Ok(FileId::of(a)? == FileId::of(b)?)
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    pub fn id_of(path: &Path) -> io::Result<(u64, u64)> {
+        let meta = fs::metadata(path)?;
+        Ok((meta.dev(), meta.ino()))
+    }
+
+    pub fn links_of(path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.nlink())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+    use std::fs;
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+
+    // needed to open directories
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ByHandleFileInformation {
+        attributes: u32,
+        creation_time: [u32; 2],
+        last_access_time: [u32; 2],
+        last_write_time: [u32; 2],
+        volume_serial_number: u32,
+        size_high: u32,
+        size_low: u32,
+        links: u32,
+        index_high: u32,
+        index_low: u32,
+    }
+
+    extern "system" {
+        fn GetFileInformationByHandle(
+            file: *mut c_void,
+            info: *mut ByHandleFileInformation,
+        ) -> i32;
+    }
+
+    pub fn id_of(path: &Path) -> io::Result<(u64, u64)> {
+        let info = info_of(path)?;
+        let index = (u64::from(info.index_high) << 32) | u64::from(info.index_low);
+        Ok((u64::from(info.volume_serial_number), index))
+    }
+
+    pub fn links_of(path: &Path) -> io::Result<u64> {
+        Ok(u64::from(info_of(path)?.links))
+    }
+
+    fn info_of(path: &Path) -> io::Result<ByHandleFileInformation> {
+        let file = fs::OpenOptions::new()
+            .access_mode(0)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?;
+        let mut info = ByHandleFileInformation::default();
+        // SAFETY: the handle is open and `info` is a valid structure for the call to fill.
+        if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    pub fn id_of(path: &Path) -> io::Result<(u64, u64)> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: file identities are not supported", path.display()),
+        ))
+    }
+
+    pub fn links_of(path: &Path) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: link counts are not supported", path.display()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::identity::{same_file, FileId};
+    use std::fs;
+
+    #[test]
+    fn file_identity() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"same")
+            .file("b.txt", b"same")
+            .symlink("link.txt", "a.txt")
+            .build()
+            .unwrap();
+        fs::hard_link(tree.join("a.txt"), tree.join("hard.txt")).unwrap();
+        assert!(same_file(tree.join("a.txt"), tree.join("hard.txt")).unwrap());
+        assert!(same_file(tree.join("a.txt"), tree.join("link.txt")).unwrap());
+        assert!(!same_file(tree.join("a.txt"), tree.join("b.txt")).unwrap());
+        let id = FileId::of(tree.join("a.txt")).unwrap();
+        assert_eq!(id.device(), FileId::of(tree.join("b.txt")).unwrap().device());
+        assert!(FileId::of(tree.join("missing.txt")).is_err());
+    }
+}
@@ -0,0 +1,476 @@
+//! A persistent index of scanned trees, for `locate`-style queries without rescanning.
+//!
+//! The index is a directory holding one snapshot file per indexed root. Snapshots are plain
+//! text: a header followed by one line per entry with its size, modification time and
+//! [encoded](crate::encode_path) path, separated by tabs.
+//!
+//! Updates with [`UpdateOptions::cache_dirs`] also keep, next to the snapshot, the
+//! modification time and number of entries of each directory, in the same format.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::atomic::write_atomic;
+use crate::encoding::{decode_path, encode_path};
+use crate::hash::Hash;
+use crate::result::{Error, ErrorKind, Result};
+use crate::vfs::{FileKind, FsDirName, ReadFs, RealFs};
+use crate::ReadDir;
+
+const MAGIC: &str = "fs-helper-index 1";
+const EXTENSION: &str = "idx";
+const DIRS_MAGIC: &str = "fs-helper-index-dirs 1";
+const DIRS_EXTENSION: &str = "dirs";
+/// Directories modified this close to the previous scan may have changed again within the
+/// same timestamp (2 s on FAT), so they are always read.
+const TIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+/// An indexed entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// The entries of a root directory at the time of a scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub root: PathBuf,
+    pub taken: SystemTime,
+    pub entries: Vec<IndexEntry>,
+}
+
+/// A directory of snapshots, keyed by root.
+#[derive(Debug, Clone)]
+pub struct Index {
+    dir: PathBuf,
+}
+
+/// Options of [`Index::update_with`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateOptions {
+    /// Reuses the indexed files of directories whose modification time and number of
+    /// entries are unchanged since the previous update with this option, instead of
+    /// reading their metadata again; such directories are only listed.
+    ///
+    /// A directory's modification time changes when entries are added, removed or renamed
+    /// in it, but not when a file in it is written to: files changed in place keep the
+    /// size and modification time of the previous update until `force_refresh` is used.
+    /// Restoring a directory's time (e.g. `touch -d`, or extracting an archive) after
+    /// changing it also hides the change.
+    pub cache_dirs: bool,
+    /// Reads every directory as if nothing was cached, and records them again.
+    pub force_refresh: bool,
+}
+
+/// What the previous update recorded of a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DirRecord {
+    modified: SystemTime,
+    entries: usize,
+}
+
+impl Index {
+    /// Opens the index stored in `dir`, creating the directory if needed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Index> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Index {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Scans `root` and replaces its snapshot. Entries whose metadata can not be read
+    /// are skipped.
+    pub fn update<P: AsRef<Path>>(&self, root: P) -> Result<Snapshot> {
+        self.update_with(root, &UpdateOptions::default())
+    }
+
+    /// Scans `root` like [`Index::update`], with options.
+    ///
+    /// # Arguments:
+    ///
+    /// * `root` - root directory.
+    /// * `options` - whether unchanged directories are taken from the previous snapshot.
+    pub fn update_with<P: AsRef<Path>>(
+        &self,
+        root: P,
+        options: &UpdateOptions,
+    ) -> Result<Snapshot> {
+        let rd = ReadDir::try_new(root)?;
+        if options.cache_dirs {
+            let root = rd.root().to_path_buf();
+            return self.update_cached(root, options.force_refresh);
+        }
+        let root = rd.root().to_path_buf();
+        let entries = rd
+            .filter_map(|entry| {
+                let meta = *entry.metadata().ok()?;
+                Some(IndexEntry {
+                    path: entry.into_path(),
+                    len: meta.len,
+                    modified: meta.modified,
+                })
+            })
+            .collect();
+        let snapshot = Snapshot {
+            root,
+            taken: SystemTime::now(),
+            entries,
+        };
+        self.remove_dirs(&snapshot.root)?;
+        write_atomic(self.path_of(&snapshot.root), encode(&snapshot).as_bytes())?;
+        Ok(snapshot)
+    }
+
+    fn update_cached(&self, root: PathBuf, force_refresh: bool) -> Result<Snapshot> {
+        let taken = SystemTime::now();
+        let (previous, records) = match (force_refresh, self.snapshot(&root)?) {
+            (false, Some(previous)) => (Some(previous), self.dirs(&root)?),
+            _ => (None, HashMap::new()),
+        };
+        let previous_taken = previous.as_ref().map_or(UNIX_EPOCH, |previous| previous.taken);
+        let indexed: HashMap<&Path, &IndexEntry> = previous
+            .iter()
+            .flat_map(|previous| &previous.entries)
+            .map(|entry| (entry.path.as_path(), entry))
+            .collect();
+        let mut snapshot = Snapshot {
+            root: root.clone(),
+            taken,
+            entries: Vec::new(),
+        };
+        let mut dirs = HashMap::new();
+        let mut stack = vec![root.clone()];
+        while let Some(dir) = stack.pop() {
+            // unreadable directories are skipped, as by `update`
+            let (modified, names) = match list_dir(&dir) {
+                Ok(listing) => listing,
+                Err(_) => continue,
+            };
+            let record = modified.map(|modified| DirRecord {
+                modified,
+                entries: names.len(),
+            });
+            let unchanged = record.is_some_and(|record| {
+                records.get(&dir) == Some(&record)
+                    && record.modified + TIME_GRANULARITY <= previous_taken
+            });
+            let mut sub_dirs = Vec::new();
+            for name in names {
+                let path = dir.join(&name.name);
+                if name.kind == FileKind::Dir {
+                    sub_dirs.push(path);
+                    continue;
+                }
+                let cached = indexed.get(path.as_path()).filter(|_| unchanged);
+                let entry = match cached {
+                    Some(entry) => (*entry).clone(),
+                    None => match name.meta.map_or_else(|| RealFs.symlink_metadata(&path), Ok) {
+                        Ok(meta) => IndexEntry {
+                            path,
+                            len: meta.len,
+                            modified: meta.modified,
+                        },
+                        Err(_) => continue,
+                    },
+                };
+                snapshot.entries.push(entry);
+            }
+            if let Some(record) = record {
+                dirs.insert(dir, record);
+            }
+            stack.extend(sub_dirs.into_iter().rev());
+        }
+        // without the directory records, a crash before both files are written costs a
+        // full scan instead of reusing entries of the wrong snapshot
+        self.remove_dirs(&root)?;
+        write_atomic(self.path_of(&root), encode(&snapshot).as_bytes())?;
+        write_atomic(self.dirs_path_of(&root), encode_dirs(&dirs).as_bytes())?;
+        Ok(snapshot)
+    }
+
+    /// Reads the directory records of `root`; none if it was not updated with
+    /// `cache_dirs`.
+    fn dirs(&self, root: &Path) -> Result<HashMap<PathBuf, DirRecord>> {
+        let path = self.dirs_path_of(root);
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        decode_dirs(&fs::read_to_string(path)?)
+    }
+
+    fn remove_dirs(&self, root: &Path) -> Result<()> {
+        let path = self.dirs_path_of(root);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the snapshot of `root`, if it has been indexed.
+    /// The root must be given in canonical form, as returned by `Snapshot::root`.
+    pub fn snapshot<P: AsRef<Path>>(&self, root: P) -> Result<Option<Snapshot>> {
+        let path = self.path_of(root.as_ref());
+        if !path.exists() {
+            return Ok(None);
+        }
+        decode(&fs::read_to_string(path)?).map(Some)
+    }
+
+    /// Removes the snapshot of `root`. Returns false if it was not indexed.
+    pub fn remove<P: AsRef<Path>>(&self, root: P) -> Result<bool> {
+        let path = self.path_of(root.as_ref());
+        if !path.exists() {
+            return Ok(false);
+        }
+        self.remove_dirs(root.as_ref())?;
+        fs::remove_file(path)?;
+        Ok(true)
+    }
+
+    /// Returns all snapshots of the index.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        let mut snapshots = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                snapshots.push(decode(&fs::read_to_string(path)?)?);
+            }
+        }
+        snapshots.sort_by(|a, b| a.root.cmp(&b.root));
+        Ok(snapshots)
+    }
+
+    /// Returns the indexed paths whose file name contains `name`, sorted.
+    pub fn locate(&self, name: &str) -> Result<Vec<PathBuf>> {
+        self.query(|entry| {
+            entry
+                .path
+                .file_name()
+                .is_some_and(|file_name| file_name.to_string_lossy().contains(name))
+        })
+    }
+
+    /// Returns the indexed paths modified after `since`, sorted.
+    pub fn changed_since(&self, since: SystemTime) -> Result<Vec<PathBuf>> {
+        self.query(|entry| entry.modified.is_some_and(|modified| modified > since))
+    }
+
+    fn query<F: Fn(&IndexEntry) -> bool>(&self, matches: F) -> Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = self
+            .snapshots()?
+            .into_iter()
+            .flat_map(|snapshot| snapshot.entries)
+            .filter(|entry| matches(entry))
+            .map(|entry| entry.path)
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn path_of(&self, root: &Path) -> PathBuf {
+        let key = Hash::of(encode_path(root).as_bytes()).to_hex();
+        self.dir.join(format!("{}.{}", key, EXTENSION))
+    }
+
+    fn dirs_path_of(&self, root: &Path) -> PathBuf {
+        self.path_of(root).with_extension(DIRS_EXTENSION)
+    }
+}
+
+/// Returns the modification time of a directory and the names of its entries.
+fn list_dir(dir: &Path) -> io::Result<(Option<SystemTime>, Vec<FsDirName>)> {
+    let modified = RealFs.symlink_metadata(dir)?.modified;
+    let names = RealFs.read_dir_names(dir)?.collect::<io::Result<_>>()?;
+    Ok((modified, names))
+}
+
+fn encode(snapshot: &Snapshot) -> String {
+    let mut out = format!(
+        "{}\n{}\t{}\n",
+        MAGIC,
+        encode_time(Some(snapshot.taken)),
+        encode_path(&snapshot.root)
+    );
+    for entry in &snapshot.entries {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            entry.len,
+            encode_time(entry.modified),
+            encode_path(&entry.path)
+        ));
+    }
+    out
+}
+
+fn decode(s: &str) -> Result<Snapshot> {
+    let invalid = || Error::new(ErrorKind::Encoding, "invalid index snapshot");
+    let mut lines = s.lines();
+    if lines.next() != Some(MAGIC) {
+        return Err(invalid());
+    }
+    let (taken, root) = lines
+        .next()
+        .and_then(|line| line.split_once('\t'))
+        .ok_or_else(invalid)?;
+    let mut snapshot = Snapshot {
+        root: decode_path(root)?,
+        taken: decode_time(taken).flatten().ok_or_else(invalid)?,
+        entries: Vec::new(),
+    };
+    for line in lines {
+        let mut fields = line.splitn(3, '\t');
+        let (len, modified, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(len), Some(modified), Some(path)) => (len, modified, path),
+            _ => return Err(invalid()),
+        };
+        snapshot.entries.push(IndexEntry {
+            path: decode_path(path)?,
+            len: len.parse().map_err(|_| invalid())?,
+            modified: decode_time(modified).ok_or_else(invalid)?,
+        });
+    }
+    Ok(snapshot)
+}
+
+fn encode_dirs(dirs: &HashMap<PathBuf, DirRecord>) -> String {
+    let mut out = format!("{}\n", DIRS_MAGIC);
+    for (dir, record) in dirs {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            record.entries,
+            encode_time(Some(record.modified)),
+            encode_path(dir)
+        ));
+    }
+    out
+}
+
+fn decode_dirs(s: &str) -> Result<HashMap<PathBuf, DirRecord>> {
+    let invalid = || Error::new(ErrorKind::Encoding, "invalid index directory records");
+    let mut lines = s.lines();
+    if lines.next() != Some(DIRS_MAGIC) {
+        return Err(invalid());
+    }
+    let mut dirs = HashMap::new();
+    for line in lines {
+        let mut fields = line.splitn(3, '\t');
+        let (entries, modified, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(entries), Some(modified), Some(path)) => (entries, modified, path),
+            _ => return Err(invalid()),
+        };
+        let record = DirRecord {
+            modified: decode_time(modified).flatten().ok_or_else(invalid)?,
+            entries: entries.parse().map_err(|_| invalid())?,
+        };
+        dirs.insert(decode_path(path)?, record);
+    }
+    Ok(dirs)
+}
+
+/// Times are stored as `seconds.nanoseconds` since the Unix epoch, or `-` if unknown.
+fn encode_time(time: Option<SystemTime>) -> String {
+    match time.and_then(|time| time.duration_since(UNIX_EPOCH).ok()) {
+        Some(since) => format!("{}.{:09}", since.as_secs(), since.subsec_nanos()),
+        None => "-".to_string(),
+    }
+}
+
+fn decode_time(s: &str) -> Option<Option<SystemTime>> {
+    if s == "-" {
+        return Some(None);
+    }
+    let (secs, nanos) = s.split_once('.')?;
+    let since = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    Some(Some(UNIX_EPOCH + since))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::index::{Index, Snapshot, UpdateOptions};
+    use crate::times::set_mtime;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn index_update_and_query() {
+        let tree = TreeBuilder::new()
+            .file("src/main.rs", b"fn main() {}")
+            .file("src/lib.rs", b"")
+            .file("notes\ttab.txt", b"12")
+            .build()
+            .unwrap();
+        let store = TreeBuilder::new().build().unwrap();
+        let index = Index::open(store.join("index")).unwrap();
+
+        let snapshot = index.update(tree.path()).unwrap();
+        assert_eq!(snapshot.entries.len(), 3);
+        assert_eq!(
+            index.snapshot(&snapshot.root).unwrap(),
+            Some(snapshot.clone())
+        );
+
+        assert_eq!(
+            index.locate("ma").unwrap(),
+            [snapshot.root.join("src/main.rs")]
+        );
+        assert_eq!(index.locate("tab").unwrap().len(), 1);
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(index.changed_since(hour_ago).unwrap().len(), 3);
+        assert!(index
+            .changed_since(SystemTime::now() + Duration::from_secs(3600))
+            .unwrap()
+            .is_empty());
+
+        assert!(index.remove(&snapshot.root).unwrap());
+        assert_eq!(index.snapshot(&snapshot.root).unwrap(), None);
+    }
+
+    #[test]
+    fn index_update_cache_dirs() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"1")
+            .file("sub/b.txt", b"12")
+            .build()
+            .unwrap();
+        let store = TreeBuilder::new().build().unwrap();
+        let index = Index::open(store.path()).unwrap();
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for dir in [tree.path().to_path_buf(), tree.join("sub")] {
+            set_mtime(dir, hour_ago).unwrap();
+        }
+        let cached = UpdateOptions {
+            cache_dirs: true,
+            ..UpdateOptions::default()
+        };
+        let len_of = |snapshot: &Snapshot, name: &str| {
+            let entry = snapshot.entries.iter().find(|e| e.path.ends_with(name));
+            entry.map(|entry| entry.len)
+        };
+        let first = index.update_with(tree.path(), &cached).unwrap();
+        assert_eq!(first.entries.len(), 2);
+
+        // written in place: the directory's time is unchanged, so the old size is kept
+        fs::write(tree.join("sub/b.txt"), b"12345").unwrap();
+        fs::write(tree.join("c.txt"), b"").unwrap();
+        let second = index.update_with(tree.path(), &cached).unwrap();
+        assert_eq!(len_of(&second, "b.txt"), Some(2));
+        assert_eq!(len_of(&second, "c.txt"), Some(0));
+        assert_eq!(index.snapshot(&second.root).unwrap(), Some(second.clone()));
+
+        let forced = UpdateOptions {
+            force_refresh: true,
+            ..cached
+        };
+        let refreshed = index.update_with(tree.path(), &forced).unwrap();
+        assert_eq!(len_of(&refreshed, "b.txt"), Some(5));
+        assert_eq!(refreshed.entries.len(), 3);
+        assert!(index.remove(&refreshed.root).unwrap());
+        assert_eq!(fs::read_dir(store.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::encoding::{decode_path, encode_path};
+use crate::result::{Error, ErrorKind, Result};
+use crate::watch::{WatchEvent, WatchEventKind};
+
+const MAGIC: &str = "fs-helper-journal 1";
+
+/// An append-only file of watch events with sequence numbers, so a consumer that restarts
+/// can catch up on the changes it missed with [`Journal::events_since`] instead of
+/// rescanning the tree. Attach it to a watcher with `Watcher::with_journal`.
+///
+/// The file is plain text: a header followed by one line per event with its sequence
+/// number, kind and [encoded](crate::encode_path) path, separated by tabs. A line torn by
+/// a crash while appending is dropped when the journal is opened.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    last_seq: u64,
+}
+
+impl Journal {
+    /// Opens the journal stored at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Journal> {
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            fs::write(&path, format!("{}\n", MAGIC))?;
+        }
+        let text = fs::read_to_string(&path)?;
+        let complete = text.rfind('\n').map_or(0, |end| end + 1);
+        let last_seq = decode(&text[..complete])?.last().map_or(0, |(seq, _)| *seq);
+        let file = OpenOptions::new().append(true).open(&path)?;
+        if complete < text.len() {
+            file.set_len(complete as u64)?;
+        }
+        Ok(Journal {
+            path,
+            file,
+            last_seq,
+        })
+    }
+
+    /// Returns the sequence number of the last event, 0 if there are none.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Appends events, numbering them from `last_seq() + 1`, and returns the new last
+    /// sequence number. The events are written with a single write.
+    pub fn append(&mut self, events: &[WatchEvent]) -> Result<u64> {
+        let mut out = String::new();
+        let mut seq = self.last_seq;
+        for event in events {
+            seq += 1;
+            out.push_str(&format!(
+                "{}\t{}\t{}\n",
+                seq,
+                encode_kind(event.kind),
+                encode_path(&event.path)
+            ));
+        }
+        self.file.write_all(out.as_bytes())?;
+        self.last_seq = seq;
+        Ok(seq)
+    }
+
+    /// Returns the events with a sequence number greater than `seq`, in order.
+    ///
+    /// # Arguments:
+    ///
+    /// * `seq` - last sequence number the consumer has processed, 0 for all events.
+    pub fn events_since(&self, seq: u64) -> Result<Vec<(u64, WatchEvent)>> {
+        let text = fs::read_to_string(&self.path)?;
+        let complete = text.rfind('\n').map_or(0, |end| end + 1);
+        let mut events = decode(&text[..complete])?;
+        events.retain(|(event_seq, _)| *event_seq > seq);
+        Ok(events)
+    }
+}
+
+fn encode_kind(kind: WatchEventKind) -> &'static str {
+    match kind {
+        WatchEventKind::Created => "created",
+        WatchEventKind::Modified => "modified",
+        WatchEventKind::Removed => "removed",
+    }
+}
+
+fn decode(s: &str) -> Result<Vec<(u64, WatchEvent)>> {
+    let invalid = || Error::new(ErrorKind::Encoding, "invalid watch journal");
+    let mut lines = s.lines();
+    if lines.next() != Some(MAGIC) {
+        return Err(invalid());
+    }
+    let mut events = Vec::new();
+    for line in lines {
+        let mut fields = line.splitn(3, '\t');
+        let (seq, kind, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(seq), Some(kind), Some(path)) => (seq, kind, path),
+            _ => return Err(invalid()),
+        };
+        let kind = match kind {
+            "created" => WatchEventKind::Created,
+            "modified" => WatchEventKind::Modified,
+            "removed" => WatchEventKind::Removed,
+            _ => return Err(invalid()),
+        };
+        let event = WatchEvent {
+            path: decode_path(path)?,
+            kind,
+        };
+        events.push((seq.parse().map_err(|_| invalid())?, event));
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::journal::Journal;
+    use crate::watch::Watcher;
+    use crate::{WatchEvent, WatchEventKind};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn journal_replay() {
+        let tree = TreeBuilder::new().file("data/a.txt", b"1").build().unwrap();
+        let journal_path = tree.join("events.journal");
+        let journal = Journal::open(&journal_path).unwrap();
+        let mut watcher = Watcher::new(tree.join("data"))
+            .unwrap()
+            .with_journal(journal);
+        fs::write(tree.join("data/b.txt"), b"2").unwrap();
+        watcher.poll().unwrap();
+        fs::remove_file(tree.join("data/a.txt")).unwrap();
+        watcher.poll().unwrap();
+        drop(watcher);
+
+        // a torn last line is dropped on open
+        let mut file = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        file.write_all(b"3\tcrea").unwrap();
+        let journal = Journal::open(&journal_path).unwrap();
+        assert_eq!(journal.last_seq(), 2);
+        let event = |name, kind| WatchEvent {
+            path: tree.join(name),
+            kind,
+        };
+        assert_eq!(
+            journal.events_since(1).unwrap(),
+            [(2, event("data/a.txt", WatchEventKind::Removed))]
+        );
+        assert_eq!(
+            journal.events_since(0).unwrap()[0],
+            (1, event("data/b.txt", WatchEventKind::Created))
+        );
+        assert!(journal.events_since(2).unwrap().is_empty());
+    }
+}
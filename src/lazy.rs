@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use crate::entry::Entry;
+use crate::vfs::FileKind;
+use crate::walker::{Listing, Walker};
+
+/// Traversal driven directly by `next()`, without a channel or background thread.
+/// Visits entries in the same order as the single-threaded mode.
+pub(crate) struct LazyWalk {
+    walker: Walker,
+    stack: Vec<(PathBuf, usize)>,
+    current: Option<(PathBuf, Listing, usize)>,
+    sub_dirs: Vec<PathBuf>,
+}
+
+impl LazyWalk {
+    pub(crate) fn new(walker: Walker, root: PathBuf) -> LazyWalk {
+        LazyWalk {
+            walker,
+            stack: vec![(root, 1)],
+            current: None,
+            sub_dirs: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for LazyWalk {
+    type Item = Entry;
+
+    /// Advances the traversal.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.walker.is_cancelled() {
+            return None;
+        }
+        loop {
+            if let Some((dir, entries, depth)) = &mut self.current {
+                let depth = *depth;
+                match entries.next() {
+                    Some(entry) => match self.walker.check(dir, depth, entry) {
+                        Some(entry) if entry.kind() != FileKind::Dir => {
+                            return Some(self.walker.make_entry(entry, depth))
+                        }
+                        Some(entry) => {
+                            let path = entry.into_path();
+                            if self.walker.descends(&path, depth) {
+                                self.sub_dirs.push(path)
+                            }
+                        }
+                        None => {}
+                    },
+                    None => {
+                        self.current = None;
+                        let sub_dirs = self.sub_dirs.drain(..).rev();
+                        self.stack.extend(sub_dirs.map(|dir| (dir, depth + 1)));
+                    }
+                }
+            } else {
+                let (dir, depth) = self.stack.pop()?;
+                if let Some(entries) = self.walker.read_dir(&dir) {
+                    self.current = Some((dir, entries, depth));
+                }
+            }
+        }
+    }
+}
@@ -1,32 +1,362 @@
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::path::{self, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
+mod acl;
+mod atomic;
+mod attrs;
+#[cfg(unix)]
+mod audit;
+mod bisync;
+mod breakdown;
+#[cfg(any(target_os = "macos", windows))]
+mod bulk;
+mod cache;
+mod cancel;
+mod capabilities;
+mod case;
+mod cas;
+mod changeset;
+mod chunk;
+mod compare;
+mod complete;
+mod copy;
+mod count;
+mod dedupe;
+#[cfg(feature = "delta")]
+pub mod delta;
+mod diff;
+mod direct;
+mod dirfd;
+pub mod dirs;
+mod durability;
+mod empty;
+mod encoding;
+mod entry;
+mod estimate;
+mod expand;
+mod fifo;
+mod fixture;
+mod flat;
+mod fold;
+mod format;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub mod fuse;
+mod hash;
+mod identity;
+#[cfg(feature = "index")]
+pub mod index;
+mod journal;
+mod lazy;
+mod links;
+#[cfg(unix)]
+mod mlocate;
+mod mounts;
+mod normalize;
+mod open;
+mod order;
+mod overlay;
+mod page;
+mod partition;
+mod paths;
+mod pipeline;
+mod portable;
+mod profile;
+mod progress;
+mod quota;
+mod queue;
+mod recent;
+mod remote;
+mod remove;
+mod reserve;
 mod result;
-use crate::result::Result;
+mod retention;
+mod rules;
+mod sample;
+mod scanner;
+mod shred;
+mod space;
+mod split;
+mod spool;
+mod stats;
+mod stream;
+mod streams;
+mod times;
+mod union;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+mod verify;
+mod vfs;
+mod visit;
+mod walker;
+mod watch;
+pub use crate::acl::{acls_supported, read_acl, write_acl, Acl};
+pub use crate::atomic::{write_atomic, write_atomic_with};
+pub use crate::attrs::{get_attrs, set_append_only, set_immutable, FileAttrs};
+#[cfg(unix)]
+pub use crate::audit::{audit_permissions, AuditFinding, AuditPolicy, PermissionIssue};
+pub use crate::bisync::{
+    apply_sync, keep_both, newest_wins, plan_sync, sync_bidirectional, sync_remote, Baseline,
+    BisyncReport, Conflict, ConflictCopy, Direction, Propagation, Resolution, SyncFailed,
+    SyncOptions, SyncPlan, Version,
+};
+pub use crate::breakdown::{classify, BreakdownReport, TypeStats};
+pub use crate::cache::CacheDir;
+pub use crate::cancel::{CancelToken, Cancelled};
+pub use crate::capabilities::{probe_capabilities, FsCapabilities};
+pub use crate::case::{
+    eq_ignore_case, find_case_collisions, fold_case, is_case_insensitive_fs, names_eq,
+    path_eq_ignore_case,
+};
+pub use crate::cas::{blob_path, gc, load_blob, store_blob};
+pub use crate::changeset::{Change, ChangeSet, Content, RollbackFailed};
+pub use crate::chunk::{Chunk, Chunker, ChunkerOptions};
+pub use crate::compare::files_equal;
+pub use crate::complete::list_dir_completions;
+pub use crate::copy::{
+    copy_dir, copy_dir_in, CopyOptions, IoHints, Symlinks, Verify, VerifyFailure,
+};
+pub use crate::count::{count_entries, Counts};
+pub use crate::dedupe::{dedupe_hardlink, dedupe_reflink, find_duplicates, DedupeReport};
+pub use crate::diff::{diff, dirs_equal, Diff};
+pub use crate::durability::Durability;
+pub use crate::empty::{
+    find_empty_dirs, find_empty_dirs_ignoring, remove_empty_dirs, remove_empty_dirs_ignoring,
+};
+pub use crate::encoding::{decode_path, encode_path};
+pub use crate::entry::Entry;
+pub use crate::estimate::{estimate, Estimate, OpPlan, Throughput};
+pub use crate::expand::{expand, expand_with};
+pub use crate::fifo::create_fifo;
+#[cfg(windows)]
+pub use crate::fifo::{create_named_pipe, pipe_path};
+pub use crate::fixture::{TempTree, TreeBuilder};
+pub use crate::flat::{FlatEntry, ReadDirFlat};
+pub use crate::fold::walk_fold;
+pub use crate::format::{format_count, format_duration, format_rate, format_size};
+pub use crate::hash::{Hash, Hasher};
+pub use crate::identity::{same_file, FileId};
+pub use crate::journal::Journal;
+pub use crate::links::{
+    find_broken_symlinks, remove_broken_symlinks, retarget_symlinks, BrokenLink,
+};
+#[cfg(unix)]
+pub use crate::mlocate::{export_mlocate, import_mlocate, LocateDb};
+pub use crate::mounts::{list_mounts, mount_for, MountInfo};
+pub use crate::normalize::{normalize_path, normalize_str, paths_eq_normalized, Normalization};
+pub use crate::open::open_read_shared;
+pub use crate::overlay::Overlay;
+pub use crate::page::{list_page, PageCursor};
+pub use crate::partition::partition;
+pub use crate::pipeline::{hash_tree_parallel, HashTree};
+pub use crate::paths::{depth_of, is_unc, is_within, long_path, simplify_verbatim};
+pub use crate::portable::{
+    find_nonportable_names, make_portable, name_issue, sanitize_filename, NameIssue, NonPortable,
+    SanitizePolicy,
+};
+pub use crate::profile::Profile;
+pub use crate::progress::{Operation, ProgressEvent, ProgressSink};
+pub use crate::quota::{write_with_quota, QuotaTracker};
+pub use crate::recent::{newest_files, oldest_files};
+pub use crate::remote::{LocalDir, Remote, RemoteStat};
+pub use crate::remove::{remove_tree, RemoveReport};
+pub use crate::reserve::{create_new_exclusive, reserve_paths};
+pub use crate::result::{Error, ErrorKind, Result};
+pub use crate::retention::{cleanup, CleanupReport, RetentionPolicy};
+pub use crate::rules::RuleSet;
+pub use crate::sample::{sample, Sampling};
+pub use crate::scanner::Scanner;
+pub use crate::shred::{shred, shred_dir};
+pub use crate::space::{space_info, usage_report, SpaceInfo, UsageReport};
+pub use crate::split::ReadDirSplit;
+pub use crate::spool::SpooledTempFile;
+pub use crate::stats::{IoStats, Phase};
+pub use crate::stream::{receive_tree, receive_tree_with, stream_tree, stream_tree_with};
+pub use crate::streams::{list_streams, Stream};
+pub use crate::times::{copy_timestamps, set_atime, set_mtime, set_times, touch};
+pub use crate::union::{union_walk, UnionEntry};
+pub use crate::verify::{verify_complete, Completeness};
+pub use crate::vfs::{
+    FileKind, Fs, FsDirEntry, FsDirName, FsMetadata, FsReadDir, FsReadNames, MemFs, ReadFs,
+    ReadOnlyFs, RealFs,
+};
+pub use crate::visit::{walk, walk_in, Control, Visitor};
+pub use crate::walker::{Priority, SymlinkPolicy};
+pub use crate::watch::{watch_and_run, WatchEvent, WatchEventKind, Watcher};
+
+use crate::lazy::LazyWalk;
+use crate::order::{Reorder, Window, REORDER_WINDOW, ROOT_SEQ};
+use crate::partition::Partition;
+use crate::queue::WorkQueue;
+use crate::walker::{DirJob, EntrySender, Walker};
 
 /// ReadDir iterator reads the directory recursively.
 /// First returns all files of current directory and then visit all subdirectories.
-/// Implemented with threads now (yield operator not implemented yet)!
+/// Symbolic links are yielded as entries and never followed.
+///
+/// By default one background thread walks the tree and sends the entries to the iterator.
+/// In multithreaded mode (`is_multithreaded`) directories are read by a pool of worker
+/// threads, so the order of entries is not deterministic, unless `preserve_order` is set
+/// to yield them in the single-threaded order. In lazy mode (`is_lazy`) no thread is
+/// started: each call to `next()` reads as much of the tree as it needs.
+///
+/// Directories that can not be read, e.g. because they were removed or renamed during
+/// the traversal, are skipped and reported to `progress` as `SkippedEntry`.
+///
+/// Thread safety: ReadDir is `Send` (it can be moved into another thread, e.g. a rayon or
+/// tokio blocking task) but not `Sync`, as iteration needs `&mut self`.
+/// Entries and errors are `Send + Sync`.
 pub struct ReadDir {
+    fs: Arc<dyn ReadFs>,
     root: PathBuf,
-    rx: Option<mpsc::Receiver<PathBuf>>,
-    pub is_multithreaded: bool
+    rx: Option<mpsc::Receiver<Entry>>,
+    lazy: Option<LazyWalk>,
+    ordered: Option<Reorder>,
+    cancel: Arc<AtomicBool>,
+    workers: Vec<thread::JoinHandle<()>>,
+    queue: Option<Arc<WorkQueue<DirJob>>>,
+    deadline: Option<Instant>,
+    partition: Option<Arc<Partition>>,
+    pub is_multithreaded: bool,
+    /// If set, traversal is driven by `next()` itself, without a background thread.
+    /// Takes precedence over `is_multithreaded`.
+    pub is_lazy: bool,
+    /// If set, yielded paths are normalized to the given Unicode form.
+    pub normalization: Option<Normalization>,
+    /// If set, receives scan progress events.
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    /// If set, at most this many entries are buffered between the workers and the consumer;
+    /// workers wait while the buffer is full. Unbounded by default.
+    pub channel_capacity: Option<usize>,
+    /// Number of worker threads in multithreaded mode; defaults to the available parallelism.
+    pub threads: Option<usize>,
+    /// If set, directories on other mounted filesystems than the root are not descended into.
+    pub same_file_system: bool,
+    /// How many times listing a directory is retried after a stale file handle error.
+    pub stale_retries: u32,
+    /// If set, the entries of each directory are sorted by this key, e.g. to surface the
+    /// largest files early. Costs one metadata read per entry. In multithreaded mode
+    /// directories are still processed in no particular order.
+    pub priority: Option<Priority>,
+    /// If set, only entries selected by these include/exclude rules are yielded;
+    /// excluded directories are not descended into.
+    pub rules: Option<Arc<RuleSet>>,
+    /// If set, directories at this depth are not descended into: with 1 only the entries
+    /// of the root are yielded.
+    pub max_depth: Option<usize>,
+    /// What to do with symbolic links; they are yielded by default.
+    pub symlinks: SymlinkPolicy,
+    /// If set, the traversal ends early once the token is cancelled, e.g. by ctrl-c with
+    /// `CancelToken::on_signals`; check the token to tell a cancelled scan from a
+    /// complete one.
+    pub cancel_token: Option<CancelToken>,
+    /// If set, entries hold the path of their directory, shared with their siblings (see
+    /// `Entry::shared_dir`), and their name, and join them on the first call to
+    /// `Entry::path`. Scans that only look at names (`Entry::file_name`) or count entries
+    /// then allocate a name per entry instead of a full path. Ignored with `normalization`.
+    pub shared_dirs: bool,
+    /// If set, a multithreaded traversal yields entries in the order of the single-threaded
+    /// one: workers send whole directory listings, numbered as the directories are found,
+    /// and the listings finished ahead of the one being yielded wait in a reorder buffer.
+    /// The buffer holds a few dozen listings; workers wait while it is full, so a slow
+    /// directory holds up the traversal. Unordered traversal gives the best throughput.
+    /// `channel_capacity` then counts listings instead of entries.
+    pub preserve_order: bool
 }
 
 impl ReadDir {
     /// Attempts to create a new iterator.
+    /// The root is canonicalized; on Windows the verbatim prefix is dropped when the root
+    /// is usable without it, so drive and UNC roots yield ordinary paths.
     ///
     /// # Arguments:
     ///
     /// * `dir` - root directory.
     pub fn try_new<P: AsRef<Path>>(dir: P) -> Result<ReadDir> {
-        Ok(ReadDir {
-            root: fs::canonicalize(dir)?,
+        ReadDir::try_new_in(Arc::new(RealFs), dir)
+    }
+
+    /// Attempts to create a new iterator over a directory of the given filesystem.
+    ///
+    /// # Arguments:
+    ///
+    /// * `fs` - filesystem to read.
+    /// * `dir` - root directory.
+    pub fn try_new_in<P: AsRef<Path>>(fs: Arc<dyn ReadFs>, dir: P) -> Result<ReadDir> {
+        let root = simplify_verbatim(fs.canonicalize(dir.as_ref())?);
+        Ok(ReadDir::with_root(fs, root))
+    }
+
+    /// Creates an iterator tuned for network filesystems (NFS, SMB).
+    /// The root is made absolute without being canonicalized, to save round trips to the
+    /// server; the scan uses two worker threads, retries stale file handles and stays on
+    /// the root's filesystem. Timeouts are set per call, see `next_timeout` and `with_deadline`.
+    ///
+    /// # Arguments:
+    ///
+    /// * `dir` - root directory.
+    pub fn nfs_mode<P: AsRef<Path>>(dir: P) -> Result<ReadDir> {
+        let mut rd = ReadDir::with_root(Arc::new(RealFs), path::absolute(dir)?);
+        rd.is_multithreaded = true;
+        rd.threads = Some(NFS_THREADS);
+        rd.same_file_system = true;
+        rd.stale_retries = NFS_STALE_RETRIES;
+        Ok(rd)
+    }
+
+    /// Creates an iterator for each root of a profile, configured by its settings.
+    /// Chain them (e.g. with `flatten`) to scan all roots in order.
+    ///
+    /// # Arguments:
+    ///
+    /// * `profile` - scan settings.
+    pub fn from_profile(profile: &Profile) -> Result<Vec<ReadDir>> {
+        let rules = (!profile.rules.is_empty()).then(|| Arc::new(profile.rules.clone()));
+        profile
+            .roots
+            .iter()
+            .map(|root| {
+                let mut rd = ReadDir::try_new(root)?;
+                rd.rules = rules.clone();
+                rd.max_depth = profile.max_depth;
+                rd.symlinks = profile.symlinks;
+                rd.is_multithreaded = profile.threads.is_some();
+                rd.threads = profile.threads;
+                Ok(rd)
+            })
+            .collect()
+    }
+
+    fn with_root(fs: Arc<dyn ReadFs>, root: PathBuf) -> ReadDir {
+        ReadDir {
+            fs,
+            root,
             rx: None,
-            is_multithreaded: false
-        })
+            lazy: None,
+            ordered: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+            workers: Vec::new(),
+            queue: None,
+            deadline: None,
+            partition: None,
+            is_multithreaded: false,
+            is_lazy: false,
+            normalization: None,
+            progress: None,
+            channel_capacity: None,
+            threads: None,
+            same_file_system: false,
+            stale_retries: 0,
+            priority: None,
+            rules: None,
+            max_depth: None,
+            symlinks: SymlinkPolicy::Yield,
+            cancel_token: None,
+            shared_dirs: false,
+            preserve_order: false
+        }
     }
 
     /// Returns a root directory.
@@ -34,74 +364,288 @@ impl ReadDir {
         &self.root
     }
 
+    /// Sets a point in time after which the scan no longer blocks.
+    /// Past the deadline `next_timeout` returns `ErrorKind::Timeout` errors and `next()` ends
+    /// the iteration, so a scan stuck on a slow network filesystem can not hang the caller.
+    ///
+    /// # Arguments:
+    ///
+    /// * `deadline` - time by which the scan must finish.
+    pub fn with_deadline(mut self, deadline: Instant) -> ReadDir {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Returns the next entry, waiting at most `timeout` (and not past the deadline) for it.
+    /// Returns `Ok(None)` when the traversal is complete, and an error of kind
+    /// `ErrorKind::Timeout` if no entry arrived in time (the traversal goes on; call again).
+    /// In lazy mode entries are read by the calling thread, so only the deadline applies,
+    /// and only between entries.
+    ///
+    /// # Arguments:
+    ///
+    /// * `timeout` - maximum time to wait.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<Entry>> {
+        self.next_within(Some(timeout))
+    }
+
+    /// Shares the traversal between `consumers` iterators, e.g. one per thread hashing or
+    /// uploading files while the scan goes on; each entry is yielded by one of them.
+    ///
+    /// # Arguments:
+    ///
+    /// * `consumers` - number of iterators, at least 1.
+    pub fn split(self, consumers: usize) -> Vec<ReadDirSplit> {
+        let split = ReadDirSplit::new(self);
+        vec![split; consumers.max(1)]
+    }
+
+    /// Calls `f` on every entry from `threads` threads at once, while the scan goes on.
+    /// Stops at the first error returned by `f`, which is returned once the calls in
+    /// progress have finished.
+    ///
+    /// # Arguments:
+    ///
+    /// * `threads` - number of threads calling `f`, at least 1.
+    /// * `f` - processing of an entry.
+    pub fn for_each_parallel<F>(self, threads: usize, f: F) -> Result<()>
+    where
+        F: Fn(Entry) -> Result<()> + Sync,
+    {
+        let failure = Mutex::new(None);
+        let stop = AtomicBool::new(false);
+        let (f, failure, stop) = (&f, &failure, &stop);
+        thread::scope(|scope| {
+            for split in self.split(threads) {
+                scope.spawn(move || {
+                    for entry in split {
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        if let Err(e) = f(entry) {
+                            stop.store(true, Ordering::Relaxed);
+                            failure.lock().unwrap().get_or_insert(e);
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        let failure = failure.lock().unwrap().take();
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn next_within(&mut self, timeout: Option<Duration>) -> Result<Option<Entry>> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(Error::new(ErrorKind::Timeout, "scan deadline exceeded"));
+                }
+                Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)))
+            }
+            None => timeout,
+        };
+        let started = self.rx.is_some() || self.lazy.is_some() || self.ordered.is_some();
+        if let (false, Some(progress)) = (started, &self.progress) {
+            progress.event(&ProgressEvent::ScanStarted { root: &self.root });
+        }
+        let next = self.advance(timeout)?;
+        if let Some(progress) = &self.progress {
+            match &next {
+                Some(entry) => progress.event(&ProgressEvent::EntryVisited {
+                    path: entry.path(),
+                    depth: entry.depth(),
+                }),
+                None => progress.event(&ProgressEvent::OperationFinished {
+                    operation: Operation::Scan,
+                }),
+            }
+        }
+        Ok(next)
+    }
+
+    /// Returns the next entry of the selected traversal mode.
+    fn advance(&mut self, timeout: Option<Duration>) -> Result<Option<Entry>> {
+        if self.is_lazy {
+            if self.lazy.is_none() {
+                self.lazy = Some(LazyWalk::new(self.walker(), self.root.clone()));
+            }
+            return Ok(self.lazy.as_mut().and_then(|walk| walk.next()));
+        }
+        if self.rx.is_none() && self.ordered.is_none() {
+            self.run();
+        }
+        if let Some(ordered) = &mut self.ordered {
+            return match ordered.next(timeout) {
+                Ok(entry) => Ok(entry),
+                Err(_) => Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("no entry within {:?}", timeout.unwrap_or_default()),
+                )),
+            };
+        }
+        let receiver = match &self.rx {
+            Some(receiver) => receiver,
+            None => return Ok(None),
+        };
+        match timeout {
+            None => Ok(receiver.recv().ok()),
+            Some(timeout) => match receiver.recv_timeout(timeout) {
+                Ok(entry) => Ok(Some(entry)),
+                Err(mpsc::RecvTimeoutError::Disconnected) => Ok(None),
+                Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("no entry within {:?}", timeout),
+                )),
+            },
+        }
+    }
+
+    fn walker(&self) -> Walker {
+        Walker {
+            fs: Arc::clone(&self.fs),
+            norm: self.normalization,
+            progress: self.progress.clone(),
+            cancel: Arc::clone(&self.cancel),
+            device: if self.same_file_system {
+                self.fs.metadata(&self.root).ok().and_then(|meta| meta.device)
+            } else {
+                None
+            },
+            stale_retries: self.stale_retries,
+            priority: self.priority,
+            partition: self.partition.clone(),
+            rules: self.rules.clone(),
+            root: self.root.clone(),
+            max_depth: self.max_depth,
+            symlinks: self.symlinks,
+            token: self.cancel_token.clone(),
+            shared_dirs: self.shared_dirs,
+        }
+    }
+
     /// Makes the iterator multithreaded.
     fn run(&mut self) {
-        let (tx, rx) = mpsc::channel();
-        self.rx = Some(rx);
         let root = PathBuf::from(self.root());
+        let walker = self.walker();
+        if self.is_multithreaded && self.preserve_order {
+            self.run_ordered(root, walker);
+            return;
+        }
+        let (tx, rx) = EntrySender::channel(self.channel_capacity);
+        self.rx = Some(rx);
         if self.is_multithreaded {
-            thread::spawn(|| Self::visit_multithreaded(root, tx).unwrap());
+            let workers = self.worker_count();
+            let queue = Arc::new(WorkQueue::new((root, 1, ROOT_SEQ)));
+            self.queue = Some(Arc::clone(&queue));
+            for _ in 0..workers {
+                let queue = Arc::clone(&queue);
+                let tx = tx.clone();
+                let walker = walker.clone();
+                // fails only when the iterator has been dropped
+                self.workers.push(thread::spawn(move || {
+                    let _ = walker.visit_multithreaded(&queue, tx);
+                }));
+            }
         } else {
-            thread::spawn(|| Self::visit(root, tx).unwrap());
+            self.workers.push(thread::spawn(move || {
+                let _ = walker.visit(root, 1, tx);
+            }));
         }
     }
 
-    fn visit(dir: PathBuf, tx: mpsc::Sender<PathBuf>) -> Result<()> {
-        let mut sub_dirs: Vec<PathBuf> = Vec::new();
-        let entries = fs::read_dir(dir)?;
-        for entry in entries {
-            let path = entry?.path();
-            if path.is_dir() {
-                sub_dirs.push(path)
-            } else {
-                tx.send(path)?;
-            }
+    /// Starts a multithreaded traversal whose listings are put back in order by `next()`.
+    fn run_ordered(&mut self, root: PathBuf, walker: Walker) {
+        let (tx, rx) = EntrySender::channel(self.channel_capacity);
+        let window = Arc::new(Window::new(REORDER_WINDOW));
+        self.ordered = Some(Reorder::new(rx, Arc::clone(&window)));
+        let queue = Arc::new(WorkQueue::new((root, 1, ROOT_SEQ)));
+        self.queue = Some(Arc::clone(&queue));
+        let seqs = Arc::new(AtomicU64::new(ROOT_SEQ + 1));
+        for _ in 0..self.worker_count() {
+            let queue = Arc::clone(&queue);
+            let seqs = Arc::clone(&seqs);
+            let window = Arc::clone(&window);
+            let tx = tx.clone();
+            let walker = walker.clone();
+            // fails only when the iterator has been dropped
+            self.workers.push(thread::spawn(move || {
+                let _ = walker.visit_multithreaded_ordered(&queue, &seqs, &window, tx);
+            }));
         }
-        for sub_dir in sub_dirs {
-            Self::visit(sub_dir, tx.clone())?;
-        }
-        Ok(())
     }
 
-    fn visit_multithreaded(dir: PathBuf, tx: mpsc::Sender<PathBuf>) -> Result<()> {
-        let entries = fs::read_dir(dir)?;
-        for entry in entries {
-            let path = entry?.path();
-            if path.is_dir() {
-                let _tx = tx.clone();
-                thread::spawn(|| {
-                    println!("New thread created!");
-                    Self::visit_multithreaded(path, _tx).unwrap()
-                });
-            } else {
-                tx.send(path)?;
+    /// Returns the number of worker threads of a multithreaded traversal.
+    fn worker_count(&self) -> usize {
+        self.threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(4, |n| n.get()))
+            .max(1)
+    }
+}
+
+// Compile-time checks of the thread-safety contract.
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send::<ReadDir>();
+    assert_send_sync::<Entry>();
+    assert_send_sync::<Error>();
+    assert_send_sync::<MemFs>();
+    assert_send_sync::<Hash>();
+};
+
+/// Worker threads used by `ReadDir::nfs_mode`, to avoid flooding the server with requests.
+const NFS_THREADS: usize = 2;
+
+/// Stale file handle retries used by `ReadDir::nfs_mode`.
+const NFS_STALE_RETRIES: u32 = 3;
+
+/// How long dropping a ReadDir waits for its worker threads before detaching them.
+const DROP_JOIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+impl Drop for ReadDir {
+    /// Cancels the traversal and joins the worker threads.
+    /// Workers blocked for longer than a short timeout (e.g. on a hung network filesystem)
+    /// are detached; they stop as soon as their current filesystem call returns.
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        if let Some(queue) = &self.queue {
+            queue.close();
+        }
+        // pending sends fail once the receiver is gone
+        self.rx = None;
+        self.ordered = None;
+        let deadline = Instant::now() + DROP_JOIN_TIMEOUT;
+        while self.workers.iter().any(|w| !w.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        for worker in self.workers.drain(..) {
+            if worker.is_finished() {
+                let _ = worker.join();
             }
         }
-        Ok(())
     }
 }
 
 impl Iterator for ReadDir {
-    type Item = PathBuf;
+    type Item = Entry;
 
     /// Advances the iterator and returns the next value.
+    /// With a deadline set, returns `None` once it has passed.
     fn next(&mut self) -> Option<Self::Item> {
-        if self.rx.is_none() {
-            self.run();
-        }
-        if let Some(receiver) = &self.rx {
-            if let Ok(path) = receiver.recv() {
-                return Some(path);
-            }
-        }
-        None
+        self.next_within(None).ok().flatten()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ReadDir;
+    use crate::{ReadDir, TempTree, TreeBuilder};
     use std::env;
+    use std::path::PathBuf;
 
     #[test]
     fn read_dir_new() {
@@ -111,72 +655,435 @@ mod tests {
 
     #[test]
     fn read_dir_next() {
-        let dir = "/tmp/fs-helper-test-1";
-        utils::create_test_dir(dir);
-
-        let rd = ReadDir::try_new(".").unwrap();
-        for path in rd {
-            println!("{}", path.display());
-        }
+        let tree = create_test_tree();
 
-        utils::clean(dir);
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        let paths: Vec<_> = rd.map(|entry| entry.path().to_path_buf()).collect();
+        assert_eq!(paths.len(), 11);
+        assert!(paths[..3].iter().all(|path| path.parent() == Some(tree.path())));
+        assert_eq!(sorted(paths), test_tree_paths(&tree));
     }
 
     #[test]
     fn read_dir_next_multithreaded() {
-        let dir = "/tmp/fs-helper-test-2";
-        utils::create_test_dir(dir);
+        let tree = create_test_tree();
 
-        let mut rd = ReadDir::try_new(".").unwrap();
+        let mut rd = ReadDir::try_new(tree.path()).unwrap();
         rd.is_multithreaded = true;
-        for path in rd {
-            println!("{}", path.display());
+        let paths: Vec<_> = rd.map(|entry| entry.path().to_path_buf()).collect();
+        assert_eq!(paths.len(), 11);
+        assert_eq!(sorted(paths), test_tree_paths(&tree));
+    }
+
+    #[test]
+    fn read_dir_multithreaded_worker_panic() {
+        use crate::{FsMetadata, FsReadDir, MemFs, ReadFs};
+        use std::io::{self, Read};
+        use std::path::{Path, PathBuf};
+        use std::sync::{mpsc, Arc};
+        use std::time::Duration;
+
+        /// Panics when listing directories named `bad`, as a faulty callback would.
+        struct PanickingFs(MemFs);
+
+        impl ReadFs for PanickingFs {
+            fn read_dir(&self, path: &Path) -> io::Result<FsReadDir> {
+                assert!(!path.ends_with("bad"), "listing {}", path.display());
+                self.0.read_dir(path)
+            }
+
+            fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+                self.0.metadata(path)
+            }
+
+            fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+                self.0.symlink_metadata(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                self.0.canonicalize(path)
+            }
+
+            fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+                self.0.open(path)
+            }
+        }
+
+        let fs = MemFs::new();
+        TreeBuilder::new()
+            .file("a.txt", "")
+            .file("bad/b.txt", "")
+            .file("ok/c.txt", "")
+            .build_in(&fs, "/root")
+            .unwrap();
+        let fs: Arc<dyn ReadFs> = Arc::new(PanickingFs(fs));
+        for preserve_order in [false, true] {
+            let mut rd = ReadDir::try_new_in(Arc::clone(&fs), "/root").unwrap();
+            rd.is_multithreaded = true;
+            rd.threads = Some(2);
+            rd.preserve_order = preserve_order;
+            // the other worker must not wait for the directory that was never finished
+            let (tx, rx) = mpsc::channel();
+            std::thread::spawn(move || tx.send(rd.count()).unwrap());
+            assert!(rx.recv_timeout(Duration::from_secs(10)).is_ok());
+        }
+    }
+
+    #[test]
+    fn read_dir_entry_depth() {
+        let tree = create_test_tree();
+        let dir = tree.path();
+
+        let rd = ReadDir::try_new(dir).unwrap();
+        let root = rd.root().to_path_buf();
+        let mut count = 0;
+        for entry in rd {
+            assert_eq!(Some(entry.depth()), crate::depth_of(entry.path(), &root));
+            count += 1;
+        }
+        assert_eq!(count, 11);
+    }
+
+    #[test]
+    fn read_dir_progress() {
+        use crate::ProgressEvent;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let tree = create_test_tree();
+        let dir = tree.path();
+
+        let events = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&events);
+        let mut rd = ReadDir::try_new(dir).unwrap();
+        rd.progress = Some(Arc::new(move |event: &ProgressEvent| {
+            if let ProgressEvent::EntryVisited { .. } = event {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        assert_eq!(rd.count(), 11);
+        assert_eq!(events.load(Ordering::Relaxed), 11);
+    }
+
+    #[test]
+    fn read_dir_mem_fs() {
+        use crate::MemFs;
+        use std::sync::Arc;
+
+        let fs = MemFs::new();
+        fs.write("/root/a.txt", "a").unwrap();
+        fs.write("/root/sub/b.txt", "b").unwrap();
+        fs.symlink("sub", "/root/link").unwrap();
+
+        for (is_lazy, is_multithreaded) in [(true, false), (false, false), (false, true)] {
+            let mut rd = ReadDir::try_new_in(Arc::new(fs.clone()), "/root").unwrap();
+            rd.is_lazy = is_lazy;
+            rd.is_multithreaded = is_multithreaded;
+            let mut paths: Vec<_> = rd.map(|e| e.into_path()).collect();
+            paths.sort();
+            let expected = ["/root/a.txt", "/root/link", "/root/sub/b.txt"];
+            assert_eq!(paths, expected.map(std::path::PathBuf::from));
         }
 
-        utils::clean(dir);
+        // metadata returned with the listing is what the entries report
+        let mut rd = ReadDir::try_new_in(Arc::new(fs.clone()), "/root").unwrap();
+        rd.max_depth = Some(1);
+        let entries: Vec<_> = rd.collect();
+        fs.write("/root/a.txt", "abc").unwrap();
+        let a = entries.iter().find(|e| e.path().ends_with("a.txt")).unwrap();
+        assert_eq!(a.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn read_dir_preserve_order() {
+        let mut builder = TreeBuilder::new().file("a.txt", "");
+        for i in 0..10 {
+            for j in 0..5 {
+                builder = builder.file(format!("d{}/f{}.txt", i, j), "");
+                builder = builder.file(format!("d{}/s{}/g.txt", i, j), "");
+            }
+        }
+        let tree = builder.build().unwrap();
+        let expected: Vec<_> = ReadDir::try_new(tree.path()).unwrap().collect();
+        assert_eq!(expected.len(), 101);
+        for capacity in [None, Some(1)] {
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.is_multithreaded = true;
+            rd.preserve_order = true;
+            rd.threads = Some(4);
+            rd.channel_capacity = capacity;
+            assert_eq!(rd.collect::<Vec<_>>(), expected);
+        }
     }
 
-    mod utils {
-        use std::fmt::Debug;
-        use std::fs;
+    #[test]
+    fn read_dir_preserve_order_slow_dir() {
+        use crate::order::REORDER_WINDOW;
+        use crate::{FsMetadata, FsReadDir, MemFs, ReadFs};
+        use std::io::{self, Read};
         use std::path::{Path, PathBuf};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        /// Lists `/root/a` slowly, counting the directories listed meanwhile.
+        struct SlowFs {
+            fs: MemFs,
+            listed: AtomicUsize,
+            listed_while_slow: AtomicUsize,
+        }
 
-        pub fn create_test_dir<P: AsRef<Path> + Debug>(dir: P) {
-            // first level
-            if !dir.as_ref().exists() {
-                fs::create_dir(&dir).unwrap();
+        impl ReadFs for SlowFs {
+            fn read_dir(&self, path: &Path) -> io::Result<FsReadDir> {
+                if path == Path::new("/root/a") {
+                    let before = self.listed.load(Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(300));
+                    let during = self.listed.load(Ordering::SeqCst) - before;
+                    self.listed_while_slow.store(during, Ordering::SeqCst);
+                } else {
+                    self.listed.fetch_add(1, Ordering::SeqCst);
+                }
+                self.fs.read_dir(path)
             }
-            for fname in ["file01.txt", "file02.txt", "file03.txt"] {
-                fs::File::create(format!("{}/{}", dir.as_ref().display(), fname)).unwrap();
+
+            fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+                self.fs.metadata(path)
             }
-            // second level
-            let sub_dir = PathBuf::from(format!("{}/{}", dir.as_ref().display(), "subdir1"));
-            if !sub_dir.exists() {
-                fs::create_dir(&sub_dir).unwrap();
+
+            fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+                self.fs.symlink_metadata(path)
             }
-            for fname in ["file11.txt", "file12.txt", "file13.txt"] {
-                fs::File::create(format!("{}/{}", sub_dir.display(), fname)).unwrap();
+
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                self.fs.canonicalize(path)
             }
-            // third level
-            let sub_dir = PathBuf::from(format!("{}/{}", sub_dir.display(), "subdir2"));
-            if !sub_dir.exists() {
-                fs::create_dir(&sub_dir).unwrap();
+
+            fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+                self.fs.open(path)
             }
-            for fname in [
-                "file21.txt",
-                "file22.txt",
-                "file23.txt",
-                "file24.txt",
-                "file25.txt",
-            ] {
-                fs::File::create(format!("{}/{}", sub_dir.display(), fname)).unwrap();
+        }
+
+        let mut builder = TreeBuilder::new().file("a/x.txt", "");
+        for i in 0..500 {
+            builder = builder.file(format!("d{:03}/f.txt", i), "");
+        }
+        let fs = MemFs::new();
+        builder.build_in(&fs, "/root").unwrap();
+        let expected: Vec<_> = ReadDir::try_new_in(Arc::new(fs.clone()), "/root")
+            .unwrap()
+            .map(|entry| entry.into_path())
+            .collect();
+        let slow = Arc::new(SlowFs {
+            fs,
+            listed: AtomicUsize::new(0),
+            listed_while_slow: AtomicUsize::new(0),
+        });
+        let mut rd = ReadDir::try_new_in(slow.clone(), "/root").unwrap();
+        rd.is_multithreaded = true;
+        rd.preserve_order = true;
+        rd.threads = Some(4);
+        let paths: Vec<_> = rd.map(|entry| entry.into_path()).collect();
+        assert_eq!(paths, expected);
+        // the other workers stop once the reorder buffer is full
+        assert!(slow.listed_while_slow.load(Ordering::SeqCst) <= REORDER_WINDOW + 4);
+    }
+
+    #[test]
+    fn read_dir_concurrent_modification() {
+        use crate::ProgressEvent;
+        use std::sync::{Arc, Mutex};
+
+        for (is_lazy, is_multithreaded) in [(true, false), (false, false), (false, true)] {
+            let mut builder = TreeBuilder::new().file("a.txt", "");
+            for i in 0..20 {
+                builder = builder.file(format!("d{}/sub/f.txt", i), "");
             }
+            let tree = builder.build().unwrap();
+
+            let skipped = Arc::new(Mutex::new(Vec::new()));
+            let sink = Arc::clone(&skipped);
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.is_lazy = is_lazy;
+            rd.is_multithreaded = is_multithreaded;
+            rd.progress = Some(Arc::new(move |event: &ProgressEvent| {
+                if let ProgressEvent::SkippedEntry { path, .. } = event {
+                    sink.lock().unwrap().push(path.to_path_buf());
+                }
+            }));
+            let mut count = 0;
+            for _ in rd.by_ref().take(1) {
+                for i in 0..20 {
+                    std::fs::remove_dir_all(tree.join(format!("d{}", i))).unwrap();
+                }
+                count += 1;
+            }
+            count += rd.count();
+            assert!(count <= 21);
+            assert!(skipped.lock().unwrap().iter().all(|path| path.starts_with(tree.path())));
+        }
+    }
+
+    #[test]
+    fn read_dir_cancel_token() {
+        let tree = create_test_tree();
+        for is_lazy in [false, true] {
+            let token = crate::CancelToken::new();
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.is_lazy = is_lazy;
+            rd.cancel_token = Some(token.clone());
+            token.cancel();
+            assert_eq!(rd.count(), 0);
         }
+    }
+
+    #[test]
+    fn read_dir_drop_joins_workers() {
+        let tree = create_test_tree();
+        for is_multithreaded in [false, true] {
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.is_multithreaded = is_multithreaded;
+            assert_eq!(rd.by_ref().take(2).count(), 2);
+            let cancel = std::sync::Arc::clone(&rd.cancel);
+            let start = std::time::Instant::now();
+            drop(rd);
+            assert!(cancel.load(std::sync::atomic::Ordering::Relaxed));
+            assert!(start.elapsed() < super::DROP_JOIN_TIMEOUT);
+        }
+    }
+
+    #[test]
+    fn read_dir_moved_into_thread() {
+        let tree = create_test_tree();
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        let count = std::thread::spawn(move || rd.count()).join().unwrap();
+        assert_eq!(count, 11);
+    }
+
+    #[test]
+    fn read_dir_bounded_next_timeout() {
+        use std::time::Duration;
 
-        pub fn clean<P: AsRef<Path>>(dir: P) {
-            if dir.as_ref().exists() {
-                fs::remove_dir_all(&dir).unwrap();
+        let tree = create_test_tree();
+        let mut rd = ReadDir::try_new(tree.path()).unwrap();
+        rd.channel_capacity = Some(1);
+        let mut count = 0;
+        loop {
+            match rd.next_timeout(Duration::from_secs(5)) {
+                Ok(Some(_)) => count += 1,
+                Ok(None) => break,
+                Err(e) => panic!("{}", e),
             }
         }
+        assert_eq!(count, 11);
+    }
+
+    #[test]
+    fn read_dir_deadline() {
+        use crate::ErrorKind;
+        use std::time::{Duration, Instant};
+
+        let tree = create_test_tree();
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        let mut rd = rd.with_deadline(Instant::now());
+        match rd.next_timeout(Duration::from_secs(5)) {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::Timeout),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+        assert!(rd.next().is_none());
+
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        let rd = rd.with_deadline(Instant::now() + Duration::from_secs(60));
+        assert_eq!(rd.count(), 11);
+    }
+
+    #[test]
+    fn read_dir_nfs_mode() {
+        let tree = create_test_tree();
+        let rd = ReadDir::nfs_mode(tree.path()).unwrap();
+        assert!(rd.is_multithreaded && rd.same_file_system);
+        assert_eq!(rd.count(), 11);
+
+        let mut rd = ReadDir::try_new(tree.path()).unwrap();
+        rd.is_lazy = true;
+        rd.same_file_system = true;
+        assert_eq!(rd.count(), 11);
+    }
+
+    #[test]
+    fn read_dir_priority() {
+        use crate::Priority;
+
+        let tree = TreeBuilder::new()
+            .file("small.txt", b"1")
+            .file("large.txt", b"12345")
+            .file("medium.txt", b"123")
+            .build()
+            .unwrap();
+        for is_lazy in [true, false] {
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.is_lazy = is_lazy;
+            rd.priority = Some(Priority::Largest);
+            let names: Vec<_> = rd.map(|e| e.path().file_name().unwrap().to_owned()).collect();
+            assert_eq!(names, ["large.txt", "medium.txt", "small.txt"]);
+        }
+    }
+
+    #[test]
+    fn read_dir_next_lazy() {
+        let tree = create_test_tree();
+        let dir = tree.path();
+
+        let expected: Vec<_> = ReadDir::try_new(dir).unwrap().collect();
+        let mut rd = ReadDir::try_new(dir).unwrap();
+        rd.is_lazy = true;
+        let entries: Vec<_> = rd.collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn read_dir_deep_tree() {
+        // stays below PATH_MAX, since paths are absolute
+        let depth = 1500;
+        let deepest: std::path::PathBuf = std::iter::repeat_n("d", depth).collect();
+        let tree = TreeBuilder::new().file(deepest.join("f"), "").build().unwrap();
+        let dir = tree.path();
+
+        let entries: Vec<_> = ReadDir::try_new(dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].depth(), depth + 1);
+
+        let mut rd = ReadDir::try_new(dir).unwrap();
+        rd.is_multithreaded = true;
+        assert_eq!(rd.count(), 1);
+    }
+
+    fn create_test_tree() -> TempTree {
+        TreeBuilder::new()
+            .file("file01.txt", "")
+            .file("file02.txt", "")
+            .file("file03.txt", "")
+            .file("subdir1/file11.txt", "")
+            .file("subdir1/file12.txt", "")
+            .file("subdir1/file13.txt", "")
+            .file("subdir1/subdir2/file21.txt", "")
+            .file("subdir1/subdir2/file22.txt", "")
+            .file("subdir1/subdir2/file23.txt", "")
+            .file("subdir1/subdir2/file24.txt", "")
+            .file("subdir1/subdir2/file25.txt", "")
+            .build()
+            .unwrap()
+    }
+
+    /// The files of [`create_test_tree`], sorted.
+    fn test_tree_paths(tree: &TempTree) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = (1..=3).map(|i| format!("file0{}.txt", i)).collect();
+        paths.extend((1..=3).map(|i| format!("subdir1/file1{}.txt", i)));
+        paths.extend((1..=5).map(|i| format!("subdir1/subdir2/file2{}.txt", i)));
+        sorted(paths.into_iter().map(|path| tree.join(path)).collect())
+    }
+
+    fn sorted(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        paths.sort();
+        paths
     }
 }
@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::temp_path_for;
+use crate::result::Result;
+use crate::vfs::FileKind;
+use crate::ReadDir;
+
+/// A symbolic link whose target does not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// Path of the link.
+    pub link: PathBuf,
+    /// Target of the link, as stored in the link (possibly relative to its directory).
+    pub target: PathBuf,
+}
+
+/// Returns the symbolic links under `root` whose targets do not exist, sorted by link path.
+pub fn find_broken_symlinks<P: AsRef<Path>>(root: P) -> Result<Vec<BrokenLink>> {
+    let mut broken = Vec::new();
+    for link in symlinks(root)? {
+        match fs::metadata(&link) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let target = fs::read_link(&link)?;
+                broken.push(BrokenLink { link, target });
+            }
+            _ => {}
+        }
+    }
+    Ok(broken)
+}
+
+/// Removes the symbolic links under `root` whose targets do not exist.
+/// Returns the removed links.
+pub fn remove_broken_symlinks<P: AsRef<Path>>(root: P) -> Result<Vec<BrokenLink>> {
+    let broken = find_broken_symlinks(root)?;
+    for link in &broken {
+        fs::remove_file(&link.link)?;
+    }
+    Ok(broken)
+}
+
+/// Rewrites the symbolic links under `root` whose targets start with `from`, replacing
+/// that prefix with `to`, e.g. after a tree was moved to another machine or mount point.
+/// Each link is replaced atomically. Returns the rewritten links.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `from` - old target prefix.
+/// * `to` - new target prefix.
+pub fn retarget_symlinks<P, F, T>(root: P, from: F, to: T) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    let mut rewritten = Vec::new();
+    for link in symlinks(root)? {
+        let target = fs::read_link(&link)?;
+        let rest = match target.strip_prefix(from.as_ref()) {
+            Ok(rest) => rest,
+            Err(_) => continue,
+        };
+        let tmp = temp_path_for(&link);
+        symlink(&to.as_ref().join(rest), &tmp)?;
+        if let Err(e) = fs::rename(&tmp, &link) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        rewritten.push(link);
+    }
+    Ok(rewritten)
+}
+
+fn symlinks<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>> {
+    let mut rd = ReadDir::try_new(root)?;
+    rd.is_lazy = true;
+    let mut links = Vec::new();
+    for entry in rd {
+        if entry.metadata()?.kind == FileKind::Symlink {
+            links.push(entry.into_path());
+        }
+    }
+    links.sort();
+    Ok(links)
+}
+
+/// Creates a symbolic link at `link` pointing to `target`.
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Creates a symbolic link at `link` pointing to `target`; on Windows a directory link
+/// if the target (resolved against the link's directory) is a directory.
+#[cfg(windows)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    let resolved = link
+        .parent()
+        .map_or(target.to_path_buf(), |p| p.join(target));
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::links::{find_broken_symlinks, remove_broken_symlinks, retarget_symlinks};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn broken_symlinks_found_and_retargeted() {
+        let tree = TreeBuilder::new()
+            .file("new/a.txt", b"a")
+            .symlink("ok", "new/a.txt")
+            .symlink("moved", "old/a.txt")
+            .symlink("gone", "missing.txt")
+            .build()
+            .unwrap();
+
+        let broken = find_broken_symlinks(tree.path()).unwrap();
+        let links: Vec<_> = broken.iter().map(|b| b.link.clone()).collect();
+        assert_eq!(links, [tree.join("gone"), tree.join("moved")]);
+        assert_eq!(broken[1].target, Path::new("old/a.txt"));
+
+        let rewritten = retarget_symlinks(tree.path(), "old", "new").unwrap();
+        assert_eq!(rewritten, [tree.join("moved")]);
+        assert_eq!(fs::read(tree.join("moved")).unwrap(), b"a");
+
+        let removed = remove_broken_symlinks(tree.path()).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(fs::symlink_metadata(tree.join("gone")).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Reading and writing mlocate databases (see `mlocate.db(5)`), so trees scanned by
+//! fs-helper can be searched with `locate` and existing databases can be imported.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::paths::simplify_verbatim;
+use crate::result::{Error, ErrorKind, Result};
+
+const MAGIC: &[u8; 8] = b"\0mlocate";
+const VERSION: u8 = 0;
+const FILE: u8 = 0;
+const DIR: u8 = 1;
+const END: u8 = 2;
+
+/// Contents of an mlocate database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocateDb {
+    /// Root directory the database was built from.
+    pub root: PathBuf,
+    /// The root and every entry below it, in database order.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Writes an mlocate database of the tree under `root`.
+/// Directories that can not be read are written as empty. Symbolic links are not followed.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `out` - destination of the database, e.g. a file next to `/var/lib/mlocate/mlocate.db`.
+pub fn export_mlocate<P: AsRef<Path>, W: Write>(root: P, mut out: W) -> Result<()> {
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    out.write_all(MAGIC)?;
+    // configuration block size (empty), format version, require visibility, padding
+    out.write_all(&0u32.to_be_bytes())?;
+    out.write_all(&[VERSION, 0, 0, 0])?;
+    write_name(&mut out, root.as_os_str())?;
+    let mut stack = vec![root];
+    while let Some(dir) = stack.pop() {
+        let modified = fs::symlink_metadata(&dir)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        out.write_all(&modified.as_secs().to_be_bytes())?;
+        out.write_all(&modified.subsec_nanos().to_be_bytes())?;
+        out.write_all(&[0; 4])?;
+        write_name(&mut out, dir.as_os_str())?;
+        let mut children = Vec::new();
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+                children.push((entry.file_name(), is_dir));
+            }
+        }
+        children.sort();
+        for (name, is_dir) in &children {
+            out.write_all(&[if *is_dir { DIR } else { FILE }])?;
+            write_name(&mut out, name)?;
+        }
+        out.write_all(&[END])?;
+        let sub_dirs = children.into_iter().filter(|(_, is_dir)| *is_dir);
+        let sub_dirs: Vec<PathBuf> = sub_dirs.map(|(name, _)| dir.join(name)).collect();
+        stack.extend(sub_dirs.into_iter().rev());
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Reads an mlocate database.
+///
+/// # Arguments:
+///
+/// * `input` - the database.
+pub fn import_mlocate<R: Read>(input: R) -> Result<LocateDb> {
+    let mut input = BufReader::new(input);
+    let mut header = [0u8; 16];
+    input.read_exact(&mut header)?;
+    if &header[..8] != MAGIC || header[12] != VERSION {
+        return Err(invalid());
+    }
+    let conf_size = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+    let root = PathBuf::from(OsStr::from_bytes(&read_name(&mut input)?));
+    skip(&mut input, conf_size as u64)?;
+    let mut db = LocateDb {
+        paths: vec![root.clone()],
+        root,
+    };
+    let mut dir_header = [0u8; 16];
+    loop {
+        match input.read_exact(&mut dir_header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let dir = PathBuf::from(OsStr::from_bytes(&read_name(&mut input)?));
+        loop {
+            let mut kind = [0u8];
+            input.read_exact(&mut kind)?;
+            match kind[0] {
+                FILE | DIR => {
+                    let name = read_name(&mut input)?;
+                    db.paths.push(dir.join(OsStr::from_bytes(&name)));
+                }
+                END => break,
+                _ => return Err(invalid()),
+            }
+        }
+    }
+    Ok(db)
+}
+
+fn write_name<W: Write>(out: &mut W, name: &OsStr) -> Result<()> {
+    out.write_all(name.as_bytes())?;
+    out.write_all(&[0])?;
+    Ok(())
+}
+
+fn read_name<R: BufRead>(input: &mut R) -> Result<Vec<u8>> {
+    let mut name = Vec::new();
+    input.read_until(0, &mut name)?;
+    if name.pop() != Some(0) {
+        return Err(invalid());
+    }
+    Ok(name)
+}
+
+fn skip<R: Read>(input: &mut R, len: u64) -> Result<()> {
+    let skipped = io::copy(&mut input.take(len), &mut io::sink())?;
+    if skipped != len {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+fn invalid() -> Error {
+    Error::new(ErrorKind::Encoding, "invalid mlocate database")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::mlocate::{export_mlocate, import_mlocate};
+
+    #[test]
+    fn mlocate_round_trip() {
+        let tree = TreeBuilder::new()
+            .file("a/1.txt", b"")
+            .file("a/b/2.txt", b"")
+            .file("3.txt", b"")
+            .build()
+            .unwrap();
+        let mut db = Vec::new();
+        export_mlocate(tree.path(), &mut db).unwrap();
+        assert_eq!(&db[..8], b"\0mlocate");
+
+        let imported = import_mlocate(&db[..]).unwrap();
+        let root = imported.root.clone();
+        let mut paths = imported.paths;
+        paths.sort();
+        let expected = ["", "3.txt", "a", "a/1.txt", "a/b", "a/b/2.txt"];
+        let expected: Vec<_> = expected.iter().map(|p| root.join(p)).collect();
+        assert_eq!(paths, expected);
+    }
+}
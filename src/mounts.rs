@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+
+/// A mounted filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    /// What is mounted: a device, a remote share or a pseudo filesystem name.
+    pub source: String,
+    /// Where it is mounted.
+    pub mount_point: PathBuf,
+    /// Type of the filesystem, e.g. `ext4`, `apfs` or `NTFS`.
+    pub fs_type: String,
+    /// Whether the filesystem is mounted read-only.
+    pub read_only: bool,
+}
+
+/// Returns the mounted filesystems: `/proc/self/mounts` on Linux, `getmntinfo` on macOS and
+/// the logical drives on Windows. Fails with an error of kind `io::ErrorKind::Unsupported`
+/// on other platforms.
+pub fn list_mounts() -> Result<Vec<MountInfo>> {
+    Ok(sys::list()?)
+}
+
+/// Returns the mount holding `path`, i.e. the one with the longest mount point containing
+/// its canonical form. Unlike comparing device numbers, this also tells filesystems apart
+/// that report the same device (e.g. bind mounts) and names their type.
+pub fn mount_for<P: AsRef<Path>>(path: P) -> Result<Option<MountInfo>> {
+    let path = simplify_verbatim(fs::canonicalize(path)?);
+    let mounts = list_mounts()?;
+    // later mounts hide earlier ones at the same point; max_by_key returns the last maximum
+    Ok(mounts
+        .into_iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count()))
+}
+
+/// Parses the `mounts` format of Linux: one mount per line, as
+/// `source mount_point fs_type options dump pass`, with octal escapes for spaces.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mounts(text: &str) -> Vec<MountInfo> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let source = unescape(fields.next()?);
+            let mount_point = PathBuf::from(unescape(fields.next()?));
+            let fs_type = unescape(fields.next()?);
+            let read_only = fields.next()?.split(',').any(|option| option == "ro");
+            Some(MountInfo {
+                source,
+                mount_point,
+                fs_type,
+                read_only,
+            })
+        })
+        .collect()
+}
+
+/// Decodes the `\ooo` escapes of the mounts format.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs;
+    use std::io;
+
+    use crate::mounts::{parse_mounts, MountInfo};
+
+    pub(super) fn list() -> io::Result<Vec<MountInfo>> {
+        Ok(parse_mounts(&fs::read_to_string("/proc/self/mounts")?))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::{c_char, c_int, CStr};
+    use std::io;
+    use std::path::PathBuf;
+    use std::ptr;
+    use std::slice;
+
+    use crate::mounts::MountInfo;
+
+    const MNT_NOWAIT: c_int = 2;
+    const MNT_RDONLY: u32 = 1;
+
+    /// `struct statfs` with 64-bit inodes.
+    #[repr(C)]
+    struct StatFs {
+        f_bsize: u32,
+        f_iosize: i32,
+        f_blocks: u64,
+        f_bfree: u64,
+        f_bavail: u64,
+        f_files: u64,
+        f_ffree: u64,
+        f_fsid: [i32; 2],
+        f_owner: u32,
+        f_type: u32,
+        f_flags: u32,
+        f_fssubtype: u32,
+        f_fstypename: [c_char; 16],
+        f_mntonname: [c_char; 1024],
+        f_mntfromname: [c_char; 1024],
+        f_flags_ext: u32,
+        f_reserved: [u32; 7],
+    }
+
+    extern "C" {
+        #[cfg_attr(target_arch = "x86_64", link_name = "getmntinfo$INODE64")]
+        fn getmntinfo(buf: *mut *mut StatFs, flags: c_int) -> c_int;
+    }
+
+    fn string(chars: &[c_char]) -> String {
+        // SAFETY: the kernel NUL-terminates the names within their arrays.
+        unsafe { CStr::from_ptr(chars.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub(super) fn list() -> io::Result<Vec<MountInfo>> {
+        let mut buf = ptr::null_mut();
+        // SAFETY: `buf` receives a pointer to a static array owned by libc.
+        let n = unsafe { getmntinfo(&mut buf, MNT_NOWAIT) };
+        if n <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: getmntinfo returned `n` entries at `buf`.
+        let entries = unsafe { slice::from_raw_parts(buf, n as usize) };
+        Ok(entries
+            .iter()
+            .map(|entry| MountInfo {
+                source: string(&entry.f_mntfromname),
+                mount_point: PathBuf::from(string(&entry.f_mntonname)),
+                fs_type: string(&entry.f_fstypename),
+                read_only: entry.f_flags & MNT_RDONLY != 0,
+            })
+            .collect())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+    use std::path::PathBuf;
+    use std::ptr;
+
+    use crate::mounts::MountInfo;
+
+    const FILE_READ_ONLY_VOLUME: u32 = 0x0008_0000;
+
+    extern "system" {
+        fn GetLogicalDrives() -> u32;
+        fn GetVolumeInformationW(
+            root: *const u16,
+            name: *mut u16,
+            name_size: u32,
+            serial: *mut u32,
+            max_component: *mut u32,
+            flags: *mut u32,
+            fs_name: *mut u16,
+            fs_name_size: u32,
+        ) -> i32;
+    }
+
+    pub(super) fn list() -> io::Result<Vec<MountInfo>> {
+        // SAFETY: no arguments.
+        let drives = unsafe { GetLogicalDrives() };
+        if drives == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut mounts = Vec::new();
+        for letter in (0..26u8).filter(|i| drives & (1 << i) != 0) {
+            let root = format!("{}:\\", (b'A' + letter) as char);
+            let wide: Vec<u16> = root.encode_utf16().chain(Some(0)).collect();
+            let mut flags = 0;
+            let mut fs_name = [0u16; 64];
+            // SAFETY: `wide` is NUL-terminated and the buffers are as large as declared.
+            let ok = unsafe {
+                GetVolumeInformationW(
+                    wide.as_ptr(),
+                    ptr::null_mut(),
+                    0,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    &mut flags,
+                    fs_name.as_mut_ptr(),
+                    fs_name.len() as u32,
+                )
+            };
+            // drives without media (e.g. an empty card reader) have no volume information
+            let fs_type = match ok {
+                0 => String::new(),
+                _ => {
+                    let len = fs_name.iter().position(|&c| c == 0).unwrap_or(0);
+                    String::from_utf16_lossy(&fs_name[..len])
+                }
+            };
+            mounts.push(MountInfo {
+                source: root.clone(),
+                mount_point: PathBuf::from(root),
+                fs_type,
+                read_only: flags & FILE_READ_ONLY_VOLUME != 0,
+            });
+        }
+        Ok(mounts)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod sys {
+    use std::io;
+
+    use crate::mounts::MountInfo;
+
+    pub(super) fn list() -> io::Result<Vec<MountInfo>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "listing mounts is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mounts::{list_mounts, mount_for, parse_mounts};
+    use std::path::Path;
+
+    #[test]
+    fn parse_proc_mounts() {
+        let text = "/dev/sda1 / ext4 rw,relatime 0 0\n\
+                    server:/share /mnt/my\\040share nfs4 ro,vers=4.2 0 0\n";
+        let mounts = parse_mounts(text);
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].fs_type, "ext4");
+        assert!(!mounts[0].read_only);
+        assert_eq!(mounts[1].mount_point, Path::new("/mnt/my share"));
+        assert!(mounts[1].read_only);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn mounts_of_this_system() {
+        let mounts = list_mounts().unwrap();
+        assert!(mounts
+            .iter()
+            .any(|mount| mount.mount_point == Path::new("/")));
+        let mount = mount_for("/tmp").unwrap().unwrap();
+        assert!(Path::new("/tmp").starts_with(&mount.mount_point));
+    }
+}
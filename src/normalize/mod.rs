@@ -0,0 +1,196 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+mod tables;
+
+/// Unicode normalization form.
+///
+/// The built-in tables cover Latin, Greek and Cyrillic letters and Hangul syllables,
+/// which is what macOS decomposes in practice; other scripts are left untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Canonical composition (used by Linux and Windows in practice).
+    Nfc,
+    /// Canonical decomposition (returned by HFS+ and often by APFS on macOS).
+    Nfd,
+}
+
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+struct Tables {
+    decompose: HashMap<u32, (u32, u32)>,
+    compose: HashMap<(u32, u32), u32>,
+    classes: HashMap<u32, u8>,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| Tables {
+        decompose: tables::DECOMPOSITIONS
+            .iter()
+            .map(|&(c, a, b, _)| (c, (a, b)))
+            .collect(),
+        compose: tables::DECOMPOSITIONS
+            .iter()
+            .filter(|&&(_, _, _, composable)| composable)
+            .map(|&(c, a, b, _)| ((a, b), c))
+            .collect(),
+        classes: tables::COMBINING_CLASSES.iter().copied().collect(),
+    })
+}
+
+fn combining_class(c: u32) -> u8 {
+    tables().classes.get(&c).copied().unwrap_or(0)
+}
+
+fn decompose_char(c: u32, out: &mut Vec<u32>) {
+    if (S_BASE..S_BASE + S_COUNT).contains(&c) {
+        let s = c - S_BASE;
+        out.push(L_BASE + s / N_COUNT);
+        out.push(V_BASE + (s % N_COUNT) / T_COUNT);
+        if !s.is_multiple_of(T_COUNT) {
+            out.push(T_BASE + s % T_COUNT);
+        }
+    } else if let Some(&(a, b)) = tables().decompose.get(&c) {
+        decompose_char(a, out);
+        if b != 0 {
+            decompose_char(b, out);
+        }
+    } else {
+        out.push(c);
+    }
+}
+
+fn compose_pair(a: u32, b: u32) -> Option<u32> {
+    if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+        return Some(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT);
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&a)
+        && (a - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&b)
+    {
+        return Some(a + (b - T_BASE));
+    }
+    tables().compose.get(&(a, b)).copied()
+}
+
+fn decompose(s: &str) -> Vec<u32> {
+    let mut out = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        decompose_char(c as u32, &mut out);
+    }
+    // canonical ordering of combining marks
+    let mut i = 0;
+    while i < out.len() {
+        if combining_class(out[i]) == 0 {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < out.len() && combining_class(out[i]) != 0 {
+            i += 1;
+        }
+        out[start..i].sort_by_key(|&c| combining_class(c));
+    }
+    out
+}
+
+fn compose(chars: Vec<u32>) -> Vec<u32> {
+    let mut out: Vec<u32> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class: i16 = -1;
+    for c in chars {
+        let class = combining_class(c) as i16;
+        if let Some(pos) = starter {
+            let blocked = last_class != -1 && (last_class == 0 || last_class >= class);
+            if !blocked {
+                if let Some(composed) = compose_pair(out[pos], c) {
+                    out[pos] = composed;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+            last_class = -1;
+        } else {
+            last_class = class;
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Normalizes a string to the given form.
+pub fn normalize_str(s: &str, form: Normalization) -> String {
+    let chars = decompose(s);
+    let chars = match form {
+        Normalization::Nfd => chars,
+        Normalization::Nfc => compose(chars),
+    };
+    chars.into_iter().filter_map(char::from_u32).collect()
+}
+
+/// Normalizes every component of a path to the given form.
+/// Components that are not valid UTF-8 are kept as is.
+pub fn normalize_path<P: AsRef<Path>>(path: P, form: Normalization) -> PathBuf {
+    let path = path.as_ref();
+    match path.to_str() {
+        Some(s) => PathBuf::from(normalize_str(s, form)),
+        None => path
+            .components()
+            .map(|c| normalize_os_str(c.as_os_str(), form))
+            .collect(),
+    }
+}
+
+fn normalize_os_str(s: &OsStr, form: Normalization) -> Cow<'_, OsStr> {
+    match s.to_str() {
+        Some(s) => Cow::Owned(normalize_str(s, form).into()),
+        None => Cow::Borrowed(s),
+    }
+}
+
+/// Compares two paths after canonical normalization,
+/// so NFC and NFD spellings of the same name are considered equal.
+pub fn paths_eq_normalized<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> bool {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    a == b || normalize_path(a, Normalization::Nfd) == normalize_path(b, Normalization::Nfd)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::normalize::{normalize_str, paths_eq_normalized, Normalization};
+
+    #[test]
+    fn normalize_round_trip() {
+        let nfc = "Ёлка café Ångström Việt 한글";
+        let nfd = normalize_str(nfc, Normalization::Nfd);
+        assert_ne!(nfc, nfd);
+        assert_eq!(nfd.chars().count(), nfc.chars().count() + 10);
+        assert_eq!(normalize_str(&nfd, Normalization::Nfc), nfc);
+    }
+
+    #[test]
+    fn compare_normalized_paths() {
+        assert!(paths_eq_normalized(
+            "/tmp/caf\u{e9}.txt",
+            "/tmp/cafe\u{301}.txt"
+        ));
+        assert!(!paths_eq_normalized(
+            "/tmp/cafe.txt",
+            "/tmp/cafe\u{301}.txt"
+        ));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+use crate::result::Result;
+
+/// Opens a file for reading without preventing other processes from reading, writing,
+/// renaming or deleting it while it is open.
+///
+/// On Windows the file is opened with `FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE`
+/// (std only shares read and write by default, which blocks renames and deletes).
+/// On Unix open files never block other processes, so this is `File::open`.
+/// Scanning and hashing in this crate open files this way.
+pub fn open_read_shared<P: AsRef<Path>>(path: P) -> Result<fs::File> {
+    Ok(open_options().open(path)?)
+}
+
+#[cfg(windows)]
+fn open_options() -> fs::OpenOptions {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+
+    let mut options = fs::OpenOptions::new();
+    options
+        .read(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE);
+    options
+}
+
+#[cfg(not(windows))]
+fn open_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.read(true);
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::open::open_read_shared;
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn open_shared_allows_rename() {
+        let tree = TreeBuilder::new().file("a.txt", b"data").build().unwrap();
+
+        let mut file = open_read_shared(tree.join("a.txt")).unwrap();
+        fs::rename(tree.join("a.txt"), tree.join("b.txt")).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "data");
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use std::vec;
+
+use crate::entry::Entry;
+
+/// Sequence number of the root directory; the others are numbered as they are found.
+pub(crate) const ROOT_SEQ: u64 = 0;
+
+/// How many listings may wait to be yielded, sent or in the buffer of a [`Reorder`].
+pub(crate) const REORDER_WINDOW: usize = 64;
+
+/// The entries of a directory read by a multithreaded traversal with `preserve_order`, and
+/// the sequence numbers given to its subdirectories, in listing order.
+pub(crate) struct DirListing {
+    pub(crate) seq: u64,
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) children: Vec<u64>,
+}
+
+/// Puts the directory listings sent by the workers back into the order of the
+/// single-threaded traversal: the entries of a directory, then its subdirectories, depth
+/// first. Listings finished ahead of the one being yielded wait in a buffer, bounded by
+/// the [`Window`] it shares with the workers.
+pub(crate) struct Reorder {
+    rx: mpsc::Receiver<DirListing>,
+    window: Arc<Window>,
+    pending: HashMap<u64, DirListing>,
+    /// Directories left to yield, the next one last.
+    stack: Vec<u64>,
+    current: vec::IntoIter<Entry>,
+}
+
+impl Reorder {
+    pub(crate) fn new(rx: mpsc::Receiver<DirListing>, window: Arc<Window>) -> Reorder {
+        Reorder {
+            rx,
+            window,
+            pending: HashMap::new(),
+            stack: vec![ROOT_SEQ],
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    /// Returns the next entry, waiting at most `timeout` for the listing it is in.
+    /// Ends when all directories are yielded or the workers are gone.
+    pub(crate) fn next(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Entry>, mpsc::RecvTimeoutError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(entry) = self.current.next() {
+                return Ok(Some(entry));
+            }
+            let seq = match self.stack.last() {
+                Some(seq) => *seq,
+                None => return Ok(None),
+            };
+            if let Some(listing) = self.pending.remove(&seq) {
+                self.window.release();
+                self.stack.pop();
+                self.stack.extend(listing.children.iter().rev());
+                self.current = listing.entries.into_iter();
+                continue;
+            }
+            self.window.want(seq);
+            let listing = match deadline {
+                None => self.rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                Some(deadline) => {
+                    self.rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+            };
+            match listing {
+                Ok(listing) => {
+                    self.pending.insert(listing.seq, listing);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Reorder {
+    /// Releases the workers waiting for room in the window.
+    fn drop(&mut self) {
+        self.window.close();
+    }
+}
+
+/// Bounds the listings sent to a [`Reorder`] and not yielded yet. Workers wait for room
+/// before sending, except with the listing the consumer waits for, which is always let in.
+pub(crate) struct Window {
+    state: Mutex<WindowState>,
+    cond: Condvar,
+    capacity: usize,
+}
+
+struct WindowState {
+    /// Listings sent and not yielded yet.
+    held: usize,
+    /// Sequence number of the listing the consumer waits for.
+    wanted: u64,
+    closed: bool,
+}
+
+impl Window {
+    pub(crate) fn new(capacity: usize) -> Window {
+        Window {
+            state: Mutex::new(WindowState {
+                held: 0,
+                wanted: ROOT_SEQ,
+                closed: false,
+            }),
+            cond: Condvar::new(),
+            capacity,
+        }
+    }
+
+    /// Waits until the listing `seq` may be sent. While waiting, `help` is called with the
+    /// sequence number the consumer waits for, to read that directory if no worker has
+    /// started on it yet; it returns whether it did, so that the workers waiting here do
+    /// not leave it unread.
+    pub(crate) fn enter<F: FnMut(u64) -> bool>(&self, seq: u64, mut help: F) {
+        // the listing last found to be read by another worker already
+        let mut started = None;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed || state.held < self.capacity || state.wanted == seq {
+                state.held += 1;
+                return;
+            }
+            if started == Some(state.wanted) {
+                state = self.cond.wait(state).unwrap();
+                continue;
+            }
+            let wanted = state.wanted;
+            drop(state);
+            if !help(wanted) {
+                started = Some(wanted);
+            }
+            state = self.state.lock().unwrap();
+        }
+    }
+
+    /// Sets the listing the consumer waits for.
+    fn want(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.wanted != seq {
+            state.wanted = seq;
+            self.cond.notify_all();
+        }
+    }
+
+    /// Makes room for a listing, once one is yielded.
+    fn release(&self) {
+        self.state.lock().unwrap().held -= 1;
+        self.cond.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cond.notify_all();
+    }
+}
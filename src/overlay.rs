@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::result::Result;
+use crate::vfs::{FileKind, FsDirEntry, FsMetadata, FsReadDir, ReadFs};
+use crate::ReadDir;
+
+/// Prefix of the whiteout entries that hide entries of lower layers.
+const WHITEOUT: &str = ".wh.";
+/// Marks a directory as opaque: it hides the contents of the directory in lower layers.
+const OPAQUE: &str = ".wh..wh..opq";
+
+/// A merged, read-only view of several directory trees, like a union mount: an entry of
+/// an upper layer hides the entry with the same relative path in the lower ones, and
+/// directories present in several layers show the entries of all of them. Useful to see
+/// what a tree would look like after applying a patch directory, without copying it.
+///
+/// Lower entries can be removed from the view with whiteouts, as in OCI image layers:
+/// an upper `.wh.name` entry hides `name`, and an upper `.wh..wh..opq` entry hides all
+/// lower contents of its directory. Whiteouts themselves are not shown.
+///
+/// The view is a [`ReadFs`] whose paths are absolute, `/` standing for the root of the
+/// layers, so it can be traversed with `ReadDir::try_new_in` or [`Overlay::walk`].
+/// Symbolic links are resolved within the layer holding them.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    layers: Vec<PathBuf>,
+}
+
+/// Splits a path of the view into its names, ignoring `.` and resolving `..` lexically.
+fn names_of(path: &Path) -> Vec<OsString> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name.to_os_string()),
+            Component::ParentDir => {
+                names.pop();
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+fn whiteout_of(name: &OsStr) -> OsString {
+    let mut whiteout = OsString::from(WHITEOUT);
+    whiteout.push(name);
+    whiteout
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{}: not found", path.display()),
+    )
+}
+
+impl Overlay {
+    /// Creates a view of layers; the first is the upper one.
+    ///
+    /// # Arguments:
+    ///
+    /// * `layers` - root directories of the layers, from the upper to the lowest.
+    pub fn new<I, P>(layers: I) -> Overlay
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        Overlay {
+            layers: layers
+                .into_iter()
+                .map(|layer| layer.as_ref().to_path_buf())
+                .collect(),
+        }
+    }
+
+    /// Returns the real path behind a path of the view, i.e. the path in the upper layer
+    /// holding it, or `None` if it is not in the view.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let names = names_of(path.as_ref());
+        self.layer_of(&names).map(|layer| join(layer, &names))
+    }
+
+    /// Returns a traversal of the merged tree, yielding paths of the view.
+    pub fn walk(self) -> Result<ReadDir> {
+        ReadDir::try_new_in(Arc::new(self), "/")
+    }
+
+    /// Returns the upper layer holding the entry.
+    fn layer_of(&self, names: &[OsString]) -> Option<&Path> {
+        for layer in &self.layers {
+            if fs::symlink_metadata(join(layer, names)).is_ok() {
+                return Some(layer);
+            }
+            if masks(layer, names) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn real_path(&self, path: &Path) -> io::Result<PathBuf> {
+        self.resolve(path).ok_or_else(|| not_found(path))
+    }
+}
+
+fn join(layer: &Path, names: &[OsString]) -> PathBuf {
+    let mut path = layer.to_path_buf();
+    path.extend(names);
+    path
+}
+
+/// Checks whether a layer hides the entry in the layers below it: with a whiteout of the
+/// entry or of one of its parents, an opaque parent, or a parent that is not a directory.
+fn masks(layer: &Path, names: &[OsString]) -> bool {
+    let mut dir = layer.to_path_buf();
+    for (i, name) in names.iter().enumerate() {
+        if fs::symlink_metadata(dir.join(whiteout_of(name))).is_ok()
+            || (i > 0 && fs::symlink_metadata(dir.join(OPAQUE)).is_ok())
+        {
+            return true;
+        }
+        dir.push(name);
+        if i + 1 < names.len() {
+            if let Ok(meta) = fs::symlink_metadata(&dir) {
+                if !meta.is_dir() {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+impl ReadFs for Overlay {
+    fn read_dir(&self, path: &Path) -> io::Result<FsReadDir> {
+        let names = names_of(path);
+        let view = join(Path::new("/"), &names);
+        if self.metadata(path)?.kind != FileKind::Dir {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{}: not a directory", path.display()),
+            ));
+        }
+        let mut seen: HashSet<OsString> = HashSet::new();
+        let mut entries = Vec::new();
+        for layer in &self.layers {
+            let dir = join(layer, &names);
+            match fs::symlink_metadata(&dir) {
+                Ok(meta) if meta.is_dir() => {}
+                // a file hides directories below it
+                Ok(_) => break,
+                Err(_) if masks(layer, &names) => break,
+                Err(_) => continue,
+            }
+            let mut opaque = false;
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                if name == OPAQUE {
+                    opaque = true;
+                    continue;
+                }
+                let hidden = name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(WHITEOUT))
+                    .map(OsString::from);
+                if let Some(hidden) = hidden {
+                    seen.insert(hidden);
+                    continue;
+                }
+                if seen.insert(name.clone()) {
+                    entries.push(Ok(FsDirEntry {
+                        path: view.join(&name),
+                        kind: entry.file_type()?.into(),
+                        meta: None,
+                    }));
+                }
+            }
+            if opaque {
+                break;
+            }
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        fs::metadata(self.real_path(path)?).map(FsMetadata::from)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        fs::symlink_metadata(self.real_path(path)?).map(FsMetadata::from)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let names = names_of(path);
+        self.layer_of(&names).ok_or_else(|| not_found(path))?;
+        Ok(join(Path::new("/"), &names))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(fs::File::open(self.real_path(path)?)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::overlay::Overlay;
+    use crate::vfs::ReadFs;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn overlay_view() {
+        let lower = TreeBuilder::new()
+            .file("a.txt", b"lower")
+            .file("b.txt", b"")
+            .file("dir/c.txt", b"")
+            .file("gone/d.txt", b"")
+            .file("opaque/e.txt", b"")
+            .build()
+            .unwrap();
+        let upper = TreeBuilder::new()
+            .file("a.txt", b"upper")
+            .file(".wh.b.txt", b"")
+            .file("dir/f.txt", b"")
+            .file(".wh.gone", b"")
+            .file("opaque/.wh..wh..opq", b"")
+            .file("opaque/g.txt", b"")
+            .build()
+            .unwrap();
+        let overlay = Overlay::new([upper.path(), lower.path()]);
+        let mut contents = String::new();
+        overlay
+            .open(Path::new("/a.txt"))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "upper");
+        assert_eq!(overlay.resolve("/dir/c.txt"), Some(lower.join("dir/c.txt")));
+        assert_eq!(overlay.resolve("/gone/d.txt"), None);
+        assert_eq!(overlay.resolve("/b.txt"), None);
+
+        let mut paths: Vec<_> = overlay
+            .walk()
+            .unwrap()
+            .map(|entry| entry.into_path())
+            .collect();
+        paths.sort();
+        let expected: Vec<PathBuf> = ["/a.txt", "/dir/c.txt", "/dir/f.txt", "/opaque/g.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(paths, expected);
+    }
+}
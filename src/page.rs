@@ -0,0 +1,102 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::entry::Entry;
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+
+/// Position in the listing of a directory, returned by [`list_page`] to get the next page.
+///
+/// The first page takes a snapshot of the names of the directory, sorted, and the cursor
+/// shares it, so pages neither overlap nor miss entries when the directory changes
+/// between calls, and do not depend on `telldir` offsets staying valid (they do not on
+/// many network filesystems). Only names are kept, not entries or their metadata.
+#[derive(Debug, Clone)]
+pub struct PageCursor {
+    dir: PathBuf,
+    names: Arc<[OsString]>,
+    next: usize,
+}
+
+impl PageCursor {
+    /// Returns the number of entries of the snapshot not listed yet.
+    pub fn remaining(&self) -> usize {
+        self.names.len() - self.next
+    }
+}
+
+/// Lists one page of the entries of `dir` (directories included, `.` and `..` excluded),
+/// sorted by name, with the cursor of the next page, if any. Entries removed since the
+/// first page are left out; entries created since then are not listed.
+///
+/// # Arguments:
+///
+/// * `dir` - directory to list; later pages list the directory of the cursor.
+/// * `cursor` - cursor returned with the previous page, `None` for the first page.
+/// * `page_size` - maximum number of entries of the page.
+pub fn list_page<P: AsRef<Path>>(
+    dir: P,
+    cursor: Option<&PageCursor>,
+    page_size: usize,
+) -> Result<(Vec<Entry>, Option<PageCursor>)> {
+    let cursor = match cursor {
+        Some(cursor) => cursor.clone(),
+        None => {
+            let dir = simplify_verbatim(fs::canonicalize(dir)?);
+            let mut names = Vec::new();
+            for entry in fs::read_dir(&dir)? {
+                names.push(entry?.file_name());
+            }
+            names.sort();
+            PageCursor {
+                dir,
+                names: names.into(),
+                next: 0,
+            }
+        }
+    };
+    let mut entries = Vec::with_capacity(page_size.min(cursor.remaining()));
+    let mut next = cursor.next;
+    while entries.len() < page_size && next < cursor.names.len() {
+        let path = cursor.dir.join(&cursor.names[next]);
+        next += 1;
+        if fs::symlink_metadata(&path).is_ok() {
+            entries.push(Entry::new(path, 1));
+        }
+    }
+    let cursor = (next < cursor.names.len()).then_some(PageCursor { next, ..cursor });
+    Ok((entries, cursor))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::page::list_page;
+    use std::fs;
+
+    #[test]
+    fn pages_of_a_directory() {
+        let mut builder = TreeBuilder::new().dir("big/sub");
+        for i in 0..10 {
+            builder = builder.file(format!("big/{}.txt", i), b"");
+        }
+        let tree = builder.build().unwrap();
+        let (page, cursor) = list_page(tree.join("big"), None, 4).unwrap();
+        let names: Vec<_> = page.iter().map(|e| e.path().file_name().unwrap()).collect();
+        assert_eq!(names, ["0.txt", "1.txt", "2.txt", "3.txt"]);
+        let cursor = cursor.unwrap();
+        assert_eq!(cursor.remaining(), 7);
+
+        fs::remove_file(tree.join("big/4.txt")).unwrap();
+        fs::write(tree.join("big/00.txt"), b"").unwrap();
+        let (page, cursor) = list_page(tree.join("big"), Some(&cursor), 4).unwrap();
+        assert_eq!(page[0].path(), tree.join("big/5.txt"));
+        assert_eq!(page.len(), 4);
+        let (page, cursor) = list_page(tree.join("big"), cursor.as_ref(), 4).unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[1].path(), tree.join("big/sub"));
+        assert!(cursor.is_none());
+    }
+}
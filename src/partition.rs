@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::count::count_entries;
+use crate::result::Result;
+use crate::vfs::FileKind;
+use crate::ReadDir;
+
+/// Part of a root directory visited by one ReadDir of a [`partition`].
+pub(crate) struct Partition {
+    /// Top-level subdirectories of the partition.
+    subtrees: HashSet<PathBuf>,
+    /// Whether the entries located directly in the root belong to the partition.
+    root_entries: bool,
+}
+
+impl Partition {
+    /// Checks whether an entry located directly in the root belongs to the partition.
+    pub(crate) fn contains(&self, kind: FileKind, path: &Path) -> bool {
+        if kind == FileKind::Dir {
+            self.subtrees.contains(path)
+        } else {
+            self.root_entries
+        }
+    }
+}
+
+/// Splits a tree into `n` iterators which together yield every entry of the tree once,
+/// so the scan can be distributed across threads, processes or machines.
+/// Top-level subdirectories are assigned whole, largest first to the least loaded
+/// partition, weighted by their number of entries; the entries located directly in the
+/// root go to the first partition. Partitions may be empty if the root has few subdirectories.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `n` - number of partitions.
+pub fn partition<P: AsRef<Path>>(root: P, n: usize) -> Result<Vec<ReadDir>> {
+    let root = ReadDir::try_new(root)?.root().to_path_buf();
+    let n = n.max(1);
+    let mut loads = vec![0u64; n];
+    let mut subtrees: Vec<(u64, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(&root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            let counts = count_entries(entry.path(), |_, _| true)?;
+            subtrees.push((
+                counts.files + counts.dirs + counts.symlinks + 1,
+                entry.path(),
+            ));
+        } else {
+            loads[0] += 1;
+        }
+    }
+    subtrees.sort_by(|a, b| b.cmp(a));
+    let mut parts: Vec<HashSet<PathBuf>> = vec![HashSet::new(); n];
+    for (weight, path) in subtrees {
+        let lightest = (0..n).min_by_key(|&i| loads[i]).unwrap_or(0);
+        loads[lightest] += weight;
+        parts[lightest].insert(path);
+    }
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, subtrees)| {
+            let mut rd = ReadDir::try_new(&root)?;
+            rd.partition = Some(Arc::new(Partition {
+                subtrees,
+                root_entries: i == 0,
+            }));
+            Ok(rd)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::partition::partition;
+    use std::collections::HashSet;
+
+    #[test]
+    fn partitions_cover_tree() {
+        let mut builder = TreeBuilder::new().file("root.txt", b"");
+        for (dir, files) in [("a", 6), ("b", 3), ("c", 2), ("d", 1)] {
+            for i in 0..files {
+                builder = builder.file(format!("{}/sub/{}.txt", dir, i), b"");
+            }
+        }
+        let tree = builder.build().unwrap();
+
+        let parts = partition(tree.path(), 2).unwrap();
+        assert_eq!(parts.len(), 2);
+        let sizes: Vec<Vec<_>> = parts
+            .into_iter()
+            .map(|rd| rd.map(|entry| entry.into_path()).collect())
+            .collect();
+        assert_eq!((sizes[0].len(), sizes[1].len()), (6, 7));
+        let all: HashSet<_> = sizes.into_iter().flatten().collect();
+        assert_eq!(all.len(), 13);
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// Maximum path length (in UTF-16 units) accepted by Windows APIs without the verbatim prefix.
+const MAX_PATH: usize = 260;
+
+/// Returns the depth of `path` relative to `root`, or `None` if `path` is not within `root`.
+/// The root itself has depth 0.
+///
+/// # Arguments:
+///
+/// * `path` - path to measure.
+/// * `root` - root directory.
+pub fn depth_of<P: AsRef<Path>, R: AsRef<Path>>(path: P, root: R) -> Option<usize> {
+    let rel = path.as_ref().strip_prefix(root.as_ref()).ok()?;
+    let mut depth = 0usize;
+    for component in rel.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => depth = depth.checked_sub(1)?,
+            _ => depth += 1,
+        }
+    }
+    Some(depth)
+}
+
+/// Checks whether `path` is located within `root`.
+/// Paths are compared component by component, so `/tmp/foobar` is not within `/tmp/foo`.
+///
+/// # Arguments:
+///
+/// * `path` - path to check.
+/// * `root` - root directory.
+pub fn is_within<P: AsRef<Path>, R: AsRef<Path>>(path: P, root: R) -> bool {
+    depth_of(path, root).is_some()
+}
+
+/// Checks whether `path` is a Windows UNC path (`\\server\share\...` or `\\?\UNC\server\share\...`).
+/// Always false on other platforms.
+pub fn is_unc<P: AsRef<Path>>(path: P) -> bool {
+    match path.as_ref().components().next() {
+        Some(Component::Prefix(prefix)) => {
+            matches!(prefix.kind(), Prefix::UNC(..) | Prefix::VerbatimUNC(..))
+        }
+        _ => false,
+    }
+}
+
+/// Converts a Windows verbatim path (as returned by `fs::canonicalize`) into its ordinary form:
+/// `\\?\C:\dir` becomes `C:\dir` and `\\?\UNC\server\share\dir` becomes `\\server\share\dir`.
+///
+/// The path is returned unchanged if it has no verbatim prefix or if it could not be used
+/// without one: too long for `MAX_PATH`, or containing components with trailing dots or spaces.
+pub fn simplify_verbatim<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(drive) => format!("{}:", drive as char),
+            Prefix::VerbatimUNC(server, share) => match (server.to_str(), share.to_str()) {
+                (Some(server), Some(share)) => format!(r"\\{}\{}", server, share),
+                _ => return path.to_path_buf(),
+            },
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
+    };
+    let mut simplified = PathBuf::from(prefix);
+    for component in components {
+        if let Component::Normal(name) = component {
+            let name = name.to_string_lossy();
+            if name.ends_with('.') || name.ends_with(' ') {
+                return path.to_path_buf();
+            }
+        }
+        simplified.push(component);
+    }
+    if simplified.as_os_str().len() >= MAX_PATH {
+        return path.to_path_buf();
+    }
+    simplified
+}
+
+/// Adds the verbatim prefix to an absolute Windows path too long for `MAX_PATH`, so that
+/// it can still be opened: `C:\dir` becomes `\\?\C:\dir` and `\\server\share\dir` becomes
+/// `\\?\UNC\server\share\dir`. The reverse of [`simplify_verbatim`].
+///
+/// The path is returned unchanged if it is short enough, already verbatim, relative, or
+/// contains `..` components, which verbatim paths do not resolve. Always unchanged on other
+/// platforms.
+pub fn long_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    if path.as_os_str().len() < MAX_PATH {
+        return path.to_path_buf();
+    }
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(drive) => format!(r"\\?\{}:", drive as char),
+            Prefix::UNC(server, share) => match (server.to_str(), share.to_str()) {
+                (Some(server), Some(share)) => format!(r"\\?\UNC\{}\{}", server, share),
+                _ => return path.to_path_buf(),
+            },
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
+    };
+    let mut long = PathBuf::from(prefix);
+    for component in components {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => return path.to_path_buf(),
+            _ => long.push(component),
+        }
+    }
+    long
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paths::{depth_of, is_unc, is_within, long_path, simplify_verbatim};
+    use std::path::Path;
+
+    #[test]
+    fn depth_of_path() {
+        assert_eq!(depth_of("/tmp/a", "/tmp/a"), Some(0));
+        assert_eq!(depth_of("/tmp/a/b/c.txt", "/tmp/a"), Some(2));
+        assert_eq!(depth_of("/tmp/a/b/../c.txt", "/tmp/a"), Some(1));
+        assert_eq!(depth_of("/tmp/a/../../c.txt", "/tmp/a"), None);
+        assert_eq!(depth_of("/var/a", "/tmp"), None);
+    }
+
+    #[test]
+    fn is_within_component_wise() {
+        assert!(is_within("/tmp/foo/bar", "/tmp/foo"));
+        assert!(!is_within("/tmp/foobar", "/tmp/foo"));
+        assert!(!is_within("/tmp/foo/../bar", "/tmp/foo"));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn verbatim_prefix_ignored_on_unix() {
+        assert!(!is_unc("//server/share/dir"));
+        assert_eq!(simplify_verbatim("/tmp/dir"), Path::new("/tmp/dir"));
+        let long = format!("/tmp/{}", "a".repeat(300));
+        assert_eq!(long_path(&long), Path::new(&long));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn verbatim_prefix_simplified() {
+        assert!(is_unc(r"\\server\share\dir"));
+        assert!(is_unc(r"\\?\UNC\server\share\dir"));
+        assert!(!is_unc(r"C:\dir"));
+        assert_eq!(
+            simplify_verbatim(r"\\?\C:\dir\file.txt"),
+            Path::new(r"C:\dir\file.txt")
+        );
+        assert_eq!(
+            simplify_verbatim(r"\\?\UNC\server\share\dir"),
+            Path::new(r"\\server\share\dir")
+        );
+        assert_eq!(simplify_verbatim(r"\\?\C:\dir."), Path::new(r"\\?\C:\dir."));
+        assert_eq!(depth_of(r"\\server\share\a\b", r"\\server\share"), Some(2));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_made_verbatim() {
+        let name = "a".repeat(300);
+        assert_eq!(long_path(r"C:\dir\file.txt"), Path::new(r"C:\dir\file.txt"));
+        assert_eq!(
+            long_path(format!(r"C:\dir\.\{}", name)),
+            Path::new(&format!(r"\\?\C:\dir\{}", name))
+        );
+        assert_eq!(
+            long_path(format!(r"\\server\share\{}", name)),
+            Path::new(&format!(r"\\?\UNC\server\share\{}", name))
+        );
+        let parent = format!(r"C:\dir\..\{}", name);
+        assert_eq!(long_path(&parent), Path::new(&parent));
+        let verbatim = format!(r"\\?\C:\{}", name);
+        assert_eq!(long_path(&verbatim), Path::new(&verbatim));
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::vec;
+
+use crate::hash::Hash;
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+use crate::stats::{IoStats, Phase, Recorder};
+
+/// Number of files a worker may hash ahead of the consumer.
+const AHEAD_PER_WORKER: u64 = 16;
+
+type Job = (u64, PathBuf);
+type Hashed = (u64, PathBuf, Result<Hash>);
+
+/// Number of results taken by the consumer, which the traversal waits on to stay at most
+/// a window ahead.
+struct Window {
+    taken: Mutex<u64>,
+    cond: Condvar,
+    cancel: AtomicBool,
+}
+
+/// Hashes of the regular files of a tree in path order, as returned by
+/// [`hash_tree_parallel`].
+///
+/// Yields each file or unreadable directory with its hash or error, sorted as [`Path`]s
+/// compare, e.g. for a manifest that is the same on every run. Dropping the iterator stops
+/// the traversal; files being hashed are finished in the background.
+pub struct HashTree {
+    root: PathBuf,
+    rx: mpsc::Receiver<Hashed>,
+    pending: BTreeMap<u64, (PathBuf, Result<Hash>)>,
+    next: u64,
+    window: Arc<Window>,
+    stats: Arc<Recorder>,
+    finished: Option<Duration>,
+}
+
+impl HashTree {
+    /// Returns the root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the files hashed and the bytes read so far, with the time spent listing
+    /// directories (`Phase::Scan`) and hashing (`Phase::Data`, summed over the workers).
+    /// The wall time stops when the last hash is yielded.
+    pub fn stats(&self) -> IoStats {
+        match self.finished {
+            Some(wall_time) => self.stats.stats_at(wall_time),
+            None => self.stats.stats(),
+        }
+    }
+}
+
+impl Iterator for HashTree {
+    type Item = (PathBuf, Result<Hash>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.pending.contains_key(&self.next) {
+            // every result up to the last one is sent before the channel closes
+            let (seq, path, hash) = match self.rx.recv() {
+                Ok(hashed) => hashed,
+                Err(_) => {
+                    self.finished.get_or_insert_with(|| self.stats.elapsed());
+                    return None;
+                }
+            };
+            self.pending.insert(seq, (path, hash));
+        }
+        let item = self.pending.remove(&self.next);
+        self.next += 1;
+        *self.window.taken.lock().unwrap() = self.next;
+        self.window.cond.notify_all();
+        item
+    }
+}
+
+impl Drop for HashTree {
+    fn drop(&mut self) {
+        self.window.cancel.store(true, Ordering::Relaxed);
+        self.window.cond.notify_all();
+    }
+}
+
+/// Hashes the regular files under `root` with a pool of worker threads while the tree is
+/// still being traversed, and yields the hashes in path order. Reading, hashing and
+/// listing overlap, so fast disks stay busy, yet the output does not depend on which
+/// worker finishes first. Symbolic links are neither followed nor hashed.
+///
+/// Workers run at most a few files ahead of the consumer, so memory stays bounded even
+/// when one large file holds up the results after it.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `hash` - hashes one file, e.g. with [`Hash::of_file`] or [`Hash::of_file_direct`].
+pub fn hash_tree_parallel<P, F>(root: P, hash: F) -> Result<HashTree>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> Result<Hash> + Send + Sync + 'static,
+{
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let workers = thread::available_parallelism().map_or(4, |n| n.get());
+    let window = Arc::new(Window {
+        taken: Mutex::new(0),
+        cond: Condvar::new(),
+        cancel: AtomicBool::new(false),
+    });
+    let (job_tx, job_rx) = mpsc::channel::<Job>();
+    let (tx, rx) = mpsc::channel::<Hashed>();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let hash = Arc::new(hash);
+    let stats = Arc::new(Recorder::start());
+    for _ in 0..workers {
+        let (job_rx, tx, hash) = (Arc::clone(&job_rx), tx.clone(), Arc::clone(&hash));
+        let stats = Arc::clone(&stats);
+        thread::spawn(move || loop {
+            let job = job_rx.lock().unwrap().recv();
+            let (seq, path) = match job {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            let result = stats.time(Phase::Data, || hash(&path));
+            if result.is_ok() {
+                stats.read(fs::metadata(&path).map_or(0, |meta| meta.len()));
+                stats.file();
+            }
+            if tx.send((seq, path, result)).is_err() {
+                return;
+            }
+        });
+    }
+    let ahead = workers as u64 * AHEAD_PER_WORKER;
+    let (start, shared, scan) = (root.clone(), Arc::clone(&window), Arc::clone(&stats));
+    thread::spawn(move || traverse(&start, ahead, &shared, &scan, &job_tx, &tx));
+    Ok(HashTree {
+        root,
+        rx,
+        pending: BTreeMap::new(),
+        next: 0,
+        window,
+        stats,
+        finished: None,
+    })
+}
+
+/// Lists the tree depth first with the entries of each directory sorted, so files come in
+/// path order, and numbers the files for the workers.
+fn traverse(
+    root: &Path,
+    ahead: u64,
+    window: &Window,
+    stats: &Recorder,
+    jobs: &mpsc::Sender<Job>,
+    results: &mpsc::Sender<Hashed>,
+) {
+    let mut seq = 0;
+    let mut stack: Vec<vec::IntoIter<(PathBuf, bool)>> = Vec::new();
+    let mut dir = Some(root.to_path_buf());
+    loop {
+        if let Some(path) = dir.take() {
+            match stats.time(Phase::Scan, || sorted_entries(&path)) {
+                Ok(entries) => stack.push(entries.into_iter()),
+                Err(e) => {
+                    if !wait_for_room(window, seq, ahead)
+                        || results.send((seq, path, Err(e.into()))).is_err()
+                    {
+                        return;
+                    }
+                    seq += 1;
+                }
+            }
+        }
+        let (path, is_dir) = match stack.last_mut().map(|entries| entries.next()) {
+            Some(Some(entry)) => entry,
+            Some(None) => {
+                stack.pop();
+                continue;
+            }
+            None => return,
+        };
+        if is_dir {
+            dir = Some(path);
+            continue;
+        }
+        if !wait_for_room(window, seq, ahead) || jobs.send((seq, path)).is_err() {
+            return;
+        }
+        seq += 1;
+    }
+}
+
+/// Returns the subdirectories and regular files of `dir` sorted by name, with whether each
+/// is a directory.
+fn sorted_entries(dir: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() || file_type.is_file() {
+            entries.push((entry.path(), file_type.is_dir()));
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Waits until the result `seq` is at most `ahead` results ahead of the consumer; returns
+/// false if the consumer is gone.
+fn wait_for_room(window: &Window, seq: u64, ahead: u64) -> bool {
+    let mut taken = window.taken.lock().unwrap();
+    while seq >= *taken + ahead && !window.cancel.load(Ordering::Relaxed) {
+        taken = window.cond.wait(taken).unwrap();
+    }
+    !window.cancel.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::hash::Hash;
+    use crate::pipeline::hash_tree_parallel;
+    use crate::stats::Phase;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn hashes_in_path_order() {
+        let mut builder = TreeBuilder::new()
+            .file("a.txt", b"a")
+            .file("b/c.txt", b"c")
+            .file("b-d.txt", b"d")
+            .symlink("link", "a.txt");
+        for i in 0..100 {
+            builder = builder.file(format!("many/{:03}", i), format!("{}", i).as_bytes());
+        }
+        let tree = builder.build().unwrap();
+        // the first files take longest, so they finish last
+        let many = tree.join("many");
+        let mut tree_hashes = hash_tree_parallel(tree.path(), move |path| {
+            if !path.starts_with(&many) {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Hash::of_file(path)
+        })
+        .unwrap();
+        assert_eq!(tree_hashes.root(), tree.path());
+        let hashes: Vec<_> = tree_hashes
+            .by_ref()
+            .map(|(path, hash)| (path, hash.unwrap()))
+            .collect();
+        let stats = tree_hashes.stats();
+        assert_eq!(stats.files, 103);
+        assert_eq!(stats.bytes_read, 3 + 10 + 180);
+        assert!(stats.phase(Phase::Data) >= Duration::from_millis(150));
+        assert_eq!(hashes.len(), 103);
+        assert_eq!(hashes[0], (tree.join("a.txt"), Hash::of(b"a")));
+        assert_eq!(hashes[1].0, tree.join("b/c.txt"));
+        assert_eq!(hashes[2].0, tree.join("b-d.txt"));
+        assert_eq!(hashes[102], (tree.join("many/099"), Hash::of(b"99")));
+        assert!(hashes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        // dropping the iterator early stops the traversal
+        let mut hashes = hash_tree_parallel(tree.path(), |path| Hash::of_file(path)).unwrap();
+        assert!(hashes.next().is_some());
+    }
+}
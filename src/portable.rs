@@ -0,0 +1,218 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+
+/// Maximum length of a name component on Windows, in UTF-16 units.
+const MAX_COMPONENT: usize = 255;
+
+/// Device names reserved by Windows, also with an extension (`NUL.txt`).
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that are not allowed in Windows names, besides control characters.
+const ILLEGAL: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Why a name can not be used on Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameIssue {
+    /// A reserved device name, such as `CON` or `lpt1.txt`.
+    Reserved,
+    /// The name ends with a dot or a space, which Windows strips.
+    TrailingDotOrSpace,
+    /// The name contains a character that is not allowed.
+    IllegalChar(char),
+    /// The name is longer than 255 UTF-16 units.
+    TooLong(usize),
+}
+
+/// An entry whose name can not be used on Windows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonPortable {
+    pub path: PathBuf,
+    pub issue: NameIssue,
+}
+
+/// Checks whether a name can be used on Windows; returns the first issue found.
+pub fn name_issue<S: AsRef<OsStr>>(name: S) -> Option<NameIssue> {
+    let name = name.as_ref().to_string_lossy();
+    if let Some(c) = name.chars().find(|c| c.is_control() || ILLEGAL.contains(c)) {
+        return Some(NameIssue::IllegalChar(c));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some(NameIssue::TrailingDotOrSpace);
+    }
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Some(NameIssue::Reserved);
+    }
+    let len = name.encode_utf16().count();
+    if len > MAX_COMPONENT {
+        return Some(NameIssue::TooLong(len));
+    }
+    None
+}
+
+/// Returns the entries under `root` whose names can not be used on Windows, sorted by path,
+/// so trees created on other platforms can be validated before shipping them.
+/// Directories that can not be read are skipped.
+pub fn find_nonportable_names<P: AsRef<Path>>(root: P) -> Result<Vec<NonPortable>> {
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let mut found = Vec::new();
+    let mut stack = vec![root];
+    while let Some(dir) = stack.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if let Some(issue) = name_issue(entry.file_name()) {
+                found.push(NonPortable {
+                    path: entry.path(),
+                    issue,
+                });
+            }
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                stack.push(entry.path());
+            }
+        }
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(found)
+}
+
+/// Turns a name into one that can be used on Windows: illegal characters become `_`,
+/// trailing dots and spaces are removed, reserved names get a `_` suffix and long names
+/// are truncated.
+pub fn make_portable(name: &str) -> String {
+    let policy = SanitizePolicy {
+        max_bytes: usize::MAX,
+        ..SanitizePolicy::default()
+    };
+    sanitize_filename(name, &policy)
+}
+
+/// Settings of [`sanitize_filename`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanitizePolicy {
+    /// Replaces disallowed characters; if `None`, they are removed.
+    pub replacement: Option<char>,
+    /// Also applies the Windows rules: reserved names, illegal characters and
+    /// trailing dots or spaces.
+    pub portable: bool,
+    /// Maximum length of the name in UTF-8 bytes (255 on most Unix filesystems).
+    pub max_bytes: usize,
+}
+
+impl Default for SanitizePolicy {
+    fn default() -> SanitizePolicy {
+        SanitizePolicy {
+            replacement: Some('_'),
+            portable: true,
+            max_bytes: 255,
+        }
+    }
+}
+
+/// Turns untrusted input (e.g. an uploaded file name) into a single safe name component:
+/// path separators and control characters are replaced, `.` and `..` are never returned,
+/// and the length is limited. The result is never empty.
+///
+/// # Arguments:
+///
+/// * `name` - the input name.
+/// * `policy` - which rules to apply.
+pub fn sanitize_filename(name: &str, policy: &SanitizePolicy) -> String {
+    let disallowed = |c: char| {
+        c.is_control() || c == '/' || c == '\\' || (policy.portable && ILLEGAL.contains(&c))
+    };
+    let mut safe = String::with_capacity(name.len());
+    for c in name.chars() {
+        match (disallowed(c), policy.replacement) {
+            (false, _) => safe.push(c),
+            (true, Some(replacement)) => safe.push(replacement),
+            (true, None) => {}
+        }
+    }
+    if policy.portable {
+        while safe.ends_with('.') || safe.ends_with(' ') {
+            safe.pop();
+        }
+        if name_issue(&safe) == Some(NameIssue::Reserved) {
+            match safe.find('.') {
+                Some(dot) => safe.insert(dot, '_'),
+                None => safe.push('_'),
+            }
+        }
+    }
+    while safe.len() > policy.max_bytes
+        || (policy.portable && safe.encode_utf16().count() > MAX_COMPONENT)
+    {
+        safe.pop();
+    }
+    if safe.is_empty() || safe == "." || safe == ".." {
+        safe = policy.replacement.unwrap_or('_').to_string();
+    }
+    safe
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::portable::{
+        find_nonportable_names, make_portable, name_issue, sanitize_filename, NameIssue,
+        SanitizePolicy,
+    };
+
+    #[test]
+    fn nonportable_names() {
+        assert_eq!(name_issue("nul.txt"), Some(NameIssue::Reserved));
+        assert_eq!(name_issue("COM1"), Some(NameIssue::Reserved));
+        assert_eq!(name_issue("console"), None);
+        assert_eq!(name_issue("a:b"), Some(NameIssue::IllegalChar(':')));
+        assert_eq!(name_issue("dir."), Some(NameIssue::TrailingDotOrSpace));
+        assert_eq!(name_issue("x".repeat(256)), Some(NameIssue::TooLong(256)));
+
+        assert_eq!(make_portable("a:b?.txt"), "a_b_.txt");
+        assert_eq!(make_portable("aux.tar.gz"), "aux_.tar.gz");
+        assert_eq!(make_portable("end. "), "end");
+        assert_eq!(name_issue(make_portable(&"é".repeat(300))), None);
+
+        let tree = TreeBuilder::new()
+            .file("ok.txt", b"")
+            .file("con/a?.txt", b"")
+            .build()
+            .unwrap();
+        let found = find_nonportable_names(tree.path()).unwrap();
+        let paths: Vec<_> = found.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(paths, [tree.join("con"), tree.join("con/a?.txt")]);
+    }
+
+    #[test]
+    fn sanitize_untrusted_names() {
+        let policy = SanitizePolicy::default();
+        assert_eq!(
+            sanitize_filename("../../etc/passwd", &policy),
+            ".._.._etc_passwd"
+        );
+        assert_eq!(sanitize_filename("..", &policy), "_");
+        assert_eq!(sanitize_filename("a\0b\nc", &policy), "a_b_c");
+        assert_eq!(sanitize_filename("LPT1", &policy), "LPT1_");
+        assert_eq!(sanitize_filename(&"é".repeat(200), &policy).len(), 254);
+
+        let unix = SanitizePolicy {
+            replacement: None,
+            portable: false,
+            max_bytes: 8,
+        };
+        assert_eq!(sanitize_filename("a:b/c?d.txt", &unix), "a:bc?d.t");
+        assert_eq!(sanitize_filename("/", &unix), "_");
+    }
+}
@@ -0,0 +1,410 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::expand::expand;
+use crate::result::{Error, ErrorKind, Result};
+use crate::rules::RuleSet;
+use crate::walker::SymlinkPolicy;
+
+/// Declarative settings of a scan, usually read from a configuration file with
+/// [`Profile::from_toml`] and turned into iterators with `ReadDir::from_profile`:
+///
+/// ```toml
+/// # directories to scan; `~` and environment variables are expanded
+/// roots = ["~/projects", "/srv/data"]
+/// # include/exclude rules, see `RuleSet`
+/// rules = ["target/", "*.log", "!keep.log"]
+/// max_depth = 8
+/// # "yield" (default) or "skip"
+/// symlinks = "skip"
+/// # worker threads; the scan is single-threaded if not set
+/// threads = 4
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Root directories to scan.
+    pub roots: Vec<PathBuf>,
+    /// Include/exclude rules applied under every root.
+    pub rules: RuleSet,
+    /// If set, directories at this depth are not descended into.
+    pub max_depth: Option<usize>,
+    /// What to do with symbolic links.
+    pub symlinks: SymlinkPolicy,
+    /// If set, the scan is multithreaded with this many worker threads.
+    pub threads: Option<usize>,
+}
+
+impl Profile {
+    /// Reads a profile from a TOML file. Roots are expanded like shell paths,
+    /// and relative ones are taken relative to the directory of the file.
+    /// Fails with an error of kind `ErrorKind::Encoding` for invalid or unknown settings.
+    ///
+    /// Only the part of TOML a profile needs is supported: top-level keys with strings,
+    /// integers, booleans and arrays as values.
+    ///
+    /// # Arguments:
+    ///
+    /// * `path` - path of the profile.
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Profile> {
+        let path = path.as_ref();
+        let mut profile = Profile::parse(&fs::read_to_string(path)?)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        for root in &mut profile.roots {
+            *root = base.join(expand(&*root));
+        }
+        Ok(profile)
+    }
+
+    /// Parses a profile from TOML text; roots are left as they are written.
+    pub fn parse(text: &str) -> Result<Profile> {
+        let mut profile = Profile::default();
+        let mut seen: Vec<String> = Vec::new();
+        for (key, value, line) in Parser::new(text).document()? {
+            let invalid = |message: &str| invalid(line, &format!("`{}` {}", key, message));
+            if seen.contains(&key) {
+                return Err(invalid("is set twice"));
+            }
+            match key.as_str() {
+                "roots" => {
+                    profile.roots = value
+                        .strings()
+                        .ok_or_else(|| invalid("must be an array of strings"))?
+                        .into_iter()
+                        .map(PathBuf::from)
+                        .collect()
+                }
+                "rules" => {
+                    let rules = value
+                        .strings()
+                        .ok_or_else(|| invalid("must be an array of strings"))?;
+                    profile.rules = RuleSet::parse(&rules.join("\n"));
+                }
+                "max_depth" => {
+                    profile.max_depth = Some(
+                        value
+                            .positive()
+                            .ok_or_else(|| invalid("must be a positive integer"))?,
+                    )
+                }
+                "symlinks" => {
+                    profile.symlinks = match value {
+                        Value::Str(policy) if policy == "yield" => SymlinkPolicy::Yield,
+                        Value::Str(policy) if policy == "skip" => SymlinkPolicy::Skip,
+                        _ => return Err(invalid("must be \"yield\" or \"skip\"")),
+                    }
+                }
+                "threads" => {
+                    profile.threads = Some(
+                        value
+                            .positive()
+                            .ok_or_else(|| invalid("must be a positive integer"))?,
+                    )
+                }
+                _ => return Err(invalid("is not a profile setting")),
+            }
+            seen.push(key);
+        }
+        Ok(profile)
+    }
+}
+
+fn invalid(line: usize, message: &str) -> Error {
+    Error::new(
+        ErrorKind::Encoding,
+        format!("invalid profile, line {}: {}", line, message),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn strings(self) -> Option<Vec<String>> {
+        match self {
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::Str(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+
+    fn positive(self) -> Option<usize> {
+        match self {
+            Value::Int(n) if n > 0 => usize::try_from(n).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Parser of the TOML subset used by profiles.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Parser {
+        Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> Error {
+        invalid(self.line, message)
+    }
+
+    /// Skips spaces and tabs, and a comment up to the end of the line.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skips spaces, comments and line breaks.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('\n' | '\r') => self.bump(),
+                _ => return,
+            };
+        }
+    }
+
+    /// Returns the key/value pairs of the document with the lines they start on.
+    fn document(mut self) -> Result<Vec<(String, Value, usize)>> {
+        let mut pairs = Vec::new();
+        loop {
+            self.skip_blank();
+            let line = self.line;
+            let key = match self.peek() {
+                None => return Ok(pairs),
+                Some('[') => return Err(self.error("tables are not supported")),
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                Some(_) => self.bare_key()?,
+            };
+            self.skip_spaces();
+            if self.bump() != Some('=') {
+                return Err(self.error("expected `=` after the key"));
+            }
+            self.skip_spaces();
+            let value = self.value()?;
+            self.skip_spaces();
+            if self.peek() == Some('\r') {
+                self.bump();
+            }
+            if !matches!(self.peek(), None | Some('\n')) {
+                return Err(self.error("expected the end of the line after the value"));
+            }
+            pairs.push((key, value, line));
+        }
+    }
+
+    fn bare_key(&mut self) -> Result<String> {
+        let mut key = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                break;
+            }
+            key.push(c);
+            self.bump();
+        }
+        if key.is_empty() {
+            return Err(self.error("expected a key"));
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::Str(self.basic_string()?)),
+            Some('\'') => Ok(Value::Str(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('t' | 'f') => match self.bare_key()?.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(self.error("invalid value")),
+            },
+            Some('+' | '-' | '0'..='9') => self.integer(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn integer(&mut self) -> Result<Value> {
+        let mut digits = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '_' => {}
+                '+' | '-' | '0'..='9' => digits.push(c),
+                _ => break,
+            }
+            self.bump();
+        }
+        digits
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| self.error("invalid integer"))
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank();
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("expected `,` or `]` in the array")),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(s),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.escape()?),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char> {
+        let c = match self.bump() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some(u @ ('u' | 'U')) => {
+                let len = if u == 'u' { 4 } else { 8 };
+                let hex: String = (0..len).filter_map(|_| self.bump()).collect();
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("invalid unicode escape"))?
+            }
+            _ => return Err(self.error("invalid escape")),
+        };
+        Ok(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::{ErrorKind, Profile, ReadDir, RuleSet, SymlinkPolicy};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_profile() {
+        let profile = Profile::parse(
+            "# scan settings\n\
+             roots = [\"/data\", 'C:\\logs',]\n\
+             rules = [\n  \"*.log\", # noisy\n  \"!keep.log\"\n]\n\
+             max_depth = 3\n\
+             symlinks = \"skip\"\n\
+             threads = 1_0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            profile.roots,
+            [PathBuf::from("/data"), PathBuf::from("C:\\logs")]
+        );
+        assert_eq!(profile.rules, RuleSet::parse("*.log\n!keep.log"));
+        assert_eq!(profile.max_depth, Some(3));
+        assert_eq!(profile.symlinks, SymlinkPolicy::Skip);
+        assert_eq!(profile.threads, Some(10));
+
+        for text in [
+            "depth = 3",
+            "threads = 0",
+            "symlinks = \"follow\"",
+            "roots = \"/data\"",
+            "roots = [\"/a\"]\nroots = [\"/b\"]",
+            "[scan]",
+            "max_depth = 3 4",
+            "roots = [\"/a\"",
+        ] {
+            let err = Profile::parse(text).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Encoding, "{}", text);
+        }
+    }
+
+    #[test]
+    fn read_dir_from_profile() {
+        let tree = TreeBuilder::new()
+            .file(
+                "profile.toml",
+                "roots = [\"a\", \"b\"]\nrules = [\"*.log\"]\nmax_depth = 1\n",
+            )
+            .file("a/1.txt", b"")
+            .file("a/1.log", b"")
+            .file("a/sub/2.txt", b"")
+            .file("b/3.txt", b"")
+            .build()
+            .unwrap();
+        let profile = Profile::from_toml(tree.join("profile.toml")).unwrap();
+        assert_eq!(profile.roots, [tree.join("a"), tree.join("b")]);
+        let mut paths: Vec<_> = ReadDir::from_profile(&profile)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .map(|entry| entry.into_path())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, [tree.join("a/1.txt"), tree.join("b/3.txt")]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Long-running operations that report progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Scan,
+    Hash,
+    Copy,
+    Sync,
+}
+
+/// Progress event reported by long-running operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent<'a> {
+    /// A traversal of `root` has started.
+    ScanStarted { root: &'a Path },
+    /// An entry was yielded by a traversal.
+    EntryVisited { path: &'a Path, depth: usize },
+    /// A directory or entry could not be read and was skipped,
+    /// e.g. because it was removed or renamed during a traversal.
+    SkippedEntry { path: &'a Path, kind: io::ErrorKind },
+    /// `bytes` more bytes of `path` were read for hashing.
+    BytesHashed { path: &'a Path, bytes: u64 },
+    /// `bytes` more bytes of `path` were written by a copy.
+    BytesCopied { path: &'a Path, bytes: u64 },
+    /// An operation has completed.
+    OperationFinished { operation: Operation },
+}
+
+/// Receiver of progress events, shared by every operation of the crate,
+/// so one progress bar implementation covers all of them.
+/// Implemented for closures taking a `&ProgressEvent`.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: &ProgressEvent<'_>);
+}
+
+impl<F: Fn(&ProgressEvent<'_>) + Send + Sync> ProgressSink for F {
+    fn event(&self, event: &ProgressEvent<'_>) {
+        self(event)
+    }
+}
+
+/// A reader reporting what is read from it as `BytesCopied` events of `path`, and counting
+/// it, for operations copying from readers.
+pub(crate) struct CopyReader<'a, R> {
+    inner: R,
+    path: &'a Path,
+    progress: Option<&'a dyn ProgressSink>,
+    /// Bytes read so far.
+    pub(crate) bytes: u64,
+}
+
+impl<'a, R: Read> CopyReader<'a, R> {
+    pub(crate) fn new(
+        inner: R,
+        path: &'a Path,
+        progress: Option<&'a dyn ProgressSink>,
+    ) -> CopyReader<'a, R> {
+        CopyReader {
+            inner,
+            path,
+            progress,
+            bytes: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for CopyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let (Some(progress), true) = (self.progress, n > 0) {
+            progress.event(&ProgressEvent::BytesCopied {
+                path: self.path,
+                bytes: n as u64,
+            });
+        }
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+/// Work queue shared by traversal workers.
+/// Tracks the number of items in progress, so that workers know when the traversal is complete.
+pub(crate) struct WorkQueue<T> {
+    state: Mutex<State<T>>,
+    cond: Condvar,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    active: usize,
+    closed: bool,
+}
+
+impl<T> WorkQueue<T> {
+    pub(crate) fn new(initial: T) -> WorkQueue<T> {
+        WorkQueue {
+            state: Mutex::new(State {
+                items: VecDeque::from([initial]),
+                active: 0,
+                closed: false,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Adds an item to the queue.
+    pub(crate) fn push(&self, item: T) {
+        self.state.lock().unwrap().items.push_back(item);
+        self.cond.notify_one();
+    }
+
+    /// Takes the next item, blocking while other workers may still produce items.
+    /// Returns `None` when the queue is empty and no item is in progress, or it was closed.
+    /// Every returned item must be acknowledged with [`WorkQueue::done`].
+    pub(crate) fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(item) = state.items.pop_front() {
+                state.active += 1;
+                return Some(item);
+            }
+            if state.active == 0 {
+                return None;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    /// Takes the first queued item matching `pred`, without waiting.
+    /// A returned item must be acknowledged with [`WorkQueue::done`], like one from `pop`.
+    pub(crate) fn take<F: Fn(&T) -> bool>(&self, pred: F) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return None;
+        }
+        let index = state.items.iter().position(pred)?;
+        state.active += 1;
+        state.items.remove(index)
+    }
+
+    /// Stops the queue: pending items are discarded and waiting workers are released.
+    pub(crate) fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.items.clear();
+        self.cond.notify_all();
+    }
+
+    /// Marks an item taken with [`WorkQueue::pop`] as processed.
+    pub(crate) fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+        if state.active == 0 && state.items.is_empty() {
+            self.cond.notify_all();
+        }
+    }
+}
+
+/// Acknowledges an item taken from the queue when dropped, even if the worker panics.
+pub(crate) struct DoneGuard<'a, T>(pub(crate) &'a WorkQueue<T>);
+
+impl<T> Drop for DoneGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.done();
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::count::count_entries;
+use crate::paths::{depth_of, simplify_verbatim};
+use crate::result::{Error, ErrorKind, Result};
+use crate::retention::{cleanup, CleanupReport, RetentionPolicy};
+
+/// Keeps the total size of the files of a managed directory, such as a cache, under a
+/// limit: writes through the tracker first make room by removing files according to a
+/// [`RetentionPolicy`], oldest first by default.
+///
+/// The usage is measured with [`count_entries`] when the tracker is created and then
+/// kept up to date by its own writes; call [`QuotaTracker::refresh`] after the directory
+/// is changed by other means.
+#[derive(Debug, Clone)]
+pub struct QuotaTracker {
+    root: PathBuf,
+    max_total: u64,
+    policy: RetentionPolicy,
+    used: u64,
+}
+
+impl QuotaTracker {
+    /// Creates a tracker of `root`, measuring its usage.
+    ///
+    /// # Arguments:
+    ///
+    /// * `root` - managed directory.
+    /// * `max_total` - maximum total size of its files, in bytes.
+    pub fn new<P: AsRef<Path>>(root: P, max_total: u64) -> Result<QuotaTracker> {
+        let root = simplify_verbatim(fs::canonicalize(root)?);
+        let used = count_entries(&root, |_, _| true)?.bytes;
+        Ok(QuotaTracker {
+            root,
+            max_total,
+            policy: RetentionPolicy::default(),
+            used,
+        })
+    }
+
+    /// Sets the rules applied when room has to be made. Its `max_total_size` is lowered
+    /// as needed to fit the file being written; the other rules also apply then.
+    ///
+    /// # Arguments:
+    ///
+    /// * `policy` - which files to evict.
+    pub fn with_policy(mut self, policy: RetentionPolicy) -> QuotaTracker {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the managed directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the maximum total size, in bytes.
+    pub fn max_total(&self) -> u64 {
+        self.max_total
+    }
+
+    /// Returns the current total size of the files, in bytes.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Accounts for `len` bytes removed from the directory by the caller.
+    pub(crate) fn released(&mut self, len: u64) {
+        self.used -= len.min(self.used);
+    }
+
+    /// Measures the usage again.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.used = count_entries(&self.root, |_, _| true)?.bytes;
+        Ok(())
+    }
+
+    /// Writes `bytes` to `path` atomically (see [`write_atomic`]), first evicting files if
+    /// the limit would be exceeded. Returns what was evicted. Fails with an error of kind
+    /// `ErrorKind::Conflict` if the contents are larger than the limit or `path` is not
+    /// inside the managed directory.
+    ///
+    /// # Arguments:
+    ///
+    /// * `path` - file to write, relative to the root or absolute.
+    /// * `bytes` - new contents.
+    pub fn write<P: AsRef<Path>>(&mut self, path: P, bytes: &[u8]) -> Result<CleanupReport> {
+        let path = self.root.join(path.as_ref());
+        if depth_of(&path, &self.root).unwrap_or(0) == 0 {
+            let message = format!("{}: outside of {}", path.display(), self.root.display());
+            return Err(Error::new(ErrorKind::Conflict, message));
+        }
+        let len = bytes.len() as u64;
+        if len > self.max_total {
+            let message = format!(
+                "{}: {} bytes do not fit in a quota of {} bytes",
+                path.display(),
+                len,
+                self.max_total
+            );
+            return Err(Error::new(ErrorKind::Conflict, message));
+        }
+        let old_len = fs::metadata(&path).map_or(0, |meta| meta.len());
+        let mut report = CleanupReport::default();
+        if self.used - old_len.min(self.used) + len > self.max_total {
+            // the file being replaced may be kept, so it is not counted as freed
+            let room = self.max_total - len;
+            let policy = RetentionPolicy {
+                max_total_size: Some(self.policy.max_total_size.map_or(room, |max| max.min(room))),
+                ..self.policy.clone()
+            };
+            report = cleanup(&self.root, &policy, false)?;
+            self.used -= report.bytes_freed.min(self.used);
+        }
+        let old_len = if report.removed.contains(&path) { 0 } else { old_len };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&path, bytes)?;
+        self.used = self.used - old_len.min(self.used) + len;
+        Ok(report)
+    }
+}
+
+/// Writes `bytes` to `path` under `root`, first evicting the oldest files of `root` if its
+/// total size would exceed `max_total`. See [`QuotaTracker::write`]; use a tracker to
+/// avoid measuring the directory on every write.
+///
+/// # Arguments:
+///
+/// * `root` - managed directory.
+/// * `path` - file to write, relative to the root or absolute.
+/// * `bytes` - new contents.
+/// * `max_total` - maximum total size of the files of `root`, in bytes.
+pub fn write_with_quota<R, P>(
+    root: R,
+    path: P,
+    bytes: &[u8],
+    max_total: u64,
+) -> Result<CleanupReport>
+where
+    R: AsRef<Path>,
+    P: AsRef<Path>,
+{
+    QuotaTracker::new(root, max_total)?.write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::quota::{write_with_quota, QuotaTracker};
+    use crate::times::set_mtime;
+    use crate::ErrorKind;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn quota_evicts_oldest() {
+        let tree = TreeBuilder::new()
+            .file("1.bin", b"1")
+            .file("2.bin", b"22")
+            .file("3.bin", b"333")
+            .build()
+            .unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["1.bin", "2.bin", "3.bin"].iter().enumerate() {
+            set_mtime(tree.join(name), now - Duration::from_secs(60 * (3 - i as u64))).unwrap();
+        }
+        let mut quota = QuotaTracker::new(tree.path(), 8).unwrap();
+        assert_eq!(quota.used(), 6);
+        let report = quota.write("4.bin", b"4444").unwrap();
+        assert_eq!(report.removed, [tree.join("1.bin"), tree.join("2.bin")]);
+        assert_eq!(quota.used(), 7);
+        assert_eq!(fs::read(tree.join("4.bin")).unwrap(), b"4444");
+
+        // replacing a file only counts the difference
+        assert!(quota.write("4.bin", b"5").unwrap().removed.is_empty());
+        assert_eq!(quota.used(), 4);
+        let err = quota.write("big.bin", &[0; 9]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert!(quota.write("../escape.bin", b"").is_err());
+
+        let report = write_with_quota(tree.path(), "sub/5.bin", b"55555", 8).unwrap();
+        assert_eq!(report.removed, [tree.join("3.bin")]);
+        assert!(tree.join("sub/5.bin").exists());
+    }
+}
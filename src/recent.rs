@@ -0,0 +1,121 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::result::Result;
+use crate::vfs::FileKind;
+use crate::ReadDir;
+
+/// Returns the `n` most recently modified regular files under `root` with their
+/// modification times, newest first. Files are found in a single pass, keeping only the
+/// best `n` candidates in memory. Files whose modification time is unknown are left out.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `n` - maximum number of files to return.
+pub fn newest_files<P: AsRef<Path>>(root: P, n: usize) -> Result<Vec<(PathBuf, SystemTime)>> {
+    // a min-heap keyed by time holds the newest files, its top is the first to go
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for_each_file(root, |modified, path| {
+        heap.push(Reverse((modified, path)));
+        if heap.len() > n {
+            heap.pop();
+        }
+    })?;
+    let mut files: Vec<_> = heap
+        .into_iter()
+        .map(|Reverse((modified, path))| (path, modified))
+        .collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(files)
+}
+
+/// Like [`newest_files`], but returns the `n` least recently modified files, oldest first.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `n` - maximum number of files to return.
+pub fn oldest_files<P: AsRef<Path>>(root: P, n: usize) -> Result<Vec<(PathBuf, SystemTime)>> {
+    // a max-heap keyed by time holds the oldest files
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for_each_file(root, |modified, path| {
+        heap.push((modified, path));
+        if heap.len() > n {
+            heap.pop();
+        }
+    })?;
+    let mut files: Vec<_> = heap
+        .into_iter()
+        .map(|(modified, path)| (path, modified))
+        .collect();
+    files.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(files)
+}
+
+fn for_each_file<P, F>(root: P, mut f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(SystemTime, PathBuf),
+{
+    let mut rd = ReadDir::try_new(root)?;
+    rd.is_multithreaded = true;
+    for entry in rd {
+        let meta = match entry.metadata() {
+            Ok(meta) => *meta,
+            // removed since it was listed
+            Err(_) => continue,
+        };
+        if let (FileKind::File, Some(modified)) = (meta.kind, meta.modified) {
+            f(modified, entry.into_path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::recent::{newest_files, oldest_files};
+    use crate::times::set_mtime;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn newest_and_oldest() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"")
+            .file("b/c.txt", b"")
+            .file("b/d/e.txt", b"")
+            .file("f.txt", b"")
+            .build()
+            .unwrap();
+        let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for (i, name) in ["b/d/e.txt", "a.txt", "f.txt", "b/c.txt"]
+            .iter()
+            .enumerate()
+        {
+            set_mtime(tree.join(name), base + Duration::from_secs(i as u64)).unwrap();
+        }
+        let paths = |files: Vec<(_, SystemTime)>| -> Vec<_> {
+            files.into_iter().map(|(path, _)| path).collect()
+        };
+        assert_eq!(
+            paths(newest_files(tree.path(), 2).unwrap()),
+            [tree.join("b/c.txt"), tree.join("f.txt")]
+        );
+        let oldest = oldest_files(tree.path(), 3).unwrap();
+        assert_eq!(oldest[0].1, base);
+        assert_eq!(
+            paths(oldest),
+            [
+                tree.join("b/d/e.txt"),
+                tree.join("a.txt"),
+                tree.join("f.txt")
+            ]
+        );
+        assert!(newest_files(tree.path(), 0).unwrap().is_empty());
+        assert_eq!(newest_files(tree.path(), 10).unwrap().len(), 4);
+    }
+}
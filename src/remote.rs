@@ -0,0 +1,279 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::changeset::Content;
+use crate::diff::relative_files_in;
+use crate::encoding::encode_path;
+#[cfg(feature = "delta")]
+use crate::delta::{Delta, Signature};
+use crate::hash::{Hash, Hasher};
+use crate::paths::long_path;
+use crate::result::{Error, Result};
+use crate::vfs::{FileKind, Fs, RealFs};
+
+/// Metadata of an entry of a [`Remote`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteStat {
+    /// Length of the contents, or of the target of a symbolic link.
+    pub len: u64,
+    /// Modification time, if the backend records it.
+    pub modified: Option<SystemTime>,
+    /// Permission bits of a file, if the backend records them.
+    pub mode: Option<u32>,
+    pub is_symlink: bool,
+}
+
+/// A tree of files and symbolic links that [`sync_remote`] can synchronize with, e.g. over
+/// SFTP, S3 or WebDAV. Paths are relative to the root of the tree; directories are
+/// implicit, so writing an entry creates the directories above it. Contents are streamed,
+/// so files need not fit in memory.
+///
+/// Backends that know the hashes of their entries without reading them can override
+/// [`Remote::hash`]; hashes must be computed as [`Remote::hash`] does by default. Those
+/// that can compute [delta](crate::delta) signatures and apply deltas where the data is,
+/// without transferring it, can override [`Remote::signature`] and [`Remote::patch`].
+///
+/// [`sync_remote`]: crate::sync_remote
+pub trait Remote {
+    /// Lists the relative paths of all the entries.
+    fn list(&self) -> Result<Vec<PathBuf>>;
+
+    /// Opens a file for reading.
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>>;
+
+    /// Returns the target of a symbolic link.
+    fn read_link(&self, path: &Path) -> Result<PathBuf>;
+
+    /// Creates or replaces a file with everything read from `data`; it gets the permission
+    /// bits `mode` if the backend supports them, or if `None` those of the file it
+    /// replaces. The old entry must stay readable until `data` is read to the end.
+    fn write(&self, path: &Path, data: &mut dyn Read, mode: Option<u32>) -> Result<()>;
+
+    /// Creates or replaces a symbolic link at `path` pointing to `target`.
+    fn symlink(&self, target: &Path, path: &Path) -> Result<()>;
+
+    /// Deletes an entry; deleting a missing entry is not an error.
+    fn delete(&self, path: &Path) -> Result<()>;
+
+    /// Returns the metadata of an entry, `None` if it does not exist.
+    fn stat(&self, path: &Path) -> Result<Option<RemoteStat>>;
+
+    /// Returns the hash of an entry: of a tag byte (0 for a file, 1 for a symbolic link),
+    /// the length of the data as 8 little-endian bytes, and the data: the contents of a
+    /// file, or the [encoded](crate::encode_path) target of a link. A file and a link thus
+    /// never have the same hash.
+    fn hash(&self, path: &Path) -> Result<Hash> {
+        let stat = self.stat(path)?.ok_or_else(|| not_found(path))?;
+        match stat.is_symlink {
+            true => Ok(link_hash(&self.read_link(path)?)),
+            false => Ok(file_hash(stat.len, self.open(path)?)?),
+        }
+    }
+
+    /// Returns the signature of a file, to compute a delta of its new version against.
+    /// Defaults to reading the file.
+    #[cfg(feature = "delta")]
+    fn signature(&self, path: &Path, block_size: usize) -> Result<Signature> {
+        Ok(Signature::of_reader(self.open(path)?, block_size)?)
+    }
+
+    /// Replaces a file with the new version `delta` makes of it; it gets the permission
+    /// bits as with [`Remote::write`]. Defaults to writing the new version, reading copied
+    /// blocks from the old one.
+    #[cfg(feature = "delta")]
+    fn patch(&self, path: &Path, delta: &Delta, mode: Option<u32>) -> Result<()> {
+        let mut new = delta.reader(|| self.open(path).map_err(io::Error::other));
+        self.write(path, &mut new, mode)
+    }
+}
+
+/// A local directory as a [`Remote`], such as the local side of a sync.
+#[derive(Clone)]
+pub struct LocalDir {
+    fs: Arc<dyn Fs>,
+    root: PathBuf,
+}
+
+impl LocalDir {
+    pub fn new<P: AsRef<Path>>(root: P) -> LocalDir {
+        LocalDir::new_in(Arc::new(RealFs), root)
+    }
+
+    /// Creates a directory of another filesystem, e.g. a [`MemFs`](crate::MemFs).
+    pub fn new_in<P: AsRef<Path>>(fs: Arc<dyn Fs>, root: P) -> LocalDir {
+        LocalDir {
+            fs,
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path of an entry, with the verbatim prefix on Windows if it is too long
+    /// without it.
+    fn path(&self, path: &Path) -> PathBuf {
+        long_path(self.root.join(path))
+    }
+}
+
+impl fmt::Debug for LocalDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalDir").field("root", &self.root).finish()
+    }
+}
+
+impl Remote for LocalDir {
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        let files = relative_files_in(self.fs.clone(), &self.root)?;
+        Ok(files.into_iter().collect())
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        Ok(self.fs.open(&self.path(path))?)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        Ok(self.fs.read_link(&self.path(path))?)
+    }
+
+    fn write(&self, path: &Path, data: &mut dyn Read, mode: Option<u32>) -> Result<()> {
+        let path = self.path(path);
+        if let Some(parent) = path.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        Ok(self.fs.write_atomic(&path, data, mode)?)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> Result<()> {
+        Content::Symlink(target.to_path_buf()).write(&*self.fs, &self.path(path), None)
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        match self.fs.remove_file(&self.path(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn stat(&self, path: &Path) -> Result<Option<RemoteStat>> {
+        let path = self.path(path);
+        match self.fs.symlink_metadata(&path) {
+            Ok(meta) => Ok(Some(RemoteStat {
+                len: meta.len,
+                modified: meta.modified,
+                mode: match meta.kind {
+                    FileKind::File => self.fs.mode(&path).ok(),
+                    _ => None,
+                },
+                is_symlink: meta.kind == FileKind::Symlink,
+            })),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Returns the error for an entry that does not exist.
+pub(crate) fn not_found(path: &Path) -> Error {
+    let message = format!("{}: no such entry", path.display());
+    io::Error::new(io::ErrorKind::NotFound, message).into()
+}
+
+const FILE_TAG: u8 = 0;
+const LINK_TAG: u8 = 1;
+
+/// Returns what precedes the data of an entry in its hash, see [`Remote::hash`].
+fn entry_header(tag: u8, len: u64) -> [u8; 9] {
+    let mut header = [tag; 9];
+    header[1..].copy_from_slice(&len.to_le_bytes());
+    header
+}
+
+/// Hashes a file of length `len` with the contents read from `data`.
+pub(crate) fn file_hash<R: Read>(len: u64, data: R) -> io::Result<Hash> {
+    Hash::of_reader((&entry_header(FILE_TAG, len)[..]).chain(data))
+}
+
+/// Hashes a symbolic link to `target`.
+pub(crate) fn link_hash(target: &Path) -> Hash {
+    let target = encode_path(target);
+    let mut hasher = Hasher::new();
+    hasher.update(&entry_header(LINK_TAG, target.len() as u64));
+    hasher.update(target.as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::remote::{file_hash, link_hash, LocalDir, Remote};
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn local_dir_remote() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"a")
+            .symlink("link", "a.txt")
+            .build()
+            .unwrap();
+        let remote = LocalDir::new(tree.path());
+        remote.write(Path::new("b/c.txt"), &mut &b"new"[..], Some(0o700)).unwrap();
+        remote.symlink(Path::new("c.txt"), Path::new("b/link")).unwrap();
+        assert_eq!(
+            remote.list().unwrap(),
+            [
+                PathBuf::from("a.txt"),
+                PathBuf::from("b/c.txt"),
+                PathBuf::from("b/link"),
+                PathBuf::from("link")
+            ]
+        );
+        let mut contents = Vec::new();
+        remote.open(Path::new("b/c.txt")).unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"new");
+        assert_eq!(remote.read_link(Path::new("b/link")).unwrap(), Path::new("c.txt"));
+        let stat = remote.stat(Path::new("b/c.txt")).unwrap().unwrap();
+        assert_eq!(stat.len, 3);
+        #[cfg(unix)]
+        assert_eq!(stat.mode, Some(0o700));
+        assert!(remote.stat(Path::new("link")).unwrap().unwrap().is_symlink);
+        let hash = remote.hash(Path::new("b/c.txt")).unwrap();
+        assert_eq!(hash, file_hash(3, &b"new"[..]).unwrap());
+        assert_eq!(remote.hash(Path::new("link")).unwrap(), link_hash(Path::new("a.txt")));
+        // a file with the contents a link was hashed from before is not the link
+        let link = remote.hash(Path::new("link")).unwrap();
+        for imitation in [&b"link:a.txt"[..], b"a.txt", b"\x01\x05\0\0\0\0\0\0\0a.txt"] {
+            remote.write(Path::new("imitation"), &mut &imitation[..], None).unwrap();
+            assert_ne!(remote.hash(Path::new("imitation")).unwrap(), link);
+        }
+        remote.delete(Path::new("b/c.txt")).unwrap();
+        remote.delete(Path::new("b/c.txt")).unwrap();
+        assert_eq!(remote.stat(Path::new("b/c.txt")).unwrap(), None);
+    }
+
+    #[cfg(feature = "delta")]
+    #[test]
+    fn local_dir_patch() {
+        use crate::delta::Delta;
+
+        let old: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let tree = TreeBuilder::new().file("data.bin", &old).build().unwrap();
+        let remote = LocalDir::new(tree.path());
+        let mut new = old[10_000..].to_vec();
+        new.extend_from_slice(b"appended");
+        new.extend_from_slice(&old[..10_000]);
+
+        let signature = remote.signature(Path::new("data.bin"), 1000).unwrap();
+        let delta = Delta::compute(&signature, &new);
+        assert_eq!(delta.literal_len(), 8);
+        remote.patch(Path::new("data.bin"), &delta, None).unwrap();
+        assert_eq!(std::fs::read(tree.join("data.bin")).unwrap(), new);
+    }
+}
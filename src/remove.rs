@@ -0,0 +1,200 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::dirfd::Dir;
+use crate::format::format_count;
+use crate::result::{Error, ErrorKind, Result};
+use crate::vfs::FileKind;
+
+/// Result of a [`remove_tree`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RemoveReport {
+    /// Number of entries removed, directories included.
+    pub removed: u64,
+    /// Entries that could not be removed or listed, with the kind of the error.
+    /// The directories holding them are kept too, but not reported.
+    pub failures: Vec<(PathBuf, io::ErrorKind)>,
+}
+
+impl RemoveReport {
+    /// Checks whether everything was removed.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for RemoveReport {
+    /// Formats the report for people, e.g. `1,204 entries removed, 2 failures`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries removed, {} failures",
+            format_count(self.removed),
+            format_count(self.failures.len() as u64)
+        )
+    }
+}
+
+/// Removes `path` and, if it is a directory, everything under it, depth first. Unlike
+/// `fs::remove_dir_all`, it does not stop at the first entry that can not be removed:
+/// it removes everything else and reports what is left. Symbolic links are removed,
+/// never followed; directories are reached relative to their open parent.
+///
+/// With `force`, entries that can not be listed or removed because of their permissions
+/// are retried once after giving the owner full access to the directories involved
+/// (clearing read-only attributes on Windows), like `rm -rf` run by the owner.
+/// Fails only if `path` itself does not exist or has no parent.
+///
+/// # Arguments:
+///
+/// * `path` - file or directory to remove.
+/// * `force` - adjust permissions to remove what is otherwise denied.
+pub fn remove_tree<P: AsRef<Path>>(path: P, force: bool) -> Result<RemoveReport> {
+    let path = path.as_ref();
+    let kind = FileKind::from(fs::symlink_metadata(path)?.file_type());
+    let name = path.file_name().ok_or_else(|| {
+        let message = format!("{}: can not remove a path without a name", path.display());
+        Error::new(ErrorKind::File, message)
+    })?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut remover = Remover {
+        force,
+        report: RemoveReport::default(),
+    };
+    let parent = Dir::open(parent)?;
+    remover.remove(&parent, name, path, kind, &mut || false);
+    Ok(remover.report)
+}
+
+struct Remover {
+    force: bool,
+    report: RemoveReport,
+}
+
+impl Remover {
+    fn failed(&mut self, path: &Path, error: &io::Error) {
+        self.report.failures.push((path.to_path_buf(), error.kind()));
+    }
+
+    /// Removes the entry `name` of `dir`, its contents first. `grant` gives access to
+    /// `dir` itself and returns whether it did; it is tried once, if removing is denied.
+    fn remove(
+        &mut self,
+        dir: &Dir,
+        name: &OsStr,
+        path: &Path,
+        kind: FileKind,
+        grant: &mut dyn FnMut() -> bool,
+    ) -> bool {
+        if kind == FileKind::Dir && !self.remove_contents(dir, name, path) {
+            return false;
+        }
+        let remove = || match kind {
+            FileKind::Dir => dir.remove_dir(name),
+            _ => dir.remove_file(name),
+        };
+        let mut result = remove();
+        if let Err(e) = &result {
+            if self.force && e.kind() == io::ErrorKind::PermissionDenied {
+                // Windows refuses to remove read-only files, whatever their directory
+                let granted = grant() | (cfg!(windows) && dir.grant_owner(name).is_ok());
+                if granted {
+                    result = remove();
+                }
+            }
+        }
+        match result {
+            Ok(()) => {
+                self.report.removed += 1;
+                true
+            }
+            Err(e) => {
+                self.failed(path, &e);
+                false
+            }
+        }
+    }
+
+    /// Removes the contents of the subdirectory `name` of `parent`; returns whether it
+    /// is empty now.
+    fn remove_contents(&mut self, parent: &Dir, name: &OsStr, path: &Path) -> bool {
+        let open = || parent.open_dir(name).and_then(|dir| Ok((dir.entries()?, dir)));
+        let mut granted = false;
+        let mut opened = open();
+        if let Err(e) = &opened {
+            if self.force && e.kind() == io::ErrorKind::PermissionDenied {
+                granted = parent.grant_owner(name).is_ok();
+                if granted {
+                    opened = open();
+                }
+            }
+        }
+        let (entries, dir) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.failed(path, &e);
+                return false;
+            }
+        };
+        let mut empty = true;
+        for entry in entries {
+            let (child, kind): (OsString, FileKind) = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.failed(path, &e);
+                    empty = false;
+                    continue;
+                }
+            };
+            let mut grant = || {
+                if granted {
+                    return false;
+                }
+                granted = parent.grant_owner(name).is_ok();
+                granted
+            };
+            empty &= self.remove(&dir, &child, &path.join(&child), kind, &mut grant);
+        }
+        empty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::remove::remove_tree;
+    use std::fs;
+
+    #[test]
+    fn remove_tree_reports() {
+        let tree = TreeBuilder::new()
+            .file("data/a.txt", b"")
+            .file("data/locked/b.txt", b"")
+            .symlink("data/link", "../outside")
+            .file("outside/c.txt", b"")
+            .build()
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let readonly = fs::Permissions::from_mode(0o500);
+            fs::set_permissions(tree.join("data/locked"), readonly).unwrap();
+        }
+        let report = remove_tree(tree.join("data"), true).unwrap();
+        assert!(report.is_complete());
+        assert_eq!(report.removed, 5);
+        assert_eq!(report.to_string(), "5 entries removed, 0 failures");
+        assert!(!tree.join("data").exists());
+        assert!(tree.join("outside/c.txt").exists());
+
+        assert!(remove_tree(tree.join("data"), false).is_err());
+        let report = remove_tree(tree.join("outside/c.txt"), false).unwrap();
+        assert_eq!(report.removed, 1);
+    }
+}
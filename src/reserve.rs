@@ -0,0 +1,82 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::result::{Error, ErrorKind, Result};
+
+/// Creates `path` as a new empty file opened for writing, failing with an error of kind
+/// `ErrorKind::Conflict` if anything already exists there. The check and the creation are
+/// one atomic operation (`O_CREAT | O_EXCL`, `CREATE_NEW` on Windows), so when several
+/// producers race for the same name exactly one of them gets it. A symbolic link at
+/// `path` is a conflict too, even if dangling, and is never followed.
+///
+/// # Arguments:
+///
+/// * `path` - file to claim.
+pub fn create_new_exclusive<P: AsRef<Path>>(path: P) -> Result<File> {
+    let path = path.as_ref();
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => Ok(file),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(Error::new(
+            ErrorKind::Conflict,
+            format!("{}: already exists", path.display()),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Claims several output files at once with [`create_new_exclusive`], returning them in
+/// the order of `paths`. It is all or nothing: if one of the paths is taken (error of kind
+/// `ErrorKind::Conflict`) or cannot be created, the files already claimed by this call
+/// are removed before the error is returned, so nothing is left behind to clean up.
+///
+/// # Arguments:
+///
+/// * `paths` - files to claim.
+pub fn reserve_paths<I, P>(paths: I) -> Result<Vec<File>>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let mut claimed: Vec<(PathBuf, File)> = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        match create_new_exclusive(path) {
+            Ok(file) => claimed.push((path.to_path_buf(), file)),
+            Err(e) => {
+                for (path, file) in claimed {
+                    drop(file);
+                    let _ = fs::remove_file(path);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(claimed.into_iter().map(|(_, file)| file).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::reserve::{create_new_exclusive, reserve_paths};
+    use crate::ErrorKind;
+    use std::io::Write;
+
+    #[test]
+    fn exclusive_reservation() {
+        let tree = TreeBuilder::new().file("taken.txt", b"old").build().unwrap();
+        let mut file = create_new_exclusive(tree.join("new.txt")).unwrap();
+        file.write_all(b"new").unwrap();
+        let err = create_new_exclusive(tree.join("new.txt")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+
+        let err = reserve_paths([tree.join("a.txt"), tree.join("taken.txt")]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert!(!tree.join("a.txt").exists());
+        assert_eq!(std::fs::read(tree.join("taken.txt")).unwrap(), b"old");
+
+        let files = reserve_paths([tree.join("a.txt"), tree.join("b.txt")]).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(tree.join("b.txt").exists());
+    }
+}
@@ -1,18 +1,53 @@
+use std::fmt;
 use std::io;
 use std::sync::mpsc;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorKind {
     File,
-    Channel
+    Channel,
+    Encoding,
+    Timeout,
+    Conflict,
+    Verification,
+    Cancelled
 }
 
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
-    cause: Box<dyn std::error::Error>
+    cause: Box<dyn std::error::Error + Send + Sync>
+}
+
+impl Error {
+    pub(crate) fn new<E>(kind: ErrorKind, cause: E) -> Error
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Error {
+            kind,
+            cause: cause.into()
+        }
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} error: {}", self.kind, self.cause)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.cause.as_ref())
+    }
 }
 
 impl From<io::Error> for Error {
@@ -24,7 +59,7 @@ impl From<io::Error> for Error {
     }
 }
 
-impl<T: 'static> From<mpsc::SendError<T>> for Error {
+impl<T: Send + Sync + 'static> From<mpsc::SendError<T>> for Error {
     fn from(e: mpsc::SendError<T>) -> Error {
         Error {
             kind: ErrorKind::Channel,
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::format::{format_count, format_size};
+use crate::result::Result;
+use crate::vfs::FileKind;
+use crate::ReadDir;
+
+/// Rules deciding which files [`cleanup`] removes; a file is removed if any rule selects it.
+/// Only regular files are considered, symbolic links and directories are left alone.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Remove files last modified longer ago than this.
+    pub max_age: Option<Duration>,
+    /// Keep only this many most recently modified files per directory.
+    pub keep_newest: Option<usize>,
+    /// Remove the oldest remaining files until the total size is at most this many bytes.
+    pub max_total_size: Option<u64>,
+}
+
+/// Result of a cleanup.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Files that were (or, in dry-run mode, would be) removed, oldest first.
+    pub removed: Vec<PathBuf>,
+    /// Number of bytes freed.
+    pub bytes_freed: u64,
+}
+
+impl fmt::Display for CleanupReport {
+    /// Formats the report for people, e.g. `12 files removed, 1.2 MiB freed`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files removed, {} freed",
+            format_count(self.removed.len() as u64),
+            format_size(self.bytes_freed)
+        )
+    }
+}
+
+struct Candidate {
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+}
+
+/// Removes files under `root` according to a retention policy, e.g. to rotate logs or
+/// trim a cache. Files whose modification time is unknown are treated as the oldest.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `policy` - which files to remove.
+/// * `dry_run` - only report what would be removed.
+pub fn cleanup<P: AsRef<Path>>(
+    root: P,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let mut rd = ReadDir::try_new(root)?;
+    rd.is_lazy = true;
+    let mut files = Vec::new();
+    for entry in rd {
+        let meta = *entry.metadata()?;
+        if meta.kind == FileKind::File {
+            files.push(Candidate {
+                path: entry.into_path(),
+                len: meta.len,
+                modified: meta.modified.unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+    // newest first, so the files to keep come before the ones to remove
+    files.sort_by(|a, b| {
+        b.modified
+            .cmp(&a.modified)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    let now = SystemTime::now();
+    let mut per_dir: HashMap<&Path, usize> = HashMap::new();
+    let mut remove = vec![false; files.len()];
+    for (file, remove) in files.iter().zip(remove.iter_mut()) {
+        let age = now.duration_since(file.modified).unwrap_or_default();
+        let kept_in_dir = per_dir.entry(file.path.parent().unwrap()).or_default();
+        *kept_in_dir += 1;
+        *remove = policy.max_age.is_some_and(|max_age| age > max_age)
+            || policy.keep_newest.is_some_and(|keep| *kept_in_dir > keep);
+    }
+    if let Some(max_total_size) = policy.max_total_size {
+        let mut total: u64 = files
+            .iter()
+            .zip(&remove)
+            .filter(|(_, remove)| !**remove)
+            .map(|(file, _)| file.len)
+            .sum();
+        for (file, remove) in files.iter().zip(remove.iter_mut()).rev() {
+            if total <= max_total_size {
+                break;
+            }
+            if !*remove {
+                *remove = true;
+                total -= file.len;
+            }
+        }
+    }
+
+    let mut report = CleanupReport::default();
+    for (file, _) in files
+        .into_iter()
+        .zip(remove)
+        .rev()
+        .filter(|(_, remove)| *remove)
+    {
+        if !dry_run {
+            fs::remove_file(&file.path)?;
+        }
+        report.bytes_freed += file.len;
+        report.removed.push(file.path);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::retention::{cleanup, RetentionPolicy};
+    use crate::times::set_mtime;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn cleanup_by_policy() {
+        let tree = TreeBuilder::new()
+            .file("logs/1.log", b"1")
+            .file("logs/2.log", b"22")
+            .file("logs/3.log", b"333")
+            .file("cache/4.bin", b"4444")
+            .build()
+            .unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["logs/1.log", "logs/2.log", "logs/3.log", "cache/4.bin"]
+            .iter()
+            .enumerate()
+        {
+            let days = Duration::from_secs(86400 * (10 - i as u64));
+            set_mtime(tree.join(name), now - days).unwrap();
+        }
+
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(86400 * 9 + 3600)),
+            ..RetentionPolicy::default()
+        };
+        let report = cleanup(tree.path(), &policy, true).unwrap();
+        assert_eq!(report.removed, [tree.join("logs/1.log")]);
+
+        let policy = RetentionPolicy {
+            keep_newest: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let report = cleanup(tree.path(), &policy, true).unwrap();
+        assert_eq!(
+            report.removed,
+            [tree.join("logs/1.log"), tree.join("logs/2.log")]
+        );
+
+        let policy = RetentionPolicy {
+            max_total_size: Some(5),
+            ..RetentionPolicy::default()
+        };
+        let report = cleanup(tree.path(), &policy, false).unwrap();
+        assert_eq!(report.removed.len(), 3);
+        assert_eq!(report.bytes_freed, 6);
+        assert!(tree.join("cache/4.bin").exists());
+        assert!(!tree.join("logs/3.log").exists());
+    }
+}
@@ -0,0 +1,577 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::case::fold_case;
+use crate::result::Result;
+
+/// An ordered list of include and exclude rules selecting paths of a tree, written in
+/// gitignore style (with rsync-style `+ ` and `- ` prefixes also accepted):
+///
+/// ```text
+/// # comments and blank lines are ignored
+/// *.log          exclude files and directories named *.log, at any depth
+/// !keep.log      include again (also `+ keep.log`)
+/// build/         exclude directories named build, but not files
+/// /target        exclude `target` in the root only
+/// docs/**/*.tmp  exclude *.tmp anywhere under docs
+/// ```
+///
+/// Patterns support `*` and `?` (not matching `/`), classes such as `[a-z]` or `[!0-9]`,
+/// and `**` matching any number of directories. A pattern containing a `/` (other than a
+/// trailing one) is anchored to the root; otherwise it matches the name at any depth.
+/// The last matching rule decides; paths no rule matches are included. Like in git,
+/// nothing below an excluded directory is visited, so it can not be included again.
+///
+/// The rules are compiled into one automaton that reads each path once. Rules naming a
+/// file or directory without wildcards are found by lookup, so large ignore files cost
+/// little more than small ones. Patterns that are a literal with a leading or trailing `*`,
+/// such as `*.log`, are compared as strings, and those ending in an extension are also
+/// found by lookup; the others go through the general matcher.
+///
+/// Names are compared case-sensitively unless the rules are made to
+/// [`ignore_case`](RuleSet::ignore_case).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    automaton: Automaton,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    include: bool,
+    dir_only: bool,
+    segments: Vec<Segment>,
+}
+
+/// A component of a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// `**`, any number of names.
+    AnyNames,
+    /// A name without wildcards.
+    Literal(String),
+    /// `*` followed by a literal, such as `*.log`.
+    Suffix(String),
+    /// A literal followed by `*`.
+    Prefix(String),
+    /// A literal between two `*`.
+    Contains(String),
+    /// A name with wildcards, as characters.
+    Glob(Vec<char>),
+}
+
+/// A nondeterministic automaton over path components, with one state per position in the
+/// pattern of each anchored rule, the last one accepting. Unanchored rules match the last
+/// name only, so they are looked up by name instead.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Automaton {
+    steps: Vec<Step>,
+    /// States active before the first name, closed over `**`, literal states included.
+    initial: Vec<usize>,
+    /// States reading each literal name, for finding them without scanning the others.
+    literals: HashMap<String, Vec<usize>>,
+    /// Unanchored rules by literal name.
+    last_literals: HashMap<String, Vec<usize>>,
+    /// Unanchored rules `*` and a literal starting with a `.`, by the literal.
+    last_extensions: HashMap<String, Vec<usize>>,
+    /// Unanchored rules with other wildcards.
+    last_globs: Vec<(usize, Segment)>,
+}
+
+/// What a state of an [`Automaton`] does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// Reads a name matching the segment to go to the next state.
+    Read(Segment),
+    /// Accepts the path for the rule with this index.
+    Accept(usize),
+}
+
+impl RuleSet {
+    /// Parses rules, one per line.
+    pub fn parse(text: &str) -> RuleSet {
+        let rules: Vec<Rule> = text.lines().filter_map(Rule::parse).collect();
+        RuleSet {
+            automaton: Automaton::compile(&rules),
+            rules,
+            ignore_case: false,
+        }
+    }
+
+    /// Makes the rules match names ignoring case, as on case-insensitive filesystems
+    /// (see [`is_case_insensitive_fs`](crate::is_case_insensitive_fs)). Patterns and
+    /// names are compared in the form [`fold_case`] gives.
+    pub fn ignore_case(mut self) -> RuleSet {
+        if !self.ignore_case {
+            for rule in &mut self.rules {
+                rule.segments.iter_mut().for_each(Segment::fold_case);
+            }
+            self.automaton = Automaton::compile(&self.rules);
+            self.ignore_case = true;
+        }
+        self
+    }
+
+    /// Reads rules from a file such as `.gitignore` or an rsync filter file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RuleSet> {
+        Ok(RuleSet::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Checks whether a path is selected by the rules.
+    ///
+    /// # Arguments:
+    ///
+    /// * `relative` - path relative to the root of the tree.
+    /// * `is_dir` - whether the path is a directory.
+    pub fn is_included<P: AsRef<Path>>(&self, relative: P, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        let names: Vec<_> = relative
+            .as_ref()
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) if self.ignore_case => {
+                    Some(Name::new(Cow::Owned(fold_case(name))))
+                }
+                Component::Normal(name) => Some(Name::new(name.to_string_lossy())),
+                _ => None,
+            })
+            .collect();
+        self.automaton
+            .matches(&names)
+            .into_iter()
+            .filter(|rule| is_dir || !self.rules[*rule].dir_only)
+            .max()
+            .is_none_or(|rule| self.rules[rule].include)
+    }
+
+    /// Checks whether there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (include, pattern) = if let Some(pattern) = line.strip_prefix("+ ") {
+            (true, pattern)
+        } else if let Some(pattern) = line.strip_prefix("- ") {
+            (false, pattern)
+        } else if let Some(pattern) = line.strip_prefix('!') {
+            (true, pattern)
+        } else {
+            (false, line.strip_prefix('\\').unwrap_or(line))
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let mut segments: Vec<Segment> = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(Segment::parse)
+            .collect();
+        if segments.is_empty() {
+            return None;
+        }
+        if !anchored {
+            segments.insert(0, Segment::AnyNames);
+        }
+        Some(Rule {
+            include,
+            dir_only,
+            segments,
+        })
+    }
+}
+
+impl Segment {
+    fn parse(pattern: &str) -> Segment {
+        const WILDCARDS: [char; 4] = ['*', '?', '[', '\\'];
+        if pattern == "**" {
+            return Segment::AnyNames;
+        }
+        if !pattern.contains(WILDCARDS) {
+            return Segment::Literal(pattern.to_string());
+        }
+        let (leading, rest) = match pattern.strip_prefix('*') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (trailing, literal) = match rest.strip_suffix('*') {
+            Some(literal) => (true, literal),
+            None => (false, rest),
+        };
+        if literal.is_empty() || literal.contains(WILDCARDS) {
+            return Segment::Glob(pattern.chars().collect());
+        }
+        let literal = literal.to_string();
+        match (leading, trailing) {
+            (true, false) => Segment::Suffix(literal),
+            (false, true) => Segment::Prefix(literal),
+            _ => Segment::Contains(literal),
+        }
+    }
+
+    /// Replaces the literal parts of the segment with their case-folded form.
+    fn fold_case(&mut self) {
+        match self {
+            Segment::AnyNames => {}
+            Segment::Literal(literal)
+            | Segment::Suffix(literal)
+            | Segment::Prefix(literal)
+            | Segment::Contains(literal) => *literal = fold_case(&*literal),
+            Segment::Glob(pattern) => {
+                *pattern = fold_case(pattern.iter().collect::<String>()).chars().collect()
+            }
+        }
+    }
+
+    /// Checks whether a single name matches; `**` matches any name.
+    fn matches(&self, name: &Name<'_>) -> bool {
+        match self {
+            Segment::AnyNames => true,
+            Segment::Literal(literal) => *literal == name.text,
+            Segment::Suffix(literal) => name.text.ends_with(literal.as_str()),
+            Segment::Prefix(literal) => name.text.starts_with(literal.as_str()),
+            Segment::Contains(literal) => name.text.contains(literal.as_str()),
+            Segment::Glob(pattern) => match_name(pattern, name.chars()),
+        }
+    }
+}
+
+/// A path component, split into characters on first use by a glob, once for all the
+/// patterns matched against it.
+struct Name<'a> {
+    text: Cow<'a, str>,
+    chars: OnceCell<Vec<char>>,
+}
+
+impl<'a> Name<'a> {
+    fn new(text: Cow<'a, str>) -> Name<'a> {
+        Name {
+            text,
+            chars: OnceCell::new(),
+        }
+    }
+
+    fn chars(&self) -> &[char] {
+        self.chars.get_or_init(|| self.text.chars().collect())
+    }
+
+    /// The suffixes of the name starting with a `.`, longest first.
+    fn extensions(&self) -> impl Iterator<Item = &str> {
+        let text = self.text.as_ref();
+        text.bytes().enumerate().filter(|(_, b)| *b == b'.').map(|(i, _)| &text[i..])
+    }
+}
+
+impl Automaton {
+    fn compile(rules: &[Rule]) -> Automaton {
+        let mut automaton = Automaton::default();
+        let mut starts = Vec::new();
+        for (rule_index, rule) in rules.iter().enumerate() {
+            // unanchored patterns are `**` and one name: they only look at the last name
+            if let [Segment::AnyNames, name] = &rule.segments[..] {
+                match name {
+                    Segment::Literal(literal) => {
+                        automaton.last_literals.entry(literal.clone()).or_default().push(rule_index)
+                    }
+                    Segment::Suffix(literal) if literal.starts_with('.') => automaton
+                        .last_extensions
+                        .entry(literal.clone())
+                        .or_default()
+                        .push(rule_index),
+                    _ => automaton.last_globs.push((rule_index, name.clone())),
+                }
+                continue;
+            }
+            starts.push(automaton.steps.len());
+            for segment in &rule.segments {
+                let state = automaton.steps.len();
+                if let Segment::Literal(literal) = segment {
+                    automaton.literals.entry(literal.clone()).or_default().push(state);
+                }
+                automaton.steps.push(Step::Read(segment.clone()));
+            }
+            automaton.steps.push(Step::Accept(rule_index));
+        }
+        // the closure of the starts is the step after step 0
+        let mut active = Active::new(automaton.steps.len(), 0);
+        let mut initial = Vec::new();
+        for start in starts {
+            automaton.enter(start, &mut active, &mut initial);
+        }
+        let reached = active.stamps[1].iter().enumerate().filter(|(_, stamp)| **stamp == 1);
+        automaton.initial = reached.map(|(state, _)| state).collect();
+        automaton
+    }
+
+    /// Returns the rules matching a path, in no particular order.
+    fn matches(&self, names: &[Name<'_>]) -> Vec<usize> {
+        let mut rules = Vec::new();
+        if let Some(last) = names.last() {
+            rules.extend(self.last_literals.get(last.text.as_ref()).into_iter().flatten());
+            if !self.last_extensions.is_empty() {
+                for extension in last.extensions() {
+                    rules.extend(self.last_extensions.get(extension).into_iter().flatten());
+                }
+            }
+            let globs = self.last_globs.iter().filter(|(_, glob)| glob.matches(last));
+            rules.extend(globs.map(|(rule, _)| *rule));
+        }
+        if self.steps.is_empty() {
+            return rules;
+        }
+        let mut active = Active::new(self.steps.len(), 1);
+        // states to try each name on; literal states are found through `literals` instead
+        let mut current = Vec::new();
+        for &state in &self.initial {
+            active.stamps[1][state] = 1;
+            if !self.is_literal(state) {
+                current.push(state);
+            }
+        }
+        let mut next = Vec::new();
+        for name in names {
+            for &state in &current {
+                match &self.steps[state] {
+                    Step::Read(Segment::AnyNames) => self.enter(state, &mut active, &mut next),
+                    Step::Read(glob) if glob.matches(name) => {
+                        self.enter(state + 1, &mut active, &mut next)
+                    }
+                    _ => {}
+                }
+            }
+            for &state in self.literals.get(name.text.as_ref()).into_iter().flatten() {
+                if active.contains(state) {
+                    self.enter(state + 1, &mut active, &mut next);
+                }
+            }
+            active.step += 1;
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        rules.extend(current.iter().filter_map(|&state| match self.steps[state] {
+            Step::Accept(rule) => Some(rule),
+            Step::Read(_) => None,
+        }));
+        rules
+    }
+
+    /// Activates a state for the next name and, past any `**`, the states after it.
+    fn enter(&self, state: usize, active: &mut Active, list: &mut Vec<usize>) {
+        let step = active.step + 1;
+        let stamp = &mut active.stamps[step % 2][state];
+        if *stamp != step {
+            *stamp = step;
+            if !self.is_literal(state) {
+                list.push(state);
+            }
+        }
+        if let Step::Read(Segment::AnyNames) = self.steps[state] {
+            self.enter(state + 1, active, list);
+        }
+    }
+
+    fn is_literal(&self, state: usize) -> bool {
+        matches!(self.steps[state], Step::Read(Segment::Literal(_)))
+    }
+}
+
+/// States of an [`Automaton`] active at a step of a run, by the last step at which each
+/// was activated, with one array for even and one for odd steps, so nothing needs clearing
+/// between steps.
+struct Active {
+    stamps: [Vec<usize>; 2],
+    step: usize,
+}
+
+impl Active {
+    fn new(states: usize, step: usize) -> Active {
+        Active {
+            stamps: [vec![0; states], vec![0; states]],
+            step,
+        }
+    }
+
+    fn contains(&self, state: usize) -> bool {
+        self.stamps[self.step % 2][state] == self.step
+    }
+}
+
+/// Matches a single name against a pattern with `*`, `?` and `[...]`.
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') if class_end(&pattern[p..]).is_some() => match_class(&pattern[p..], name[n]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == name[n]).then_some(2),
+            Some(&c) => (c == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            (None, Some((star, tried))) => {
+                p = star;
+                n = tried + 1;
+                backtrack = Some((star, tried + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Returns the position of the `]` closing the class at the start of `pattern`.
+/// A `]` right after the opening `[` (or `[!`) belongs to the class.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let first = if matches!(pattern.get(1), Some('!' | '^')) {
+        3
+    } else {
+        2
+    };
+    pattern
+        .iter()
+        .skip(first)
+        .position(|&c| c == ']')
+        .map(|i| i + first)
+}
+
+/// Matches a character against the class at the start of `pattern`; returns the length of
+/// the class if it matches.
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let close = class_end(pattern)?;
+    let negated = matches!(pattern[1], '!' | '^');
+    let items = &pattern[if negated { 2 } else { 1 }..close];
+    let mut found = false;
+    let mut i = 0;
+    while i < items.len() {
+        if i + 2 < items.len() && items[i + 1] == '-' {
+            found |= (items[i]..=items[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= items[i] == c;
+            i += 1;
+        }
+    }
+    (found != negated).then_some(close + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::rules::{Name, RuleSet, Segment};
+    use crate::ReadDir;
+    use std::sync::Arc;
+
+    #[test]
+    fn rule_set_matching() {
+        let match_name =
+            |pattern, name: &str| Segment::parse(pattern).matches(&Name::new(name.into()));
+        assert!(match_name("*.log", "a.log"));
+        assert!(!match_name("*.log", "a.log.gz"));
+        assert!(match_name("a?c[0-9][!x]", "abc5y"));
+        assert!(!match_name("a?c[0-9][!x]", "abc5x"));
+        assert!(match_name("[ab", "[ab"));
+        assert!(match_name("[]a]", "]"));
+        assert!(match_name("*.log", ".log"));
+        assert!(match_name("core*", "core.1"));
+        assert!(!match_name("core*", "a.core"));
+        assert!(match_name("*~*", "a~b"));
+        assert!(match_name("*", ""));
+        assert_eq!(Segment::parse("*.log"), Segment::Suffix(".log".to_string()));
+        assert_eq!(Segment::parse("*.l?g"), Segment::Glob("*.l?g".chars().collect()));
+
+        let rules = RuleSet::parse(
+            "# build output\n\
+             *.log\n\
+             !keep.log\n\
+             build/\n\
+             /target\n\
+             docs/**/*.tmp\n\
+             - *.bak\n",
+        );
+        assert!(!rules.is_included("x/a.log", false));
+        assert!(rules.is_included("x/keep.log", false));
+        assert!(!rules.is_included("src/build", true));
+        assert!(rules.is_included("src/build", false));
+        assert!(!rules.is_included("target", true));
+        assert!(rules.is_included("src/target", true));
+        assert!(!rules.is_included("docs/a/b/c.tmp", false));
+        assert!(!rules.is_included("docs/c.tmp", false));
+        assert!(rules.is_included("c.tmp", false));
+        assert!(!rules.is_included("a.bak", false));
+        assert!(rules.is_included("main.rs", false));
+        assert!(RuleSet::parse("").is_included("a.log", false));
+
+        // extensions found by lookup, also with more than one dot
+        let rules = RuleSet::parse("*.tar.gz\n*.gz\n!keep*.gz\n*.1\n");
+        assert!(!rules.is_included("a.tar.gz", false));
+        assert!(!rules.is_included("a.b.gz", false));
+        assert!(rules.is_included("keep.tar.gz", false));
+        assert!(!rules.is_included("x/1.2.1", false));
+        assert!(rules.is_included("gz", false));
+
+        // `**` matching nothing, and literals after wildcards
+        let rules = RuleSet::parse("a/**/b/c\n/x/*/*.rs\n*/d/");
+        assert!(!rules.is_included("a/b/c", false));
+        assert!(!rules.is_included("a/1/2/b/c", false));
+        assert!(rules.is_included("a/b/c/d", false));
+        assert!(!rules.is_included("x/y/z.rs", false));
+        assert!(rules.is_included("x/z.rs", false));
+        assert!(!rules.is_included("q/d", true));
+        assert!(rules.is_included("d", true));
+
+        // ignoring case, in literals, lookups and globs
+        let text = "Makefile\n*.LOG\n/Docs/*.md\n[A-C]*.txt\n";
+        let rules = RuleSet::parse(text);
+        assert!(rules.is_included("makefile", false));
+        assert!(rules.is_included("a.log", false));
+        let rules = rules.ignore_case();
+        assert!(!rules.is_included("x/MAKEFILE", false));
+        assert!(!rules.is_included("a.log", false));
+        assert!(!rules.is_included("a.Log", false));
+        assert!(!rules.is_included("docs/README.MD", false));
+        assert!(!rules.is_included("b1.TXT", false));
+        assert!(rules.is_included("d1.txt", false));
+        assert_eq!(rules.clone().ignore_case(), rules);
+    }
+
+    #[test]
+    fn read_dir_with_rules() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"")
+            .file("a.log", b"")
+            .file("build/out.txt", b"")
+            .file("src/build.txt", b"")
+            .build()
+            .unwrap();
+        let mut rd = ReadDir::try_new(tree.path()).unwrap();
+        rd.rules = Some(Arc::new(RuleSet::parse("*.log\nbuild/")));
+        let mut paths: Vec<_> = rd.map(|entry| entry.into_path()).collect();
+        paths.sort();
+        assert_eq!(paths, [tree.join("a.txt"), tree.join("src/build.txt")]);
+    }
+}
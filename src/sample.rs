@@ -0,0 +1,103 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+
+use crate::entry::Entry;
+use crate::result::Result;
+use crate::ReadDir;
+
+/// How [`sample`] picks entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// The first entries in traversal order; stops as soon as enough were found.
+    First,
+    /// A uniformly random selection of the whole tree (reservoir sampling);
+    /// needs a full scan, but keeps only `n` entries in memory.
+    Reservoir,
+    /// At most this many entries of each directory, in traversal order, so a few large
+    /// directories do not crowd out the rest; stops as soon as enough were found.
+    PerDirectory(usize),
+}
+
+/// Returns up to `n` entries of a tree, e.g. for a quick preview of a huge tree.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `n` - maximum number of entries.
+/// * `strategy` - how entries are picked.
+pub fn sample<P: AsRef<Path>>(root: P, n: usize, strategy: Sampling) -> Result<Vec<Entry>> {
+    let mut rd = ReadDir::try_new(root)?;
+    rd.is_lazy = true;
+    let entries = match strategy {
+        Sampling::First => rd.take(n).collect(),
+        Sampling::Reservoir => reservoir(rd, n),
+        Sampling::PerDirectory(quota) => {
+            let mut taken: HashMap<PathBuf, usize> = HashMap::new();
+            rd.filter(|entry| {
+                let parent = entry.path().parent().unwrap_or(Path::new(""));
+                let count = taken.entry(parent.to_path_buf()).or_default();
+                *count += 1;
+                *count <= quota
+            })
+            .take(n)
+            .collect()
+        }
+    };
+    Ok(entries)
+}
+
+/// Algorithm R: the i-th entry replaces a random slot with probability n / i.
+fn reservoir<I: Iterator<Item = Entry>>(entries: I, n: usize) -> Vec<Entry> {
+    let mut state = RandomState::new().hash_one(0u64) | 1;
+    let mut picked = Vec::with_capacity(n);
+    for (i, entry) in entries.enumerate() {
+        if picked.len() < n {
+            picked.push(entry);
+            continue;
+        }
+        // xorshift64*
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        let slot = (state.wrapping_mul(0x2545_F491_4F6C_DD1D) % (i as u64 + 1)) as usize;
+        if slot < n {
+            picked[slot] = entry;
+        }
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::sample::{sample, Sampling};
+    use std::collections::HashSet;
+
+    #[test]
+    fn sample_strategies() {
+        let mut builder = TreeBuilder::new();
+        for i in 0..10 {
+            builder = builder.file(format!("big/{}.txt", i), b"");
+        }
+        let tree = builder
+            .file("small/a.txt", b"")
+            .file("small/b.txt", b"")
+            .build()
+            .unwrap();
+
+        let first = sample(tree.path(), 3, Sampling::First).unwrap();
+        assert_eq!(first.len(), 3);
+
+        let random = sample(tree.path(), 5, Sampling::Reservoir).unwrap();
+        let unique: HashSet<_> = random.iter().map(|entry| entry.path()).collect();
+        assert_eq!((random.len(), unique.len()), (5, 5));
+
+        let quota = sample(tree.path(), 100, Sampling::PerDirectory(1)).unwrap();
+        assert_eq!(quota.len(), 2);
+        assert!(quota
+            .iter()
+            .any(|entry| entry.path().starts_with(tree.join("small"))));
+    }
+}
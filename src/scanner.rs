@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::journal::Journal;
+use crate::result::Result;
+use crate::watch::{WatchEvent, Watcher};
+
+struct Inner {
+    watcher: Watcher,
+    subscribers: Vec<mpsc::Sender<WatchEvent>>,
+}
+
+impl Inner {
+    /// Rescans the tree and sends the changes to the subscribers still listening.
+    fn poll(&mut self) -> Result<Vec<WatchEvent>> {
+        let events = self.watcher.poll()?;
+        self.subscribers
+            .retain(|tx| events.iter().all(|event| tx.send(event.clone()).is_ok()));
+        Ok(events)
+    }
+}
+
+/// One entry point for keeping track of a tree: the current listing ([`Scanner::scan`]),
+/// what changed ([`Scanner::changes`]) and a stream of changes ([`Scanner::subscribe`]),
+/// optionally kept up to date in the background ([`Scanner::watch`]) and recorded in a
+/// [`Journal`]. Every rescan is incremental: it only reports what differs from the
+/// previous one, and each change reaches every subscriber once.
+pub struct Scanner {
+    root: PathBuf,
+    inner: Arc<Mutex<Inner>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Scanner {
+    /// Creates a scanner of `root`, scanning it once.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Scanner> {
+        let watcher = Watcher::new(root)?;
+        Ok(Scanner {
+            root: watcher.root().to_path_buf(),
+            inner: Arc::new(Mutex::new(Inner {
+                watcher,
+                subscribers: Vec::new(),
+            })),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        })
+    }
+
+    /// Records all changes found by the scanner in a journal.
+    ///
+    /// # Arguments:
+    ///
+    /// * `journal` - journal to append the changes to.
+    pub fn with_journal(self, journal: Journal) -> Scanner {
+        self.lock().watcher.set_journal(journal);
+        self
+    }
+
+    /// Returns a root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Rescans the tree and returns the paths of all its entries (except directories),
+    /// sorted. Changes found on the way go to the subscribers.
+    pub fn scan(&self) -> Result<Vec<PathBuf>> {
+        let mut inner = self.lock();
+        inner.poll()?;
+        Ok(inner.watcher.paths())
+    }
+
+    /// Rescans the tree and returns the changes since the previous rescan, sorted by path.
+    /// They also go to the subscribers.
+    pub fn changes(&self) -> Result<Vec<WatchEvent>> {
+        self.lock().poll()
+    }
+
+    /// Returns a receiver of all changes found from now on, by any rescan.
+    pub fn subscribe(&self) -> mpsc::Receiver<WatchEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.lock().subscribers.push(tx);
+        rx
+    }
+
+    /// Starts rescanning the tree in a background thread every `interval`, so subscribers
+    /// are told of changes without calls to `scan` or `changes`. Rescans that fail
+    /// (e.g. while the root is briefly missing) are retried at the next interval.
+    /// The thread stops when the scanner is dropped; calling `watch` again changes
+    /// the interval.
+    ///
+    /// # Arguments:
+    ///
+    /// * `interval` - time between rescans.
+    pub fn watch(&mut self, interval: Duration) {
+        self.stop_watching();
+        self.stop = Arc::new(AtomicBool::new(false));
+        let (inner, stop) = (Arc::clone(&self.inner), Arc::clone(&self.stop));
+        self.thread = Some(thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let _ = inner.lock().unwrap_or_else(|e| e.into_inner()).poll();
+            }
+        }));
+    }
+
+    fn stop_watching(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // a panic in a subscriber's thread does not corrupt the state
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Scanner {
+    /// Stops the background rescans.
+    fn drop(&mut self) {
+        self.stop_watching();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::scanner::Scanner;
+    use crate::{Journal, WatchEvent, WatchEventKind};
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn scan_changes_subscribe() {
+        let tree = TreeBuilder::new().file("data/a.txt", b"1").build().unwrap();
+        let journal = Journal::open(tree.join("journal")).unwrap();
+        let mut scanner = Scanner::new(tree.join("data"))
+            .unwrap()
+            .with_journal(journal);
+        let events = scanner.subscribe();
+        assert_eq!(scanner.scan().unwrap(), [tree.join("data/a.txt")]);
+
+        fs::write(tree.join("data/b.txt"), b"2").unwrap();
+        let created = WatchEvent {
+            path: tree.join("data/b.txt"),
+            kind: WatchEventKind::Created,
+        };
+        assert_eq!(scanner.changes().unwrap(), vec![created.clone()]);
+        assert!(scanner.changes().unwrap().is_empty());
+        assert_eq!(events.try_recv().unwrap(), created);
+
+        scanner.watch(Duration::from_millis(10));
+        fs::remove_file(tree.join("data/a.txt")).unwrap();
+        let removed = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(removed.path, tree.join("data/a.txt"));
+        assert_eq!(removed.kind, WatchEventKind::Removed);
+        drop(scanner);
+
+        let journal = Journal::open(tree.join("journal")).unwrap();
+        assert_eq!(journal.last_seq(), 2);
+    }
+}
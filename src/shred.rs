@@ -0,0 +1,115 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::BuildHasher;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::atomic::temp_path_for;
+use crate::result::Result;
+use crate::ReadDir;
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Pseudo-random generator (xorshift64*) used to produce overwrite patterns.
+struct Noise(u64);
+
+impl Noise {
+    fn new() -> Noise {
+        Noise(RandomState::new().hash_one(0u64) | 1)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            let bytes = self.0.wrapping_mul(0x2545_F491_4F6C_DD1D).to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Overwrites the contents of a file `passes` times with random data (and a final pass of zeros),
+/// flushing each pass to disk, then renames and removes it. Symbolic links are removed,
+/// never followed.
+///
+/// Overwriting in place does not guarantee that the data is unrecoverable: SSDs and flash
+/// media remap writes (wear levelling), and copy-on-write or journaling filesystems
+/// (Btrfs, ZFS, APFS, ext4 with `data=journal`), snapshots and backups may keep old blocks.
+/// Use full-disk encryption when this matters.
+///
+/// # Arguments:
+///
+/// * `path` - file to shred.
+/// * `passes` - number of random overwrite passes.
+pub fn shred<P: AsRef<Path>>(path: P, passes: usize) -> Result<()> {
+    let path = path.as_ref();
+    if fs::symlink_metadata(path)?.is_file() {
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        let len = file.metadata()?.len();
+        let mut noise = Noise::new();
+        let mut buf = vec![0u8; BUF_SIZE];
+        for pass in 0..=passes {
+            file.seek(SeekFrom::Start(0))?;
+            let mut remaining = len;
+            while remaining > 0 {
+                let n = remaining.min(BUF_SIZE as u64) as usize;
+                if pass < passes {
+                    noise.fill(&mut buf[..n]);
+                } else {
+                    buf[..n].fill(0);
+                }
+                file.write_all(&buf[..n])?;
+                remaining -= n as u64;
+            }
+            file.sync_data()?;
+        }
+        file.set_len(0)?;
+        file.sync_all()?;
+    }
+    // hide the original name before unlinking
+    let hidden = temp_path_for(path);
+    fs::rename(path, &hidden)?;
+    fs::remove_file(hidden)?;
+    Ok(())
+}
+
+/// Shreds every file under `dir` with [`shred`] and removes the directory tree.
+///
+/// # Arguments:
+///
+/// * `dir` - directory to shred.
+/// * `passes` - number of random overwrite passes.
+pub fn shred_dir<P: AsRef<Path>>(dir: P, passes: usize) -> Result<()> {
+    let mut rd = ReadDir::try_new(&dir)?;
+    rd.is_lazy = true;
+    for entry in rd {
+        shred(entry.path(), passes)?;
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::shred::{shred, shred_dir};
+    use std::fs;
+
+    #[test]
+    fn shred_files() {
+        let tree = TreeBuilder::new()
+            .file("secret.txt", vec![b'x'; 100_000])
+            .file("sub/other.txt", b"secret")
+            .build()
+            .unwrap();
+        let dir = tree.path();
+
+        shred(dir.join("secret.txt"), 2).unwrap();
+        assert!(!dir.join("secret.txt").exists());
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+
+        shred_dir(dir, 1).unwrap();
+        assert!(!dir.exists());
+    }
+}
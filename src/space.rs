@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::fold::walk_fold;
+use crate::format::{format_count, format_size};
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+
+/// Space and inodes of the filesystem holding a path, as returned by [`space_info`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpaceInfo {
+    /// Size of the filesystem, in bytes.
+    pub total: u64,
+    /// Free bytes, including those reserved for the superuser.
+    pub free: u64,
+    /// Free bytes available to unprivileged users.
+    pub available: u64,
+    /// Number of inodes, or `None` if the filesystem has no fixed number (e.g. NTFS, btrfs).
+    pub inodes_total: Option<u64>,
+    /// Number of free inodes, or `None` if the filesystem has no fixed number.
+    pub inodes_free: Option<u64>,
+}
+
+impl SpaceInfo {
+    /// Returns the number of inodes in use, if the filesystem has a fixed number.
+    pub fn inodes_used(&self) -> Option<u64> {
+        Some(self.inodes_total? - self.inodes_free?.min(self.inodes_total?))
+    }
+
+    /// Checks whether files can not be created although there is space left, because all
+    /// the inodes are taken: "disk full" errors with free space are often this.
+    pub fn inodes_exhausted(&self) -> bool {
+        self.inodes_free == Some(0) && self.inodes_total.is_some_and(|total| total > 0)
+    }
+}
+
+impl fmt::Display for SpaceInfo {
+    /// Formats the space for people, e.g. `1.2 GiB of 50.0 GiB available, 1,234 of 3,276,800
+    /// inodes free`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} available",
+            format_size(self.available),
+            format_size(self.total)
+        )?;
+        match (self.inodes_free, self.inodes_total) {
+            (Some(free), Some(total)) if total > 0 => write!(
+                f,
+                ", {} of {} inodes free",
+                format_count(free),
+                format_count(total)
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the space and inodes of the filesystem holding `path`: `statvfs` on Linux,
+/// `statfs` on macOS and `GetDiskFreeSpaceExW` on Windows, which reports no inodes.
+/// Fails with an error of kind `io::ErrorKind::Unsupported` on other platforms.
+pub fn space_info<P: AsRef<Path>>(path: P) -> Result<SpaceInfo> {
+    Ok(sys::space(path.as_ref())?)
+}
+
+/// Inode usage of a tree, as computed by [`usage_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageReport {
+    /// Space and inodes of the filesystem holding the tree.
+    pub space: SpaceInfo,
+    /// Entries of the tree below the root, of any kind; each takes an inode, hard links
+    /// aside.
+    pub entries: u64,
+    /// Entries directly in each directory of the tree, the root included.
+    pub per_dir: BTreeMap<PathBuf, u64>,
+}
+
+impl UsageReport {
+    /// Returns the `n` directories with the most entries, most first.
+    pub fn largest_dirs(&self, n: usize) -> Vec<(&Path, u64)> {
+        let mut dirs: Vec<_> = self
+            .per_dir
+            .iter()
+            .map(|(dir, entries)| (dir.as_path(), *entries))
+            .collect();
+        dirs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        dirs.truncate(n);
+        dirs
+    }
+
+    /// Returns the share of the inodes in use on the filesystem taken by the tree, from 0
+    /// to 1, if the filesystem has a fixed number of inodes.
+    pub fn inode_share(&self) -> Option<f64> {
+        let used = self.space.inodes_used().filter(|&used| used > 0)?;
+        Some((self.entries as f64 / used as f64).min(1.0))
+    }
+}
+
+impl fmt::Display for UsageReport {
+    /// Formats the report for people, e.g. `12,345 entries in 67 directories; 1.2 GiB of
+    /// 50.0 GiB available, 1,234 of 3,276,800 inodes free`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries in {} directories; {}",
+            format_count(self.entries),
+            format_count(self.per_dir.len() as u64),
+            self.space
+        )
+    }
+}
+
+struct DirCount {
+    dir: PathBuf,
+    entries: u64,
+    per_dir: BTreeMap<PathBuf, u64>,
+}
+
+/// Counts the entries of every directory under `root`, to find where the inodes of a
+/// filesystem went, along with the [`space_info`] of the filesystem.
+/// Symbolic links are counted, not followed. Directories are read in parallel.
+pub fn usage_report<P: AsRef<Path>>(root: P) -> Result<UsageReport> {
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let space = space_info(&root)?;
+    let counted = walk_fold(
+        &root,
+        |dir| DirCount {
+            dir: dir.to_path_buf(),
+            entries: 0,
+            per_dir: BTreeMap::new(),
+        },
+        |mut count, _| {
+            count.entries += 1;
+            count
+        },
+        |mut parent, mut child| {
+            // the subdirectory is an entry of its parent
+            parent.entries += 1;
+            parent.per_dir.append(&mut child.per_dir);
+            parent.per_dir.insert(child.dir, child.entries);
+            parent
+        },
+    )?;
+    let mut per_dir = counted.per_dir;
+    per_dir.insert(counted.dir, counted.entries);
+    Ok(UsageReport {
+        space,
+        entries: per_dir.values().sum(),
+        per_dir,
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_char, c_int, c_ulong, CString};
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use crate::space::SpaceInfo;
+
+    /// `struct statvfs64`, as laid out by glibc and musl.
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        f_blocks: u64,
+        f_bfree: u64,
+        f_bavail: u64,
+        f_files: u64,
+        f_ffree: u64,
+        f_favail: u64,
+        f_fsid: c_ulong,
+        #[cfg(target_pointer_width = "32")]
+        f_unused: c_int,
+        f_flag: c_ulong,
+        f_namemax: c_ulong,
+        f_spare: [c_int; 6],
+    }
+
+    extern "C" {
+        fn statvfs64(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+
+    pub(super) fn space(path: &Path) -> io::Result<SpaceInfo> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut buf = MaybeUninit::<StatVfs>::uninit();
+        // SAFETY: `path` is a valid C string and `buf` is large enough for the result.
+        if unsafe { statvfs64(path.as_ptr(), buf.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: statvfs64 succeeded and filled `buf`.
+        let buf = unsafe { buf.assume_init() };
+        // c_ulong is 32 bits wide on 32-bit targets
+        #[allow(clippy::unnecessary_cast)]
+        let unit = buf.f_frsize as u64;
+        // filesystems allocating inodes on demand report none
+        let inodes = |count: u64| (buf.f_files > 0).then_some(count);
+        Ok(SpaceInfo {
+            total: buf.f_blocks * unit,
+            free: buf.f_bfree * unit,
+            available: buf.f_bavail * unit,
+            inodes_total: inodes(buf.f_files),
+            inodes_free: inodes(buf.f_ffree),
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::{c_char, c_int, CString};
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use crate::space::SpaceInfo;
+
+    /// `struct statfs` with 64-bit inodes.
+    #[repr(C)]
+    struct StatFs {
+        f_bsize: u32,
+        f_iosize: i32,
+        f_blocks: u64,
+        f_bfree: u64,
+        f_bavail: u64,
+        f_files: u64,
+        f_ffree: u64,
+        f_fsid: [i32; 2],
+        f_owner: u32,
+        f_type: u32,
+        f_flags: u32,
+        f_fssubtype: u32,
+        f_fstypename: [c_char; 16],
+        f_mntonname: [c_char; 1024],
+        f_mntfromname: [c_char; 1024],
+        f_flags_ext: u32,
+        f_reserved: [u32; 7],
+    }
+
+    extern "C" {
+        #[cfg_attr(target_arch = "x86_64", link_name = "statfs$INODE64")]
+        fn statfs(path: *const c_char, buf: *mut StatFs) -> c_int;
+    }
+
+    pub(super) fn space(path: &Path) -> io::Result<SpaceInfo> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut buf = MaybeUninit::<StatFs>::uninit();
+        // SAFETY: `path` is a valid C string and `buf` is large enough for the result.
+        if unsafe { statfs(path.as_ptr(), buf.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: statfs succeeded and filled `buf`.
+        let buf = unsafe { buf.assume_init() };
+        let unit = buf.f_bsize as u64;
+        let inodes = |count: u64| (buf.f_files > 0).then_some(count);
+        Ok(SpaceInfo {
+            total: buf.f_blocks * unit,
+            free: buf.f_bfree * unit,
+            available: buf.f_bavail * unit,
+            inodes_total: inodes(buf.f_files),
+            inodes_free: inodes(buf.f_ffree),
+        })
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    use crate::space::SpaceInfo;
+
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            dir: *const u16,
+            available: *mut u64,
+            total: *mut u64,
+            free: *mut u64,
+        ) -> i32;
+    }
+
+    pub(super) fn space(path: &Path) -> io::Result<SpaceInfo> {
+        let dir = if path.is_dir() { path } else { path.parent().unwrap_or(path) };
+        let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+        let (mut available, mut total, mut free) = (0, 0, 0);
+        // SAFETY: `wide` is NUL-terminated and the outputs are valid.
+        if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, &mut free) }
+            == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // NTFS and ReFS allocate file records on demand
+        Ok(SpaceInfo {
+            total,
+            free,
+            available,
+            inodes_total: None,
+            inodes_free: None,
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    use crate::space::SpaceInfo;
+
+    pub(super) fn space(_path: &Path) -> io::Result<SpaceInfo> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "space information is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::space::{space_info, usage_report, SpaceInfo};
+
+    #[test]
+    fn inode_usage() {
+        let space = SpaceInfo {
+            total: 10 << 30,
+            free: 4 << 30,
+            available: 3 << 30,
+            inodes_total: Some(1000),
+            inodes_free: Some(0),
+        };
+        assert_eq!(space.inodes_used(), Some(1000));
+        assert!(space.inodes_exhausted());
+        assert_eq!(
+            space.to_string(),
+            "3.0 GiB of 10.0 GiB available, 0 of 1,000 inodes free"
+        );
+
+        let tree = TreeBuilder::new()
+            .file("1.txt", b"")
+            .file("a/2.txt", b"")
+            .file("a/3.txt", b"")
+            .file("a/b/4.txt", b"")
+            .symlink("link", "1.txt")
+            .build()
+            .unwrap();
+        if cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+            let space = space_info(tree.path()).unwrap();
+            assert!(space.total >= space.free && space.free >= space.available);
+            let report = usage_report(tree.path()).unwrap();
+            assert_eq!(report.entries, 7);
+            assert_eq!(report.per_dir.len(), 3);
+            assert_eq!(report.per_dir[&tree.join("a")], 3);
+            assert_eq!(report.largest_dirs(1), [(tree.path(), 3)]);
+        }
+    }
+}
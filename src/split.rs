@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+
+use crate::entry::Entry;
+use crate::ReadDir;
+
+/// One of several consumers of a traversal, as returned by [`ReadDir::split`]: yields the
+/// entries not taken by the others, so each entry goes to one consumer. Clones are more
+/// consumers of the same traversal, which stops once all of them are dropped.
+///
+/// The consumers take turns at the shared iterator; in lazy mode the one taking its turn
+/// also reads the directories, so scanning is not parallel then.
+#[derive(Clone)]
+pub struct ReadDirSplit {
+    inner: Arc<Mutex<ReadDir>>,
+}
+
+impl ReadDirSplit {
+    pub(crate) fn new(rd: ReadDir) -> ReadDirSplit {
+        ReadDirSplit {
+            inner: Arc::new(Mutex::new(rd)),
+        }
+    }
+}
+
+impl Iterator for ReadDirSplit {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // a consumer panicking in between calls leaves the iterator consistent
+        let mut rd = self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        rd.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::result::{Error, ErrorKind};
+    use crate::ReadDir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn split_consumers() {
+        let mut builder = TreeBuilder::new();
+        for i in 0..50 {
+            builder = builder.file(format!("d{}/f{}.txt", i % 7, i), "");
+        }
+        let tree = builder.build().unwrap();
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        let mut expected: Vec<_> = rd.map(|e| e.into_path()).collect();
+        expected.sort();
+
+        for is_multithreaded in [false, true] {
+            let mut rd = ReadDir::try_new(tree.path()).unwrap();
+            rd.is_multithreaded = is_multithreaded;
+            let handles: Vec<thread::JoinHandle<Vec<_>>> = rd
+                .split(3)
+                .into_iter()
+                .map(|split| thread::spawn(move || split.map(|e| e.into_path()).collect()))
+                .collect();
+            let mut paths: Vec<_> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+            paths.sort();
+            assert_eq!(paths, expected);
+        }
+
+        let seen = Mutex::new(Vec::new());
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        rd.for_each_parallel(4, |entry| {
+            seen.lock().unwrap().push(entry.into_path());
+            Ok(())
+        })
+        .unwrap();
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(seen, expected);
+
+        // the first error stops the consumers and is returned
+        let calls = AtomicUsize::new(0);
+        let rd = ReadDir::try_new(tree.path()).unwrap();
+        let err = rd
+            .for_each_parallel(2, |_| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Err(Error::new(ErrorKind::File, "upload failed"))
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::File);
+        assert!(calls.load(Ordering::Relaxed) <= 2);
+    }
+}
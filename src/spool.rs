@@ -0,0 +1,169 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::temp_path_for;
+
+/// A temporary file kept in memory while it is small. Once written past a threshold, its
+/// contents move to an anonymous file on disk and later reads and writes go there, so
+/// callers can handle small and large data alike without ever holding large data in memory.
+///
+/// The file on disk has no name: on Unix it is removed right after being created, on
+/// Windows it is deleted when closed. Either way nothing is left behind, even after a crash.
+#[derive(Debug)]
+pub struct SpooledTempFile {
+    threshold: usize,
+    dir: Option<PathBuf>,
+    inner: Spool,
+}
+
+#[derive(Debug)]
+enum Spool {
+    Memory(Cursor<Vec<u8>>),
+    Disk(File),
+}
+
+impl SpooledTempFile {
+    /// Creates an empty file held in memory until it exceeds `threshold` bytes, then moved
+    /// to the system temp directory.
+    pub fn new(threshold: usize) -> SpooledTempFile {
+        SpooledTempFile {
+            threshold,
+            dir: None,
+            inner: Spool::Memory(Cursor::new(Vec::new())),
+        }
+    }
+
+    /// Like [`SpooledTempFile::new`], with the file on disk created in `dir`.
+    ///
+    /// # Arguments:
+    ///
+    /// * `dir` - directory of the file on disk, e.g. on a disk with more space.
+    /// * `threshold` - maximum size kept in memory, in bytes.
+    pub fn new_in<P: AsRef<Path>>(dir: P, threshold: usize) -> SpooledTempFile {
+        SpooledTempFile {
+            dir: Some(dir.as_ref().to_path_buf()),
+            ..SpooledTempFile::new(threshold)
+        }
+    }
+
+    /// Checks whether the contents have moved to disk.
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.inner, Spool::Disk(_))
+    }
+
+    /// Moves the contents to disk now, keeping the current position. Does nothing if they
+    /// are already there.
+    pub fn spill(&mut self) -> io::Result<()> {
+        let cursor = match &self.inner {
+            Spool::Memory(cursor) => cursor,
+            Spool::Disk(_) => return Ok(()),
+        };
+        let dir = self.dir.clone().unwrap_or_else(env::temp_dir);
+        let mut file = create_anonymous(&dir)?;
+        file.write_all(cursor.get_ref())?;
+        file.seek(SeekFrom::Start(cursor.position()))?;
+        self.inner = Spool::Disk(file);
+        Ok(())
+    }
+}
+
+/// Creates a file for reading and writing that is removed when closed.
+fn create_anonymous(dir: &Path) -> io::Result<File> {
+    let path = temp_path_for(&dir.join("spool"));
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const GENERIC_READ: u32 = 0x8000_0000;
+        const GENERIC_WRITE: u32 = 0x4000_0000;
+        const DELETE: u32 = 0x0001_0000;
+        const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x0400_0000;
+        // deleting on close needs the right to delete
+        options
+            .access_mode(GENERIC_READ | GENERIC_WRITE | DELETE)
+            .custom_flags(FILE_FLAG_DELETE_ON_CLOSE);
+    }
+    let file = options.open(&path)?;
+    if cfg!(unix) {
+        fs::remove_file(&path)?;
+    }
+    Ok(file)
+}
+
+impl Read for SpooledTempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Spool::Memory(cursor) => cursor.read(buf),
+            Spool::Disk(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for SpooledTempFile {
+    /// Writes to memory, or to disk once the write would take the file past the threshold.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Spool::Memory(cursor) = &self.inner {
+            let end = cursor.position() as usize + buf.len();
+            if end > self.threshold {
+                self.spill()?;
+            }
+        }
+        match &mut self.inner {
+            Spool::Memory(cursor) => cursor.write(buf),
+            Spool::Disk(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Spool::Memory(_) => Ok(()),
+            Spool::Disk(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for SpooledTempFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.inner {
+            Spool::Memory(cursor) => cursor.seek(pos),
+            Spool::Disk(file) => file.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::spool::SpooledTempFile;
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn spools_to_disk_past_threshold() {
+        let tree = TreeBuilder::new().dir("tmp").build().unwrap();
+        let mut spool = SpooledTempFile::new_in(tree.join("tmp"), 8);
+        spool.write_all(b"small").unwrap();
+        assert!(!spool.is_spilled());
+        spool.write_all(b" and large").unwrap();
+        assert!(spool.is_spilled());
+        if cfg!(unix) {
+            assert_eq!(fs::read_dir(tree.join("tmp")).unwrap().count(), 0);
+        }
+
+        spool.seek(SeekFrom::Start(6)).unwrap();
+        let mut rest = String::new();
+        spool.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "and large");
+
+        let mut small = SpooledTempFile::new(1024);
+        small.write_all(b"abc").unwrap();
+        small.rewind().unwrap();
+        let mut contents = Vec::new();
+        small.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"abc");
+        assert!(!small.is_spilled());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::format::{format_count, format_duration, format_rate, format_size};
+
+/// Part of an operation timed separately in [`IoStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    /// Listing directories.
+    Scan,
+    /// Reading, writing or hashing file contents.
+    Data,
+    /// Creating directories and copying permissions, ACLs, streams and attributes.
+    Metadata,
+    /// Flushing to disk.
+    Sync,
+    /// Checking copies.
+    Verify,
+}
+
+/// I/O statistics of an operation, e.g. as returned by [`copy_dir`], for logging and
+/// comparing runs.
+///
+/// [`copy_dir`]: crate::copy_dir
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// Bytes of file contents read.
+    pub bytes_read: u64,
+    /// Bytes of file contents written.
+    pub bytes_written: u64,
+    /// Files processed, symbolic links included.
+    pub files: u64,
+    /// Time from the start to the end of the operation.
+    pub wall_time: Duration,
+    /// Time spent in each phase. Phases run by several threads at once are summed over the
+    /// threads, so they may add up to more than the wall time.
+    pub phases: BTreeMap<Phase, Duration>,
+}
+
+impl IoStats {
+    /// Returns the bytes read or written per second of wall time, whichever is larger.
+    pub fn throughput(&self) -> f64 {
+        let bytes = self.bytes_read.max(self.bytes_written);
+        bytes as f64 / self.wall_time.as_secs_f64()
+    }
+
+    /// Returns the time spent in a phase, zero if it did not occur.
+    pub fn phase(&self, phase: Phase) -> Duration {
+        self.phases.get(&phase).copied().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for IoStats {
+    /// Formats the statistics for people, e.g. `1,234 files, 1.2 GiB read, 1.2 GiB written
+    /// in 12.3 s (100.0 MiB/s)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} read, {} written in {} ({})",
+            format_count(self.files),
+            format_size(self.bytes_read),
+            format_size(self.bytes_written),
+            format_duration(self.wall_time),
+            format_rate(self.throughput())
+        )
+    }
+}
+
+/// Collects [`IoStats`] while an operation runs, from any number of threads.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    started: Instant,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    files: AtomicU64,
+    phases: Mutex<BTreeMap<Phase, Duration>>,
+}
+
+impl Recorder {
+    /// Starts the clock of the wall time.
+    pub(crate) fn start() -> Recorder {
+        Recorder {
+            started: Instant::now(),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            files: AtomicU64::new(0),
+            phases: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Runs `step`, adding the time it takes to `phase`.
+    pub(crate) fn time<T, F: FnOnce() -> T>(&self, phase: Phase, step: F) -> T {
+        let started = Instant::now();
+        let result = step();
+        *self.phases.lock().unwrap().entry(phase).or_default() += started.elapsed();
+        result
+    }
+
+    /// Returns the statistics so far, with the wall time until now.
+    pub(crate) fn stats(&self) -> IoStats {
+        self.stats_at(self.started.elapsed())
+    }
+
+    /// Returns the statistics so far, with the given wall time.
+    pub(crate) fn stats_at(&self, wall_time: Duration) -> IoStats {
+        IoStats {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+            wall_time,
+            phases: self.phases.lock().unwrap().clone(),
+        }
+    }
+
+    /// Returns the time since the start.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{IoStats, Phase, Recorder};
+    use std::time::Duration;
+
+    #[test]
+    fn records_stats() {
+        let recorder = Recorder::start();
+        recorder.read(3 << 20);
+        recorder.written(1 << 20);
+        recorder.file();
+        assert_eq!(recorder.time(Phase::Sync, || 42), 42);
+        let stats = IoStats {
+            wall_time: Duration::from_secs(2),
+            ..recorder.stats()
+        };
+        assert_eq!(stats.files, 1);
+        assert!(stats.phases.contains_key(&Phase::Sync));
+        assert_eq!(stats.phase(Phase::Data), Duration::ZERO);
+        assert_eq!(stats.throughput(), (3 << 19) as f64);
+        assert_eq!(
+            stats.to_string(),
+            "1 files, 3.0 MiB read, 1.0 MiB written in 2.0 s (1.5 MiB/s)"
+        );
+    }
+}
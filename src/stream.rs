@@ -0,0 +1,415 @@
+//! A streaming format for sending a tree through a pipe or socket with [`stream_tree`] and
+//! recreating it with [`receive_tree`], without intermediate files.
+//!
+//! The stream starts with a magic number, followed by one record per entry in path order,
+//! parents first, and an end record. Each record is a tag byte and the
+//! [encoded](crate::encode_path) relative path prefixed with its length, followed by:
+//! for a directory, its permissions; for a file, its permissions, modification time and
+//! contents prefixed with their length; for a symbolic link, its target. Integers are
+//! big-endian; lengths and times are 64-bit, permissions and nanoseconds 32-bit.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::atomic::temp_path_for;
+use crate::cancel::{CancelToken, Cancelled};
+use crate::encoding::{decode_path, encode_path};
+use crate::links::symlink;
+use crate::paths::simplify_verbatim;
+use crate::progress::{CopyReader, Operation, ProgressEvent, ProgressSink};
+use crate::result::{Error, ErrorKind, Result};
+use crate::stats::{IoStats, Phase, Recorder};
+use crate::times::set_mtime;
+
+const MAGIC: &[u8; 8] = b"fshtree1";
+const END: u8 = 0;
+const DIR: u8 = 1;
+const FILE: u8 = 2;
+const SYMLINK: u8 = 3;
+
+/// Writes the tree under `root` to `out` in the streaming format. Entries that are neither
+/// directories, files nor symbolic links are left out; links are not followed. Fails if a
+/// file changes size while it is being written.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `out` - destination of the stream, e.g. a socket or stdout.
+pub fn stream_tree<P: AsRef<Path>, W: Write>(root: P, out: W) -> Result<IoStats> {
+    stream_tree_with(root, out, None, None)
+}
+
+/// Like [`stream_tree`], reporting the bytes of each file sent to `progress` and checking
+/// `cancel` between entries, like [`copy_dir`](crate::copy_dir). A cancelled stream has
+/// no end record, so the receiver fails instead of taking it for the whole tree; the error
+/// has kind `ErrorKind::Cancelled` and a [`Cancelled`] cause.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `out` - destination of the stream, e.g. a socket or stdout.
+/// * `progress` - receives the progress events.
+/// * `cancel` - stops the transfer when cancelled.
+pub fn stream_tree_with<P: AsRef<Path>, W: Write>(
+    root: P,
+    mut out: W,
+    progress: Option<&dyn ProgressSink>,
+    cancel: Option<&CancelToken>,
+) -> Result<IoStats> {
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let stats = Recorder::start();
+    out.write_all(MAGIC)?;
+    let mut stack = vec![root.clone()];
+    while let Some(dir) = stack.pop() {
+        let mut entries = stats.time(Phase::Scan, || -> io::Result<Vec<_>> {
+            let mut entries = Vec::new();
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                entries.push((entry.path(), entry.file_type()?));
+            }
+            Ok(entries)
+        })?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut sub_dirs = Vec::new();
+        for (path, file_type) in entries {
+            if cancel.is_some_and(|token| token.is_cancelled()) {
+                out.flush()?;
+                return Err(cancelled(&stats));
+            }
+            let relative = path.strip_prefix(&root).unwrap_or(&path);
+            if file_type.is_dir() {
+                write_header(&mut out, DIR, relative)?;
+                out.write_all(&mode_of(&fs::symlink_metadata(&path)?).to_be_bytes())?;
+                sub_dirs.push(path);
+            } else if file_type.is_file() {
+                write_header(&mut out, FILE, relative)?;
+                write_file(&mut out, &path, &stats, progress)?;
+                stats.file();
+            } else if file_type.is_symlink() {
+                write_header(&mut out, SYMLINK, relative)?;
+                write_bytes(&mut out, encode_path(fs::read_link(&path)?).as_bytes())?;
+                stats.file();
+            }
+        }
+        stack.extend(sub_dirs.into_iter().rev());
+    }
+    out.write_all(&[END])?;
+    out.flush()?;
+    finished(progress);
+    Ok(stats.stats())
+}
+
+/// Recreates a tree written by [`stream_tree`] under `dst`, which is created if needed.
+/// Files are written to temporary files and renamed into place, so a failed transfer
+/// leaves no partial file behind; entries already in `dst` are replaced.
+///
+/// Fails with an error of kind `ErrorKind::Encoding` if the stream is invalid or has a
+/// path outside `dst`.
+///
+/// # Arguments:
+///
+/// * `input` - the stream, e.g. a socket or stdin.
+/// * `dst` - destination directory.
+pub fn receive_tree<R: Read, P: AsRef<Path>>(input: R, dst: P) -> Result<IoStats> {
+    receive_tree_with(input, dst, None, None)
+}
+
+/// Like [`receive_tree`], reporting the bytes of each file received to `progress` and
+/// checking `cancel` between entries. A cancelled transfer keeps the entries received so
+/// far and fails with an error of kind `ErrorKind::Cancelled` and a [`Cancelled`] cause.
+///
+/// # Arguments:
+///
+/// * `input` - the stream, e.g. a socket or stdin.
+/// * `dst` - destination directory.
+/// * `progress` - receives the progress events.
+/// * `cancel` - stops the transfer when cancelled.
+pub fn receive_tree_with<R: Read, P: AsRef<Path>>(
+    mut input: R,
+    dst: P,
+    progress: Option<&dyn ProgressSink>,
+    cancel: Option<&CancelToken>,
+) -> Result<IoStats> {
+    let dst = dst.as_ref();
+    let stats = Recorder::start();
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid());
+    }
+    fs::create_dir_all(dst)?;
+    loop {
+        let tag = read_u8(&mut input)?;
+        if tag == END {
+            break;
+        }
+        if cancel.is_some_and(|token| token.is_cancelled()) {
+            return Err(cancelled(&stats));
+        }
+        let path = dst.join(read_path(&mut input)?);
+        match tag {
+            DIR => {
+                let mode = read_u32(&mut input)?;
+                if !path.is_dir() {
+                    fs::create_dir(&path)?;
+                }
+                apply_mode(&path, mode)?;
+            }
+            FILE => {
+                read_file(&mut input, &path, &stats, progress)?;
+                stats.file();
+            }
+            SYMLINK => {
+                let target = decode_path(&read_string(&mut input)?)?;
+                if fs::symlink_metadata(&path).is_ok() {
+                    fs::remove_file(&path)?;
+                }
+                symlink(&target, &path)?;
+                stats.file();
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    finished(progress);
+    Ok(stats.stats())
+}
+
+fn cancelled(stats: &Recorder) -> Error {
+    let cancelled = Cancelled {
+        stats: stats.stats(),
+    };
+    Error::new(ErrorKind::Cancelled, cancelled)
+}
+
+fn finished(progress: Option<&dyn ProgressSink>) {
+    if let Some(progress) = progress {
+        progress.event(&ProgressEvent::OperationFinished {
+            operation: Operation::Copy,
+        });
+    }
+}
+
+fn write_header<W: Write>(out: &mut W, tag: u8, path: &Path) -> Result<()> {
+    out.write_all(&[tag])?;
+    write_bytes(out, encode_path(path).as_bytes())
+}
+
+fn write_bytes<W: Write>(out: &mut W, bytes: &[u8]) -> Result<()> {
+    out.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+fn write_file<W: Write>(
+    out: &mut W,
+    path: &Path,
+    stats: &Recorder,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<()> {
+    let mut file = File::open(path)?;
+    let meta = file.metadata()?;
+    let modified = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    out.write_all(&mode_of(&meta).to_be_bytes())?;
+    out.write_all(&modified.as_secs().to_be_bytes())?;
+    out.write_all(&modified.subsec_nanos().to_be_bytes())?;
+    out.write_all(&meta.len().to_be_bytes())?;
+    let mut data = CopyReader::new((&mut file).take(meta.len()), path, progress);
+    let copied = stats.time(Phase::Data, || io::copy(&mut data, out))?;
+    if copied != meta.len() || file.read(&mut [0])? != 0 {
+        let cause = format!("{} changed while being streamed", path.display());
+        return Err(Error::new(ErrorKind::File, cause));
+    }
+    stats.read(copied);
+    Ok(())
+}
+
+fn read_file<R: Read>(
+    input: &mut R,
+    path: &Path,
+    stats: &Recorder,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<()> {
+    let mode = read_u32(input)?;
+    let secs = read_u64(input)?;
+    let nanos = read_u32(input)?;
+    let len = read_u64(input)?;
+    let temp = temp_path_for(path);
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        let mut data = CopyReader::new(input.take(len), path, progress);
+        let copied = stats.time(Phase::Data, || io::copy(&mut data, &mut file))?;
+        if copied != len {
+            return Err(invalid());
+        }
+        stats.written(copied);
+        drop(file);
+        let modified = UNIX_EPOCH + Duration::new(secs, nanos.min(999_999_999));
+        set_mtime(&temp, modified)?;
+        apply_mode(&temp, mode)?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Reads a relative path, rejecting paths that would leave the destination.
+fn read_path<R: Read>(input: &mut R) -> Result<PathBuf> {
+    let path = decode_path(&read_string(input)?)?;
+    let mut components = path.components();
+    if path.as_os_str().is_empty() || !components.all(|c| matches!(c, Component::Normal(_))) {
+        return Err(invalid());
+    }
+    Ok(path)
+}
+
+fn read_string<R: Read>(input: &mut R) -> Result<String> {
+    let len = read_u64(input)?;
+    let mut bytes = Vec::new();
+    if input.take(len).read_to_end(&mut bytes)? as u64 != len {
+        return Err(invalid());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn read_u8<R: Read>(input: &mut R) -> Result<u8> {
+    let mut buf = [0; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Returns the Unix permission bits; elsewhere, 0o444 for read-only entries, 0o644 for others.
+#[cfg(unix)]
+fn mode_of(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(meta: &fs::Metadata) -> u32 {
+    if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+fn apply_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::set_permissions(path, fs::Permissions::from_mode(mode))?)
+}
+
+#[cfg(not(unix))]
+fn apply_mode(path: &Path, mode: u32) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    Ok(fs::set_permissions(path, permissions)?)
+}
+
+fn invalid() -> Error {
+    Error::new(ErrorKind::Encoding, "invalid tree stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::dirs_equal;
+    use crate::fixture::TreeBuilder;
+    use crate::result::ErrorKind;
+    use crate::stream::{receive_tree, receive_tree_with, stream_tree, stream_tree_with};
+    use crate::times::set_mtime;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn stream_round_trip() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"alpha")
+            .file("b/c.txt", b"")
+            .dir("empty")
+            .symlink("b/link", "../a.txt")
+            .build()
+            .unwrap();
+        let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 123);
+        set_mtime(tree.join("a.txt"), modified).unwrap();
+        let mut stream = Vec::new();
+        let sent = stream_tree(tree.path(), &mut stream).unwrap();
+        assert_eq!((sent.files, sent.bytes_read), (3, 5));
+
+        let dst = TreeBuilder::new().build().unwrap();
+        let received = receive_tree(&stream[..], dst.join("copy")).unwrap();
+        assert_eq!((received.files, received.bytes_written), (3, 5));
+        assert!(dirs_equal(tree.path(), dst.join("copy")).unwrap());
+        assert!(dst.join("copy/empty").is_dir());
+        let meta = fs::metadata(dst.join("copy/a.txt")).unwrap();
+        assert_eq!(meta.modified().unwrap(), modified);
+
+        // truncated streams and paths leaving the destination are rejected
+        assert!(receive_tree(&stream[..stream.len() - 3], dst.join("cut")).is_err());
+        let mut evil = b"fshtree1\x02".to_vec();
+        evil.extend_from_slice(&5u64.to_be_bytes());
+        evil.extend_from_slice(b"../xy");
+        let err = receive_tree(&evil[..], dst.join("evil")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Encoding);
+        assert!(!dst.join("x").exists());
+    }
+
+    #[test]
+    fn stream_progress_and_cancel() {
+        use crate::cancel::{CancelToken, Cancelled};
+        use crate::progress::{Operation, ProgressEvent, ProgressSink};
+        use std::error::Error;
+        use std::sync::Mutex;
+
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"alpha")
+            .file("b.txt", b"beta")
+            .build()
+            .unwrap();
+        let events = Mutex::new((0, 0));
+        let sink = |event: &ProgressEvent| {
+            let mut events = events.lock().unwrap();
+            match event {
+                ProgressEvent::BytesCopied { bytes, .. } => events.0 += bytes,
+                ProgressEvent::OperationFinished {
+                    operation: Operation::Copy,
+                } => events.1 += 1,
+                _ => {}
+            }
+        };
+        let progress = Some(&sink as &dyn ProgressSink);
+        let mut stream = Vec::new();
+        stream_tree_with(tree.path(), &mut stream, progress, None).unwrap();
+        assert_eq!(*events.lock().unwrap(), (9, 1));
+        let dst = TreeBuilder::new().build().unwrap();
+        receive_tree_with(&stream[..], dst.join("copy"), progress, None).unwrap();
+        assert_eq!(*events.lock().unwrap(), (18, 2));
+        assert!(dirs_equal(tree.path(), dst.join("copy")).unwrap());
+
+        let token = CancelToken::new();
+        token.cancel();
+        let err = stream_tree_with(tree.path(), &mut Vec::new(), None, Some(&token)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        let err = receive_tree_with(&stream[..], dst.join("cut"), None, Some(&token)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        let cancelled = err.source().unwrap().downcast_ref::<Cancelled>().unwrap();
+        assert_eq!(cancelled.stats.files, 0);
+        assert_eq!(fs::read_dir(dst.join("cut")).unwrap().count(), 0);
+    }
+}
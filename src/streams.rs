@@ -0,0 +1,159 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::result::Result;
+
+/// An alternate data stream of a file (NTFS).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    /// Name of the stream, e.g. `Zone.Identifier`; the file can be opened as `path:name`.
+    pub name: String,
+    /// Size of the stream in bytes.
+    pub len: u64,
+}
+
+/// Returns the alternate data streams of a file or directory, without the unnamed main
+/// stream. Other platforms than Windows have no alternate streams, so the list is empty.
+pub fn list_streams<P: AsRef<Path>>(path: P) -> Result<Vec<Stream>> {
+    Ok(sys::list(path.as_ref())?)
+}
+
+/// Copies the alternate data streams of `from` to `to`, which must exist.
+pub(crate) fn copy_streams(from: &Path, to: &Path) -> io::Result<()> {
+    for stream in sys::list(from)? {
+        let mut reader = fs::File::open(stream_path(from, &stream.name))?;
+        let mut writer = fs::File::create(stream_path(to, &stream.name))?;
+        io::copy(&mut reader, &mut writer)?;
+    }
+    Ok(())
+}
+
+fn stream_path(path: &Path, name: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(":");
+    path.push(name);
+    path.into()
+}
+
+#[cfg(windows)]
+pub(crate) mod sys {
+    use std::ffi::c_void;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    use crate::streams::Stream;
+
+    const FIND_STREAM_INFO_STANDARD: i32 = 0;
+    const ERROR_HANDLE_EOF: i32 = 38;
+    // MAX_PATH + 36
+    const MAX_STREAM_NAME: usize = 296;
+
+    #[repr(C)]
+    struct FindStreamData {
+        stream_size: i64,
+        stream_name: [u16; MAX_STREAM_NAME],
+    }
+
+    extern "system" {
+        fn FindFirstStreamW(
+            file_name: *const u16,
+            info_level: i32,
+            data: *mut FindStreamData,
+            flags: u32,
+        ) -> *mut c_void;
+        fn FindNextStreamW(find: *mut c_void, data: *mut FindStreamData) -> i32;
+        fn FindClose(find: *mut c_void) -> i32;
+    }
+
+    /// Turns `:name:$DATA` into `name`; `None` for the main stream `::$DATA`.
+    fn stream_of(data: &FindStreamData) -> Option<Stream> {
+        let len = data.stream_name.iter().position(|&c| c == 0)?;
+        let full = String::from_utf16_lossy(&data.stream_name[..len]);
+        let name = full.strip_prefix(':')?.strip_suffix(":$DATA")?;
+        (!name.is_empty()).then(|| Stream {
+            name: name.to_string(),
+            len: data.stream_size as u64,
+        })
+    }
+
+    pub(crate) fn list(path: &Path) -> io::Result<Vec<Stream>> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut data = FindStreamData {
+            stream_size: 0,
+            stream_name: [0; MAX_STREAM_NAME],
+        };
+        // SAFETY: `wide` is NUL-terminated and `data` is a valid WIN32_FIND_STREAM_DATA.
+        let find =
+            unsafe { FindFirstStreamW(wide.as_ptr(), FIND_STREAM_INFO_STANDARD, &mut data, 0) };
+        if find as isize == -1 {
+            let e = io::Error::last_os_error();
+            // no streams at all, e.g. a directory without named streams
+            return match e.raw_os_error() {
+                Some(ERROR_HANDLE_EOF) => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+        let mut streams = Vec::new();
+        loop {
+            streams.extend(stream_of(&data));
+            // SAFETY: `find` is a valid search handle.
+            if unsafe { FindNextStreamW(find, &mut data) } == 0 {
+                break;
+            }
+        }
+        let e = io::Error::last_os_error();
+        // SAFETY: `find` is a valid search handle, closed once.
+        unsafe { FindClose(find) };
+        match e.raw_os_error() {
+            Some(ERROR_HANDLE_EOF) => Ok(streams),
+            _ => Err(e),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) mod sys {
+    use std::io;
+    use std::path::Path;
+
+    use crate::streams::Stream;
+
+    pub(crate) fn list(path: &Path) -> io::Result<Vec<Stream>> {
+        // fail like Windows for entries that do not exist
+        path.symlink_metadata()?;
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::copy::{copy_dir, CopyOptions};
+    use crate::fixture::TreeBuilder;
+    use crate::streams::list_streams;
+    use crate::ReadDir;
+
+    #[test]
+    fn alternate_streams() {
+        let tree = TreeBuilder::new().file("a.txt", b"main").build().unwrap();
+        let path = tree.join("a.txt");
+        #[cfg(windows)]
+        std::fs::write(tree.join("a.txt:extra"), b"hidden").unwrap();
+        let expected = usize::from(cfg!(windows));
+
+        let streams = list_streams(&path).unwrap();
+        assert_eq!(streams.len(), expected);
+        let entry = ReadDir::try_new(tree.path()).unwrap().next().unwrap();
+        assert_eq!(entry.has_streams().unwrap(), expected > 0);
+        assert!(list_streams(tree.join("missing")).is_err());
+
+        let dst = TreeBuilder::new().build().unwrap();
+        let options = CopyOptions {
+            data_streams: true,
+            ..CopyOptions::default()
+        };
+        copy_dir(tree.path(), dst.join("copy"), &options).unwrap();
+        assert_eq!(list_streams(dst.join("copy/a.txt")).unwrap(), streams);
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::entry::Entry;
+use crate::paths::simplify_verbatim;
+use crate::result::{Error, Result};
+use crate::visit::{walk, Control, Visitor};
+
+/// Sets the timestamps of a file or directory; `None` leaves a timestamp unchanged.
+/// Symbolic links are followed. The file is not opened, so FIFOs, devices and files that
+/// can not be read are handled like any other file.
+///
+/// # Arguments:
+///
+/// * `path` - file or directory.
+/// * `accessed` - new access time.
+/// * `modified` - new modification time.
+pub fn set_times<P: AsRef<Path>>(
+    path: P,
+    accessed: Option<SystemTime>,
+    modified: Option<SystemTime>,
+) -> Result<()> {
+    Ok(sys::set_times(path.as_ref(), accessed, modified, true)?)
+}
+
+/// Sets the modification time of a file or directory.
+pub fn set_mtime<P: AsRef<Path>>(path: P, time: SystemTime) -> Result<()> {
+    set_times(path, None, Some(time))
+}
+
+/// Sets the access time of a file or directory.
+pub fn set_atime<P: AsRef<Path>>(path: P, time: SystemTime) -> Result<()> {
+    set_times(path, Some(time), None)
+}
+
+/// Creates the file if it does not exist, and updates its modification and access times to now.
+pub fn touch<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    if !path.exists() {
+        fs::OpenOptions::new().create(true).append(true).open(path)?;
+    }
+    let now = SystemTime::now();
+    set_times(path, Some(now), Some(now))
+}
+
+/// Copies the timestamps of `meta` to `path`. Symbolic links are followed if `follow`.
+fn copy_times(meta: &fs::Metadata, path: &Path, follow: bool) -> Result<()> {
+    let (accessed, modified) = (meta.accessed()?, meta.modified()?);
+    Ok(sys::set_times(path, Some(accessed), Some(modified), follow)?)
+}
+
+struct CopyTimes<'a> {
+    src: &'a Path,
+    dst: &'a Path,
+    error: Option<Error>,
+}
+
+impl CopyTimes<'_> {
+    fn copy(&mut self, path: &Path) -> Control {
+        let rel = match path.strip_prefix(self.src) {
+            Ok(rel) => rel,
+            Err(_) => return Control::Continue,
+        };
+        let target = self.dst.join(rel);
+        if fs::symlink_metadata(&target).is_err() {
+            return Control::Continue;
+        }
+        // the times of symbolic links themselves, not of what they point to
+        let result = fs::symlink_metadata(path)
+            .map_err(Error::from)
+            .and_then(|meta| copy_times(&meta, &target, false));
+        match result {
+            Ok(()) => Control::Continue,
+            Err(e) => {
+                self.error = Some(e);
+                Control::Stop
+            }
+        }
+    }
+}
+
+impl Visitor for CopyTimes<'_> {
+    fn file(&mut self, entry: &Entry) -> Control {
+        self.copy(entry.path())
+    }
+
+    fn leave_dir(&mut self, dir: &Path, _depth: usize) -> Control {
+        self.copy(dir)
+    }
+}
+
+/// Copies modification and access times of every entry under `src` to the entry with the
+/// same relative path under `dst`. Entries missing in `dst` are skipped; symbolic links get
+/// the times of the links, not of their targets. Directory times are copied after their
+/// contents.
+pub fn copy_timestamps<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> Result<()> {
+    let src = fs::canonicalize(src)?;
+    if src.is_file() {
+        return copy_times(&fs::metadata(&src)?, dst.as_ref(), true);
+    }
+    let src = simplify_verbatim(src);
+    let mut visitor = CopyTimes {
+        src: &src,
+        dst: dst.as_ref(),
+        error: None,
+    };
+    walk(&src, &mut visitor)?;
+    match visitor.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+mod sys {
+    use std::ffi::{c_char, c_int, c_long, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    mod consts {
+        pub(super) const AT_FDCWD: i32 = -100;
+        pub(super) const AT_SYMLINK_NOFOLLOW: i32 = 0x100;
+        pub(super) const UTIME_OMIT: i64 = (1 << 30) - 2;
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    mod consts {
+        pub(super) const AT_FDCWD: i32 = -2;
+        pub(super) const AT_SYMLINK_NOFOLLOW: i32 = 0x20;
+        pub(super) const UTIME_OMIT: i64 = -2;
+    }
+    #[cfg(any(target_os = "freebsd", target_os = "netbsd"))]
+    mod consts {
+        pub(super) const AT_FDCWD: i32 = -100;
+        pub(super) const AT_SYMLINK_NOFOLLOW: i32 = 0x200;
+        #[cfg(target_os = "freebsd")]
+        pub(super) const UTIME_OMIT: i64 = -2;
+        #[cfg(target_os = "netbsd")]
+        pub(super) const UTIME_OMIT: i64 = (1 << 30) - 2;
+    }
+    use consts::{AT_FDCWD, AT_SYMLINK_NOFOLLOW, UTIME_OMIT};
+
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn utimensat(dirfd: c_int, path: *const c_char, times: *const Timespec, flags: c_int)
+            -> c_int;
+    }
+
+    fn timespec(time: Option<SystemTime>) -> Timespec {
+        let (tv_sec, tv_nsec) = match time.map(|time| time.duration_since(UNIX_EPOCH)) {
+            None => (0, UTIME_OMIT),
+            Some(Ok(since)) => (since.as_secs() as i64, since.subsec_nanos() as i64),
+            Some(Err(e)) => match e.duration().subsec_nanos() {
+                0 => (-(e.duration().as_secs() as i64), 0),
+                nanos => (-(e.duration().as_secs() as i64) - 1, 1_000_000_000 - nanos as i64),
+            },
+        };
+        Timespec {
+            tv_sec: tv_sec as c_long,
+            tv_nsec: tv_nsec as c_long,
+        }
+    }
+
+    pub(super) fn set_times(
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+        follow: bool,
+    ) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let times = [timespec(accessed), timespec(modified)];
+        let flags = if follow { 0 } else { AT_SYMLINK_NOFOLLOW };
+        // SAFETY: `c_path` is a valid NUL-terminated string and `times` holds two entries.
+        if unsafe { utimensat(AT_FDCWD, c_path.as_ptr(), times.as_ptr(), flags) } == 0 {
+            Ok(())
+        } else {
+            let e = io::Error::last_os_error();
+            Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        }
+    }
+}
+
+// other Unix systems: the file is opened to set its times
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ))
+))]
+mod sys {
+    use std::fs::{self, FileTimes};
+    use std::io;
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    pub(super) fn set_times(
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+        _follow: bool,
+    ) -> io::Result<()> {
+        let mut times = FileTimes::new();
+        if let Some(accessed) = accessed {
+            times = times.set_accessed(accessed);
+        }
+        if let Some(modified) = modified {
+            times = times.set_modified(modified);
+        }
+        fs::File::open(path)?.set_times(times)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::fs::{self, FileTimes};
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    // required to open directories
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+
+    pub(super) fn set_times(
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+        follow: bool,
+    ) -> io::Result<()> {
+        let mut flags = FILE_FLAG_BACKUP_SEMANTICS;
+        if !follow {
+            flags |= FILE_FLAG_OPEN_REPARSE_POINT;
+        }
+        let file = fs::OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(flags)
+            .open(path)?;
+        let mut times = FileTimes::new();
+        if let Some(accessed) = accessed {
+            times = times.set_accessed(accessed);
+        }
+        if let Some(modified) = modified {
+            times = times.set_modified(modified);
+        }
+        file.set_times(times)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::times::{copy_timestamps, set_atime, set_mtime, touch};
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn set_and_copy_times() {
+        let tree = TreeBuilder::new().dir("src/sub").dir("dst/sub").build().unwrap();
+        let src = tree.join("src");
+        let dst = tree.join("dst");
+        touch(src.join("sub/a.txt")).unwrap();
+        touch(dst.join("sub/a.txt")).unwrap();
+        assert!(src.join("sub/a.txt").exists());
+
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        set_mtime(src.join("sub/a.txt"), past).unwrap();
+        set_atime(src.join("sub/a.txt"), past).unwrap();
+        set_mtime(src.join("sub"), past).unwrap();
+        copy_timestamps(&src, &dst).unwrap();
+
+        let meta = fs::metadata(dst.join("sub/a.txt")).unwrap();
+        assert_eq!(meta.modified().unwrap(), past);
+        assert_eq!(meta.accessed().unwrap(), past);
+        assert_eq!(fs::metadata(dst.join("sub")).unwrap().modified().unwrap(), past);
+
+        touch(dst.join("sub/a.txt")).unwrap();
+        assert!(fs::metadata(dst.join("sub/a.txt")).unwrap().modified().unwrap() > past);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifos_and_unreadable_files() {
+        use crate::fifo::create_fifo;
+        use std::os::unix::fs::PermissionsExt;
+
+        let tree = TreeBuilder::new()
+            .file("src/secret", b"s")
+            .file("dst/secret", b"d")
+            .build()
+            .unwrap();
+        for side in ["src", "dst"] {
+            create_fifo(tree.join(side).join("pipe"), 0o600).unwrap();
+            let write_only = fs::Permissions::from_mode(0o200);
+            fs::set_permissions(tree.join(side).join("secret"), write_only).unwrap();
+        }
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        set_mtime(tree.join("src/pipe"), past).unwrap();
+        set_mtime(tree.join("src/secret"), past).unwrap();
+        copy_timestamps(tree.join("src"), tree.join("dst")).unwrap();
+        for name in ["dst/pipe", "dst/secret"] {
+            let meta = fs::symlink_metadata(tree.join(name)).unwrap();
+            assert_eq!(meta.modified().unwrap(), past);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::diff::{entries_equal, relative_files};
+use crate::result::Result;
+
+/// An entry of the union of several trees, as returned by [`union_walk`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnionEntry {
+    /// Path relative to the roots.
+    pub path: PathBuf,
+    /// Indices of the roots holding the entry, in the order the roots were given.
+    pub roots: Vec<usize>,
+    /// Whether the roots holding the entry disagree on it: different kinds, contents or
+    /// link targets.
+    pub conflict: bool,
+}
+
+impl UnionEntry {
+    /// Checks whether only one root holds the entry.
+    pub fn is_unique(&self) -> bool {
+        self.roots.len() == 1
+    }
+}
+
+/// Walks several trees as one, returning every relative path present in any of them
+/// (except directories) with the roots holding it, sorted by path. Entries held by
+/// several roots are compared, so conflicts (e.g. an installed file that differs from the
+/// packaged one) are reported. Unlike an [`Overlay`](crate::Overlay), no root takes
+/// precedence and nothing is hidden. Symbolic links are compared by their targets.
+///
+/// # Arguments:
+///
+/// * `roots` - root directories.
+pub fn union_walk<I, P>(roots: I) -> Result<Vec<UnionEntry>>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let roots: Vec<PathBuf> = roots
+        .into_iter()
+        .map(|root| root.as_ref().to_path_buf())
+        .collect();
+    let mut holders: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    for (i, root) in roots.iter().enumerate() {
+        for path in relative_files(root)? {
+            holders.entry(path).or_default().push(i);
+        }
+    }
+    let mut entries = Vec::with_capacity(holders.len());
+    for (path, held_by) in holders {
+        let first = roots[held_by[0]].join(&path);
+        let mut conflict = false;
+        for &i in &held_by[1..] {
+            if !entries_equal(&first, &roots[i].join(&path))? {
+                conflict = true;
+                break;
+            }
+        }
+        entries.push(UnionEntry {
+            path,
+            roots: held_by,
+            conflict,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::union::{union_walk, UnionEntry};
+    use std::path::PathBuf;
+
+    #[test]
+    fn union_of_trees() {
+        let installed = TreeBuilder::new()
+            .file("bin/tool", b"v2")
+            .file("share/doc.txt", b"doc")
+            .file("local.conf", b"")
+            .build()
+            .unwrap();
+        let package = TreeBuilder::new()
+            .file("bin/tool", b"v1")
+            .file("share/doc.txt", b"doc")
+            .file("share/missing.txt", b"")
+            .build()
+            .unwrap();
+        let entries = union_walk([installed.path(), package.path()]).unwrap();
+        let entry = |path: &str, roots: &[usize], conflict| UnionEntry {
+            path: PathBuf::from(path),
+            roots: roots.to_vec(),
+            conflict,
+        };
+        assert_eq!(
+            entries,
+            [
+                entry("bin/tool", &[0, 1], true),
+                entry("local.conf", &[0], false),
+                entry("share/doc.txt", &[0, 1], false),
+                entry("share/missing.txt", &[1], false)
+            ]
+        );
+        assert!(entries[1].is_unique());
+    }
+}
@@ -0,0 +1,586 @@
+//! Batched metadata reads and opens through io_uring (Linux), for scanning trees on storage
+//! where each call waits long, such as network filesystems: a batch of `statx` or `openat`
+//! operations is handed to the kernel at once and runs in parallel, instead of one call at
+//! a time per thread.
+//!
+//! Each thread sets up its own ring on first use. Where io_uring is not available (kernels
+//! before 5.6, or disabled with the `kernel.io_uring_disabled` sysctl or by a seccomp
+//! filter), the batches are done one call at a time, with the same results.
+//! [`RealFs`](crate::RealFs) reads the metadata of directory listings sorted by size or
+//! time this way when the `io-uring` feature is enabled.
+//!
+//! The kernel runs `statx` from io_uring on worker threads. On local storage with a warm
+//! cache, where each call returns at once, that costs more than calling it directly, so
+//! enable the feature for trees on slow or remote storage.
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::vfs::FsMetadata;
+
+/// Number of operations in flight at once.
+const RING_ENTRIES: u32 = 256;
+
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RING: RefCell<Option<sys::Ring>> = const { RefCell::new(None) };
+}
+
+/// Checks whether io_uring can be used, setting up this thread's ring if needed.
+pub fn is_available() -> bool {
+    with_ring(|_| ()).is_some()
+}
+
+/// Returns the metadata of each path, without following symbolic links, in the order of
+/// `paths`.
+///
+/// # Arguments:
+///
+/// * `paths` - paths of the entries, absolute or relative to the working directory.
+pub fn stat_batch<P: AsRef<Path>>(paths: &[P]) -> Vec<io::Result<FsMetadata>> {
+    let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+    with_ring(|ring| ring.stat(&paths)).unwrap_or_else(|| {
+        let stat = |path: &&Path| fs::symlink_metadata(path).map(FsMetadata::from);
+        paths.iter().map(stat).collect()
+    })
+}
+
+/// Opens each file for reading, in the order of `paths`.
+///
+/// # Arguments:
+///
+/// * `paths` - paths of the files, absolute or relative to the working directory.
+pub fn open_batch<P: AsRef<Path>>(paths: &[P]) -> Vec<io::Result<File>> {
+    let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+    with_ring(|ring| ring.open(&paths))
+        .unwrap_or_else(|| paths.iter().map(File::open).collect())
+}
+
+/// Runs `f` on this thread's ring; returns `None` if io_uring is not available.
+fn with_ring<T>(f: impl FnOnce(&mut sys::Ring) -> T) -> Option<T> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.is_none() {
+            match sys::Ring::new(RING_ENTRIES) {
+                Ok(created) => *ring = Some(created),
+                Err(_) => {
+                    UNAVAILABLE.store(true, Ordering::Relaxed);
+                    return None;
+                }
+            }
+        }
+        let result = ring.as_mut().map(f);
+        if ring.as_ref().is_some_and(|ring| ring.broken) {
+            *ring = None;
+            UNAVAILABLE.store(true, Ordering::Relaxed);
+        }
+        result
+    })
+}
+
+mod sys {
+    use std::ffi::{c_int, c_long, c_void, CString};
+    use std::fs::File;
+    use std::io;
+    use std::mem;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::vfs::{FileKind, FsMetadata};
+
+    const SYS_IO_URING_SETUP: c_long = 425;
+    const SYS_IO_URING_ENTER: c_long = 426;
+    const SYS_IO_URING_REGISTER: c_long = 427;
+    const IORING_OFF_SQ_RING: i64 = 0;
+    const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+    const IORING_OFF_SQES: i64 = 0x1000_0000;
+    const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+    const IORING_ENTER_GETEVENTS: u32 = 1;
+    const IORING_REGISTER_PROBE: u32 = 8;
+    const IO_URING_OP_SUPPORTED: u16 = 1;
+    const IORING_OP_OPENAT: u8 = 18;
+    const IORING_OP_STATX: u8 = 21;
+
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    const MAP_SHARED: c_int = 1;
+    const MAP_POPULATE: c_int = 0x8000;
+    const EINTR: i32 = 4;
+
+    const AT_FDCWD: i32 = -100;
+    const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+    const O_CLOEXEC: u32 = 0o2_000_000;
+    const STATX_BASIC_STATS: u32 = 0x7ff;
+    const STATX_MTIME: u32 = 0x40;
+    const S_IFMT: u16 = 0o170_000;
+    const S_IFDIR: u16 = 0o040_000;
+    const S_IFREG: u16 = 0o100_000;
+    const S_IFLNK: u16 = 0o120_000;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SqOffsets {
+        head: u32,
+        tail: u32,
+        ring_mask: u32,
+        ring_entries: u32,
+        flags: u32,
+        dropped: u32,
+        array: u32,
+        resv1: u32,
+        user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct CqOffsets {
+        head: u32,
+        tail: u32,
+        ring_mask: u32,
+        ring_entries: u32,
+        overflow: u32,
+        cqes: u32,
+        flags: u32,
+        resv1: u32,
+        user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Params {
+        sq_entries: u32,
+        cq_entries: u32,
+        flags: u32,
+        sq_thread_cpu: u32,
+        sq_thread_idle: u32,
+        features: u32,
+        wq_fd: u32,
+        resv: [u32; 3],
+        sq_off: SqOffsets,
+        cq_off: CqOffsets,
+    }
+
+    /// A submission queue entry.
+    #[repr(C)]
+    #[derive(Default)]
+    struct Sqe {
+        opcode: u8,
+        flags: u8,
+        ioprio: u16,
+        fd: i32,
+        /// The offset, or for `statx` the address of the result.
+        off: u64,
+        addr: u64,
+        len: u32,
+        /// The flags of the operation, e.g. the `openat` flags.
+        op_flags: u32,
+        user_data: u64,
+        buf_index: u16,
+        personality: u16,
+        splice_fd_in: i32,
+        addr3: u64,
+        pad: u64,
+    }
+
+    /// A completion queue entry.
+    #[repr(C)]
+    struct Cqe {
+        user_data: u64,
+        res: i32,
+        flags: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct StatxTime {
+        sec: i64,
+        nsec: u32,
+        reserved: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Statx {
+        mask: u32,
+        blksize: u32,
+        attributes: u64,
+        nlink: u32,
+        uid: u32,
+        gid: u32,
+        mode: u16,
+        spare0: u16,
+        ino: u64,
+        size: u64,
+        blocks: u64,
+        attributes_mask: u64,
+        atime: StatxTime,
+        btime: StatxTime,
+        ctime: StatxTime,
+        mtime: StatxTime,
+        rdev_major: u32,
+        rdev_minor: u32,
+        dev_major: u32,
+        dev_minor: u32,
+        spare: [u64; 14],
+    }
+
+    #[repr(C)]
+    struct ProbeOp {
+        op: u8,
+        resv: u8,
+        flags: u16,
+        resv2: u32,
+    }
+
+    #[repr(C)]
+    struct Probe {
+        last_op: u8,
+        ops_len: u8,
+        resv: u16,
+        resv2: [u32; 3],
+        ops: [ProbeOp; 64],
+    }
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    /// A memory mapping of part of a ring, unmapped on drop.
+    struct Mapping {
+        ptr: *mut u8,
+        len: usize,
+    }
+
+    impl Mapping {
+        fn new(fd: c_int, len: usize, offset: i64) -> io::Result<Mapping> {
+            let prot = PROT_READ | PROT_WRITE;
+            let flags = MAP_SHARED | MAP_POPULATE;
+            let ptr = unsafe { mmap(ptr::null_mut(), len, prot, flags, fd, offset) };
+            if ptr as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Mapping {
+                ptr: ptr.cast(),
+                len,
+            })
+        }
+
+        /// Returns a pointer to the value at `offset` bytes into the mapping.
+        fn at<T>(&self, offset: u32) -> *mut T {
+            debug_assert!(offset as usize + mem::size_of::<T>() <= self.len);
+            unsafe { self.ptr.add(offset as usize).cast() }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe { munmap(self.ptr.cast(), self.len) };
+        }
+    }
+
+    /// An io_uring instance, with its submission and completion queues mapped.
+    pub(super) struct Ring {
+        fd: OwnedFd,
+        sq: Mapping,
+        /// The completion queue, unless it shares the mapping of the submission queue.
+        cq: Option<Mapping>,
+        sqes: Mapping,
+        params: Params,
+        /// Set when waiting for completions failed: operations may still be in flight, so
+        /// the ring must not be used again and their buffers must not be freed.
+        pub(super) broken: bool,
+    }
+
+    impl Ring {
+        /// Sets up a ring, checking that the kernel supports `statx` and `openat` on it.
+        pub(super) fn new(entries: u32) -> io::Result<Ring> {
+            let mut params = Params::default();
+            let fd = unsafe { syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd as c_int) };
+            let raw = std::os::fd::AsRawFd::as_raw_fd(&fd);
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len =
+                params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+            let single = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+            let sq_len = if single { sq_len.max(cq_len) } else { sq_len };
+            let sq = Mapping::new(raw, sq_len, IORING_OFF_SQ_RING)?;
+            let cq = match single {
+                true => None,
+                false => Some(Mapping::new(raw, cq_len, IORING_OFF_CQ_RING)?),
+            };
+            let sqes_len = params.sq_entries as usize * mem::size_of::<Sqe>();
+            let sqes = Mapping::new(raw, sqes_len, IORING_OFF_SQES)?;
+            let ring = Ring {
+                fd,
+                sq,
+                cq,
+                sqes,
+                params,
+                broken: false,
+            };
+            ring.probe()?;
+            Ok(ring)
+        }
+
+        fn probe(&self) -> io::Result<()> {
+            let mut probe: Probe = unsafe { mem::zeroed() };
+            let fd = std::os::fd::AsRawFd::as_raw_fd(&self.fd);
+            let ops = probe.ops.len() as u32;
+            let probe_ptr = &mut probe as *mut Probe;
+            let register = IORING_REGISTER_PROBE;
+            let ret = unsafe { syscall(SYS_IO_URING_REGISTER, fd, register, probe_ptr, ops) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let supported = |op: u8| {
+                let flags = probe.ops.get(op as usize).map_or(0, |op| op.flags);
+                op <= probe.last_op && flags & IO_URING_OP_SUPPORTED != 0
+            };
+            if supported(IORING_OP_STATX) && supported(IORING_OP_OPENAT) {
+                Ok(())
+            } else {
+                Err(io::Error::from(io::ErrorKind::Unsupported))
+            }
+        }
+
+        pub(super) fn stat(&mut self, paths: &[&Path]) -> Vec<io::Result<FsMetadata>> {
+            let mut buffers: Vec<Statx> = vec![unsafe { mem::zeroed() }; paths.len()];
+            let buffers_ptr = buffers.as_mut_ptr();
+            let results = self.run(paths, |sqe, index, path| {
+                sqe.opcode = IORING_OP_STATX;
+                sqe.fd = AT_FDCWD;
+                sqe.addr = path.as_ptr() as u64;
+                sqe.len = STATX_BASIC_STATS;
+                sqe.op_flags = AT_SYMLINK_NOFOLLOW;
+                sqe.off = unsafe { buffers_ptr.add(index) } as u64;
+            });
+            let results = results.into_iter().zip(&buffers);
+            let results = results.map(|(result, buffer)| result.map(|_| metadata(buffer)));
+            let results = results.collect();
+            if self.broken {
+                mem::forget(buffers);
+            }
+            results
+        }
+
+        pub(super) fn open(&mut self, paths: &[&Path]) -> Vec<io::Result<File>> {
+            let results = self.run(paths, |sqe, _, path| {
+                sqe.opcode = IORING_OP_OPENAT;
+                sqe.fd = AT_FDCWD;
+                sqe.addr = path.as_ptr() as u64;
+                sqe.op_flags = O_CLOEXEC;
+            });
+            let file = |fd: i32| unsafe { File::from_raw_fd(fd) };
+            results.into_iter().map(|result| result.map(file)).collect()
+        }
+
+        /// Runs one operation per path, prepared by `prepare` from the index and the path as
+        /// a C string, and returns the result of each.
+        fn run<F>(&mut self, paths: &[&Path], mut prepare: F) -> Vec<io::Result<i32>>
+        where
+            F: FnMut(&mut Sqe, usize, &CString),
+        {
+            // `None` until the operation completes
+            let mut results: Vec<Option<io::Result<i32>>> = Vec::with_capacity(paths.len());
+            let mut names = Vec::with_capacity(paths.len());
+            for path in paths {
+                match CString::new(path.as_os_str().as_bytes()) {
+                    Ok(name) => {
+                        names.push(Some(name));
+                        results.push(None);
+                    }
+                    Err(e) => {
+                        names.push(None);
+                        results.push(Some(Err(io::Error::new(io::ErrorKind::InvalidInput, e))));
+                    }
+                }
+            }
+            let named = names.iter().enumerate();
+            let mut pending = named.filter_map(|(index, name)| Some((index, name.as_ref()?)));
+            let capacity = self.params.sq_entries as usize;
+            let mut failure = None;
+            loop {
+                let mut queued = 0;
+                for (index, name) in pending.by_ref().take(capacity) {
+                    let mut sqe = Sqe {
+                        user_data: index as u64,
+                        ..Sqe::default()
+                    };
+                    prepare(&mut sqe, index, name);
+                    self.push(sqe, queued);
+                    queued += 1;
+                }
+                if queued == 0 {
+                    break;
+                }
+                if let Err(e) = self.submit_and_wait(queued, &mut results) {
+                    self.broken = true;
+                    failure = Some(e);
+                    break;
+                }
+            }
+            if self.broken {
+                mem::forget(names);
+            }
+            let failed = |e: &io::Error| io::Error::new(e.kind(), e.to_string());
+            let not_run = || Err(failure.as_ref().map_or(io::ErrorKind::Other.into(), failed));
+            results.into_iter().map(|result| result.unwrap_or_else(not_run)).collect()
+        }
+
+        /// Writes an entry to the submission queue, at `queued` places past its tail.
+        fn push(&self, sqe: Sqe, queued: usize) {
+            let off = &self.params.sq_off;
+            let tail = unsafe { (*self.sq.at::<AtomicU32>(off.tail)).load(Ordering::Relaxed) };
+            let mask = unsafe { *self.sq.at::<u32>(off.ring_mask) };
+            let slot = (tail.wrapping_add(queued as u32) & mask) as usize;
+            unsafe {
+                ptr::write(self.sqes.at::<Sqe>(0).add(slot), sqe);
+                *self.sq.at::<u32>(off.array).add(slot) = slot as u32;
+            }
+        }
+
+        /// Publishes `queued` entries and waits for their completions.
+        fn submit_and_wait(
+            &self,
+            queued: usize,
+            results: &mut [Option<io::Result<i32>>],
+        ) -> io::Result<()> {
+            let fd = std::os::fd::AsRawFd::as_raw_fd(&self.fd);
+            let off = &self.params.sq_off;
+            let tail = unsafe { &*self.sq.at::<AtomicU32>(off.tail) };
+            tail.store(tail.load(Ordering::Relaxed).wrapping_add(queued as u32), Ordering::Release);
+            let (mut submitted, mut completed) = (0, 0);
+            while completed < queued {
+                let to_submit = (queued - submitted) as u32;
+                let (flags, sig) = (IORING_ENTER_GETEVENTS, ptr::null::<c_void>());
+                let ret =
+                    unsafe { syscall(SYS_IO_URING_ENTER, fd, to_submit, 1u32, flags, sig, 0usize) };
+                if ret < 0 {
+                    let e = io::Error::last_os_error();
+                    if e.raw_os_error() == Some(EINTR) {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                submitted += ret as usize;
+                completed += self.reap(results);
+            }
+            Ok(())
+        }
+
+        /// Takes the available completions, storing their results; returns their number.
+        fn reap(&self, results: &mut [Option<io::Result<i32>>]) -> usize {
+            let (ring, off) = match &self.cq {
+                Some(cq) => (cq, &self.params.cq_off),
+                None => (&self.sq, &self.params.cq_off),
+            };
+            let head = unsafe { &*ring.at::<AtomicU32>(off.head) };
+            let tail = unsafe { &*ring.at::<AtomicU32>(off.tail) };
+            let mask = unsafe { *ring.at::<u32>(off.ring_mask) };
+            let (mut current, end) = (head.load(Ordering::Relaxed), tail.load(Ordering::Acquire));
+            let mut count = 0;
+            while current != end {
+                let cqe = unsafe { &*ring.at::<Cqe>(off.cqes).add((current & mask) as usize) };
+                if let Some(result) = results.get_mut(cqe.user_data as usize) {
+                    *result = Some(match cqe.res {
+                        res if res >= 0 => Ok(res),
+                        res => Err(io::Error::from_raw_os_error(-res)),
+                    });
+                }
+                current = current.wrapping_add(1);
+                count += 1;
+            }
+            head.store(current, Ordering::Release);
+            count
+        }
+    }
+
+    fn metadata(stat: &Statx) -> FsMetadata {
+        let kind = match stat.mode & S_IFMT {
+            S_IFDIR => FileKind::Dir,
+            S_IFREG => FileKind::File,
+            S_IFLNK => FileKind::Symlink,
+            _ => FileKind::Other,
+        };
+        let modified = (stat.mask & STATX_MTIME != 0).then(|| {
+            let nanos = Duration::from_nanos(u64::from(stat.mtime.nsec));
+            match u64::try_from(stat.mtime.sec) {
+                Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nanos,
+                Err(_) => UNIX_EPOCH - Duration::from_secs(stat.mtime.sec.unsigned_abs()) + nanos,
+            }
+        });
+        FsMetadata {
+            kind,
+            len: stat.size,
+            modified,
+            device: Some(makedev(stat.dev_major, stat.dev_minor)),
+        }
+    }
+
+    /// Combines a device number the way glibc does for `st_dev`.
+    fn makedev(major: u32, minor: u32) -> u64 {
+        let (major, minor) = (u64::from(major), u64::from(minor));
+        ((major & 0xffff_f000) << 32)
+            | ((major & 0xfff) << 8)
+            | ((minor & 0xffff_ff00) << 12)
+            | (minor & 0xff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::uring::{open_batch, stat_batch};
+    use crate::vfs::{FileKind, FsMetadata};
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn batches_match_std() {
+        let mut builder = TreeBuilder::new().symlink("link", "f0").dir("dir");
+        for i in 0..300 {
+            builder = builder.file(format!("f{}", i), " ".repeat(i));
+        }
+        let tree = builder.build().unwrap();
+        let mut paths: Vec<_> = (0..300).map(|i| tree.join(format!("f{}", i))).collect();
+        paths.extend([tree.join("link"), tree.join("dir"), tree.join("missing")]);
+
+        let stats = stat_batch(&paths);
+        for (path, stat) in paths.iter().zip(&stats).take(302) {
+            let expected = FsMetadata::from(fs::symlink_metadata(path).unwrap());
+            assert_eq!(*stat.as_ref().unwrap(), expected);
+        }
+        assert_eq!(stats[300].as_ref().unwrap().kind, FileKind::Symlink);
+        let missing = stats[302].as_ref().unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+
+        let mut files = open_batch(&paths[..300]);
+        let mut contents = String::new();
+        files[7].as_mut().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents.len(), 7);
+        assert!(open_batch(&[tree.join("missing")])[0].is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::simplify_verbatim;
+use crate::result::Result;
+
+/// Result of [`verify_complete`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Completeness {
+    /// Reachable entries that were not yielded.
+    pub missing: Vec<PathBuf>,
+    /// Yielded entries that are not reachable from the root.
+    pub unexpected: Vec<PathBuf>,
+    /// Entries yielded more than once.
+    pub duplicates: Vec<PathBuf>,
+}
+
+impl Completeness {
+    /// Checks whether the traversal yielded exactly the reachable entries, once each.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.duplicates.is_empty()
+    }
+}
+
+/// Collects every non-directory entry reachable from `dir` with a plain recursive walk.
+/// Symbolic links are not followed and unreadable directories are skipped, like in ReadDir.
+fn reachable(dir: &Path, out: &mut BTreeMap<PathBuf, usize>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => reachable(&entry.path(), out),
+            Ok(_) => {
+                out.insert(entry.path(), 0);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Checks that a traversal of `root` yielded exactly the set of reachable entries
+/// (everything except directories, without following symbolic links), each exactly once.
+/// The reference set is computed by an independent walk, so the result is only meaningful
+/// if the tree is not modified concurrently.
+///
+/// # Arguments:
+///
+/// * `root` - root directory of the traversal.
+/// * `yielded` - paths yielded by the traversal.
+pub fn verify_complete<P, I>(root: P, yielded: I) -> Result<Completeness>
+where
+    P: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let mut expected = BTreeMap::new();
+    reachable(&root, &mut expected);
+    let mut report = Completeness::default();
+    for path in yielded {
+        let path = path.as_ref();
+        match expected.get_mut(path) {
+            Some(seen) => {
+                *seen += 1;
+                if *seen == 2 {
+                    report.duplicates.push(path.to_path_buf());
+                }
+            }
+            None => report.unexpected.push(path.to_path_buf()),
+        }
+    }
+    report.missing = expected
+        .into_iter()
+        .filter(|(_, seen)| *seen == 0)
+        .map(|(path, _)| path)
+        .collect();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::verify::verify_complete;
+    use crate::ReadDir;
+    use std::path::PathBuf;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    /// Generates a random tree of files, empty directories and (possibly cyclic) symlinks.
+    fn random_tree(rng: &mut Rng) -> TreeBuilder {
+        let mut builder = TreeBuilder::new();
+        let mut dirs = vec![PathBuf::new()];
+        for i in 0..rng.below(60) + 1 {
+            let parent = dirs[rng.below(dirs.len() as u64) as usize].clone();
+            let name = parent.join(format!("e{}", i));
+            builder = match rng.below(10) {
+                0..=5 => builder.file(name, "x"),
+                6..=7 => {
+                    dirs.push(name.clone());
+                    builder.dir(name)
+                }
+                8 => builder.symlink(name, ".."),
+                _ => builder.symlink(name, "missing"),
+            };
+        }
+        builder
+    }
+
+    #[test]
+    fn traversal_is_complete_on_random_trees() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        for _ in 0..30 {
+            let tree = random_tree(&mut rng).build().unwrap();
+            for (is_lazy, is_multithreaded) in [(false, false), (true, false), (false, true)] {
+                let mut rd = ReadDir::try_new(tree.path()).unwrap();
+                rd.is_lazy = is_lazy;
+                rd.is_multithreaded = is_multithreaded;
+                let yielded: Vec<_> = rd.map(|e| e.into_path()).collect();
+                let report = verify_complete(tree.path(), &yielded).unwrap();
+                assert!(report.is_complete(), "{:?}", report);
+            }
+        }
+    }
+
+    #[test]
+    fn incomplete_traversal_reported() {
+        let tree = TreeBuilder::new()
+            .file("a", "")
+            .file("b/c", "")
+            .build()
+            .unwrap();
+        let a = tree.path().canonicalize().unwrap().join("a");
+        let report = verify_complete(tree.path(), [&a, &a, &PathBuf::from("/nope")]).unwrap();
+        assert_eq!(report.duplicates, vec![a]);
+        assert_eq!(report.unexpected, vec![PathBuf::from("/nope")]);
+        assert_eq!(report.missing.len(), 1);
+    }
+}
@@ -0,0 +1,916 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::atomic::{self, temp_path_for};
+use crate::attrs::{self, FileAttrs};
+use crate::durability::Durability;
+use crate::fifo;
+use crate::links;
+use crate::streams::{self, Stream};
+
+/// Kind of a filesystem entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl From<fs::FileType> for FileKind {
+    fn from(file_type: fs::FileType) -> FileKind {
+        if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else if file_type.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Other
+        }
+    }
+}
+
+/// Metadata of a filesystem entry, as reported by an [`Fs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsMetadata {
+    pub kind: FileKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// Identifier of the device (mounted filesystem) holding the entry, if known.
+    pub device: Option<u64>,
+}
+
+impl From<fs::Metadata> for FsMetadata {
+    fn from(meta: fs::Metadata) -> FsMetadata {
+        FsMetadata {
+            kind: meta.file_type().into(),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            device: device_of(&meta),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn device_of(meta: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_meta: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// An entry of a directory listing, as reported by an [`Fs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsDirEntry {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    /// The metadata of the entry, without following symbolic links, if the listing
+    /// returned it; entries then need no metadata read of their own.
+    pub meta: Option<FsMetadata>,
+}
+
+/// Iterator over the entries of a directory.
+pub type FsReadDir = Box<dyn Iterator<Item = io::Result<FsDirEntry>> + Send>;
+
+/// An entry of a directory listing by name, as reported by [`ReadFs::read_dir_names`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsDirName {
+    pub name: OsString,
+    pub kind: FileKind,
+    /// The metadata of the entry, as in [`FsDirEntry::meta`].
+    pub meta: Option<FsMetadata>,
+}
+
+/// Iterator over the names of the entries of a directory.
+pub type FsReadNames = Box<dyn Iterator<Item = io::Result<FsDirName>> + Send>;
+
+/// Filesystem operations that never modify the filesystem; all traversals only need these.
+pub trait ReadFs: Send + Sync {
+    /// Lists the entries of a directory.
+    fn read_dir(&self, path: &Path) -> io::Result<FsReadDir>;
+    /// Lists the names of the entries of a directory, without building their paths.
+    /// Defaults to taking the names from `read_dir`.
+    fn read_dir_names(&self, path: &Path) -> io::Result<FsReadNames> {
+        Ok(Box::new(self.read_dir(path)?.map(|entry| {
+            let entry = entry?;
+            Ok(FsDirName {
+                name: entry.path.file_name().unwrap_or_default().to_os_string(),
+                kind: entry.kind,
+                meta: entry.meta,
+            })
+        })))
+    }
+    /// Returns the metadata of an entry, following symbolic links.
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata>;
+    /// Returns the metadata of an entry, without following symbolic links.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata>;
+    /// Returns the metadata of several entries, without following symbolic links, in the
+    /// order of `paths`. Defaults to calling `symlink_metadata` for each.
+    fn symlink_metadata_batch(&self, paths: &[&Path]) -> Vec<io::Result<FsMetadata>> {
+        paths.iter().map(|path| self.symlink_metadata(path)).collect()
+    }
+    /// Returns the canonical, absolute form of a path.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+    /// Opens a file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+    /// Returns the target of a symbolic link; unsupported unless implemented.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: symbolic links are not supported", path.display()),
+        ))
+    }
+    /// Returns the permission bits of an entry, following symbolic links (on Windows only
+    /// the write bits, from the read-only flag); unsupported unless implemented.
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: permissions are not supported", path.display()),
+        ))
+    }
+    /// Returns the inode flags of an entry; unsupported unless implemented.
+    fn attrs(&self, path: &Path) -> io::Result<FileAttrs> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: file attributes are not supported", path.display()),
+        ))
+    }
+    /// Returns the alternate data streams of an entry; none unless implemented.
+    fn streams(&self, _path: &Path) -> io::Result<Vec<Stream>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Filesystem operations used by the crate.
+/// [`RealFs`] forwards to `std::fs`; [`MemFs`] keeps the tree in memory for tests.
+pub trait Fs: ReadFs {
+    /// Creates (or truncates) a file for writing.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>>;
+    /// Creates a directory and all of its missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Removes a file or a symbolic link.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Removes an empty directory.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    /// Renames an entry.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Creates or replaces a file with everything read from `data`, atomically: readers see
+    /// the old or the new contents. The file gets the permission bits `mode`, or if `None`
+    /// those of the file it replaces, where permissions are supported. Defaults to writing
+    /// a temporary file next to it and renaming it over the file.
+    fn write_atomic(&self, path: &Path, data: &mut dyn Read, mode: Option<u32>) -> io::Result<()> {
+        let tmp = temp_path_for(path);
+        let result = (|| {
+            let mut file = self.create(&tmp)?;
+            io::copy(data, &mut file)?;
+            file.flush()?;
+            drop(file);
+            if let Some(mode) = mode.or_else(|| self.mode(path).ok()) {
+                match self.set_mode(&tmp, mode) {
+                    Err(e) if e.kind() != io::ErrorKind::Unsupported => return Err(e),
+                    _ => {}
+                }
+            }
+            self.rename(&tmp, path)
+        })();
+        if result.is_err() {
+            let _ = self.remove_file(&tmp);
+        }
+        result
+    }
+    /// Creates a symbolic link at `link` pointing to `target`; unsupported unless
+    /// implemented.
+    fn symlink(&self, _target: &Path, link: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: symbolic links are not supported", link.display()),
+        ))
+    }
+    /// Sets the permission bits of an entry, following symbolic links (on Windows only the
+    /// read-only flag, from the write bits); unsupported unless implemented.
+    fn set_mode(&self, path: &Path, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: permissions are not supported", path.display()),
+        ))
+    }
+    /// Creates a named pipe (FIFO) with the given permission bits; unsupported unless
+    /// implemented.
+    fn create_fifo(&self, path: &Path, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: FIFOs are not supported", path.display()),
+        ))
+    }
+}
+
+/// The real filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl ReadFs for RealFs {
+    /// Reads the metadata of the entries with the listing on macOS, with
+    /// `getattrlistbulk`, and on Windows, with `NtQueryDirectoryFile`.
+    fn read_dir(&self, path: &Path) -> io::Result<FsReadDir> {
+        #[cfg(any(target_os = "macos", windows))]
+        {
+            let dir = path.to_path_buf();
+            let entries = crate::bulk::list(path)?;
+            Ok(Box::new(entries.into_iter().map(move |entry| {
+                entry.map(|(name, meta)| FsDirEntry {
+                    path: dir.join(name),
+                    kind: meta.kind,
+                    meta: Some(meta),
+                })
+            })))
+        }
+        #[cfg(not(any(target_os = "macos", windows)))]
+        Ok(Box::new(fs::read_dir(path)?.map(|entry| {
+            let entry = entry?;
+            Ok(FsDirEntry {
+                kind: entry.file_type()?.into(),
+                path: entry.path(),
+                meta: None,
+            })
+        })))
+    }
+
+    /// Reads names with `readdir` on Linux, which allocates just the name of each entry,
+    /// and names with metadata on macOS and Windows, as `read_dir` does.
+    fn read_dir_names(&self, path: &Path) -> io::Result<FsReadNames> {
+        #[cfg(target_os = "linux")]
+        {
+            let entries = crate::dirfd::Dir::open(path)?.entries()?;
+            Ok(Box::new(entries.into_iter().map(|entry| {
+                entry.map(|(name, kind)| FsDirName {
+                    name,
+                    kind,
+                    meta: None,
+                })
+            })))
+        }
+        #[cfg(any(target_os = "macos", windows))]
+        {
+            let entries = crate::bulk::list(path)?;
+            Ok(Box::new(entries.into_iter().map(|entry| {
+                entry.map(|(name, meta)| FsDirName {
+                    name,
+                    kind: meta.kind,
+                    meta: Some(meta),
+                })
+            })))
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+        Ok(Box::new(fs::read_dir(path)?.map(|entry| {
+            let entry = entry?;
+            Ok(FsDirName {
+                kind: entry.file_type()?.into(),
+                name: entry.file_name(),
+                meta: None,
+            })
+        })))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        fs::metadata(path).map(FsMetadata::from)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        fs::symlink_metadata(path).map(FsMetadata::from)
+    }
+
+    /// Reads the batch through io_uring with the `io-uring` feature on Linux.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn symlink_metadata_batch(&self, paths: &[&Path]) -> Vec<io::Result<FsMetadata>> {
+        crate::uring::stat_batch(paths)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        Ok(mode_of(&fs::metadata(path)?.permissions()))
+    }
+
+    fn attrs(&self, path: &Path) -> io::Result<FileAttrs> {
+        attrs::sys::get(path)
+    }
+
+    fn streams(&self, path: &Path) -> io::Result<Vec<Stream>> {
+        streams::sys::list(path)
+    }
+}
+
+impl Fs for RealFs {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        Ok(Box::new(fs::File::create(path)?))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    /// Also flushes the data to disk before the rename, like [`write_atomic`].
+    ///
+    /// [`write_atomic`]: crate::write_atomic
+    fn write_atomic(&self, path: &Path, data: &mut dyn Read, mode: Option<u32>) -> io::Result<()> {
+        atomic::write_atomic_from(path, data, Durability::DataOnly, mode)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        links::symlink(target, link)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut permissions = fs::metadata(path)?.permissions();
+        set_mode_of(&mut permissions, mode);
+        fs::set_permissions(path, permissions)
+    }
+
+    fn create_fifo(&self, path: &Path, mode: u32) -> io::Result<()> {
+        fifo::sys::create(path, mode)
+    }
+}
+
+#[cfg(unix)]
+fn mode_of(permissions: &fs::Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(permissions: &fs::Permissions) -> u32 {
+    if permissions.readonly() {
+        0o444
+    } else {
+        0o666
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn set_mode_of(permissions: &mut fs::Permissions, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.set_mode(mode);
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_mode_of(permissions: &mut fs::Permissions, mode: u32) {
+    permissions.set_readonly(mode & 0o222 == 0);
+}
+
+/// A view of a filesystem that only offers the operations of [`ReadFs`], so code handed
+/// a `ReadOnlyFs` (e.g. an audit or report tool) can not modify the tree it scans:
+/// mutating calls do not compile.
+///
+/// ```compile_fail
+/// use fs_helper::{Fs, ReadOnlyFs, RealFs};
+///
+/// let fs = ReadOnlyFs::new(RealFs);
+/// fs.remove_file("scanned.txt".as_ref()).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOnlyFs<F> {
+    inner: F,
+}
+
+impl<F: ReadFs> ReadOnlyFs<F> {
+    /// Wraps a filesystem.
+    pub fn new(inner: F) -> ReadOnlyFs<F> {
+        ReadOnlyFs { inner }
+    }
+}
+
+impl<F: ReadFs> ReadFs for ReadOnlyFs<F> {
+    fn read_dir(&self, path: &Path) -> io::Result<FsReadDir> {
+        self.inner.read_dir(path)
+    }
+
+    fn read_dir_names(&self, path: &Path) -> io::Result<FsReadNames> {
+        self.inner.read_dir_names(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn symlink_metadata_batch(&self, paths: &[&Path]) -> Vec<io::Result<FsMetadata>> {
+        self.inner.symlink_metadata_batch(paths)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.canonicalize(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        self.inner.open(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        self.inner.mode(path)
+    }
+
+    fn attrs(&self, path: &Path) -> io::Result<FileAttrs> {
+        self.inner.attrs(path)
+    }
+
+    fn streams(&self, path: &Path) -> io::Result<Vec<Stream>> {
+        self.inner.streams(path)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    /// Contents, modification time and permission bits.
+    File(Arc<Vec<u8>>, SystemTime, u32),
+    Dir,
+    Symlink(PathBuf),
+}
+
+type Tree = Arc<Mutex<BTreeMap<PathBuf, Node>>>;
+
+/// An in-memory filesystem, so code using [`Fs`] can be unit tested without touching the disk.
+/// Paths are absolute and resolved lexically; `/` always exists. Clones share the same tree.
+#[derive(Debug, Clone)]
+pub struct MemFs {
+    tree: Tree,
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        MemFs::new()
+    }
+}
+
+/// Permission bits of new files in a [`MemFs`].
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{}: not found", path.display()),
+    )
+}
+
+/// Makes a path absolute and removes `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => out.push(name),
+            Component::ParentDir => {
+                out.pop();
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+impl MemFs {
+    /// Creates an empty filesystem containing only `/`.
+    pub fn new() -> MemFs {
+        let mut tree = BTreeMap::new();
+        tree.insert(PathBuf::from("/"), Node::Dir);
+        MemFs {
+            tree: Arc::new(Mutex::new(tree)),
+        }
+    }
+
+    /// Creates a file with the given contents (and its missing parents).
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C) -> io::Result<()> {
+        let path = normalize(path.as_ref());
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let mode = self.file_mode(&path);
+        let node = Node::File(Arc::new(contents.as_ref().to_vec()), SystemTime::now(), mode);
+        insert(&self.tree, path, node)
+    }
+
+    /// Reads the contents of a file.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.open(path.as_ref())?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Creates a symbolic link at `link` pointing to `target` (and the missing parents of `link`).
+    pub fn symlink<T: AsRef<Path>, L: AsRef<Path>>(&self, target: T, link: L) -> io::Result<()> {
+        let link = normalize(link.as_ref());
+        if let Some(parent) = link.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let node = Node::Symlink(target.as_ref().to_path_buf());
+        insert(&self.tree, link, node)
+    }
+
+    /// Returns the permission bits of the file at `path`, kept when it is replaced, or the
+    /// default of new files.
+    fn file_mode(&self, path: &Path) -> u32 {
+        match self.tree.lock().unwrap().get(path) {
+            Some(Node::File(_, _, mode)) => *mode,
+            _ => DEFAULT_FILE_MODE,
+        }
+    }
+
+    /// Resolves every symbolic link of a path, returning the resolved path and its node.
+    fn resolve(&self, path: &Path) -> io::Result<(PathBuf, Node)> {
+        let tree = self.tree.lock().unwrap();
+        // components left to resolve, in reverse order; `None` stands for `..`
+        let mut pending: Vec<Option<OsString>> = Vec::new();
+        push_components(&mut pending, &normalize(path));
+        let mut current = PathBuf::from("/");
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            let name = match name {
+                Some(name) => name,
+                None => {
+                    current.pop();
+                    continue;
+                }
+            };
+            let next = current.join(&name);
+            match tree.get(&next) {
+                Some(Node::Symlink(target)) => {
+                    links += 1;
+                    if links > 40 {
+                        return Err(io::Error::other("too many levels of symbolic links"));
+                    }
+                    if target.has_root() {
+                        current = PathBuf::from("/");
+                    }
+                    push_components(&mut pending, target);
+                }
+                Some(_) => current = next,
+                None => return Err(not_found(&next)),
+            }
+        }
+        let node = tree.get(&current).cloned().ok_or_else(|| not_found(&current))?;
+        Ok((current, node))
+    }
+}
+
+fn push_components(pending: &mut Vec<Option<OsString>>, path: &Path) {
+    for component in path.components().rev() {
+        match component {
+            Component::Normal(name) => pending.push(Some(name.to_os_string())),
+            Component::ParentDir => pending.push(None),
+            _ => {}
+        }
+    }
+}
+
+fn insert(tree: &Tree, path: PathBuf, node: Node) -> io::Result<()> {
+    let mut tree = tree.lock().unwrap();
+    match path.parent().map(|parent| tree.get(parent)) {
+        Some(Some(Node::Dir)) | None => {}
+        _ => return Err(not_found(path.parent().unwrap())),
+    }
+    if let Some(Node::Dir) = tree.get(&path) {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            format!("{}: is a directory", path.display()),
+        ));
+    }
+    tree.insert(path, node);
+    Ok(())
+}
+
+fn metadata_of(node: &Node) -> FsMetadata {
+    match node {
+        Node::File(data, modified, _) => FsMetadata {
+            kind: FileKind::File,
+            len: data.len() as u64,
+            modified: Some(*modified),
+            device: None,
+        },
+        Node::Dir => FsMetadata {
+            kind: FileKind::Dir,
+            len: 0,
+            modified: None,
+            device: None,
+        },
+        Node::Symlink(target) => FsMetadata {
+            kind: FileKind::Symlink,
+            len: target.as_os_str().len() as u64,
+            modified: None,
+            device: None,
+        },
+    }
+}
+
+/// Buffers written data and stores it in the tree when dropped or flushed.
+struct MemWriter {
+    tree: Tree,
+    path: PathBuf,
+    mode: u32,
+    buf: Vec<u8>,
+}
+
+impl Write for MemWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let node = Node::File(Arc::new(self.buf.clone()), SystemTime::now(), self.mode);
+        insert(&self.tree, self.path.clone(), node)
+    }
+}
+
+impl Drop for MemWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl ReadFs for MemFs {
+    fn read_dir(&self, path: &Path) -> io::Result<FsReadDir> {
+        let (dir, node) = self.resolve(path)?;
+        if !matches!(node, Node::Dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{}: not a directory", dir.display()),
+            ));
+        }
+        // list children under the requested (possibly symlinked) path
+        let base = normalize(path);
+        let tree = self.tree.lock().unwrap();
+        let entries: Vec<_> = tree
+            .range(dir.clone()..)
+            .skip(1)
+            .take_while(|(p, _)| p.starts_with(&dir))
+            .filter(|(p, _)| p.parent() == Some(dir.as_path()))
+            .map(|(p, node)| {
+                let meta = metadata_of(node);
+                Ok(FsDirEntry {
+                    path: base.join(p.file_name().unwrap()),
+                    kind: meta.kind,
+                    meta: Some(meta),
+                })
+            })
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        self.resolve(path).map(|(_, node)| metadata_of(&node))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        let path = normalize(path);
+        let tree = self.tree.lock().unwrap();
+        tree.get(&path)
+            .map(metadata_of)
+            .ok_or_else(|| not_found(&path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.resolve(path).map(|(path, _)| path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.resolve(path)? {
+            (_, Node::File(data, _, _)) => Ok(Box::new(Cursor::new(data.to_vec()))),
+            (path, _) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", path.display()),
+            )),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        match self.tree.lock().unwrap().get(&path) {
+            Some(Node::Symlink(target)) => Ok(target.clone()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: not a symbolic link", path.display()),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    /// Directories have mode 0755.
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        match self.resolve(path)? {
+            (_, Node::File(_, _, mode)) => Ok(mode),
+            _ => Ok(0o755),
+        }
+    }
+}
+
+impl Fs for MemFs {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        let path = normalize(path);
+        let writer = MemWriter {
+            tree: Arc::clone(&self.tree),
+            mode: self.file_mode(&path),
+            path,
+            buf: Vec::new(),
+        };
+        let node = Node::File(Arc::new(Vec::new()), SystemTime::now(), writer.mode);
+        insert(&self.tree, writer.path.clone(), node)?;
+        Ok(Box::new(writer))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree.lock().unwrap();
+        for ancestor in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            match tree.get(ancestor) {
+                Some(Node::Dir) => {}
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{}: not a directory", ancestor.display()),
+                    ))
+                }
+                None => {
+                    tree.insert(ancestor.to_path_buf(), Node::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree.lock().unwrap();
+        match tree.get(&path) {
+            Some(Node::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", path.display()),
+            )),
+            Some(_) => {
+                tree.remove(&path);
+                Ok(())
+            }
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree.lock().unwrap();
+        match tree.get(&path) {
+            Some(Node::Dir) => {}
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("{}: not a directory", path.display()),
+                ))
+            }
+            None => return Err(not_found(&path)),
+        }
+        if tree.range(path.clone()..).nth(1).is_some_and(|(p, _)| p.starts_with(&path)) {
+            return Err(io::Error::new(
+                io::ErrorKind::DirectoryNotEmpty,
+                format!("{}: directory not empty", path.display()),
+            ));
+        }
+        tree.remove(&path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut tree = self.tree.lock().unwrap();
+        if !tree.contains_key(&from) {
+            return Err(not_found(&from));
+        }
+        let moved: Vec<PathBuf> = tree
+            .range(from.clone()..)
+            .take_while(|(p, _)| p.starts_with(&from))
+            .map(|(p, _)| p.clone())
+            .collect();
+        for path in moved {
+            let node = tree.remove(&path).unwrap();
+            let target = to.join(path.strip_prefix(&from).unwrap());
+            tree.insert(normalize(&target), node);
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let link = normalize(link);
+        if self.tree.lock().unwrap().contains_key(&link) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{}: already exists", link.display()),
+            ));
+        }
+        insert(&self.tree, link, Node::Symlink(target.to_path_buf()))
+    }
+
+    /// Only files have permission bits; setting those of a directory has no effect.
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let (path, _) = self.resolve(path)?;
+        if let Some(Node::File(_, _, bits)) = self.tree.lock().unwrap().get_mut(&path) {
+            *bits = mode & 0o7777;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vfs::{FileKind, Fs, MemFs, ReadFs, ReadOnlyFs};
+    use crate::ReadDir;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn mem_fs_operations() {
+        let fs = MemFs::new();
+        fs.write("/a/b/c.txt", "hello").unwrap();
+        fs.symlink("b", "/a/link").unwrap();
+        fs.create(Path::new("/a/d.txt"))
+            .unwrap()
+            .write_all(b"data")
+            .unwrap();
+
+        assert_eq!(fs.read("/a/link/c.txt").unwrap(), b"hello");
+        assert_eq!(fs.read("/a/d.txt").unwrap(), b"data");
+        assert_eq!(fs.metadata(Path::new("/a/b")).unwrap().kind, FileKind::Dir);
+        let link = fs.symlink_metadata(Path::new("/a/link")).unwrap();
+        assert_eq!(link.kind, FileKind::Symlink);
+        let names: Vec<_> = fs
+            .read_dir(Path::new("/a"))
+            .unwrap()
+            .map(|e| e.unwrap().path)
+            .collect();
+        assert_eq!(names.len(), 3);
+
+        fs.set_mode(Path::new("/a/link/c.txt"), 0o755).unwrap();
+        fs.write("/a/b/c.txt", "hello").unwrap();
+        assert_eq!(fs.mode(Path::new("/a/b/c.txt")).unwrap(), 0o755);
+        assert_eq!(fs.read_link(Path::new("/a/link")).unwrap(), Path::new("b"));
+        Fs::symlink(&fs, Path::new("d.txt"), Path::new("/a/e")).unwrap();
+        assert!(Fs::symlink(&fs, Path::new("d.txt"), Path::new("/a/e")).is_err());
+        assert_eq!(fs.read("/a/e").unwrap(), b"data");
+        fs.remove_file(Path::new("/a/e")).unwrap();
+
+        assert!(fs.remove_dir(Path::new("/a/b")).is_err());
+        fs.rename(Path::new("/a/b"), Path::new("/x")).unwrap();
+        assert_eq!(fs.read("/x/c.txt").unwrap(), b"hello");
+        fs.remove_file(Path::new("/x/c.txt")).unwrap();
+        fs.remove_dir(Path::new("/x")).unwrap();
+        assert!(fs.metadata(Path::new("/x")).is_err());
+    }
+
+    #[test]
+    fn read_only_fs_traversal() {
+        let mem = MemFs::new();
+        mem.write("/t/a.txt", "a").unwrap();
+        mem.write("/t/sub/b.txt", "b").unwrap();
+        let fs = ReadOnlyFs::new(mem);
+        assert_eq!(fs.metadata(Path::new("/t/a.txt")).unwrap().len, 1);
+        let mut paths: Vec<_> = ReadDir::try_new_in(Arc::new(fs), "/t")
+            .unwrap()
+            .map(|entry| entry.into_path())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, [Path::new("/t/a.txt"), Path::new("/t/sub/b.txt")]);
+    }
+}
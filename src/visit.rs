@@ -0,0 +1,225 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::dirfd::{Dir, DirEntry};
+use crate::entry::Entry;
+use crate::paths::simplify_verbatim;
+use crate::result::{Error, Result};
+use crate::vfs::{FileKind, ReadFs};
+
+/// Tells the walker how to continue after a visitor callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Continue the traversal.
+    Continue,
+    /// Skip the rest of the current directory (or do not descend into it, from `enter_dir`).
+    Prune,
+    /// Stop the traversal.
+    Stop,
+}
+
+/// Callbacks invoked by [`walk`]. All methods have default implementations that continue.
+pub trait Visitor {
+    /// Called before the contents of a directory are visited. The root has depth 0.
+    fn enter_dir(&mut self, _dir: &Path, _depth: usize) -> Control {
+        Control::Continue
+    }
+
+    /// Called for every entry that is not a directory.
+    fn file(&mut self, _entry: &Entry) -> Control {
+        Control::Continue
+    }
+
+    /// Called after all contents of a directory have been visited
+    /// (also when the directory was pruned from `file`).
+    fn leave_dir(&mut self, _dir: &Path, _depth: usize) -> Control {
+        Control::Continue
+    }
+
+    /// Called when a directory or an entry can not be read.
+    /// Returning `Continue` or `Prune` skips the failed item.
+    fn error(&mut self, _path: &Path, _error: Error) -> Control {
+        Control::Continue
+    }
+}
+
+enum Step<H> {
+    /// A directory to enter, with the open parent to reach it from (none for the root).
+    Enter(PathBuf, usize, Option<(H, OsString)>),
+    Leave(PathBuf, usize),
+}
+
+/// Walks the tree under `root`, reporting to `visitor`.
+/// Like `ReadDir`, all files of a directory are visited before its subdirectories.
+/// Symbolic links are reported as files and never followed.
+///
+/// Subdirectories are opened relative to their open parent, so renaming a directory
+/// of the tree (or replacing it with a link) during the walk can not lead it elsewhere.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `visitor` - callbacks receiving the entries.
+pub fn walk<P: AsRef<Path>, V: Visitor>(root: P, visitor: &mut V) -> Result<()> {
+    let root = simplify_verbatim(fs::canonicalize(root)?);
+    let list = |dir: &Path, parent: Option<&(Arc<Dir>, OsString)>| {
+        let handle = match parent {
+            Some((parent, name)) => parent.open_dir(name)?,
+            None => Dir::open(dir)?,
+        };
+        let entries = handle.entries()?;
+        Ok((Arc::new(handle), entries))
+    };
+    walk_tree(root, visitor, list, Entry::new)
+}
+
+/// Like [`walk`], for the tree of another filesystem, e.g. a [`MemFs`](crate::MemFs);
+/// entries read their metadata from it. Directories are listed by path.
+///
+/// # Arguments:
+///
+/// * `fs` - filesystem holding the tree.
+/// * `root` - root directory.
+/// * `visitor` - callbacks receiving the entries.
+pub fn walk_in<P: AsRef<Path>, V: Visitor>(
+    fs: Arc<dyn ReadFs>,
+    root: P,
+    visitor: &mut V,
+) -> Result<()> {
+    let root = simplify_verbatim(fs.canonicalize(root.as_ref())?);
+    let list = |dir: &Path, _: Option<&((), OsString)>| {
+        let entries = fs.read_dir(dir)?.map(|entry| {
+            let entry = entry?;
+            Ok((entry.path.file_name().unwrap_or_default().to_os_string(), entry.kind))
+        });
+        Ok(((), entries.collect()))
+    };
+    let entry = |path, depth| Entry::new_in(path, depth, Arc::clone(&fs));
+    walk_tree(root, visitor, list, entry)
+}
+
+/// Walks a tree whose directories are listed by `list`, given the path of a directory and
+/// the open parent and name to reach it from; `list` returns the open directory, `H`, for
+/// the subdirectories, and the entries. `entry` makes the entries passed to the visitor.
+fn walk_tree<V, H, L, E>(root: PathBuf, visitor: &mut V, mut list: L, mut entry: E) -> Result<()>
+where
+    V: Visitor,
+    H: Clone,
+    L: FnMut(&Path, Option<&(H, OsString)>) -> io::Result<(H, Vec<DirEntry>)>,
+    E: FnMut(PathBuf, usize) -> Entry,
+{
+    let mut stack = vec![Step::Enter(root, 0, None)];
+    let mut sub_dirs: Vec<(PathBuf, OsString)> = Vec::new();
+    while let Some(step) = stack.pop() {
+        let (dir, depth, parent) = match step {
+            Step::Leave(dir, depth) => {
+                if visitor.leave_dir(&dir, depth) == Control::Stop {
+                    return Ok(());
+                }
+                continue;
+            }
+            Step::Enter(dir, depth, parent) => (dir, depth, parent),
+        };
+        match visitor.enter_dir(&dir, depth) {
+            Control::Continue => {}
+            Control::Prune => continue,
+            Control::Stop => return Ok(()),
+        }
+        let (handle, entries) = match list(&dir, parent.as_ref()) {
+            Ok(listed) => listed,
+            Err(e) => {
+                if visitor.error(&dir, e.into()) == Control::Stop {
+                    return Ok(());
+                }
+                stack.push(Step::Leave(dir, depth));
+                continue;
+            }
+        };
+        let mut pruned = false;
+        for listed in entries {
+            let control = match listed {
+                Ok((name, FileKind::Dir)) => {
+                    sub_dirs.push((dir.join(&name), name));
+                    Control::Continue
+                }
+                Ok((name, _)) => visitor.file(&entry(dir.join(name), depth + 1)),
+                Err(e) => visitor.error(&dir, e.into()),
+            };
+            match control {
+                Control::Continue => {}
+                Control::Prune => {
+                    pruned = true;
+                    break;
+                }
+                Control::Stop => return Ok(()),
+            }
+        }
+        stack.push(Step::Leave(dir, depth));
+        if pruned {
+            sub_dirs.clear();
+        }
+        stack.extend(sub_dirs.drain(..).rev().map(|(path, name)| {
+            Step::Enter(path, depth + 1, Some((handle.clone(), name)))
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::visit::{walk, Control, Visitor};
+    use crate::Entry;
+    use std::path::Path;
+
+    #[derive(Default)]
+    struct Counter {
+        files: usize,
+        dirs: usize,
+        max_depth: usize,
+        log: Vec<String>,
+    }
+
+    impl Visitor for Counter {
+        fn enter_dir(&mut self, dir: &Path, depth: usize) -> Control {
+            self.dirs += 1;
+            self.log.push(format!("enter {}", depth));
+            if dir.ends_with("skip") {
+                return Control::Prune;
+            }
+            Control::Continue
+        }
+
+        fn file(&mut self, entry: &Entry) -> Control {
+            self.files += 1;
+            self.max_depth = self.max_depth.max(entry.depth());
+            Control::Continue
+        }
+
+        fn leave_dir(&mut self, _dir: &Path, depth: usize) -> Control {
+            self.log.push(format!("leave {}", depth));
+            Control::Continue
+        }
+    }
+
+    #[test]
+    fn walk_visitor() {
+        let tree = TreeBuilder::new()
+            .file("a/b/1.txt", b"")
+            .file("skip/2.txt", b"")
+            .file("3.txt", b"")
+            .build()
+            .unwrap();
+
+        let mut counter = Counter::default();
+        walk(tree.path(), &mut counter).unwrap();
+        assert_eq!(counter.files, 2);
+        assert_eq!(counter.dirs, 4);
+        assert_eq!(counter.max_depth, 3);
+        assert_eq!(counter.log.iter().filter(|s| s.starts_with("leave")).count(), 3);
+        assert_eq!(counter.log.last().unwrap(), "leave 0");
+    }
+}
@@ -0,0 +1,443 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::cancel::CancelToken;
+use crate::entry::Entry;
+use crate::normalize::{normalize_path, Normalization};
+use crate::order::{DirListing, Window};
+use crate::partition::Partition;
+use crate::progress::{ProgressEvent, ProgressSink};
+use crate::queue::{DoneGuard, WorkQueue};
+use crate::result::Result;
+use crate::rules::RuleSet;
+use crate::vfs::{FileKind, FsDirEntry, FsDirName, FsMetadata, ReadFs};
+
+/// Sending end of the entry channel, unbounded or bounded.
+pub(crate) enum EntrySender<T = Entry> {
+    Unbounded(mpsc::Sender<T>),
+    Bounded(mpsc::SyncSender<T>),
+}
+
+// derived Clone would require `T: Clone`
+impl<T> Clone for EntrySender<T> {
+    fn clone(&self) -> EntrySender<T> {
+        match self {
+            EntrySender::Unbounded(tx) => EntrySender::Unbounded(tx.clone()),
+            EntrySender::Bounded(tx) => EntrySender::Bounded(tx.clone()),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> EntrySender<T> {
+    /// Creates a channel; a bounded one blocks producers while `capacity` items are queued.
+    pub(crate) fn channel(capacity: Option<usize>) -> (EntrySender<T>, mpsc::Receiver<T>) {
+        match capacity {
+            Some(capacity) => {
+                let (tx, rx) = mpsc::sync_channel(capacity);
+                (EntrySender::Bounded(tx), rx)
+            }
+            None => {
+                let (tx, rx) = mpsc::channel();
+                (EntrySender::Unbounded(tx), rx)
+            }
+        }
+    }
+
+    pub(crate) fn send(&self, item: T) -> Result<()> {
+        match self {
+            EntrySender::Unbounded(tx) => tx.send(item)?,
+            EntrySender::Bounded(tx) => tx.send(item)?,
+        }
+        Ok(())
+    }
+}
+
+/// A directory for a worker of a multithreaded traversal to read: its path, depth and
+/// sequence number (used with `preserve_order` only).
+pub(crate) type DirJob = (PathBuf, usize, u64);
+
+/// Traversal settings shared by the traversal modes of ReadDir.
+///
+/// Directories and entries that can not be read (e.g. removed or renamed while the traversal
+/// is running) are skipped and reported as `SkippedEntry` progress events.
+/// Traversal functions only fail when the receiving end of the channel is gone.
+#[derive(Clone)]
+pub(crate) struct Walker {
+    pub(crate) fs: Arc<dyn ReadFs>,
+    pub(crate) norm: Option<Normalization>,
+    pub(crate) progress: Option<Arc<dyn ProgressSink>>,
+    pub(crate) cancel: Arc<AtomicBool>,
+    /// If set, directories on other devices than this one are not descended into.
+    pub(crate) device: Option<u64>,
+    /// How many times listing a directory is retried after a stale file handle error.
+    pub(crate) stale_retries: u32,
+    /// If set, the entries of each directory are visited in this order.
+    pub(crate) priority: Option<Priority>,
+    /// If set, only this part of the root directory is visited.
+    pub(crate) partition: Option<Arc<Partition>>,
+    /// If set, only entries selected by the rules are visited; they apply to paths
+    /// relative to `root`.
+    pub(crate) rules: Option<Arc<RuleSet>>,
+    pub(crate) root: PathBuf,
+    /// If set, directories at this depth are not descended into.
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) symlinks: SymlinkPolicy,
+    /// If set, the traversal also stops when this token is cancelled.
+    pub(crate) token: Option<CancelToken>,
+    /// If set, directories are listed by name and entries share their directory's path.
+    pub(crate) shared_dirs: bool,
+}
+
+/// An entry of a directory listing: by path, or by name in a directory whose path is shared
+/// with the other entries of the listing.
+pub(crate) enum Listed {
+    Path(FsDirEntry),
+    Name(Arc<Path>, FsDirName),
+}
+
+impl Listed {
+    pub(crate) fn kind(&self) -> FileKind {
+        match self {
+            Listed::Path(entry) => entry.kind,
+            Listed::Name(_, entry) => entry.kind,
+        }
+    }
+
+    /// Returns the metadata returned with the listing, if any.
+    pub(crate) fn meta(&self) -> Option<FsMetadata> {
+        match self {
+            Listed::Path(entry) => entry.meta,
+            Listed::Name(_, entry) => entry.meta,
+        }
+    }
+
+    /// Returns the path of the entry, joined for entries listed by name.
+    pub(crate) fn path(&self) -> Cow<'_, Path> {
+        match self {
+            Listed::Path(entry) => Cow::Borrowed(&entry.path),
+            Listed::Name(dir, entry) => Cow::Owned(dir.join(&entry.name)),
+        }
+    }
+
+    pub(crate) fn into_path(self) -> PathBuf {
+        match self {
+            Listed::Path(entry) => entry.path,
+            Listed::Name(dir, entry) => dir.join(entry.name),
+        }
+    }
+}
+
+/// Iterator over a directory listing.
+pub(crate) type Listing = Box<dyn Iterator<Item = io::Result<Listed>> + Send>;
+
+/// Order in which the entries of each directory are visited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Largest entries first.
+    Largest,
+    /// Most recently modified entries first.
+    Newest,
+}
+
+/// What a traversal does with symbolic links. Links are never followed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Yield links as entries.
+    #[default]
+    Yield,
+    /// Leave links out.
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PriorityKey {
+    Len(u64),
+    Modified(Option<SystemTime>),
+}
+
+/// Pause before retrying a directory listing that failed with a stale file handle.
+const STALE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+impl Walker {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+            || self.token.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    pub(crate) fn skipped(&self, path: &Path, error: &io::Error) {
+        if let Some(progress) = &self.progress {
+            progress.event(&ProgressEvent::SkippedEntry {
+                path,
+                kind: error.kind(),
+            });
+        }
+    }
+
+    /// Lists a directory; returns `None` if it can not be read.
+    /// Stale file handles (`ESTALE` on NFS) are retried up to `stale_retries` times.
+    pub(crate) fn read_dir(&self, dir: &Path) -> Option<Listing> {
+        let mut attempt = 0;
+        loop {
+            match self.list(dir) {
+                Ok(entries) => return Some(self.prioritize(entries)),
+                Err(e)
+                    if e.kind() == io::ErrorKind::StaleNetworkFileHandle
+                        && attempt < self.stale_retries =>
+                {
+                    attempt += 1;
+                    thread::sleep(STALE_RETRY_DELAY * attempt);
+                }
+                Err(e) => {
+                    self.skipped(dir, &e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Listing> {
+        if self.shared_dirs {
+            let shared: Arc<Path> = Arc::from(dir);
+            let names = self.fs.read_dir_names(dir)?;
+            let listed = move |name: io::Result<FsDirName>| Ok(Listed::Name(shared.clone(), name?));
+            Ok(Box::new(names.map(listed)))
+        } else {
+            Ok(Box::new(self.fs.read_dir(dir)?.map(|entry| entry.map(Listed::Path))))
+        }
+    }
+
+    /// Sorts a directory listing by the priority, if one is set. The metadata of the
+    /// entries not returned with the listing is read in one batch.
+    fn prioritize(&self, entries: Listing) -> Listing {
+        let priority = match self.priority {
+            Some(priority) => priority,
+            None => return entries,
+        };
+        let entries: Vec<_> = entries.collect();
+        let unknown = entries.iter().flatten().filter(|entry| entry.meta().is_none());
+        let paths: Vec<_> = unknown.map(Listed::path).collect();
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let mut metas = self.fs.symlink_metadata_batch(&paths).into_iter();
+        let mut keyed: Vec<_> = entries
+            .into_iter()
+            .map(|entry| {
+                let meta = entry.as_ref().ok().and_then(|entry| match entry.meta() {
+                    Some(meta) => Some(meta),
+                    None => metas.next()?.ok(),
+                });
+                let key = meta.map(|meta| match priority {
+                    Priority::Largest => Reverse(PriorityKey::Len(meta.len)),
+                    Priority::Newest => Reverse(PriorityKey::Modified(meta.modified)),
+                });
+                (key, entry)
+            })
+            .collect();
+        // entries without a key (unreadable) go last; the sort is stable
+        keyed.sort_by_key(|(key, _)| (key.is_none(), *key));
+        Box::new(keyed.into_iter().map(|(_, entry)| entry))
+    }
+
+    /// Checks whether a subdirectory found at `depth` should be visited, i.e. it is not
+    /// at the maximum depth and it is on the root's device or the device restriction is off.
+    pub(crate) fn descends(&self, dir: &Path, depth: usize) -> bool {
+        if self.max_depth.is_some_and(|max| depth >= max) {
+            return false;
+        }
+        let device = match self.device {
+            Some(device) => device,
+            None => return true,
+        };
+        match self.fs.symlink_metadata(dir) {
+            Ok(meta) => meta.device.is_none_or(|d| d == device),
+            Err(e) => {
+                self.skipped(dir, &e);
+                false
+            }
+        }
+    }
+
+    /// Returns the entry if it could be read, belongs to the partition, if any,
+    /// is selected by the rules, if any, and is not a skipped symbolic link.
+    pub(crate) fn check(
+        &self,
+        dir: &Path,
+        depth: usize,
+        entry: io::Result<Listed>,
+    ) -> Option<Listed> {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                self.skipped(dir, &e);
+                return None;
+            }
+        };
+        let kind = entry.kind();
+        if kind == FileKind::Symlink && self.symlinks == SymlinkPolicy::Skip {
+            return None;
+        }
+        if let (Some(partition), 1) = (&self.partition, depth) {
+            if !partition.contains(kind, &entry.path()) {
+                return None;
+            }
+        }
+        if let Some(rules) = &self.rules {
+            let path = entry.path();
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+            if !rules.is_included(relative, kind == FileKind::Dir) {
+                return None;
+            }
+        }
+        Some(entry)
+    }
+
+    pub(crate) fn make_entry(&self, entry: Listed, depth: usize) -> Entry {
+        let meta = entry.meta();
+        let entry = match (self.norm, entry) {
+            (None, Listed::Name(dir, entry)) => {
+                Entry::shared(dir, entry.name, depth, self.fs.clone())
+            }
+            (Some(form), entry) => {
+                Entry::new_in(normalize_path(entry.into_path(), form), depth, self.fs.clone())
+            }
+            (None, entry) => Entry::new_in(entry.into_path(), depth, self.fs.clone()),
+        };
+        entry.with_metadata(meta)
+    }
+
+    pub(crate) fn visit(&self, root: PathBuf, depth: usize, tx: EntrySender) -> Result<()> {
+        // Directories are visited in pre-order: all files of a directory first,
+        // then its subdirectories, in the order returned by the OS.
+        let mut stack: Vec<(PathBuf, usize)> = vec![(root, depth)];
+        let mut sub_dirs: Vec<PathBuf> = Vec::new();
+        while let Some((dir, depth)) = stack.pop() {
+            if self.is_cancelled() {
+                break;
+            }
+            let entries = self.read_dir(&dir).into_iter().flatten();
+            for entry in entries.filter_map(|entry| self.check(&dir, depth, entry)) {
+                if entry.kind() == FileKind::Dir {
+                    let path = entry.into_path();
+                    if self.descends(&path, depth) {
+                        sub_dirs.push(path)
+                    }
+                } else {
+                    tx.send(self.make_entry(entry, depth))?;
+                }
+            }
+            stack.extend(sub_dirs.drain(..).rev().map(|dir| (dir, depth + 1)));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn visit_multithreaded(
+        &self,
+        queue: &WorkQueue<DirJob>,
+        tx: EntrySender,
+    ) -> Result<()> {
+        while let Some((dir, depth, _)) = queue.pop() {
+            let _done = DoneGuard(queue);
+            if self.is_cancelled() {
+                queue.close();
+                break;
+            }
+            self.visit_dir(queue, &dir, depth, &tx)?;
+        }
+        Ok(())
+    }
+
+    fn visit_dir(
+        &self,
+        queue: &WorkQueue<DirJob>,
+        dir: &Path,
+        depth: usize,
+        tx: &EntrySender,
+    ) -> Result<()> {
+        let entries = self.read_dir(dir).into_iter().flatten();
+        for entry in entries.filter_map(|entry| self.check(dir, depth, entry)) {
+            if entry.kind() == FileKind::Dir {
+                let path = entry.into_path();
+                if self.descends(&path, depth) {
+                    queue.push((path, depth + 1, 0));
+                }
+            } else {
+                tx.send(self.make_entry(entry, depth))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `visit_multithreaded`, but sends whole directory listings, numbering the
+    /// subdirectories found with `seqs`, so that the consumer can restore the order of
+    /// the single-threaded traversal. Unreadable directories are sent empty. Listings are
+    /// sent once `window` has room for them; while waiting, the directory the consumer
+    /// waits for is read if no other worker has started on it.
+    pub(crate) fn visit_multithreaded_ordered(
+        &self,
+        queue: &WorkQueue<DirJob>,
+        seqs: &AtomicU64,
+        window: &Window,
+        tx: EntrySender<DirListing>,
+    ) -> Result<()> {
+        while let Some((dir, depth, seq)) = queue.pop() {
+            let _done = DoneGuard(queue);
+            if self.is_cancelled() {
+                queue.close();
+                break;
+            }
+            let listing = self.list_ordered(queue, seqs, &dir, depth, seq);
+            let mut sent = Ok(());
+            window.enter(seq, |wanted| {
+                let (dir, depth, seq) = match queue.take(|job| job.2 == wanted) {
+                    Some(job) => job,
+                    None => return false,
+                };
+                let _done = DoneGuard(queue);
+                let listing = self.list_ordered(queue, seqs, &dir, depth, seq);
+                window.enter(seq, |_| false);
+                if let Err(e) = tx.send(listing) {
+                    sent = Err(e);
+                }
+                true
+            });
+            sent?;
+            tx.send(listing)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a directory for `visit_multithreaded_ordered`, queueing its subdirectories.
+    fn list_ordered(
+        &self,
+        queue: &WorkQueue<DirJob>,
+        seqs: &AtomicU64,
+        dir: &Path,
+        depth: usize,
+        seq: u64,
+    ) -> DirListing {
+        let mut listing = DirListing {
+            seq,
+            entries: Vec::new(),
+            children: Vec::new(),
+        };
+        let entries = self.read_dir(dir).into_iter().flatten();
+        for entry in entries.filter_map(|entry| self.check(dir, depth, entry)) {
+            if entry.kind() == FileKind::Dir {
+                let path = entry.into_path();
+                if self.descends(&path, depth) {
+                    let child = seqs.fetch_add(1, Ordering::Relaxed);
+                    listing.children.push(child);
+                    queue.push((path, depth + 1, child));
+                }
+            } else {
+                listing.entries.push(self.make_entry(entry, depth));
+            }
+        }
+        listing
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::journal::Journal;
+use crate::result::Result;
+use crate::visit::Control;
+use crate::ReadDir;
+
+/// How an entry changed between two polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change of an entry (other than a directory) under a watched root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    pub path: PathBuf,
+    pub kind: WatchEventKind,
+}
+
+/// Watches a tree by polling: each call to [`Watcher::poll`] rescans it and reports what
+/// changed since the previous call. Changes are detected by size and modification time,
+/// so this works on every platform and filesystem, network ones included.
+pub struct Watcher {
+    root: PathBuf,
+    state: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+    journal: Option<Journal>,
+}
+
+impl Watcher {
+    /// Starts watching `root`, scanning it once.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Watcher> {
+        let mut watcher = Watcher {
+            root: root.as_ref().to_path_buf(),
+            state: HashMap::new(),
+            journal: None,
+        };
+        watcher.state = watcher.scan()?;
+        Ok(watcher)
+    }
+
+    /// Returns a root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the paths found by the last scan, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = self.state.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Records the events of every poll in a journal.
+    ///
+    /// # Arguments:
+    ///
+    /// * `journal` - journal to append the events to.
+    pub fn with_journal(mut self, journal: Journal) -> Watcher {
+        self.set_journal(journal);
+        self
+    }
+
+    pub(crate) fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Returns the journal the events are recorded in, if any.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Rescans the tree and returns the changes since the last scan, sorted by path.
+    /// With a journal, the changes are appended to it before they are returned.
+    pub fn poll(&mut self) -> Result<Vec<WatchEvent>> {
+        let state = self.scan()?;
+        let mut events = Vec::new();
+        for (path, current) in &state {
+            let kind = match self.state.get(path) {
+                None => WatchEventKind::Created,
+                Some(previous) if previous != current => WatchEventKind::Modified,
+                Some(_) => continue,
+            };
+            events.push(WatchEvent {
+                path: path.clone(),
+                kind,
+            });
+        }
+        for path in self.state.keys().filter(|path| !state.contains_key(*path)) {
+            events.push(WatchEvent {
+                path: path.clone(),
+                kind: WatchEventKind::Removed,
+            });
+        }
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+        if let (Some(journal), false) = (&mut self.journal, events.is_empty()) {
+            journal.append(&events)?;
+        }
+        self.state = state;
+        Ok(events)
+    }
+
+    fn scan(&self) -> Result<HashMap<PathBuf, (u64, Option<SystemTime>)>> {
+        let mut rd = ReadDir::try_new(&self.root)?;
+        rd.is_multithreaded = true;
+        Ok(rd
+            .filter_map(|entry| {
+                let meta = *entry.metadata().ok()?;
+                Some((entry.into_path(), (meta.len, meta.modified)))
+            })
+            .collect())
+    }
+}
+
+/// Adds an event to a batch, folding it into an earlier event of the same path.
+fn merge(batch: &mut BTreeMap<PathBuf, WatchEventKind>, event: WatchEvent) {
+    use WatchEventKind::*;
+    let kind = match (batch.get(&event.path), event.kind) {
+        // an entry that came and went within the batch is no change
+        (Some(Created), Removed) => {
+            batch.remove(&event.path);
+            return;
+        }
+        (Some(Created), _) => Created,
+        (Some(Removed), Created) => Modified,
+        (_, kind) => kind,
+    };
+    batch.insert(event.path, kind);
+}
+
+/// Calls `callback` whenever entries under `root` for which `filter` returns true change,
+/// the core loop of build and reload tools. The tree is polled every `debounce`; once a
+/// change is seen, polling goes on until a poll finds nothing new, and all changes are
+/// passed to `callback` as one batch, so a burst of writes (e.g. a checkout) triggers
+/// a single run. Returns when `callback` returns `Control::Stop`.
+///
+/// # Arguments:
+///
+/// * `root` - root directory.
+/// * `filter` - selects the paths to react to.
+/// * `callback` - receives the changes, sorted by path.
+/// * `debounce` - interval between polls.
+pub fn watch_and_run<P, F, C>(root: P, filter: F, mut callback: C, debounce: Duration) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> bool,
+    C: FnMut(&[WatchEvent]) -> Control,
+{
+    let mut watcher = Watcher::new(root)?;
+    let mut poll = |batch: &mut BTreeMap<PathBuf, WatchEventKind>| -> Result<bool> {
+        thread::sleep(debounce);
+        let events: Vec<_> = watcher
+            .poll()?
+            .into_iter()
+            .filter(|event| filter(&event.path))
+            .collect();
+        let changed = !events.is_empty();
+        for event in events {
+            merge(batch, event);
+        }
+        Ok(changed)
+    };
+    loop {
+        let mut batch = BTreeMap::new();
+        while !poll(&mut batch)? {}
+        while poll(&mut batch)? {}
+        if batch.is_empty() {
+            continue;
+        }
+        let events: Vec<_> = batch
+            .into_iter()
+            .map(|(path, kind)| WatchEvent { path, kind })
+            .collect();
+        if callback(&events) == Control::Stop {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::TreeBuilder;
+    use crate::watch::{watch_and_run, WatchEvent, WatchEventKind, Watcher};
+    use crate::Control;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn poll_and_watch_and_run() {
+        let tree = TreeBuilder::new()
+            .file("a.txt", b"1")
+            .file("b.txt", b"2")
+            .build()
+            .unwrap();
+        let mut watcher = Watcher::new(tree.path()).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        fs::write(tree.join("a.txt"), b"11").unwrap();
+        fs::remove_file(tree.join("b.txt")).unwrap();
+        fs::write(tree.join("c.txt"), b"3").unwrap();
+        let event = |name, kind| WatchEvent {
+            path: tree.join(name),
+            kind,
+        };
+        assert_eq!(
+            watcher.poll().unwrap(),
+            [
+                event("a.txt", WatchEventKind::Modified),
+                event("b.txt", WatchEventKind::Removed),
+                event("c.txt", WatchEventKind::Created)
+            ]
+        );
+
+        let writer = {
+            let root = tree.path().to_path_buf();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                fs::write(root.join("x.log"), b"").unwrap();
+                fs::write(root.join("x.rs"), b"").unwrap();
+            })
+        };
+        let mut batches = Vec::new();
+        watch_and_run(
+            tree.path(),
+            |path| path.extension().is_some_and(|ext| ext == "rs"),
+            |events| {
+                batches.push(events.to_vec());
+                Control::Stop
+            },
+            Duration::from_millis(20),
+        )
+        .unwrap();
+        writer.join().unwrap();
+        assert_eq!(batches, [[event("x.rs", WatchEventKind::Created)]]);
+    }
+}